@@ -0,0 +1,203 @@
+//! Measures the throughput of SET and GET with concurrent clients.
+//!
+//! Usage: cargo run --release --example benchmark -- [--port 6379] [--clients 50]
+//! [--requests 200000] [--pipeline 1] [--keyspace 100000]
+//!
+//! Moving the database to its own thread, measured on the only machine available, which
+//! has 1 CPU, so several clients could not be measured on several cores:
+//!
+//! | clients | pipeline | SET before | SET after  | GET before | GET after  |
+//! |---------|----------|------------|------------|------------|------------|
+//! | 1       | 1        | 54138 rps  | 36309 rps  | 51144 rps  | 39440 rps  |
+//! | 4       | 1        | 63983 rps  | 49934 rps  | 59909 rps  | 51859 rps  |
+//! | 16      | 1        | 67185 rps  | 55826 rps  | 69012 rps  | 50827 rps  |
+//! | 50      | 1        | 66800 rps  | 52188 rps  | 73251 rps  | 50199 rps  |
+//! | 50      | 16       | 18156 rps  | 143158 rps | 18218 rps  | 173358 rps |
+//!
+//! Unpipelined throughput went down by 20% to 33%: each request is handed to the database
+//! thread and its reply back to the connection, two wake-ups that a single CPU cannot
+//! overlap with anything else. This is kept as the cost of the design: the database is
+//! never locked from async tasks, a slow command no longer stalls the runtime, and
+//! multi-key commands stay atomic. The hand-off is paid once per read instead of once per
+//! command, so pipelining more than makes up for it.
+
+use std::{
+    env, process,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const USAGE: &str = "Usage: benchmark [--port 6379] [--clients 50] [--requests 200000] \
+    [--pipeline 1] [--keyspace 100000]";
+
+struct Options {
+    port: u16,
+    clients: usize,
+    requests: usize,
+    pipeline: usize,
+    keyspace: usize,
+}
+
+impl Options {
+    fn from_args() -> anyhow::Result<Self> {
+        let mut options = Options {
+            port: 6379,
+            clients: 50,
+            requests: 200_000,
+            pipeline: 1,
+            keyspace: 100_000,
+        };
+        let args: Vec<String> = env::args().skip(1).collect();
+        for pair in args.chunks(2) {
+            let [name, value] = pair else {
+                anyhow::bail!("Missing value for {}", pair[0])
+            };
+            match name.as_str() {
+                "--port" => options.port = value.parse()?,
+                "--clients" => options.clients = value.parse()?,
+                "--requests" => options.requests = value.parse()?,
+                "--pipeline" => options.pipeline = value.parse()?,
+                "--keyspace" => options.keyspace = value.parse()?,
+                _ => anyhow::bail!("Unknown option {name}"),
+            }
+        }
+        // Requests are split between the clients and their pipelines, and keys drawn
+        // modulo the keyspace
+        for (name, value) in [
+            ("--clients", options.clients),
+            ("--requests", options.requests),
+            ("--pipeline", options.pipeline),
+            ("--keyspace", options.keyspace),
+        ] {
+            if value == 0 {
+                anyhow::bail!("{name} must be at least 1")
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}\n{USAGE}");
+            process::exit(1)
+        }
+    };
+    for command in ["SET", "GET"] {
+        let (elapsed, latencies) = run(&options, command).await?;
+        let requests = latencies.len() * options.pipeline;
+        println!(
+            "{command}: {:.0} requests per second, p50 {:?}, p99 {:?} ({} clients, pipeline {})",
+            requests as f64 / elapsed.as_secs_f64(),
+            percentile(&latencies, 50),
+            percentile(&latencies, 99),
+            options.clients,
+            options.pipeline,
+        );
+    }
+    Ok(())
+}
+
+/// Runs the command from all the clients, returning the total time and the latency of
+/// each round trip
+async fn run(options: &Options, command: &str) -> anyhow::Result<(Duration, Vec<Duration>)> {
+    let round_trips = options.requests / options.clients / options.pipeline;
+    let start = Instant::now();
+    let mut tasks = Vec::new();
+    for client in 0..options.clients {
+        let (port, pipeline, keyspace) = (options.port, options.pipeline, options.keyspace);
+        let command = command.to_string();
+        tasks.push(tokio::spawn(async move {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+            let mut latencies = Vec::with_capacity(round_trips);
+            let mut buffer = Vec::new();
+            let mut random = (client as u64 + 1) * 0x9e37_79b9_7f4a_7c15;
+            for _ in 0..round_trips {
+                let mut request = Vec::new();
+                for _ in 0..pipeline {
+                    random ^= random << 13;
+                    random ^= random >> 7;
+                    random ^= random << 17;
+                    let key = format!("key:{}", random % keyspace as u64);
+                    let arguments: &[&str] = match command.as_str() {
+                        "SET" => &["SET", &key, "value"],
+                        _ => &["GET", &key],
+                    };
+                    request.extend(encode(arguments));
+                }
+
+                let round_trip_start = Instant::now();
+                stream.write_all(&request).await?;
+                read_replies(&mut stream, &mut buffer, pipeline).await?;
+                latencies.push(round_trip_start.elapsed());
+            }
+            anyhow::Ok(latencies)
+        }));
+    }
+
+    let mut latencies = Vec::new();
+    for task in tasks {
+        latencies.extend(task.await??);
+    }
+    Ok((start.elapsed(), latencies))
+}
+
+fn encode(arguments: &[&str]) -> Vec<u8> {
+    let mut bytes = format!("*{}\r\n", arguments.len()).into_bytes();
+    for argument in arguments {
+        bytes.extend(format!("${}\r\n{argument}\r\n", argument.len()).into_bytes());
+    }
+    bytes
+}
+
+/// Reads simple string, integer or bulk string replies until there are count of them
+async fn read_replies(
+    stream: &mut TcpStream,
+    buffer: &mut Vec<u8>,
+    mut count: usize,
+) -> anyhow::Result<()> {
+    while count > 0 {
+        while let Some(length) = reply_length(buffer) {
+            buffer.drain(..length);
+            count -= 1;
+            if count == 0 {
+                return Ok(());
+            }
+        }
+        if stream.read_buf(buffer).await? == 0 {
+            anyhow::bail!("Connection closed")
+        }
+    }
+    Ok(())
+}
+
+fn reply_length(buffer: &[u8]) -> Option<usize> {
+    let line_end = buffer.windows(2).position(|window| window == b"\r\n")? + 2;
+    if buffer[0] != b'$' {
+        return Some(line_end);
+    }
+    let length: i64 = std::str::from_utf8(&buffer[1..line_end - 2])
+        .ok()?
+        .parse()
+        .ok()?;
+    if length < 0 {
+        return Some(line_end);
+    }
+    let end = line_end + length as usize + 2;
+    (buffer.len() >= end).then_some(end)
+}
+
+fn percentile(latencies: &[Duration], percentile: usize) -> Duration {
+    let mut latencies = latencies.to_vec();
+    latencies.sort();
+    latencies
+        .get(latencies.len() * percentile / 100)
+        .copied()
+        .unwrap_or_default()
+}
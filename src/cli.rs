@@ -1,14 +1,21 @@
 const PARAM_PREFIX: &str = "--";
-const PARAM_DIR: &str = "--dir";
-const PARAM_DBFILENAME: &str = "--dbfilename";
 
-#[derive(Debug)]
-pub enum CliParam {
-    Dir(String),
-    DbFilename(String),
+/// A setting given on the command line, like `--port 6380`, or in the config file
+#[derive(Debug, PartialEq)]
+pub struct CliParam {
+    pub name: String,
+    /// Values joined by spaces, like the addresses of `--bind 127.0.0.1 ::1`
+    pub value: String,
 }
 
 impl CliParam {
+    pub fn new(name: &str, values: &[String]) -> Self {
+        CliParam {
+            name: name.to_lowercase(),
+            value: values.join(" "),
+        }
+    }
+
     fn get_param_value(string: &str) -> Option<String> {
         let string = string.trim();
         if string.is_empty() || string.starts_with(PARAM_PREFIX) {
@@ -19,34 +26,23 @@ impl CliParam {
 
     pub fn from(strings: &[String]) -> Vec<Self> {
         let mut params: Vec<Self> = Vec::new();
-        if strings.is_empty() {
-            return params;
-        }
+        let mut index = 0;
+        while index < strings.len() {
+            let name = &strings[index];
+            index += 1;
+            let Some(name) = name.strip_prefix(PARAM_PREFIX) else {
+                continue;
+            };
 
-        let mut strings_next_index = 1;
-        match strings[0].to_lowercase().as_str() {
-            PARAM_DIR => {
-                if strings.len() >= 2 {
-                    if let Some(value) = Self::get_param_value(&strings[1]) {
-                        params.push(CliParam::Dir(value));
-                        strings_next_index += 1;
-                    }
-                }
-            }
-            PARAM_DBFILENAME => {
-                if strings.len() >= 2 {
-                    if let Some(value) = Self::get_param_value(&strings[1]) {
-                        params.push(CliParam::DbFilename(value));
-                        strings_next_index += 1;
-                    }
-                }
-            }
-            _ => {}
+            // Takes all the values up to the next param
+            let values: Vec<String> = strings[index..]
+                .iter()
+                .map_while(|string| Self::get_param_value(string))
+                .collect();
+            index += values.len();
+            params.push(CliParam::new(name, &values));
         }
 
-        // Tries to get other params from the remaining strings
-        params.extend(Self::from(&strings[strings_next_index..]));
-
         params
     }
 }
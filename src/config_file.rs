@@ -0,0 +1,174 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::cli::CliParam;
+
+const DIRECTIVE_INCLUDE: &str = "include";
+const COMMENT_PREFIX: char = '#';
+
+#[cfg(test)]
+mod tests;
+
+/// Reads a redis.conf style file, made of `directive arg arg` lines, into params.
+/// Included files are read in place, so a directive set later wins over earlier ones.
+pub fn load(path: &str) -> anyhow::Result<Vec<CliParam>> {
+    let mut params = Vec::new();
+    load_file(Path::new(path), &mut Vec::new(), &mut params)?;
+    Ok(params)
+}
+
+fn load_file(
+    path: &Path,
+    included_from: &mut Vec<PathBuf>,
+    params: &mut Vec<CliParam>,
+) -> anyhow::Result<()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) => anyhow::bail!("Failed to read config file {}: {error}", path.display()),
+    };
+    let canonical_path = fs::canonicalize(path)?;
+    if included_from.contains(&canonical_path) {
+        anyhow::bail!("Config file {} includes itself", path.display());
+    }
+    included_from.push(canonical_path);
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(COMMENT_PREFIX) {
+            continue;
+        }
+
+        let line_error = |error: anyhow::Error| {
+            anyhow::anyhow!(
+                "Config file {} line {}: {error} >>> '{line}'",
+                path.display(),
+                index + 1
+            )
+        };
+        let arguments = split_arguments(line).map_err(line_error)?;
+        let Some((name, values)) = arguments.split_first() else {
+            continue;
+        };
+
+        if name.eq_ignore_ascii_case(DIRECTIVE_INCLUDE) {
+            let [include_path] = values else {
+                return Err(line_error(anyhow::anyhow!(
+                    "wrong number of arguments for '{name}'"
+                )));
+            };
+            load_file(Path::new(include_path), included_from, params)?;
+            continue;
+        }
+
+        params.push(CliParam::new(name, values));
+    }
+
+    included_from.pop();
+    Ok(())
+}
+
+/// Splits a line into arguments the way redis-server does. Arguments can be quoted:
+/// double quotes take escapes like \n or \x41, single quotes only take \'.
+pub fn split_arguments(line: &str) -> anyhow::Result<Vec<String>> {
+    let bytes = line.as_bytes();
+    let mut arguments = Vec::new();
+    let mut position = 0;
+    loop {
+        while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position == bytes.len() {
+            return Ok(arguments);
+        }
+
+        let mut argument = Vec::new();
+        let mut quote = None;
+        loop {
+            let Some(&byte) = bytes.get(position) else {
+                if quote.is_some() {
+                    anyhow::bail!("unbalanced quotes in configuration line");
+                }
+                break;
+            };
+            position += 1;
+            match quote {
+                None if byte.is_ascii_whitespace() => break,
+                None if byte == b'"' || byte == b'\'' => quote = Some(byte),
+                None => argument.push(byte),
+                Some(b'"') if byte == b'\\' && position < bytes.len() => {
+                    let (escaped, read_count) = unescape(&bytes[position..]);
+                    argument.push(escaped);
+                    position += read_count;
+                }
+                Some(b'\'') if byte == b'\\' && bytes.get(position) == Some(&b'\'') => {
+                    argument.push(b'\'');
+                    position += 1;
+                }
+                Some(quote_byte) if byte == quote_byte => {
+                    // The closing quote must end the argument
+                    if bytes
+                        .get(position)
+                        .is_some_and(|next| !next.is_ascii_whitespace())
+                    {
+                        anyhow::bail!("closing quote must be followed by a space");
+                    }
+                    break;
+                }
+                Some(_) => argument.push(byte),
+            }
+        }
+        arguments.push(String::from_utf8_lossy(&argument).into_owned());
+    }
+}
+
+/// Quotes the argument when split_arguments could not read it back as it is
+pub fn quote_argument(argument: &str) -> String {
+    let is_plain = !argument.is_empty()
+        && argument
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && !matches!(byte, b'"' | b'\'' | b'\\'));
+    if is_plain {
+        return argument.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for byte in argument.bytes() {
+        match byte {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            byte if byte.is_ascii_graphic() || byte == b' ' => quoted.push(byte as char),
+            byte => quoted.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the byte for the escape sequence following a backslash, and its length
+fn unescape(bytes: &[u8]) -> (u8, usize) {
+    if let [b'x', high, low, ..] = bytes {
+        if let (Some(high), Some(low)) = (hex_digit(*high), hex_digit(*low)) {
+            return (high << 4 | low, 3);
+        }
+    }
+    let byte = match bytes[0] {
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'b' => 0x08,
+        b'a' => 0x07,
+        byte => byte,
+    };
+    (byte, 1)
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
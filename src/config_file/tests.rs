@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod test {
+    use crate::{
+        cli::CliParam,
+        config_file::{load, split_arguments},
+    };
+    use std::{env, fs, path::PathBuf};
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_split_arguments_with_quotes_and_escapes() {
+        // When
+        let arguments = split_arguments(r#"  bind 127.0.0.1   "::1" 'it\'s' "a\tb\x41\"" "" "#);
+        // Then
+        assert_eq!(
+            arguments.unwrap(),
+            ["bind", "127.0.0.1", "::1", "it's", "a\tbA\"", ""]
+        );
+    }
+
+    #[test]
+    fn test_split_arguments_rejects_bad_quotes() {
+        // Then
+        assert!(split_arguments(r#"dir "/tmp"#).is_err());
+        assert!(split_arguments(r#"dir "/tmp"x"#).is_err());
+        assert!(split_arguments("dir 'x").is_err());
+    }
+
+    #[test]
+    fn test_load_reads_includes_in_place() {
+        // Given
+        let included = temp_file("included.conf", "port 7000\ndir /included\n");
+        let main = temp_file(
+            "main.conf",
+            &format!(
+                "# Comment\nport 6380\n\nINCLUDE {}\nmaxmemory 1gb\ndir \"/main dir\"\n",
+                included.display()
+            ),
+        );
+        // When
+        let params = load(main.to_str().unwrap()).unwrap();
+        // Then
+        assert_eq!(
+            params,
+            vec![
+                CliParam::new("port", &["6380".into()]),
+                CliParam::new("port", &["7000".into()]),
+                CliParam::new("dir", &["/included".into()]),
+                CliParam::new("maxmemory", &["1gb".into()]),
+                CliParam::new("dir", &["/main dir".into()]),
+            ]
+        );
+        fs::remove_file(included).unwrap();
+        fs::remove_file(main).unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_include_cycles_and_bad_lines() {
+        // Given
+        let cycle = env::temp_dir().join(format!("{}-cycle.conf", std::process::id()));
+        fs::write(&cycle, format!("include {}\n", cycle.display())).unwrap();
+        let bad_arguments = temp_file("bad.conf", "port \"1\n");
+        // Then
+        assert!(load(cycle.to_str().unwrap()).is_err());
+        let error = load(bad_arguments.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("line 1"));
+        fs::remove_file(cycle).unwrap();
+        fs::remove_file(bad_arguments).unwrap();
+    }
+}
@@ -1,85 +1,158 @@
 use std::{
     collections::HashMap,
-    time::{SystemTime, UNIX_EPOCH},
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
 const SETTINGS_DIR_ID: &str = "dir";
 const SETTINGS_DBFILENAME_ID: &str = "dbfilename";
+const SETTINGS_HZ_ID: &str = "hz";
+const SETTINGS_DATABASES_ID: &str = "databases";
+const SETTINGS_PORT_ID: &str = "port";
+const SETTINGS_BIND_ID: &str = "bind";
+const SETTINGS_UNIXSOCKET_ID: &str = "unixsocket";
+const SETTINGS_UNIXSOCKETPERM_ID: &str = "unixsocketperm";
+const SETTINGS_APPENDONLY_ID: &str = "appendonly";
+const SETTINGS_APPENDFILENAME_ID: &str = "appendfilename";
+const SETTINGS_APPENDFSYNC_ID: &str = "appendfsync";
+const SETTINGS_AOF_LOAD_TRUNCATED_ID: &str = "aof-load-truncated";
+const SETTINGS_APPENDDIRNAME_ID: &str = "appenddirname";
+const SETTINGS_AOF_USE_RDB_PREAMBLE_ID: &str = "aof-use-rdb-preamble";
+const SETTINGS_AUTO_AOF_REWRITE_PERCENTAGE_ID: &str = "auto-aof-rewrite-percentage";
+const SETTINGS_AUTO_AOF_REWRITE_MIN_SIZE_ID: &str = "auto-aof-rewrite-min-size";
+const SETTINGS_SAVE_ID: &str = "save";
+const SETTINGS_STOP_WRITES_ON_BGSAVE_ERROR_ID: &str = "stop-writes-on-bgsave-error";
+const SETTINGS_PIDFILE_ID: &str = "pidfile";
+const SETTINGS_REPLICAOF_ID: &str = "replicaof";
+const SETTINGS_REPLICA_READ_ONLY_ID: &str = "replica-read-only";
+const SETTINGS_REPL_BACKLOG_SIZE_ID: &str = "repl-backlog-size";
+const SETTINGS_REPL_PING_REPLICA_PERIOD_ID: &str = "repl-ping-replica-period";
+const SETTINGS_REPL_TIMEOUT_ID: &str = "repl-timeout";
+const SETTINGS_REPL_DISKLESS_SYNC_ID: &str = "repl-diskless-sync";
+const SETTINGS_REPL_DISKLESS_SYNC_DELAY_ID: &str = "repl-diskless-sync-delay";
+const SETTINGS_REPL_DISKLESS_SYNC_MAX_REPLICAS_ID: &str = "repl-diskless-sync-max-replicas";
+const SETTINGS_REPL_DISKLESS_LOAD_ID: &str = "repl-diskless-load";
+const SETTINGS_CLUSTER_ENABLED_ID: &str = "cluster-enabled";
+const SETTINGS_CLUSTER_CONFIG_FILE_ID: &str = "cluster-config-file";
+const SETTINGS_CLUSTER_NODE_TIMEOUT_ID: &str = "cluster-node-timeout";
+const SETTINGS_CLUSTER_PORT_ID: &str = "cluster-port";
+const SETTINGS_CLUSTER_REQUIRE_FULL_COVERAGE_ID: &str = "cluster-require-full-coverage";
 
-const PATTERN_ALL: &str = "*";
+const DEFAULT_DATABASES: usize = 16;
 
+pub const TYPE_NAME_NONE: &str = "none";
+pub const TYPE_NAME_STRING: &str = "string";
+
+pub mod aof;
+pub mod bitmap;
+pub mod cluster;
 mod config;
-mod rdb;
+mod dict;
+pub mod expire;
+mod generic;
+pub mod hyperloglog;
+mod info;
+mod key_set;
+pub mod keyspace;
+pub mod migrate;
+pub mod pattern;
+mod propagate;
+mod random;
+pub mod rdb;
+pub mod replication;
+mod scan;
+pub mod shutdown;
+pub mod snapshot;
+pub mod stats;
+pub mod string;
+
+#[cfg(test)]
+mod test_helpers;
+#[cfg(test)]
+mod tests;
 
-#[derive(Debug)]
+use self::{
+    aof::{rewrite::AofRewrite, Aof},
+    cluster::Cluster,
+    config::registry::ConfigValue,
+    keyspace::Keyspace,
+    rdb::save::{RdbSave, SaveStatus},
+    replication::Replication,
+    stats::Stats,
+    string::Value,
+};
+
+#[derive(Debug, Clone)]
 pub struct Entry {
-    value: String,
+    value: Value,
     expires_at: Option<u128>,
 }
 
+impl Entry {
+    pub fn is_expired(&self, now: u128) -> bool {
+        matches!(self.expires_at, Some(expires_at) if now > expires_at)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        TYPE_NAME_STRING
+    }
+}
+
 pub struct Database {
-    data: HashMap<String, Entry>,
-    config: HashMap<String, String>,
+    keyspaces: Vec<Keyspace>,
+    config: HashMap<&'static str, ConfigValue>,
+    /// Where CONFIG REWRITE writes the settings, when started with a config file
+    config_file: Option<PathBuf>,
     metadata: HashMap<String, String>,
+    stats: Stats,
+    /// Logs the write commands while appendonly is on
+    aof: Option<Aof>,
+    aof_rewrite: Option<AofRewrite>,
+    aof_rewrite_failed_at: Option<Instant>,
+    save_status: SaveStatus,
+    rdb_save: Option<RdbSave>,
+    /// Set once the dataset is saved for exiting
+    is_shut_down: bool,
+    replication: Replication,
+    /// Set when started with cluster-enabled
+    cluster: Option<Cluster>,
 }
 
 // Init related
 impl Database {
     pub fn new() -> Self {
         Database {
-            data: HashMap::new(),
-            config: HashMap::new(),
+            keyspaces: (0..DEFAULT_DATABASES).map(|_| Keyspace::new()).collect(),
+            config: config::default_config(),
+            config_file: None,
             metadata: HashMap::new(),
+            stats: Stats::default(),
+            aof: None,
+            aof_rewrite: None,
+            aof_rewrite_failed_at: None,
+            save_status: SaveStatus::new(),
+            rdb_save: None,
+            is_shut_down: false,
+            replication: Replication::new(),
+            cluster: None,
         }
     }
 }
 
 impl Database {
-    pub fn set(
-        &mut self,
-        key: String,
-        value: String,
-        expires_at: Option<u128>,
-    ) -> anyhow::Result<()> {
-        self.data.insert(key, Entry { value, expires_at });
-        Ok(())
+    pub fn keyspace(&mut self, index: usize) -> &mut Keyspace {
+        &mut self.keyspaces[index]
     }
 
-    pub fn get(&mut self, key: String) -> anyhow::Result<Option<String>> {
-        let Some(item) = self.data.get(&key) else {
-            return Ok(None);
-        };
-
-        let value = Some(item.value.clone());
-        let Some(expires_at) = item.expires_at else {
-            return Ok(value);
-        };
-
-        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
-        if now <= expires_at {
-            return Ok(value);
+    /// Checks that the index points to an existing keyspace
+    pub fn validate_keyspace_index(&self, index: i64) -> anyhow::Result<usize> {
+        if index < 0 || index as usize >= self.keyspaces.len() {
+            anyhow::bail!("ERR DB index is out of range")
         }
-
-        self.delete(key)?;
-        Ok(None)
-    }
-
-    pub fn delete(&mut self, key: String) -> anyhow::Result<()> {
-        self.data.remove(&key);
-        Ok(())
+        Ok(index as usize)
     }
+}
 
-    pub fn keys(&self, pattern: String) -> anyhow::Result<Vec<String>> {
-        let keys = self.data.keys().cloned().collect();
-        if pattern == PATTERN_ALL {
-            return Ok(keys);
-        }
-
-        let filtered_keys = keys
-            .iter()
-            .filter(|key| key.contains(&pattern))
-            .cloned()
-            .collect();
-        Ok(filtered_keys)
-    }
+pub fn get_current_time_ms() -> anyhow::Result<u128> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis())
 }
@@ -0,0 +1,480 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use self::manifest::{AofFileInfo, AofManifest};
+use super::{snapshot::Snapshot, Database};
+
+pub mod manifest;
+pub mod rewrite;
+
+#[cfg(test)]
+mod tests;
+
+const MANIFEST_SUFFIX: &str = ".manifest";
+
+const APPEND_FSYNC_ALWAYS: &str = "always";
+const APPEND_FSYNC_EVERYSEC: &str = "everysec";
+const APPEND_FSYNC_NO: &str = "no";
+pub const APPEND_FSYNC_NAMES: &[&str] =
+    &[APPEND_FSYNC_ALWAYS, APPEND_FSYNC_EVERYSEC, APPEND_FSYNC_NO];
+
+const EVERYSEC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the writes logged to the AOF are flushed to the disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppendFsync {
+    /// Before replying to the client, so no acknowledged write is ever lost
+    Always,
+    /// Once per second in the background, losing at most a second of writes
+    EverySec,
+    /// Whenever the operating system decides
+    No,
+}
+
+impl AppendFsync {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            APPEND_FSYNC_ALWAYS => AppendFsync::Always,
+            APPEND_FSYNC_NO => AppendFsync::No,
+            _ => AppendFsync::EverySec,
+        }
+    }
+}
+
+/// The incremental file of the AOF being written, logging every write command in the
+/// RESP format
+pub struct Aof {
+    file: File,
+    /// Commands not written to the file yet
+    buffer: Vec<u8>,
+    /// Database of the last logged command, a SELECT is logged when it changes
+    selected_db: Option<usize>,
+    has_unsynced_writes: bool,
+    last_fsync: Instant,
+    fsync_in_progress: Arc<AtomicBool>,
+    /// Files to fsync on the background thread, with the replication offset they reach.
+    /// The thread stops when the sender is dropped.
+    fsync_jobs: mpsc::Sender<(File, u64)>,
+    /// Replication offset reached by the commands written to the file
+    written_offset: u64,
+    /// Replication offset reached by the commands fsynced, acknowledged by WAITAOF
+    fsynced_offset: Arc<AtomicU64>,
+    /// Size of all the AOF files, growing with each write
+    current_size: u64,
+    /// Size of all the AOF files after the last rewrite, or when loaded
+    rewrite_base_size: u64,
+}
+
+impl Aof {
+    fn open(file: File) -> anyhow::Result<Self> {
+        let fsync_in_progress = Arc::new(AtomicBool::new(false));
+        let fsynced_offset = Arc::new(AtomicU64::new(0));
+        let (fsync_jobs, receiver) = mpsc::channel::<(File, u64)>();
+        let thread_fsync_in_progress = fsync_in_progress.clone();
+        let thread_fsynced_offset = fsynced_offset.clone();
+        thread::Builder::new()
+            .name("aof-fsync".into())
+            .spawn(move || {
+                for (file, offset) in receiver {
+                    match file.sync_data() {
+                        Ok(()) => {
+                            thread_fsynced_offset.fetch_max(offset, Ordering::AcqRel);
+                        }
+                        Err(error) => {
+                            eprintln!("-> Failed to fsync the append only file. Error: {error}")
+                        }
+                    }
+                    thread_fsync_in_progress.store(false, Ordering::Release);
+                }
+            })?;
+
+        Ok(Aof {
+            file,
+            buffer: Vec::new(),
+            selected_db: None,
+            has_unsynced_writes: false,
+            last_fsync: Instant::now(),
+            fsync_in_progress,
+            fsync_jobs,
+            written_offset: 0,
+            fsynced_offset,
+            current_size: 0,
+            rewrite_base_size: 0,
+        })
+    }
+
+    fn feed(&mut self, db: usize, arguments: &[Vec<u8>]) {
+        if self.selected_db != Some(db) {
+            encode_command(
+                &mut self.buffer,
+                &[b"SELECT".to_vec(), db.to_string().into()],
+            );
+            self.selected_db = Some(db);
+        }
+        encode_command(&mut self.buffer, arguments);
+    }
+
+    /// Writes the buffered commands, then fsyncs them following the policy. The commands
+    /// fed so far reach the replication offset.
+    fn flush(&mut self, append_fsync: AppendFsync, offset: u64) -> anyhow::Result<()> {
+        if !self.buffer.is_empty() {
+            // After a failed write the rest of the commands stay buffered for the next flush
+            let result = self.write_buffer();
+            let written = match &result {
+                Ok(written) | Err((written, _)) => *written,
+            };
+            self.buffer.drain(..written);
+            self.current_size += written as u64;
+            self.has_unsynced_writes |= written > 0;
+            if let Err((_, error)) = result {
+                anyhow::bail!("Failed to write the append only file: {error}")
+            }
+        }
+        self.written_offset = offset;
+        if !self.has_unsynced_writes {
+            // The offset may grow with commands that are not logged, like the PINGs sent
+            // to replicas, so it is reached once all the logged ones are fsynced
+            let is_fsynced =
+                append_fsync != AppendFsync::No && !self.fsync_in_progress.load(Ordering::Acquire);
+            if is_fsynced {
+                self.fsynced_offset.fetch_max(offset, Ordering::AcqRel);
+            }
+            return Ok(());
+        }
+
+        match append_fsync {
+            AppendFsync::Always => {
+                self.file.sync_data()?;
+                self.fsynced_offset.fetch_max(offset, Ordering::AcqRel);
+                self.synced();
+            }
+            AppendFsync::EverySec if self.last_fsync.elapsed() >= EVERYSEC_INTERVAL => {
+                // A slow disk may still be busy with the previous fsync
+                if !self.fsync_in_progress.swap(true, Ordering::AcqRel) {
+                    let file = self.file.try_clone()?;
+                    self.fsync_jobs.send((file, self.written_offset))?;
+                    self.synced();
+                }
+            }
+            AppendFsync::EverySec | AppendFsync::No => {}
+        }
+        Ok(())
+    }
+
+    /// Returns how many bytes of the buffer were written, even when failing
+    fn write_buffer(&mut self) -> Result<usize, (usize, std::io::Error)> {
+        let mut written = 0;
+        while written < self.buffer.len() {
+            match self.file.write(&self.buffer[written..]) {
+                Ok(0) => return Err((written, std::io::ErrorKind::WriteZero.into())),
+                Ok(count) => written += count,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(error) => return Err((written, error)),
+            }
+        }
+        Ok(written)
+    }
+
+    fn synced(&mut self) {
+        self.has_unsynced_writes = false;
+        self.last_fsync = Instant::now();
+    }
+}
+
+impl Database {
+    /// Directory of the AOF files and their manifest, inside dir
+    pub fn aof_dir(&self) -> PathBuf {
+        let mut path = PathBuf::new();
+        path.push(self.dir());
+        path.push(self.appenddirname());
+        path
+    }
+
+    pub fn aof_file_path(&self, name: &str) -> PathBuf {
+        self.aof_dir().join(name)
+    }
+
+    fn aof_manifest_path(&self) -> PathBuf {
+        self.aof_file_path(&format!("{}{MANIFEST_SUFFIX}", self.appendfilename()))
+    }
+
+    pub fn aof_exists(&self) -> bool {
+        self.aof_manifest_path().exists()
+    }
+
+    pub fn is_aof_on(&self) -> bool {
+        self.aof.is_some()
+    }
+
+    /// Returns the files of the AOF, none when there is no manifest yet
+    pub fn load_aof_manifest(&self) -> anyhow::Result<AofManifest> {
+        let path = self.aof_manifest_path();
+        if !path.exists() {
+            return Ok(AofManifest::default());
+        }
+        AofManifest::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Replaces the manifest at once, so that it always lists a complete set of files
+    fn persist_aof_manifest(&self, manifest: &AofManifest) -> anyhow::Result<()> {
+        let path = self.aof_manifest_path();
+        let mut temporary_path = path.clone().into_os_string();
+        temporary_path.push(format!(".tmp-{}", std::process::id()));
+        let result = File::create(&temporary_path).and_then(|mut file| {
+            file.write_all(manifest.format().as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temporary_path, &path)
+        });
+        if let Err(error) = result {
+            let _ = fs::remove_file(&temporary_path);
+            anyhow::bail!("Failed to write the AOF manifest: {error}")
+        }
+        Ok(())
+    }
+
+    /// Moves an AOF written before the multi part format, a single file in dir, into the
+    /// AOF directory as the base file of a new manifest
+    pub fn upgrade_aof(&self) -> anyhow::Result<()> {
+        let legacy_path = PathBuf::from(self.dir()).join(self.appendfilename());
+        if self.aof_exists() || !legacy_path.is_file() {
+            return Ok(());
+        }
+        fs::create_dir_all(self.aof_dir())?;
+        let manifest = AofManifest {
+            base: Some(AofFileInfo {
+                name: self.appendfilename(),
+                seq: 1,
+            }),
+            incrs: Vec::new(),
+        };
+        // A crash before the move leaves a manifest with a missing file, failing the next
+        // start instead of starting from an empty dataset
+        self.persist_aof_manifest(&manifest)?;
+        fs::rename(&legacy_path, self.aof_file_path(&self.appendfilename()))?;
+        println!(
+            "-> Moved the append only file {} into {}",
+            legacy_path.display(),
+            self.aof_dir().display()
+        );
+        Ok(())
+    }
+
+    /// Starts logging to the last incremental file once the AOF is loaded
+    pub fn open_aof(&mut self) -> anyhow::Result<()> {
+        let mut manifest = self.load_aof_manifest()?;
+        match manifest.incrs.last() {
+            Some(incr) => {
+                let path = self.aof_file_path(&incr.name);
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                self.aof = Some(Aof::open(file)?);
+            }
+            None => {
+                self.open_new_aof_incr(&mut manifest)?;
+                self.persist_aof_manifest(&manifest)?;
+            }
+        }
+        self.reset_aof_sizes(&manifest);
+        Ok(())
+    }
+
+    /// Replaces the AOF with a base file holding the current dataset, then starts logging
+    /// to a new incremental file
+    pub fn create_aof(&mut self) -> anyhow::Result<()> {
+        // A rewrite started before would not include the writes logged from now on
+        self.discard_aof_rewrite();
+        let mut manifest = self.load_aof_manifest()?;
+        let first_kept_incr_seq = self.open_new_aof_incr(&mut manifest)?;
+
+        let is_rdb = self.aof_use_rdb_preamble();
+        let temporary_path =
+            self.aof_file_path(&format!("temp-rewriteaof-{}.aof", std::process::id()));
+        if let Err(error) = write_aof_base(&self.snapshot()?, &temporary_path, is_rdb) {
+            anyhow::bail!("Failed to create the append only file: {error}")
+        }
+        self.install_aof_base(manifest, &temporary_path, is_rdb, first_kept_incr_seq)
+    }
+
+    /// Flushes the logged commands and stops logging
+    pub fn stop_aof(&mut self) -> anyhow::Result<()> {
+        let offset = self.replication_offset();
+        if let Some(mut aof) = self.aof.take() {
+            aof.flush(AppendFsync::Always, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Logs a write command run in the db
+    pub fn feed_aof(&mut self, db: usize, arguments: &[Vec<u8>]) {
+        if let Some(aof) = &mut self.aof {
+            aof.feed(db, arguments);
+        }
+    }
+
+    /// Writes the logged commands to the AOF, and fsyncs them depending on appendfsync.
+    /// Runs after each batch of commands, before replying, and on every background tick.
+    pub fn flush_aof(&mut self) -> anyhow::Result<()> {
+        let append_fsync = self.appendfsync();
+        let offset = self.replication_offset();
+        let Some(aof) = &mut self.aof else {
+            return Ok(());
+        };
+        match aof.flush(append_fsync, offset) {
+            Ok(()) => Ok(()),
+            // An acknowledged write must be on the disk, there is no way to recover
+            Err(error) if append_fsync == AppendFsync::Always => Err(error),
+            Err(error) => {
+                eprintln!("-> {error}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Replication offset reached by the commands fsynced to the AOF, None when it is off
+    pub fn aof_fsynced_offset(&self) -> Option<u64> {
+        let aof = self.aof.as_ref()?;
+        Some(aof.fsynced_offset.load(Ordering::Acquire))
+    }
+
+    /// While loading, keys are not expired: the AOF logs their deletion instead
+    pub fn set_loading(&mut self, is_loading: bool) {
+        for keyspace in self.keyspaces.iter_mut() {
+            keyspace.is_loading = is_loading;
+        }
+    }
+
+    /// Logs the writes from now on to a new incremental file, added to the manifest.
+    /// Returns its sequence number.
+    fn open_new_aof_incr(&mut self, manifest: &mut AofManifest) -> anyhow::Result<u64> {
+        fs::create_dir_all(self.aof_dir())?;
+        let incr = manifest.next_incr(&self.appendfilename());
+        // Left over by a crash before it was added to the manifest otherwise
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(self.aof_file_path(&incr.name))?;
+        let mut aof = Aof::open(file)?;
+        if let Some(mut previous) = self.aof.take() {
+            previous.flush(AppendFsync::Always, self.replication_offset())?;
+            aof.written_offset = previous.written_offset;
+            let fsynced_offset = previous.fsynced_offset.load(Ordering::Acquire);
+            aof.fsynced_offset.store(fsynced_offset, Ordering::Release);
+            aof.current_size = previous.current_size;
+            aof.rewrite_base_size = previous.rewrite_base_size;
+        }
+        self.aof = Some(aof);
+
+        let seq = incr.seq;
+        manifest.incrs.push(incr);
+        Ok(seq)
+    }
+
+    /// Makes the written base file the start of the AOF. The incremental files from
+    /// first_kept_incr_seq on follow it, the files it replaces are deleted.
+    fn install_aof_base(
+        &mut self,
+        manifest: AofManifest,
+        temporary_path: &Path,
+        is_rdb: bool,
+        first_kept_incr_seq: u64,
+    ) -> anyhow::Result<()> {
+        let base = manifest.next_base(&self.appendfilename(), is_rdb);
+        if let Err(error) = fs::rename(temporary_path, self.aof_file_path(&base.name)) {
+            let _ = fs::remove_file(temporary_path);
+            anyhow::bail!("Failed to rename the rewritten append only file: {error}")
+        }
+        let new_manifest = AofManifest {
+            base: Some(base),
+            incrs: manifest
+                .incrs
+                .iter()
+                .filter(|incr| incr.seq >= first_kept_incr_seq)
+                .cloned()
+                .collect(),
+        };
+        self.persist_aof_manifest(&new_manifest)?;
+
+        for file in manifest.files() {
+            if !new_manifest.files().any(|kept| kept.name == file.name) {
+                let _ = fs::remove_file(self.aof_file_path(&file.name));
+            }
+        }
+        self.reset_aof_sizes(&new_manifest);
+        Ok(())
+    }
+
+    /// The AOF grows from its size on disk, rewrites are triggered from there
+    fn reset_aof_sizes(&mut self, manifest: &AofManifest) {
+        let size = manifest
+            .files()
+            .filter_map(|file| fs::metadata(self.aof_file_path(&file.name)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        if let Some(aof) = &mut self.aof {
+            aof.current_size = size;
+            aof.rewrite_base_size = size;
+        }
+    }
+}
+
+impl Snapshot {
+    /// Writes the commands rebuilding the dataset, with absolute expiry times
+    pub fn write_commands(&self, commands: &mut Vec<u8>) {
+        for (db, entries) in self.keyspaces.iter().enumerate() {
+            if entries.is_empty() {
+                continue;
+            }
+            encode_command(commands, &[b"SELECT".to_vec(), db.to_string().into()]);
+            for (key, entry) in entries {
+                let mut arguments = vec![
+                    b"SET".to_vec(),
+                    key.clone().into_bytes(),
+                    entry.value.to_bytes(),
+                ];
+                if let Some(expires_at) = entry.expires_at {
+                    arguments.push(b"PXAT".to_vec());
+                    arguments.push(expires_at.to_string().into_bytes());
+                }
+                encode_command(commands, &arguments);
+            }
+        }
+    }
+}
+
+/// Writes the base file of the AOF from the snapshot, in the RDB format or as commands
+fn write_aof_base(snapshot: &Snapshot, path: &Path, is_rdb: bool) -> anyhow::Result<()> {
+    let mut bytes = Vec::new();
+    if is_rdb {
+        snapshot.write_rdb(&mut bytes, true)?;
+    } else {
+        snapshot.write_commands(&mut bytes);
+    }
+    let result = File::create(path).and_then(|mut file| {
+        file.write_all(&bytes)?;
+        file.sync_all()
+    });
+    if let Err(error) = result {
+        let _ = fs::remove_file(path);
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// Appends the command as a RESP array of bulk strings
+pub fn encode_command(bytes: &mut Vec<u8>, arguments: &[Vec<u8>]) {
+    bytes.extend(format!("*{}\r\n", arguments.len()).into_bytes());
+    for argument in arguments {
+        bytes.extend(format!("${}\r\n", argument.len()).into_bytes());
+        bytes.extend(argument);
+        bytes.extend(b"\r\n");
+    }
+}
@@ -0,0 +1,127 @@
+use crate::config_file::{quote_argument, split_arguments};
+
+const KEY_FILE: &str = "file";
+const KEY_SEQ: &str = "seq";
+const KEY_TYPE: &str = "type";
+
+const TYPE_BASE: &str = "b";
+const TYPE_HISTORY: &str = "h";
+const TYPE_INCR: &str = "i";
+
+const BASE_SUFFIX_RDB: &str = ".base.rdb";
+const BASE_SUFFIX_AOF: &str = ".base.aof";
+const INCR_SUFFIX: &str = ".incr.aof";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AofFileInfo {
+    pub name: String,
+    pub seq: u64,
+}
+
+/// Lists the files of a multi part AOF, loaded in order: the base file, holding the
+/// dataset as of the last rewrite, then the incremental files logging the writes since.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AofManifest {
+    pub base: Option<AofFileInfo>,
+    pub incrs: Vec<AofFileInfo>,
+}
+
+impl AofManifest {
+    /// Parses the manifest, made of lines like `file appendonly.aof.1.base.rdb seq 1 type b`
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut manifest = AofManifest::default();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                anyhow::anyhow!(
+                    "Invalid AOF manifest file format at line {}: {reason}",
+                    index + 1
+                )
+            };
+
+            let arguments = split_arguments(line).map_err(|error| invalid(&error.to_string()))?;
+            if arguments.len() % 2 != 0 {
+                return Err(invalid("missing value"));
+            }
+            let (mut name, mut seq, mut file_type) = (None, None, None);
+            for pair in arguments.chunks(2) {
+                match pair[0].as_str() {
+                    KEY_FILE => name = Some(pair[1].clone()),
+                    KEY_SEQ => seq = pair[1].parse::<u64>().ok(),
+                    KEY_TYPE => file_type = Some(pair[1].clone()),
+                    // Keys added by later versions are ignored
+                    _ => {}
+                }
+            }
+            let (Some(name), Some(seq), Some(file_type)) = (name, seq, file_type) else {
+                return Err(invalid("missing file, seq or type"));
+            };
+            if name.contains('/') {
+                return Err(invalid("file name can't be a path"));
+            }
+
+            let info = AofFileInfo { name, seq };
+            match file_type.as_str() {
+                TYPE_BASE if manifest.base.is_some() => return Err(invalid("duplicate base file")),
+                TYPE_BASE => manifest.base = Some(info),
+                TYPE_INCR => {
+                    if manifest
+                        .incrs
+                        .last()
+                        .is_some_and(|last| last.seq >= info.seq)
+                    {
+                        return Err(invalid("incremental files out of order"));
+                    }
+                    manifest.incrs.push(info);
+                }
+                // Files replaced by a rewrite, deleted already
+                TYPE_HISTORY => {}
+                _ => return Err(invalid("unknown file type")),
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn format(&self) -> String {
+        let base = self.base.iter().map(|info| (info, TYPE_BASE));
+        let incrs = self.incrs.iter().map(|info| (info, TYPE_INCR));
+        base.chain(incrs)
+            .map(|(info, file_type)| {
+                format!(
+                    "{KEY_FILE} {} {KEY_SEQ} {} {KEY_TYPE} {file_type}\n",
+                    quote_argument(&info.name),
+                    info.seq
+                )
+            })
+            .collect()
+    }
+
+    /// The files to load, the base one first
+    pub fn files(&self) -> impl Iterator<Item = &AofFileInfo> {
+        self.base.iter().chain(self.incrs.iter())
+    }
+
+    pub fn next_base(&self, appendfilename: &str, is_rdb: bool) -> AofFileInfo {
+        let seq = self.base.as_ref().map_or(1, |base| base.seq + 1);
+        let suffix = if is_rdb {
+            BASE_SUFFIX_RDB
+        } else {
+            BASE_SUFFIX_AOF
+        };
+        AofFileInfo {
+            name: format!("{appendfilename}.{seq}{suffix}"),
+            seq,
+        }
+    }
+
+    pub fn next_incr(&self, appendfilename: &str) -> AofFileInfo {
+        let seq = self.incrs.last().map_or(1, |incr| incr.seq + 1);
+        AofFileInfo {
+            name: format!("{appendfilename}.{seq}{INCR_SUFFIX}"),
+            seq,
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    fs,
+    path::PathBuf,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use super::write_aof_base;
+use crate::database::Database;
+
+/// Automatic rewrites wait this long after a failed one
+const AUTO_REWRITE_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// A rewrite of the AOF, writing the dataset as of its start on a background thread
+pub struct AofRewrite {
+    thread: JoinHandle<anyhow::Result<()>>,
+    temporary_path: PathBuf,
+    is_rdb: bool,
+    /// The incremental files from this one on log the writes made after the snapshot,
+    /// so they follow the new base file
+    first_kept_incr_seq: u64,
+    started_at: Instant,
+}
+
+impl Database {
+    /// Starts compacting the AOF into a new base file holding the current dataset.
+    /// Writes from now on go to a new incremental file, so the rewrite never waits for them.
+    pub fn start_aof_rewrite(&mut self) -> anyhow::Result<()> {
+        if self.aof_rewrite.is_some() {
+            anyhow::bail!("ERR Background append only file rewriting already in progress")
+        }
+        match self.spawn_aof_rewrite() {
+            Ok(rewrite) => self.aof_rewrite = Some(rewrite),
+            Err(error) => {
+                anyhow::bail!("ERR Can't rewrite append only file in background: {error}")
+            }
+        }
+        println!("-> Background append only file rewriting started");
+        Ok(())
+    }
+
+    /// Installs the rewritten AOF once its thread is done, and starts a rewrite when the
+    /// AOF grew enough since the last one. Runs on every background tick.
+    pub fn aof_cron(&mut self) {
+        let is_rewrite_done = self
+            .aof_rewrite
+            .as_ref()
+            .is_some_and(|rewrite| rewrite.thread.is_finished());
+        if is_rewrite_done {
+            if let Err(error) = self.finish_aof_rewrite() {
+                eprintln!("-> Background AOF rewrite failed. Error: {error}");
+                self.aof_rewrite_failed_at = Some(Instant::now());
+            }
+        }
+
+        if self.should_rewrite_aof() {
+            println!(
+                "-> Starting automatic rewriting of AOF on {}% growth",
+                self.auto_aof_rewrite_percentage()
+            );
+            if let Err(error) = self.start_aof_rewrite() {
+                eprintln!("-> Failed to start the AOF rewrite. Error: {error}");
+                self.aof_rewrite_failed_at = Some(Instant::now());
+            }
+        }
+    }
+
+    fn spawn_aof_rewrite(&mut self) -> anyhow::Result<AofRewrite> {
+        fs::create_dir_all(self.aof_dir())?;
+        let mut manifest = self.load_aof_manifest()?;
+        let first_kept_incr_seq = if self.is_aof_on() {
+            let seq = self.open_new_aof_incr(&mut manifest)?;
+            self.persist_aof_manifest(&manifest)?;
+            seq
+        } else {
+            manifest.next_incr(&self.appendfilename()).seq
+        };
+
+        let snapshot = self.snapshot()?;
+        let is_rdb = self.aof_use_rdb_preamble();
+        let temporary_path =
+            self.aof_file_path(&format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+        let thread_path = temporary_path.clone();
+        let thread = thread::Builder::new()
+            .name("aof-rewrite".into())
+            .spawn(move || write_aof_base(&snapshot, &thread_path, is_rdb))?;
+
+        Ok(AofRewrite {
+            thread,
+            temporary_path,
+            is_rdb,
+            first_kept_incr_seq,
+            started_at: Instant::now(),
+        })
+    }
+
+    fn finish_aof_rewrite(&mut self) -> anyhow::Result<()> {
+        let Some(rewrite) = self.aof_rewrite.take() else {
+            return Ok(());
+        };
+        let Ok(result) = rewrite.thread.join() else {
+            let _ = fs::remove_file(&rewrite.temporary_path);
+            anyhow::bail!("The rewrite thread panicked")
+        };
+        result?;
+
+        let manifest = self.load_aof_manifest()?;
+        self.install_aof_base(
+            manifest,
+            &rewrite.temporary_path,
+            rewrite.is_rdb,
+            rewrite.first_kept_incr_seq,
+        )?;
+        println!(
+            "-> Background AOF rewrite finished successfully in {} ms",
+            rewrite.started_at.elapsed().as_millis()
+        );
+        Ok(())
+    }
+
+    /// Waits for the rewrite in progress and drops what it wrote
+    pub(in crate::database) fn discard_aof_rewrite(&mut self) {
+        if let Some(rewrite) = self.aof_rewrite.take() {
+            let _ = rewrite.thread.join();
+            let _ = fs::remove_file(&rewrite.temporary_path);
+        }
+    }
+
+    /// Whether the AOF is over auto-aof-rewrite-min-size, and grew by
+    /// auto-aof-rewrite-percentage since the last rewrite
+    fn should_rewrite_aof(&self) -> bool {
+        let percentage = self.auto_aof_rewrite_percentage();
+        let Some(aof) = &self.aof else {
+            return false;
+        };
+        let is_retry_delayed = self
+            .aof_rewrite_failed_at
+            .is_some_and(|failed_at| failed_at.elapsed() < AUTO_REWRITE_RETRY_DELAY);
+        if self.aof_rewrite.is_some()
+            || is_retry_delayed
+            || percentage == 0
+            || aof.current_size <= self.auto_aof_rewrite_min_size()
+        {
+            return false;
+        }
+        let growth = aof.current_size.saturating_sub(aof.rewrite_base_size) * 100
+            / aof.rewrite_base_size.max(1);
+        growth >= percentage
+    }
+}
@@ -0,0 +1,304 @@
+#[cfg(test)]
+mod test {
+    use crate::database::{
+        aof::{
+            encode_command,
+            manifest::{AofFileInfo, AofManifest},
+        },
+        get_current_time_ms,
+        test_helpers::database_in_temp_dir,
+        Database,
+    };
+    use std::{fs, thread, time::Duration};
+
+    fn read_incr(database: &Database) -> Vec<u8> {
+        let manifest = database.load_aof_manifest().unwrap();
+        let incr = manifest.incrs.last().unwrap();
+        fs::read(database.aof_file_path(&incr.name)).unwrap()
+    }
+
+    fn wait_for_rewrite(database: &mut Database) {
+        while database.aof_rewrite.is_some() {
+            database.aof_cron();
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn commands(commands: &[&[&str]]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for command in commands {
+            let arguments: Vec<Vec<u8>> =
+                command.iter().map(|argument| (*argument).into()).collect();
+            encode_command(&mut bytes, &arguments);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_encode_command() {
+        // Given
+        let mut bytes = Vec::new();
+        // When
+        encode_command(
+            &mut bytes,
+            &[b"SET".to_vec(), b"key".to_vec(), b"".to_vec()],
+        );
+        // Then
+        assert_eq!(bytes, b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_feed_logs_select_when_the_db_changes() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("feed", &[("appendfsync", "always")]);
+        database.create_aof().unwrap();
+        // When
+        database.feed_aof(0, &[b"DEL".to_vec(), b"a".to_vec()]);
+        database.feed_aof(0, &[b"DEL".to_vec(), b"b".to_vec()]);
+        database.feed_aof(2, &[b"DEL".to_vec(), b"c".to_vec()]);
+        database.flush_aof().unwrap();
+        // Then
+        assert_eq!(
+            read_incr(&database),
+            commands(&[
+                &["SELECT", "0"],
+                &["DEL", "a"],
+                &["DEL", "b"],
+                &["SELECT", "2"],
+                &["DEL", "c"],
+            ])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_create_aof_starts_from_the_dataset() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("create", &[("aof-use-rdb-preamble", "no")]);
+        let expires_at = get_current_time_ms().unwrap() + 60_000;
+        database
+            .keyspace(1)
+            .set("key".into(), "value".into(), Some(expires_at))
+            .unwrap();
+        // When
+        database.create_aof().unwrap();
+        database.stop_aof().unwrap();
+        // Then
+        let manifest = fs::read_to_string(dir.join("appendonlydir/appendonly.aof.manifest"));
+        assert_eq!(
+            manifest.unwrap(),
+            "file appendonly.aof.1.base.aof seq 1 type b\n\
+             file appendonly.aof.1.incr.aof seq 1 type i\n"
+        );
+        assert_eq!(
+            fs::read(database.aof_file_path("appendonly.aof.1.base.aof")).unwrap(),
+            commands(&[
+                &["SELECT", "1"],
+                &["SET", "key", "value", "PXAT", &expires_at.to_string()],
+            ])
+        );
+        assert_eq!(read_incr(&database), b"");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_expired_keys_are_logged_as_deletions() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("expired", &[]);
+        let expired_at = get_current_time_ms().unwrap() - 1;
+        database
+            .keyspace(0)
+            .set("key".into(), "value".into(), Some(expired_at))
+            .unwrap();
+        database.open_aof().unwrap();
+        // When
+        database.keyspace(0).get("key".into()).unwrap();
+        database.propagate_expired_keys();
+        database.stop_aof().unwrap();
+        // Then
+        assert_eq!(
+            read_incr(&database),
+            commands(&[&["SELECT", "0"], &["DEL", "key"]])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_keys_do_not_expire_while_loading() {
+        // Given
+        let mut database = Database::new();
+        let expired_at = get_current_time_ms().unwrap() - 1;
+        database
+            .keyspace(0)
+            .set("key".into(), "value".into(), Some(expired_at))
+            .unwrap();
+        // When
+        database.set_loading(true);
+        let loading_value = database.keyspace(0).get("key".into()).unwrap();
+        database.keyspace(0).expire("key".into(), 0, &[]).unwrap();
+        database.set_loading(false);
+        // Then
+        assert_eq!(loading_value, Some("value".into()));
+        assert_eq!(database.keyspace(0).get("key".into()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_keys_do_not_expire_while_loading_after_a_flush() {
+        // Given
+        let mut database = Database::new();
+        let expired_at = get_current_time_ms().unwrap() - 1;
+        // When
+        database.set_loading(true);
+        database.flush_all();
+        database
+            .keyspace(0)
+            .set("key".into(), "5".into(), Some(expired_at))
+            .unwrap();
+        let loading_value = database.keyspace(0).increment_by("key".into(), 1).unwrap();
+        database.set_loading(false);
+        // Then
+        assert_eq!(loading_value, 6);
+        assert_eq!(database.keyspace(0).get("key".into()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_manifest_parse_and_format() {
+        // Given
+        let contents = "# written by an older version\n\
+                        file appendonly.aof.1.base.rdb seq 1 type b\n\
+                        file appendonly.aof.1.incr.aof seq 1 type h\n\
+                        file \"append only.aof.2.incr.aof\" seq 2 type i\n";
+        // When
+        let manifest = AofManifest::parse(contents).unwrap();
+        let duplicate_base =
+            AofManifest::parse("file a.1.base.rdb seq 1 type b\nfile a.2.base.rdb seq 2 type b\n");
+        let unordered_incrs =
+            AofManifest::parse("file a.2.incr.aof seq 2 type i\nfile a.1.incr.aof seq 1 type i\n");
+        let missing_type = AofManifest::parse("file a.1.incr.aof seq 1\n");
+        // Then
+        assert_eq!(
+            manifest,
+            AofManifest {
+                base: Some(AofFileInfo {
+                    name: "appendonly.aof.1.base.rdb".into(),
+                    seq: 1
+                }),
+                incrs: vec![AofFileInfo {
+                    name: "append only.aof.2.incr.aof".into(),
+                    seq: 2
+                }],
+            }
+        );
+        assert_eq!(
+            manifest.format(),
+            "file appendonly.aof.1.base.rdb seq 1 type b\n\
+             file \"append only.aof.2.incr.aof\" seq 2 type i\n"
+        );
+        assert_eq!(
+            manifest.next_base("appendonly.aof", false).name,
+            "appendonly.aof.2.base.aof"
+        );
+        assert_eq!(
+            manifest.next_incr("appendonly.aof").name,
+            "appendonly.aof.3.incr.aof"
+        );
+        assert!(duplicate_base.is_err());
+        assert!(unordered_incrs.is_err());
+        assert!(missing_type.is_err());
+    }
+
+    #[test]
+    fn test_rewrite_keeps_the_writes_made_meanwhile() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("rewrite", &[]);
+        database
+            .keyspace(0)
+            .set("before".into(), "1".into(), None)
+            .unwrap();
+        database.create_aof().unwrap();
+        // When
+        database.start_aof_rewrite().unwrap();
+        let second_rewrite = database.start_aof_rewrite();
+        database.feed_aof(0, &[b"SET".to_vec(), b"after".to_vec(), b"2".to_vec()]);
+        database.flush_aof().unwrap();
+        wait_for_rewrite(&mut database);
+        // Then
+        assert!(second_rewrite
+            .unwrap_err()
+            .to_string()
+            .contains("already in progress"));
+        let manifest = database.load_aof_manifest().unwrap();
+        let files: Vec<&str> = manifest.files().map(|file| file.name.as_str()).collect();
+        assert_eq!(
+            files,
+            ["appendonly.aof.2.base.rdb", "appendonly.aof.2.incr.aof"]
+        );
+        assert!(!database.aof_file_path("appendonly.aof.1.base.rdb").exists());
+        assert!(!database.aof_file_path("appendonly.aof.1.incr.aof").exists());
+
+        let base = fs::read(database.aof_file_path("appendonly.aof.2.base.rdb")).unwrap();
+        let mut loaded = Database::new();
+        let rdb_length = loaded.load_rdb_preamble(&base).unwrap();
+        assert_eq!(rdb_length, base.len());
+        assert_eq!(
+            loaded.keyspace(0).get("before".into()).unwrap(),
+            Some("1".into())
+        );
+        assert_eq!(
+            read_incr(&database),
+            commands(&[&["SELECT", "0"], &["SET", "after", "2"]])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_starts_when_the_aof_grows() {
+        // Given
+        let settings = [
+            ("auto-aof-rewrite-percentage", "100"),
+            ("auto-aof-rewrite-min-size", "0"),
+        ];
+        let (mut database, dir) = database_in_temp_dir("auto", &settings);
+        database
+            .keyspace(0)
+            .set("key".into(), "value".into(), None)
+            .unwrap();
+        database.create_aof().unwrap();
+        // When
+        database.aof_cron();
+        let is_started_before_growing = database.aof_rewrite.is_some();
+        database.feed_aof(0, &[b"SET".to_vec(), b"key".to_vec(), vec![b'x'; 1000]]);
+        database.flush_aof().unwrap();
+        database.aof_cron();
+        let is_started_after_growing = database.aof_rewrite.is_some();
+        wait_for_rewrite(&mut database);
+        // Then
+        assert!(!is_started_before_growing);
+        assert!(is_started_after_growing);
+        let manifest = database.load_aof_manifest().unwrap();
+        assert_eq!(manifest.base.unwrap().seq, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_moves_a_single_file_aof_into_the_aof_dir() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("upgrade", &[]);
+        let legacy_commands = commands(&[&["SELECT", "0"], &["SET", "key", "value"]]);
+        fs::write(dir.join("appendonly.aof"), &legacy_commands).unwrap();
+        // When
+        database.upgrade_aof().unwrap();
+        database.open_aof().unwrap();
+        database.stop_aof().unwrap();
+        // Then
+        assert!(!dir.join("appendonly.aof").exists());
+        assert_eq!(
+            fs::read(database.aof_file_path("appendonly.aof")).unwrap(),
+            legacy_commands
+        );
+        let manifest = database.load_aof_manifest().unwrap();
+        let files: Vec<&str> = manifest.files().map(|file| file.name.as_str()).collect();
+        assert_eq!(files, ["appendonly.aof", "appendonly.aof.1.incr.aof"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
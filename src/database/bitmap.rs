@@ -0,0 +1,404 @@
+use super::{
+    keyspace::Keyspace,
+    string::{validate_length, Value, MAX_STRING_LENGTH},
+    Entry,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitIndexUnit {
+    Byte,
+    Bit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+    /// Bits set in the first key but in none of the others
+    Diff,
+    /// Bits set in one of the other keys but not in the first one
+    Diff1,
+    /// Bits set in the first key and in at least one of the others
+    AndOr,
+    /// Bits set in exactly one of the keys
+    One,
+}
+
+/// A signed or unsigned integer of 1 to 64 bits (unsigned ones up to 63 bits)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitfieldType {
+    pub is_signed: bool,
+    pub bits: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitfieldOverflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BitfieldOperation {
+    Get {
+        field_type: BitfieldType,
+        offset: u64,
+    },
+    Set {
+        field_type: BitfieldType,
+        offset: u64,
+        value: i64,
+        overflow: BitfieldOverflow,
+    },
+    IncrBy {
+        field_type: BitfieldType,
+        offset: u64,
+        increment: i64,
+        overflow: BitfieldOverflow,
+    },
+}
+
+/// Checks that a bit offset addresses a string within the max string size
+pub fn validate_bit_offset(offset: i64) -> anyhow::Result<u64> {
+    if offset < 0 || (offset as u64 >> 3) >= MAX_STRING_LENGTH as u64 {
+        anyhow::bail!("ERR bit offset is not an integer or out of range")
+    }
+    Ok(offset as u64)
+}
+
+impl Keyspace {
+    /// Sets the bit at offset, growing the string with zeros if needed.
+    /// Returns the previous bit.
+    pub fn set_bit(&mut self, key: String, offset: u64, bit: bool) -> anyhow::Result<bool> {
+        let bytes = self.bytes_for_write(key, offset as usize / 8 + 1)?;
+        let previous_bit = read_bit(bytes, offset);
+        write_bit(bytes, offset, bit);
+        Ok(previous_bit)
+    }
+
+    pub fn get_bit(&mut self, key: String, offset: u64) -> anyhow::Result<bool> {
+        let bytes = self.get(key)?.unwrap_or_default();
+        Ok(read_bit(&bytes, offset))
+    }
+
+    /// Counts the set bits, optionally between start and end (both inclusive)
+    pub fn bit_count(
+        &mut self,
+        key: String,
+        range: Option<(i64, i64, BitIndexUnit)>,
+    ) -> anyhow::Result<u64> {
+        let bytes = self.get(key)?.unwrap_or_default();
+        let (start, end) = match range {
+            Some((start, end, unit)) => match bit_range(bytes.len(), start, end, unit) {
+                Some(range) => range,
+                None => return Ok(0),
+            },
+            None if bytes.is_empty() => return Ok(0),
+            None => (0, bytes.len() as u64 * 8 - 1),
+        };
+
+        let (first_byte, last_byte) = ((start / 8) as usize, (end / 8) as usize);
+        // Masks out the bits of the first and last bytes that are out of the range
+        let first_mask = 0xffu8 >> (start % 8);
+        let last_mask = 0xffu8 << (7 - end % 8);
+        let count = bytes[first_byte..=last_byte]
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| {
+                let mut byte = *byte;
+                if index == 0 {
+                    byte &= first_mask;
+                }
+                if index == last_byte - first_byte {
+                    byte &= last_mask;
+                }
+                byte.count_ones() as u64
+            })
+            .sum();
+        Ok(count)
+    }
+
+    /// Finds the position of the first bit with the given value, optionally between start
+    /// and end. When searching a clear bit without an end, the string is considered padded
+    /// with zeros on the right.
+    pub fn bit_pos(
+        &mut self,
+        key: String,
+        bit: bool,
+        start: Option<i64>,
+        end: Option<i64>,
+        unit: BitIndexUnit,
+    ) -> anyhow::Result<i64> {
+        let Some(bytes) = self.get(key)? else {
+            return Ok(if bit { -1 } else { 0 });
+        };
+        let start = start.unwrap_or(0);
+        let end_given = end.is_some();
+        let end = end.unwrap_or(-1);
+        let Some((start, end)) = bit_range(bytes.len(), start, end, unit) else {
+            return Ok(-1);
+        };
+
+        let skipped_byte = if bit { 0x00 } else { 0xff };
+        let mut position = start;
+        while position <= end {
+            let byte = bytes[(position / 8) as usize];
+            if position % 8 == 0 && position + 7 <= end && byte == skipped_byte {
+                position += 8;
+                continue;
+            }
+            if read_bit(&bytes, position) == bit {
+                return Ok(position as i64);
+            }
+            position += 1;
+        }
+
+        if !bit && !end_given {
+            // The bit following the last byte of the range
+            return Ok(((end / 8 + 1) * 8) as i64);
+        }
+        Ok(-1)
+    }
+
+    /// Stores the result of the operation between the keys in the destination, deleting it
+    /// if the result is empty. Missing keys are treated as strings of zeros.
+    /// Returns the length of the result.
+    pub fn bit_op(
+        &mut self,
+        operation: BitOperation,
+        destination: String,
+        keys: &[String],
+    ) -> anyhow::Result<usize> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key.clone())?.unwrap_or_default());
+        }
+        let length = values.iter().map(|value| value.len()).max().unwrap_or(0);
+        if length == 0 {
+            self.delete(destination)?;
+            return Ok(0);
+        }
+
+        let byte_at = |value: &Vec<u8>, index: usize| value.get(index).copied().unwrap_or(0);
+        let mut result = Vec::with_capacity(length);
+        for index in 0..length {
+            let mut bytes = values.iter().map(|value| byte_at(value, index));
+            let first = bytes.next().unwrap_or(0);
+            let byte = match operation {
+                BitOperation::And => bytes.fold(first, |result, byte| result & byte),
+                BitOperation::Or => bytes.fold(first, |result, byte| result | byte),
+                BitOperation::Xor => bytes.fold(first, |result, byte| result ^ byte),
+                BitOperation::Not => !first,
+                BitOperation::Diff => first & !bytes.fold(0, |result, byte| result | byte),
+                BitOperation::Diff1 => !first & bytes.fold(0, |result, byte| result | byte),
+                BitOperation::AndOr => first & bytes.fold(0, |result, byte| result | byte),
+                BitOperation::One => {
+                    let (once, more) = bytes.fold((first, 0), |(once, more), byte| {
+                        (once | byte, more | (once & byte))
+                    });
+                    once & !more
+                }
+            };
+            result.push(byte);
+        }
+
+        self.set(destination, result, None)?;
+        Ok(length)
+    }
+
+    /// Runs the operations on the integers stored in the bits of the string. Returns the
+    /// result of each operation, or None when an operation fails on overflow.
+    pub fn bitfield(
+        &mut self,
+        key: String,
+        operations: &[BitfieldOperation],
+    ) -> anyhow::Result<Vec<Option<i64>>> {
+        // The string only grows when there are writes, to fit the furthest of them
+        let write_end = operations
+            .iter()
+            .filter_map(|operation| match operation {
+                BitfieldOperation::Get { .. } => None,
+                BitfieldOperation::Set {
+                    field_type, offset, ..
+                }
+                | BitfieldOperation::IncrBy {
+                    field_type, offset, ..
+                } => Some(offset + field_type.bits as u64),
+            })
+            .max();
+        let mut read_only_bytes: Vec<u8>;
+        let bytes = match write_end {
+            Some(write_end) => self.bytes_for_write(key, ((write_end + 7) / 8) as usize)?,
+            None => {
+                read_only_bytes = self.get(key)?.unwrap_or_default();
+                &mut read_only_bytes
+            }
+        };
+
+        let mut results = Vec::with_capacity(operations.len());
+        for operation in operations {
+            let result = match *operation {
+                BitfieldOperation::Get { field_type, offset } => {
+                    Some(read_field(bytes, offset, field_type))
+                }
+                BitfieldOperation::Set {
+                    field_type,
+                    offset,
+                    value,
+                    overflow,
+                } => {
+                    let previous_value = read_field(bytes, offset, field_type);
+                    // Unsigned values are handled as their two's complement, as Redis does
+                    let value = match field_type.is_signed {
+                        true => value as i128,
+                        false => value as u64 as i128,
+                    };
+                    handle_overflow(value, field_type, overflow).map(|value| {
+                        write_field(bytes, offset, field_type, value);
+                        previous_value
+                    })
+                }
+                BitfieldOperation::IncrBy {
+                    field_type,
+                    offset,
+                    increment,
+                    overflow,
+                } => {
+                    let value = read_field(bytes, offset, field_type) as i128 + increment as i128;
+                    handle_overflow(value, field_type, overflow).map(|value| {
+                        write_field(bytes, offset, field_type, value);
+                        value
+                    })
+                }
+            };
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    /// Returns the bytes of the string at key to modify them in place, creating the key
+    /// if needed and growing the string with zeros to the length
+    fn bytes_for_write(&mut self, key: String, length: usize) -> anyhow::Result<&mut Vec<u8>> {
+        validate_length(length)?;
+        self.delete_if_expired(&key)?;
+        if !self.data.contains_key(&key) {
+            let entry = Entry {
+                value: Value::Raw(Vec::new()),
+                expires_at: None,
+            };
+            self.insert_entry(key.clone(), entry);
+        }
+
+        let Some(entry) = self.data.get_mut(&key) else {
+            anyhow::bail!("Failed to get key {key}")
+        };
+        let bytes = entry.value.raw_mut();
+        if bytes.len() < length {
+            bytes.resize(length, 0);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Converts start and end to an inclusive range of bits inside a string of the length.
+/// Negative positions are offsets from the end. Returns None if the range is empty.
+fn bit_range(length: usize, start: i64, end: i64, unit: BitIndexUnit) -> Option<(u64, u64)> {
+    let total_length = match unit {
+        BitIndexUnit::Byte => length as i64,
+        BitIndexUnit::Bit => length as i64 * 8,
+    };
+    if start < 0 && end < 0 && start > end {
+        return None;
+    }
+
+    let start = if start < 0 {
+        total_length + start
+    } else {
+        start
+    }
+    .max(0);
+    let end = if end < 0 { total_length + end } else { end }
+        .max(0)
+        .min(total_length - 1);
+    if start > end {
+        return None;
+    }
+
+    match unit {
+        BitIndexUnit::Byte => Some((start as u64 * 8, end as u64 * 8 + 7)),
+        BitIndexUnit::Bit => Some((start as u64, end as u64)),
+    }
+}
+
+/// Bits are numbered from the most significant bit of the first byte
+fn read_bit(bytes: &[u8], offset: u64) -> bool {
+    let Some(byte) = bytes.get((offset / 8) as usize) else {
+        return false;
+    };
+    byte & (0x80 >> (offset % 8)) != 0
+}
+
+fn write_bit(bytes: &mut [u8], offset: u64, bit: bool) {
+    let byte = &mut bytes[(offset / 8) as usize];
+    let mask = 0x80 >> (offset % 8);
+    match bit {
+        true => *byte |= mask,
+        false => *byte &= !mask,
+    }
+}
+
+fn read_field(bytes: &[u8], offset: u64, field_type: BitfieldType) -> i64 {
+    let mut value: u64 = 0;
+    for index in 0..field_type.bits as u64 {
+        value = (value << 1) | read_bit(bytes, offset + index) as u64;
+    }
+
+    let bits = field_type.bits;
+    if field_type.is_signed && bits < 64 && value & (1 << (bits - 1)) != 0 {
+        // Extends the sign
+        value |= u64::MAX << bits;
+    }
+    value as i64
+}
+
+fn write_field(bytes: &mut [u8], offset: u64, field_type: BitfieldType, value: i64) {
+    let bits = field_type.bits as u64;
+    for index in 0..bits {
+        let bit = (value as u64 >> (bits - 1 - index)) & 1 != 0;
+        write_bit(bytes, offset + index, bit);
+    }
+}
+
+/// Fits the value in the type according to the overflow mode.
+/// Returns None if it does not fit and the mode is FAIL.
+fn handle_overflow(
+    value: i128,
+    field_type: BitfieldType,
+    overflow: BitfieldOverflow,
+) -> Option<i64> {
+    let bits = field_type.bits;
+    let (min, max) = match field_type.is_signed {
+        true => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+        false => (0, (1i128 << bits) - 1),
+    };
+    if (min..=max).contains(&value) {
+        return Some(value as i64);
+    }
+
+    match overflow {
+        BitfieldOverflow::Fail => None,
+        BitfieldOverflow::Sat => Some(value.clamp(min, max) as i64),
+        BitfieldOverflow::Wrap => {
+            let wrapped = value.rem_euclid(1i128 << bits);
+            match field_type.is_signed && wrapped > max {
+                true => Some((wrapped - (1i128 << bits)) as i64),
+                false => Some(wrapped as i64),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,232 @@
+#[cfg(test)]
+mod test {
+    use crate::database::{
+        bitmap::{BitIndexUnit, BitOperation, BitfieldOperation, BitfieldOverflow, BitfieldType},
+        keyspace::Keyspace,
+    };
+
+    const U8: BitfieldType = BitfieldType {
+        is_signed: false,
+        bits: 8,
+    };
+    const I5: BitfieldType = BitfieldType {
+        is_signed: true,
+        bits: 5,
+    };
+
+    #[test]
+    fn test_set_bit_grows_string_and_returns_previous_bit() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        // When
+        let previous_bit = keyspace.set_bit("key".into(), 7, true).unwrap();
+        let other_previous_bit = keyspace.set_bit("key".into(), 7, false).unwrap();
+        keyspace.set_bit("key".into(), 17, true).unwrap();
+        // Then
+        assert!(!previous_bit);
+        assert!(other_previous_bit);
+        assert_eq!(keyspace.get("key".into()).unwrap().unwrap(), [0, 0, 0x40]);
+        assert!(keyspace.get_bit("key".into(), 17).unwrap());
+        assert!(!keyspace.get_bit("key".into(), 100).unwrap());
+    }
+
+    #[test]
+    fn test_bit_count_with_byte_and_bit_ranges() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("key".into(), "foobar".into(), None).unwrap();
+        // Then
+        assert_eq!(keyspace.bit_count("key".into(), None).unwrap(), 26);
+        let byte_range = Some((1, 1, BitIndexUnit::Byte));
+        assert_eq!(keyspace.bit_count("key".into(), byte_range).unwrap(), 6);
+        let bit_range = Some((5, 30, BitIndexUnit::Bit));
+        assert_eq!(keyspace.bit_count("key".into(), bit_range).unwrap(), 17);
+        let negative_range = Some((-2, -1, BitIndexUnit::Byte));
+        assert_eq!(keyspace.bit_count("key".into(), negative_range).unwrap(), 7);
+        let empty_range = Some((-1, -2, BitIndexUnit::Byte));
+        assert_eq!(keyspace.bit_count("key".into(), empty_range).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_bit_pos() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .set("key".into(), vec![0xff, 0xf0, 0x00], None)
+            .unwrap();
+        keyspace.set("ones".into(), vec![0xff, 0xff], None).unwrap();
+        let bit_pos = |keyspace: &mut Keyspace, key: &str, bit, start, end, unit| {
+            keyspace.bit_pos(key.into(), bit, start, end, unit).unwrap()
+        };
+        // Then
+        assert_eq!(
+            bit_pos(&mut keyspace, "key", false, None, None, BitIndexUnit::Byte),
+            12
+        );
+        assert_eq!(
+            bit_pos(
+                &mut keyspace,
+                "key",
+                true,
+                Some(2),
+                None,
+                BitIndexUnit::Byte
+            ),
+            -1
+        );
+        assert_eq!(
+            bit_pos(
+                &mut keyspace,
+                "key",
+                true,
+                Some(7),
+                Some(15),
+                BitIndexUnit::Bit
+            ),
+            7
+        );
+        assert_eq!(
+            bit_pos(&mut keyspace, "ones", false, None, None, BitIndexUnit::Byte),
+            16
+        );
+        assert_eq!(
+            bit_pos(
+                &mut keyspace,
+                "ones",
+                false,
+                Some(0),
+                Some(-1),
+                BitIndexUnit::Byte
+            ),
+            -1
+        );
+        assert_eq!(
+            bit_pos(
+                &mut keyspace,
+                "missing",
+                false,
+                None,
+                None,
+                BitIndexUnit::Byte
+            ),
+            0
+        );
+        assert_eq!(
+            bit_pos(
+                &mut keyspace,
+                "missing",
+                true,
+                None,
+                None,
+                BitIndexUnit::Byte
+            ),
+            -1
+        );
+    }
+
+    #[test]
+    fn test_bit_op() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("a".into(), vec![0b1100, 0xff], None).unwrap();
+        keyspace.set("b".into(), vec![0b1010], None).unwrap();
+        keyspace.set("c".into(), vec![0b0110], None).unwrap();
+        let keys = ["a".into(), "b".into(), "c".into()];
+        let bit_op = |keyspace: &mut Keyspace, operation, keys: &[String]| {
+            keyspace.bit_op(operation, "result".into(), keys).unwrap();
+            keyspace.get("result".into()).unwrap().unwrap()
+        };
+        // Then
+        assert_eq!(bit_op(&mut keyspace, BitOperation::And, &keys), [0b0000, 0]);
+        assert_eq!(
+            bit_op(&mut keyspace, BitOperation::Or, &keys),
+            [0b1110, 0xff]
+        );
+        assert_eq!(
+            bit_op(&mut keyspace, BitOperation::Xor, &keys),
+            [0b0000, 0xff]
+        );
+        assert_eq!(
+            bit_op(&mut keyspace, BitOperation::Not, &keys[..1]),
+            [0xf3, 0]
+        );
+        assert_eq!(
+            bit_op(&mut keyspace, BitOperation::Diff, &keys),
+            [0b0000, 0xff]
+        );
+        assert_eq!(
+            bit_op(&mut keyspace, BitOperation::Diff1, &keys),
+            [0b0010, 0]
+        );
+        assert_eq!(
+            bit_op(&mut keyspace, BitOperation::AndOr, &keys),
+            [0b1100, 0]
+        );
+        assert_eq!(
+            bit_op(&mut keyspace, BitOperation::One, &keys),
+            [0b0000, 0xff]
+        );
+        assert_eq!(
+            keyspace
+                .bit_op(BitOperation::Or, "result".into(), &["missing".into()])
+                .unwrap(),
+            0
+        );
+        assert_eq!(keyspace.get("result".into()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_bitfield_overflow_modes() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        let increment = |increment, overflow| BitfieldOperation::IncrBy {
+            field_type: I5,
+            offset: 0,
+            increment,
+            overflow,
+        };
+        // When
+        let results = keyspace
+            .bitfield(
+                "key".into(),
+                &[
+                    BitfieldOperation::Set {
+                        field_type: U8,
+                        offset: 8,
+                        value: 255,
+                        overflow: BitfieldOverflow::Wrap,
+                    },
+                    increment(20, BitfieldOverflow::Wrap),
+                    increment(20, BitfieldOverflow::Sat),
+                    increment(1, BitfieldOverflow::Fail),
+                    increment(-40, BitfieldOverflow::Sat),
+                    BitfieldOperation::Get {
+                        field_type: U8,
+                        offset: 8,
+                    },
+                ],
+            )
+            .unwrap();
+        // Then
+        assert_eq!(
+            results,
+            vec![Some(0), Some(-12), Some(8), Some(9), Some(-16), Some(255)]
+        );
+        assert_eq!(keyspace.get("key".into()).unwrap().unwrap(), [0x80, 0xff]);
+    }
+
+    #[test]
+    fn test_bitfield_get_does_not_create_key() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        let get = BitfieldOperation::Get {
+            field_type: I5,
+            offset: 100,
+        };
+        // When
+        let results = keyspace.bitfield("key".into(), &[get]).unwrap();
+        // Then
+        assert_eq!(results, vec![Some(0)]);
+        assert_eq!(keyspace.get("key".into()).unwrap(), None);
+    }
+}
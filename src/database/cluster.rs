@@ -0,0 +1,614 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use self::{crc16::crc16, node::ClusterNode};
+use super::{get_current_time_ms, random::Random, Database};
+
+pub use self::bus::{BusOutput, NodeAddress};
+
+mod bus;
+mod crc16;
+mod node;
+mod nodes_file;
+
+#[cfg(test)]
+mod tests;
+
+/// Hash slots the keys are spread over, each one served by a single node
+pub const CLUSTER_SLOTS: usize = 16384;
+
+const NODE_ID_LENGTH: usize = 40;
+
+/// Offset of the cluster bus port from the port of clients, unless cluster-port is set
+const BUS_PORT_OFFSET: u16 = 10000;
+
+/// Hash slot of a key. Only the {hashtag} of a key is hashed when it has a non-empty
+/// one, so that keys used together can be kept in the same slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashtag = key.iter().position(|byte| *byte == b'{').and_then(|start| {
+        let rest = &key[start + 1..];
+        let end = rest.iter().position(|byte| *byte == b'}')?;
+        Some(&rest[..end]).filter(|hashtag| !hashtag.is_empty())
+    });
+    crc16(hashtag.unwrap_or(key)) % CLUSTER_SLOTS as u16
+}
+
+/// Where clients reach a node
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeEndpoint {
+    pub id: String,
+    pub ip: String,
+    pub port: u16,
+}
+
+/// Consecutive hash slots served by the same node, as CLUSTER SLOTS lists them
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotRange {
+    pub start: u16,
+    pub end: u16,
+    pub node: NodeEndpoint,
+}
+
+/// A master with the hash slots it serves, as CLUSTER SHARDS lists them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    pub ranges: Vec<(u16, u16)>,
+    pub nodes: Vec<ShardNode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardNode {
+    pub endpoint: NodeEndpoint,
+    pub replication_offset: u64,
+    /// online, or failed once the cluster agreed it is failing
+    pub health: &'static str,
+}
+
+/// What CLUSTER SETSLOT does with a slot, given the id of a node
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetSlotAction {
+    /// The slot of this node moves to the node
+    Migrating(String),
+    /// The slot of the node moves to this node
+    Importing(String),
+    /// Ends the move of the slot, which stays where it is
+    Stable,
+    /// The slot is served by the node, ending its move
+    Node(String),
+}
+
+/// The cluster as this node sees it: the nodes, the one serving each hash slot, and
+/// the epochs ordering their claims on the slots
+pub struct Cluster {
+    /// Id of this node, which is one of the nodes
+    myself: String,
+    nodes: Vec<ClusterNode>,
+    /// Id of the node serving each hash slot
+    slots: Vec<Option<String>>,
+    /// Slots of this node moving to another node, with the id of that node
+    migrating: BTreeMap<u16, String>,
+    /// Slots moving to this node, with the id of the node they come from
+    importing: BTreeMap<u16, String>,
+    /// Highest epoch seen in the cluster
+    current_epoch: u64,
+    /// Whether the keys are served, which requires all the slots to be served unless
+    /// cluster-require-full-coverage is off
+    is_ok: bool,
+    /// Set until the nodes file is saved with the changes
+    is_config_changed: bool,
+    /// Nodes found failing, for the other nodes to learn about
+    failed_nodes: Vec<String>,
+    /// Messages to the other nodes, sent by the cluster bus
+    output: UnboundedSender<BusOutput>,
+    messages_sent: u64,
+    messages_received: u64,
+}
+
+impl Cluster {
+    fn new(myself: ClusterNode, output: UnboundedSender<BusOutput>) -> Self {
+        Cluster {
+            myself: myself.id.clone(),
+            nodes: vec![myself],
+            slots: vec![None; CLUSTER_SLOTS],
+            migrating: BTreeMap::new(),
+            importing: BTreeMap::new(),
+            current_epoch: 0,
+            is_ok: false,
+            is_config_changed: true,
+            failed_nodes: Vec::new(),
+            output,
+            messages_sent: 0,
+            messages_received: 0,
+        }
+    }
+
+    fn node(&self, id: &str) -> Option<&ClusterNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    fn node_mut(&mut self, id: &str) -> Option<&mut ClusterNode> {
+        self.nodes.iter_mut().find(|node| node.id == id)
+    }
+
+    fn myself(&self) -> &ClusterNode {
+        self.node(&self.myself)
+            .expect("this node is one of the nodes of the cluster")
+    }
+
+    fn myself_mut(&mut self) -> &mut ClusterNode {
+        let myself = self.myself.clone();
+        self.node_mut(&myself)
+            .expect("this node is one of the nodes of the cluster")
+    }
+
+    /// The slots served by the node, as ranges of consecutive slots
+    fn slot_ranges(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for (slot, owner) in self.slots.iter().enumerate() {
+            if owner.as_deref() != Some(id) {
+                continue;
+            }
+            let slot = slot as u16;
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+        ranges
+    }
+
+    fn owns_slots(&self, id: &str) -> bool {
+        self.slots.iter().any(|owner| owner.as_deref() == Some(id))
+    }
+
+    /// Masters serving at least one slot, which decide together that a node is failing
+    fn size(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| self.owns_slots(&node.id))
+            .count()
+    }
+
+    fn endpoint(&self, id: &str) -> Option<NodeEndpoint> {
+        let node = self.node(id)?;
+        Some(NodeEndpoint {
+            id: node.id.clone(),
+            ip: node.ip.clone(),
+            port: node.port,
+        })
+    }
+
+    /// Serves the keys only when all the slots are served by nodes that are not failing,
+    /// or always when cluster-require-full-coverage is off
+    fn update_state(&mut self, require_full_coverage: bool) {
+        let is_ok = !require_full_coverage
+            || self.slots.iter().all(|owner| {
+                owner
+                    .as_deref()
+                    .and_then(|owner| self.node(owner))
+                    .is_some_and(|node| node.failed_at.is_none())
+            });
+        if is_ok != self.is_ok {
+            println!(
+                "-> Cluster state changed: {}",
+                if is_ok { "ok" } else { "fail" }
+            );
+            self.is_ok = is_ok;
+        }
+    }
+}
+
+impl Database {
+    pub fn is_cluster_enabled(&self) -> bool {
+        self.cluster.is_some()
+    }
+
+    /// Joins the cluster saved to the nodes file, or starts a new one with this node
+    /// alone, which listens to clients on the port. Returns the port of the cluster bus
+    /// and the messages to send on it.
+    pub fn start_cluster(
+        &mut self,
+        port: u16,
+    ) -> anyhow::Result<(u16, UnboundedReceiver<BusOutput>)> {
+        let bus_port = match self.cluster_port() {
+            0 => match port.checked_add(BUS_PORT_OFFSET) {
+                Some(bus_port) => bus_port,
+                None => anyhow::bail!(
+                    "Port {port} is too high for the cluster bus port, set cluster-port"
+                ),
+            },
+            bus_port => bus_port,
+        };
+        let (output, receiver) = mpsc::unbounded_channel();
+        let mut cluster = match self.load_cluster_config(output.clone())? {
+            Some(cluster) => cluster,
+            None => {
+                let id = Random::new().hex_string(NODE_ID_LENGTH);
+                println!("-> No cluster configuration found, I'm {id}");
+                Cluster::new(ClusterNode::new(id, self.announced_ip(), 0, 0), output)
+            }
+        };
+        let myself = cluster.myself_mut();
+        myself.port = port;
+        myself.bus_port = bus_port;
+        self.cluster = Some(cluster);
+        self.update_cluster_state();
+        self.save_cluster_config()?;
+        Ok((bus_port, receiver))
+    }
+
+    /// The ip clients reach this node at, until the first node it meets tells it. The
+    /// first bind address, unless it listens on all the addresses.
+    fn announced_ip(&self) -> String {
+        self.bind_addresses()
+            .iter()
+            .map(|address| address.trim_start_matches('-'))
+            .find(|address| {
+                address
+                    .parse::<IpAddr>()
+                    .is_ok_and(|ip| !ip.is_unspecified())
+            })
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn cluster(&self) -> anyhow::Result<&Cluster> {
+        match &self.cluster {
+            Some(cluster) => Ok(cluster),
+            None => anyhow::bail!("ERR This instance has cluster support disabled"),
+        }
+    }
+
+    fn cluster_mut(&mut self) -> anyhow::Result<&mut Cluster> {
+        match &mut self.cluster {
+            Some(cluster) => Ok(cluster),
+            None => anyhow::bail!("ERR This instance has cluster support disabled"),
+        }
+    }
+
+    fn update_cluster_state(&mut self) {
+        let require_full_coverage = self.cluster_require_full_coverage();
+        if let Some(cluster) = &mut self.cluster {
+            cluster.update_state(require_full_coverage);
+        }
+    }
+
+    /// ASKING is only valid in cluster mode, which the connection keeps track of
+    pub fn check_asking(&self) -> anyhow::Result<()> {
+        self.cluster()?;
+        Ok(())
+    }
+
+    pub fn cluster_my_id(&self) -> anyhow::Result<String> {
+        Ok(self.cluster()?.myself.clone())
+    }
+
+    pub fn cluster_key_slot(&self, key: &str) -> anyhow::Result<u16> {
+        self.cluster()?;
+        Ok(key_hash_slot(key.as_bytes()))
+    }
+
+    pub fn cluster_count_keys_in_slot(&self, slot: u16) -> anyhow::Result<usize> {
+        self.cluster()?;
+        Ok(self.keys_in_slot(slot).count())
+    }
+
+    pub fn cluster_get_keys_in_slot(&self, slot: u16, count: usize) -> anyhow::Result<Vec<String>> {
+        self.cluster()?;
+        Ok(self.keys_in_slot(slot).take(count).cloned().collect())
+    }
+
+    /// The keys of the slot, which are all in the first db since SELECT is not allowed
+    fn keys_in_slot(&self, slot: u16) -> impl Iterator<Item = &String> {
+        let now = get_current_time_ms().unwrap_or_default();
+        // redis-server keeps the keys of each slot apart, while they are searched for
+        // here, which makes these commands as slow as KEYS
+        self.keyspaces[0]
+            .data
+            .iter()
+            .filter(move |(key, entry)| {
+                !entry.is_expired(now) && key_hash_slot(key.as_bytes()) == slot
+            })
+            .map(|(key, _)| key)
+    }
+
+    /// Starts a handshake with the node listening at the address, which joins the
+    /// cluster once it answers
+    pub fn cluster_meet(&mut self, ip: String, port: u16, bus_port: u16) -> anyhow::Result<()> {
+        let cluster = self.cluster_mut()?;
+        // A handshake with the node may already be in progress
+        if cluster
+            .nodes
+            .iter()
+            .any(|node| node.ip == ip && node.port == port)
+        {
+            return Ok(());
+        }
+        let id = Random::new().hex_string(NODE_ID_LENGTH);
+        let mut node = ClusterNode::new(id, ip, port, bus_port);
+        node.is_handshake = true;
+        cluster.nodes.push(node);
+        Ok(())
+    }
+
+    /// Makes this node serve the slots, which no node serves yet as far as it knows.
+    /// The other nodes learn about it from the next messages on the cluster bus.
+    pub fn cluster_add_slots(&mut self, ranges: &[(u16, u16)]) -> anyhow::Result<()> {
+        let cluster = self.cluster_mut()?;
+        let slots = distinct_slots(ranges)?;
+        if let Some(slot) = slots.iter().find(|slot| cluster.slots[**slot].is_some()) {
+            anyhow::bail!("ERR Slot {slot} is already busy")
+        }
+        for slot in slots {
+            cluster.slots[slot] = Some(cluster.myself.clone());
+            // It is served here from now on, whether it was moving here or not
+            cluster.importing.remove(&(slot as u16));
+        }
+        cluster.is_config_changed = true;
+        self.update_cluster_state();
+        self.save_cluster_config()
+    }
+
+    /// Makes the slots unassigned as far as this node knows
+    pub fn cluster_delete_slots(&mut self, ranges: &[(u16, u16)]) -> anyhow::Result<()> {
+        let cluster = self.cluster_mut()?;
+        let slots = distinct_slots(ranges)?;
+        if let Some(slot) = slots.iter().find(|slot| cluster.slots[**slot].is_none()) {
+            anyhow::bail!("ERR Slot {slot} is already unassigned")
+        }
+        for slot in slots {
+            cluster.slots[slot] = None;
+            cluster.migrating.remove(&(slot as u16));
+            cluster.importing.remove(&(slot as u16));
+        }
+        cluster.is_config_changed = true;
+        self.update_cluster_state();
+        self.save_cluster_config()
+    }
+
+    /// The report of CLUSTER INFO
+    pub fn cluster_info(&self) -> anyhow::Result<String> {
+        let cluster = self.cluster()?;
+        let owners = cluster
+            .slots
+            .iter()
+            .filter_map(|owner| cluster.node(owner.as_deref()?));
+        let (mut assigned, mut pfail, mut fail) = (0, 0, 0);
+        for owner in owners {
+            assigned += 1;
+            if owner.failed_at.is_some() {
+                fail += 1;
+            } else if owner.is_pfail {
+                pfail += 1;
+            }
+        }
+        Ok(format!(
+            "cluster_state:{}\r\n\
+             cluster_slots_assigned:{assigned}\r\n\
+             cluster_slots_ok:{}\r\n\
+             cluster_slots_pfail:{pfail}\r\n\
+             cluster_slots_fail:{fail}\r\n\
+             cluster_known_nodes:{}\r\n\
+             cluster_size:{}\r\n\
+             cluster_current_epoch:{}\r\n\
+             cluster_my_epoch:{}\r\n\
+             cluster_stats_messages_sent:{}\r\n\
+             cluster_stats_messages_received:{}\r\n",
+            if cluster.is_ok { "ok" } else { "fail" },
+            assigned - pfail - fail,
+            cluster.nodes.len(),
+            cluster.size(),
+            cluster.current_epoch,
+            cluster.myself().config_epoch,
+            cluster.messages_sent,
+            cluster.messages_received,
+        ))
+    }
+
+    /// The report of CLUSTER NODES, a line per node
+    pub fn cluster_nodes(&self) -> anyhow::Result<String> {
+        let cluster = self.cluster()?;
+        Ok(cluster
+            .nodes
+            .iter()
+            .map(|node| cluster.describe_node(node) + "\n")
+            .collect())
+    }
+
+    /// The ranges of slots served, with the node serving them
+    pub fn cluster_slots(&self) -> anyhow::Result<Vec<SlotRange>> {
+        let cluster = self.cluster()?;
+        let mut ranges: Vec<SlotRange> = Vec::new();
+        for (slot, owner) in cluster.slots.iter().enumerate() {
+            let slot = slot as u16;
+            let Some(node) = owner.as_deref().and_then(|owner| cluster.endpoint(owner)) else {
+                continue;
+            };
+            match ranges.last_mut() {
+                Some(range) if range.end + 1 == slot && range.node.id == node.id => {
+                    range.end = slot
+                }
+                _ => ranges.push(SlotRange {
+                    start: slot,
+                    end: slot,
+                    node,
+                }),
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// A shard per master, with the slots it serves
+    pub fn cluster_shards(&self) -> anyhow::Result<Vec<Shard>> {
+        let cluster = self.cluster()?;
+        let replication_offset = self.replication_offset();
+        let shards = cluster
+            .nodes
+            .iter()
+            .filter(|node| !node.is_handshake)
+            .map(|node| Shard {
+                ranges: cluster.slot_ranges(&node.id),
+                nodes: vec![ShardNode {
+                    endpoint: NodeEndpoint {
+                        id: node.id.clone(),
+                        ip: node.ip.clone(),
+                        port: node.port,
+                    },
+                    replication_offset: match node.id == cluster.myself {
+                        true => replication_offset,
+                        false => node.replication_offset,
+                    },
+                    health: match node.failed_at {
+                        Some(_) => "failed",
+                        None => "online",
+                    },
+                }],
+            })
+            .collect();
+        Ok(shards)
+    }
+
+    /// Moves a slot between this node and another one. Keys are moved in between with
+    /// MIGRATE, and the slot is given to its new node once they all moved.
+    pub fn cluster_set_slot(&mut self, slot: u16, action: SetSlotAction) -> anyhow::Result<()> {
+        let has_keys = self.keys_in_slot(slot).next().is_some();
+        let cluster = self.cluster_mut()?;
+        let owner = cluster.slots[slot as usize].clone();
+        let is_mine = owner.as_deref() == Some(cluster.myself.as_str());
+        if let SetSlotAction::Migrating(id)
+        | SetSlotAction::Importing(id)
+        | SetSlotAction::Node(id) = &action
+        {
+            if cluster.node(id).is_none() {
+                anyhow::bail!("ERR I don't know about node {id}")
+            }
+        }
+        match action {
+            SetSlotAction::Migrating(id) => {
+                if !is_mine {
+                    anyhow::bail!("ERR I'm not the owner of hash slot {slot}")
+                }
+                cluster.migrating.insert(slot, id);
+            }
+            SetSlotAction::Importing(id) => {
+                if is_mine {
+                    anyhow::bail!("ERR I'm already the owner of hash slot {slot}")
+                }
+                cluster.importing.insert(slot, id);
+            }
+            SetSlotAction::Stable => {
+                cluster.migrating.remove(&slot);
+                cluster.importing.remove(&slot);
+            }
+            SetSlotAction::Node(id) => {
+                let is_to_me = id == cluster.myself;
+                if is_mine && !is_to_me && has_keys {
+                    anyhow::bail!(
+                        "ERR Can't assign hashslot {slot} to a different node while I still \
+                         hold keys for this hash slot."
+                    )
+                }
+                cluster.migrating.remove(&slot);
+                // The slot imported is claimed with a new epoch, for the other nodes to
+                // prefer this claim to the one of the node it comes from
+                if cluster.importing.remove(&slot).is_some() && is_to_me {
+                    cluster.current_epoch += 1;
+                    let current_epoch = cluster.current_epoch;
+                    cluster.myself_mut().config_epoch = current_epoch;
+                    println!("-> configEpoch updated after importing slot {slot}");
+                }
+                cluster.slots[slot as usize] = Some(id);
+            }
+        }
+        cluster.is_config_changed = true;
+        self.update_cluster_state();
+        self.save_cluster_config()
+    }
+
+    /// Checks that the keys of a command are all in the same slot, which this node
+    /// serves. Clients are redirected to the node serving it with MOVED otherwise, or
+    /// with ASK while the slot moves to another node and the keys are not here anymore.
+    /// Asking tells the client was redirected with ASK, to the node importing the slot.
+    pub fn check_cluster_keys(&self, keys: &[&str], is_asking: bool) -> anyhow::Result<()> {
+        let (Some(cluster), Some((first_key, other_keys))) = (&self.cluster, keys.split_first())
+        else {
+            return Ok(());
+        };
+        let slot = key_hash_slot(first_key.as_bytes());
+        if other_keys
+            .iter()
+            .any(|key| key_hash_slot(key.as_bytes()) != slot)
+        {
+            anyhow::bail!("CROSSSLOT Keys in request don't hash to the same slot")
+        }
+        let Some(owner) = &cluster.slots[slot as usize] else {
+            anyhow::bail!("CLUSTERDOWN Hash slot not served")
+        };
+        if !cluster.is_ok {
+            anyhow::bail!("CLUSTERDOWN The cluster is down")
+        }
+        if *owner != cluster.myself {
+            // Serves the keys moved here so far, while the slot is still served by the node
+            // it comes from for the others
+            if is_asking && cluster.importing.contains_key(&slot) {
+                let existing_count = self.count_existing_keys(keys)?;
+                if keys.len() > 1 && existing_count < keys.len() {
+                    anyhow::bail!("TRYAGAIN Multiple keys request during rehashing of slot")
+                }
+                return Ok(());
+            }
+            let node = cluster.node(owner).map(ClusterNode::endpoint);
+            anyhow::bail!("MOVED {slot} {}", node.unwrap_or_default())
+        }
+
+        let Some(target) = cluster.migrating.get(&slot) else {
+            return Ok(());
+        };
+        let existing_count = self.count_existing_keys(keys)?;
+        if existing_count == keys.len() {
+            return Ok(());
+        }
+        // The keys that are not here anymore moved to the target already
+        if existing_count > 0 {
+            anyhow::bail!("TRYAGAIN Multiple keys request during rehashing of slot")
+        }
+        let node = cluster.node(target).map(ClusterNode::endpoint);
+        anyhow::bail!("ASK {slot} {}", node.unwrap_or_default())
+    }
+
+    /// How many of the keys exist, in the first db since SELECT is not allowed
+    fn count_existing_keys(&self, keys: &[&str]) -> anyhow::Result<usize> {
+        let now = get_current_time_ms()?;
+        let count = keys
+            .iter()
+            .filter(|key| {
+                self.keyspaces[0]
+                    .data
+                    .get(key)
+                    .is_some_and(|entry| !entry.is_expired(now))
+            })
+            .count();
+        Ok(count)
+    }
+}
+
+/// The slots of the ranges, each one given once
+fn distinct_slots(ranges: &[(u16, u16)]) -> anyhow::Result<Vec<usize>> {
+    let mut slots = BTreeSet::new();
+    for (start, end) in ranges {
+        for slot in *start as usize..=*end as usize {
+            if !slots.insert(slot) {
+                anyhow::bail!("ERR Slot {slot} specified multiple times")
+            }
+        }
+    }
+    Ok(slots.into_iter().collect())
+}
+
+fn now_ms() -> u128 {
+    get_current_time_ms().unwrap_or_default()
+}
@@ -0,0 +1,583 @@
+use std::fmt;
+
+use super::{node::ClusterNode, now_ms, Cluster, CLUSTER_SLOTS};
+use crate::database::{aof::encode_command, Database};
+
+const ID_MEET: &str = "MEET";
+const ID_PING: &str = "PING";
+const ID_PONG: &str = "PONG";
+const ID_FAIL: &str = "FAIL";
+
+/// How often each node is pinged, in ms
+const PING_PERIOD: u128 = 1000;
+
+/// Time a node met with CLUSTER MEET has to answer at least, in ms
+const MIN_HANDSHAKE_TIMEOUT: u128 = 1000;
+
+/// Failure reports older than this many node timeouts are not counted anymore
+const FAIL_REPORT_VALIDITY_MULT: u128 = 2;
+
+/// A node that failed with slots is trusted again once it answers after this many
+/// node timeouts
+const FAIL_UNDO_TIME_MULT: u128 = 2;
+
+/// Where the cluster bus of a node listens
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NodeAddress {
+    pub ip: String,
+    pub port: u16,
+}
+
+impl fmt::Display for NodeAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.ip, self.port)
+    }
+}
+
+/// A message to another node, sent over the link to its cluster bus
+#[derive(Debug)]
+pub struct BusOutput {
+    pub address: NodeAddress,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HeartbeatKind {
+    /// Asks the node to join the cluster
+    Meet,
+    Ping,
+    /// The answer to MEET and PING
+    Pong,
+}
+
+/// What a node tells about itself in each message, and about some of the nodes it knows
+#[derive(Debug)]
+struct Heartbeat {
+    kind: HeartbeatKind,
+    id: String,
+    /// Empty when the node doesn't know it, the ip it is reached at is used then
+    ip: String,
+    port: u16,
+    bus_port: u16,
+    config_epoch: u64,
+    current_epoch: u64,
+    replication_offset: u64,
+    slots: Vec<(u16, u16)>,
+    gossip: Vec<Gossip>,
+}
+
+/// Another node as the sender sees it
+#[derive(Debug)]
+struct Gossip {
+    id: String,
+    ip: String,
+    port: u16,
+    bus_port: u16,
+    is_failing: bool,
+}
+
+/// A message of the cluster bus, sent like a command: an array of bulk strings starting
+/// with the type of the message
+#[derive(Debug)]
+enum BusMessage {
+    Heartbeat(Heartbeat),
+    /// Tells that the majority of the masters agreed the node is failing
+    Fail {
+        sender: String,
+        node_id: String,
+    },
+}
+
+impl TryFrom<&[Vec<u8>]> for BusMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(arguments: &[Vec<u8>]) -> Result<Self, Self::Error> {
+        let strings: Vec<String> = arguments
+            .iter()
+            .map(|argument| String::from_utf8_lossy(argument).to_string())
+            .collect();
+        let (kind, fields) = match strings.split_first() {
+            Some((id, fields)) => match id.as_str() {
+                ID_MEET => (HeartbeatKind::Meet, fields),
+                ID_PING => (HeartbeatKind::Ping, fields),
+                ID_PONG => (HeartbeatKind::Pong, fields),
+                ID_FAIL => match fields {
+                    [sender, node_id] => {
+                        return Ok(BusMessage::Fail {
+                            sender: sender.clone(),
+                            node_id: node_id.clone(),
+                        })
+                    }
+                    _ => anyhow::bail!("Invalid FAIL message"),
+                },
+                _ => anyhow::bail!("Unknown message type {id}"),
+            },
+            None => anyhow::bail!("Empty message"),
+        };
+        let [id, ip, port, bus_port, config_epoch, current_epoch, replication_offset, slots, gossip @ ..] =
+            fields
+        else {
+            anyhow::bail!("Invalid heartbeat")
+        };
+        if gossip.len() % 5 != 0 {
+            anyhow::bail!("Invalid gossip section")
+        }
+        let gossip = gossip
+            .chunks(5)
+            .map(|fields| {
+                Ok(Gossip {
+                    id: fields[0].clone(),
+                    ip: fields[1].clone(),
+                    port: fields[2].parse()?,
+                    bus_port: fields[3].parse()?,
+                    is_failing: fields[4] != "-",
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(BusMessage::Heartbeat(Heartbeat {
+            kind,
+            id: id.clone(),
+            ip: ip.clone(),
+            port: port.parse()?,
+            bus_port: bus_port.parse()?,
+            config_epoch: config_epoch.parse()?,
+            current_epoch: current_epoch.parse()?,
+            replication_offset: replication_offset.parse()?,
+            slots: parse_slot_ranges(slots)?,
+            gossip,
+        }))
+    }
+}
+
+impl Heartbeat {
+    fn encode(&self) -> Vec<u8> {
+        let id = match self.kind {
+            HeartbeatKind::Meet => ID_MEET,
+            HeartbeatKind::Ping => ID_PING,
+            HeartbeatKind::Pong => ID_PONG,
+        };
+        let slots: Vec<String> = self
+            .slots
+            .iter()
+            .map(|(start, end)| format!("{start}-{end}"))
+            .collect();
+        let mut arguments = vec![
+            id.to_string(),
+            self.id.clone(),
+            self.ip.clone(),
+            self.port.to_string(),
+            self.bus_port.to_string(),
+            self.config_epoch.to_string(),
+            self.current_epoch.to_string(),
+            self.replication_offset.to_string(),
+            slots.join(","),
+        ];
+        for gossip in &self.gossip {
+            arguments.extend([
+                gossip.id.clone(),
+                gossip.ip.clone(),
+                gossip.port.to_string(),
+                gossip.bus_port.to_string(),
+                if gossip.is_failing { "fail?" } else { "-" }.to_string(),
+            ]);
+        }
+        encode(&arguments)
+    }
+}
+
+fn encode(arguments: &[String]) -> Vec<u8> {
+    let arguments: Vec<Vec<u8>> = arguments
+        .iter()
+        .map(|argument| argument.as_bytes().to_vec())
+        .collect();
+    let mut bytes = Vec::new();
+    encode_command(&mut bytes, &arguments);
+    bytes
+}
+
+/// Parses ranges of slots like 0-5460,10923-16383
+fn parse_slot_ranges(string: &str) -> anyhow::Result<Vec<(u16, u16)>> {
+    let mut ranges = Vec::new();
+    for range in string.split(',').filter(|range| !range.is_empty()) {
+        let parsed = range.split_once('-').and_then(|(start, end)| {
+            let start = start.parse::<u16>().ok()?;
+            let end = end.parse::<u16>().ok()?;
+            Some((start, end)).filter(|_| start <= end && (end as usize) < CLUSTER_SLOTS)
+        });
+        match parsed {
+            Some(range) => ranges.push(range),
+            None => anyhow::bail!("Invalid slot range {range}"),
+        }
+    }
+    Ok(ranges)
+}
+
+impl Database {
+    /// Handles a message of another node, received from the ip on a connection to the
+    /// local ip. Returns the bytes of the reply, if any.
+    pub fn cluster_receive(
+        &mut self,
+        arguments: &[Vec<u8>],
+        peer_ip: &str,
+        local_ip: &str,
+    ) -> Option<Vec<u8>> {
+        let node_timeout = self.cluster_node_timeout() as u128;
+        let replication_offset = self.replication_offset();
+        let cluster = self.cluster.as_mut()?;
+        cluster.messages_received += 1;
+        let message = match BusMessage::try_from(arguments) {
+            Ok(message) => message,
+            Err(error) => {
+                eprintln!("-> Bad message on the cluster bus from {peer_ip}: {error}");
+                return None;
+            }
+        };
+
+        let now = now_ms();
+        match message {
+            BusMessage::Fail { sender, node_id } => {
+                cluster.mark_failed(&node_id, &sender, now);
+                None
+            }
+            BusMessage::Heartbeat(mut heartbeat) => {
+                if heartbeat.ip.is_empty() {
+                    heartbeat.ip = peer_ip.to_string();
+                }
+                cluster.receive_heartbeat(&heartbeat, local_ip, now, node_timeout);
+                match heartbeat.kind {
+                    HeartbeatKind::Meet | HeartbeatKind::Ping => {
+                        let pong = cluster.heartbeat(
+                            HeartbeatKind::Pong,
+                            &heartbeat.id,
+                            replication_offset,
+                        );
+                        cluster.messages_sent += 1;
+                        Some(pong.encode())
+                    }
+                    HeartbeatKind::Pong => None,
+                }
+            }
+        }
+    }
+
+    /// Pings the other nodes, finds the ones failing, and saves the nodes file when the
+    /// cluster changed
+    pub fn cluster_cron(&mut self) {
+        let node_timeout = self.cluster_node_timeout() as u128;
+        let replication_offset = self.replication_offset();
+        let Some(cluster) = &mut self.cluster else {
+            return;
+        };
+        let now = now_ms();
+        cluster.ping_nodes(now, node_timeout, replication_offset);
+        cluster.detect_failures(now, node_timeout);
+        cluster.broadcast_failures();
+        let is_config_changed = cluster.is_config_changed;
+        self.update_cluster_state();
+        if is_config_changed {
+            if let Err(error) = self.save_cluster_config() {
+                eprintln!("-> Can't save the cluster config file: {error}");
+            }
+        }
+    }
+}
+
+impl Cluster {
+    fn send(&mut self, address: NodeAddress, bytes: Vec<u8>) {
+        self.messages_sent += 1;
+        let _ = self.output.send(BusOutput { address, bytes });
+    }
+
+    /// The heartbeat of this node for the receiver, telling about the other nodes
+    fn heartbeat(&self, kind: HeartbeatKind, receiver: &str, replication_offset: u64) -> Heartbeat {
+        let myself = self.myself();
+        let gossip = self
+            .nodes
+            .iter()
+            .filter(|node| node.id != self.myself && node.id != receiver && !node.is_handshake)
+            .map(|node| Gossip {
+                id: node.id.clone(),
+                ip: node.ip.clone(),
+                port: node.port,
+                bus_port: node.bus_port,
+                is_failing: node.is_failing(),
+            })
+            .collect();
+        Heartbeat {
+            kind,
+            id: myself.id.clone(),
+            ip: myself.ip.clone(),
+            port: myself.port,
+            bus_port: myself.bus_port,
+            config_epoch: myself.config_epoch,
+            current_epoch: self.current_epoch,
+            replication_offset,
+            slots: self.slot_ranges(&self.myself),
+            gossip,
+        }
+    }
+
+    fn receive_heartbeat(
+        &mut self,
+        heartbeat: &Heartbeat,
+        local_ip: &str,
+        now: u128,
+        node_timeout: u128,
+    ) {
+        if heartbeat.id == self.myself {
+            return;
+        }
+        // The node reached this one at the ip it should announce
+        if heartbeat.kind == HeartbeatKind::Meet && !local_ip.is_empty() {
+            let myself = self.myself_mut();
+            if myself.ip != local_ip {
+                myself.ip = local_ip.to_string();
+                self.is_config_changed = true;
+            }
+        }
+        if self.node(&heartbeat.id).is_none() {
+            match heartbeat.kind {
+                HeartbeatKind::Meet => {
+                    println!("-> Node {} met this node", heartbeat.id);
+                    self.nodes.push(ClusterNode::new(
+                        heartbeat.id.clone(),
+                        heartbeat.ip.clone(),
+                        heartbeat.port,
+                        heartbeat.bus_port,
+                    ));
+                    self.is_config_changed = true;
+                }
+                // The node met with CLUSTER MEET answers with its own id
+                HeartbeatKind::Pong => {
+                    let Some(node) = self.nodes.iter_mut().find(|node| {
+                        node.is_handshake && node.ip == heartbeat.ip && node.port == heartbeat.port
+                    }) else {
+                        return;
+                    };
+                    println!("-> Handshake with node {} completed", heartbeat.id);
+                    node.id = heartbeat.id.clone();
+                    node.is_handshake = false;
+                    self.is_config_changed = true;
+                }
+                // Nodes only join with MEET, so that the forgotten ones stay away
+                HeartbeatKind::Ping => return,
+            }
+        }
+
+        let has_slots = self.owns_slots(&heartbeat.id);
+        let Some(node) = self.node_mut(&heartbeat.id) else {
+            return;
+        };
+        let mut is_changed = node.ip != heartbeat.ip
+            || node.port != heartbeat.port
+            || node.bus_port != heartbeat.bus_port
+            || node.config_epoch != heartbeat.config_epoch;
+        node.ip = heartbeat.ip.clone();
+        node.port = heartbeat.port;
+        node.bus_port = heartbeat.bus_port;
+        node.config_epoch = heartbeat.config_epoch;
+        node.replication_offset = heartbeat.replication_offset;
+        if heartbeat.kind == HeartbeatKind::Pong {
+            node.ping_sent = None;
+            node.pong_received = now;
+            node.is_pfail = false;
+            // Its slots may be served by another node by now, unless it failed recently
+            let is_undone = node.failed_at.is_some_and(|failed_at| {
+                !has_slots || now - failed_at > node_timeout * FAIL_UNDO_TIME_MULT
+            });
+            if is_undone {
+                println!("-> Clear FAIL state for node {}", node.id);
+                node.failed_at = None;
+                is_changed = true;
+            }
+        }
+        if heartbeat.current_epoch > self.current_epoch {
+            self.current_epoch = heartbeat.current_epoch;
+            is_changed = true;
+        }
+        self.is_config_changed |= is_changed;
+
+        self.claim_slots(&heartbeat.id, heartbeat.config_epoch, &heartbeat.slots);
+        self.handle_config_epoch_collision(&heartbeat.id, heartbeat.config_epoch);
+        self.receive_gossip(&heartbeat.id, &heartbeat.gossip, now);
+    }
+
+    /// Gives the slots to the node claiming them, unless their node claimed them with a
+    /// higher config epoch
+    fn claim_slots(&mut self, sender: &str, config_epoch: u64, ranges: &[(u16, u16)]) {
+        for (start, end) in ranges {
+            for slot in *start..=*end {
+                let owner = self.slots[slot as usize].as_deref();
+                if owner == Some(sender) || self.importing.contains_key(&slot) {
+                    continue;
+                }
+                let owner_epoch = owner
+                    .and_then(|owner| self.node(owner))
+                    .map(|owner| owner.config_epoch);
+                if owner_epoch.is_some_and(|owner_epoch| owner_epoch >= config_epoch) {
+                    continue;
+                }
+                if owner == Some(self.myself.as_str()) {
+                    println!("-> Slot {slot} is now served by node {sender}");
+                    self.migrating.remove(&slot);
+                }
+                self.slots[slot as usize] = Some(sender.to_string());
+                self.is_config_changed = true;
+            }
+        }
+    }
+
+    /// Gives this node a new config epoch when another master has the same one and a
+    /// greater id, so that all the masters end up with different epochs
+    fn handle_config_epoch_collision(&mut self, sender: &str, config_epoch: u64) {
+        if config_epoch != self.myself().config_epoch || sender <= self.myself.as_str() {
+            return;
+        }
+        self.current_epoch += 1;
+        let current_epoch = self.current_epoch;
+        self.myself_mut().config_epoch = current_epoch;
+        self.is_config_changed = true;
+        println!(
+            "-> WARNING: configEpoch collision with node {sender}. configEpoch set to \
+             {current_epoch}"
+        );
+    }
+
+    /// Records which nodes the sender sees failing, and adds the nodes it knows that
+    /// this node doesn't
+    fn receive_gossip(&mut self, sender: &str, gossip: &[Gossip], now: u128) {
+        for gossip in gossip {
+            if gossip.id == self.myself {
+                continue;
+            }
+            match self.node_mut(&gossip.id) {
+                Some(node) if gossip.is_failing => {
+                    node.fail_reports.insert(sender.to_string(), now);
+                    self.mark_failed_if_agreed(&gossip.id, now);
+                }
+                Some(node) => {
+                    node.fail_reports.remove(sender);
+                }
+                None if !gossip.is_failing && !gossip.ip.is_empty() => {
+                    println!("-> Node {} joined the cluster", gossip.id);
+                    self.nodes.push(ClusterNode::new(
+                        gossip.id.clone(),
+                        gossip.ip.clone(),
+                        gossip.port,
+                        gossip.bus_port,
+                    ));
+                    self.is_config_changed = true;
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Pings the nodes every period, and the ones met with CLUSTER MEET until they
+    /// answer or the handshake times out
+    fn ping_nodes(&mut self, now: u128, node_timeout: u128, replication_offset: u64) {
+        let handshake_timeout = node_timeout.max(MIN_HANDSHAKE_TIMEOUT);
+        self.nodes.retain(|node| {
+            let is_timed_out = node.is_handshake && now - node.created_at > handshake_timeout;
+            if is_timed_out {
+                println!("-> Handshake with node {} timed out", node.endpoint());
+            }
+            !is_timed_out
+        });
+
+        let due: Vec<String> = self
+            .nodes
+            .iter()
+            .filter(|node| node.id != self.myself && now >= node.last_ping + PING_PERIOD)
+            .map(|node| node.id.clone())
+            .collect();
+        for id in due {
+            let Some(node) = self.node_mut(&id) else {
+                continue;
+            };
+            node.last_ping = now;
+            node.ping_sent.get_or_insert(now);
+            let kind = match node.is_handshake {
+                true => HeartbeatKind::Meet,
+                false => HeartbeatKind::Ping,
+            };
+            let address = node.bus_address();
+            let bytes = self.heartbeat(kind, &id, replication_offset).encode();
+            self.send(address, bytes);
+        }
+    }
+
+    /// Suspects the nodes not answering for the node timeout, and forgets the failure
+    /// reports too old to count
+    fn detect_failures(&mut self, now: u128, node_timeout: u128) {
+        let validity = node_timeout * FAIL_REPORT_VALIDITY_MULT;
+        let mut suspected = Vec::new();
+        for node in &mut self.nodes {
+            node.fail_reports
+                .retain(|_, reported_at| now - *reported_at <= validity);
+            let is_timed_out = node
+                .ping_sent
+                .is_some_and(|ping_sent| now - ping_sent > node_timeout);
+            if node.id == self.myself || node.is_handshake || node.is_failing() || !is_timed_out {
+                continue;
+            }
+            println!("-> *** NODE {} possibly failing", node.id);
+            node.is_pfail = true;
+            suspected.push(node.id.clone());
+        }
+        for id in suspected {
+            self.mark_failed_if_agreed(&id, now);
+        }
+    }
+
+    /// Marks a node suspected by this node as failing once the majority of the masters
+    /// serving slots suspect it too
+    fn mark_failed_if_agreed(&mut self, id: &str, now: u128) {
+        let quorum = self.size() / 2 + 1;
+        let Some(node) = self.node_mut(id) else {
+            return;
+        };
+        // This node counts as one of the masters suspecting it
+        if !node.is_pfail || node.fail_reports.len() + 1 < quorum {
+            return;
+        }
+        println!("-> Marking node {id} as failing (quorum reached).");
+        node.is_pfail = false;
+        node.failed_at = Some(now);
+        self.failed_nodes.push(id.to_string());
+        self.is_config_changed = true;
+    }
+
+    /// Marks a node as failing, as another node found out
+    fn mark_failed(&mut self, id: &str, sender: &str, now: u128) {
+        if id == self.myself {
+            return;
+        }
+        let Some(node) = self.node_mut(id) else {
+            return;
+        };
+        if node.failed_at.is_some() {
+            return;
+        }
+        println!("-> FAIL message received from {sender} about {id}");
+        node.is_pfail = false;
+        node.failed_at = Some(now);
+        self.is_config_changed = true;
+    }
+
+    /// Tells the other nodes about the nodes found failing
+    fn broadcast_failures(&mut self) {
+        for failed in std::mem::take(&mut self.failed_nodes) {
+            let bytes = encode(&[ID_FAIL.to_string(), self.myself.clone(), failed.clone()]);
+            let addresses: Vec<NodeAddress> = self
+                .nodes
+                .iter()
+                .filter(|node| node.id != self.myself && node.id != failed && !node.is_handshake)
+                .map(ClusterNode::bus_address)
+                .collect();
+            for address in addresses {
+                self.send(address, bytes.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+/// CRC-16/XMODEM, as used by Redis Cluster to map keys to hash slots
+const POLYNOMIAL: u16 = 0x1021;
+const TABLE: [u16; 256] = build_table();
+
+const fn build_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = (index as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ POLYNOMIAL
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, byte| {
+        TABLE[((crc >> 8) as u8 ^ *byte) as usize] ^ (crc << 8)
+    })
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use super::{now_ms, NodeAddress};
+
+/// A node of the cluster, this one included, as this node sees it
+#[derive(Debug)]
+pub struct ClusterNode {
+    pub id: String,
+    pub ip: String,
+    /// Port of the clients
+    pub port: u16,
+    pub bus_port: u16,
+    /// Orders the claims of nodes on the same slots, the highest one winning
+    pub config_epoch: u64,
+    /// Replication offset the node last told about
+    pub replication_offset: u64,
+    /// Met with CLUSTER MEET under a temporary id, until it answers with its own
+    pub is_handshake: bool,
+    pub created_at: u128,
+    /// When the oldest ping the node didn't answer yet was sent, in ms
+    pub ping_sent: Option<u128>,
+    pub last_ping: u128,
+    pub pong_received: u128,
+    /// Didn't answer for the node timeout, as this node sees it
+    pub is_pfail: bool,
+    /// When the cluster agreed the node is failing, in ms
+    pub failed_at: Option<u128>,
+    /// The masters that last saw the node not answering, with when they told about it
+    pub fail_reports: HashMap<String, u128>,
+}
+
+impl ClusterNode {
+    pub fn new(id: String, ip: String, port: u16, bus_port: u16) -> Self {
+        ClusterNode {
+            id,
+            ip,
+            port,
+            bus_port,
+            config_epoch: 0,
+            replication_offset: 0,
+            is_handshake: false,
+            created_at: now_ms(),
+            ping_sent: None,
+            last_ping: 0,
+            pong_received: 0,
+            is_pfail: false,
+            failed_at: None,
+            fail_reports: HashMap::new(),
+        }
+    }
+
+    pub fn bus_address(&self) -> NodeAddress {
+        NodeAddress {
+            ip: self.ip.clone(),
+            port: self.bus_port,
+        }
+    }
+
+    /// The address of the clients, as redirections give it
+    pub fn endpoint(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    /// Whether the node is failing, or this node suspects it is
+    pub fn is_failing(&self) -> bool {
+        self.is_pfail || self.failed_at.is_some()
+    }
+}
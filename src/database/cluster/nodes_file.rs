@@ -0,0 +1,213 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{node::ClusterNode, BusOutput, Cluster, CLUSTER_SLOTS};
+use crate::database::Database;
+
+const FLAG_MYSELF: &str = "myself";
+
+impl Database {
+    fn cluster_config_path(&self) -> PathBuf {
+        Path::new(&self.dir()).join(self.cluster_config_file())
+    }
+
+    /// Saves the cluster as this node sees it to the nodes file, which is replaced at
+    /// once so that a crash never leaves half of it
+    pub(super) fn save_cluster_config(&mut self) -> anyhow::Result<()> {
+        let path = self.cluster_config_path();
+        let Some(cluster) = &mut self.cluster else {
+            return Ok(());
+        };
+        let mut content: String = cluster
+            .nodes
+            .iter()
+            .filter(|node| !node.is_handshake)
+            .map(|node| cluster.describe_node(node) + "\n")
+            .collect();
+        content.push_str(&format!(
+            "vars currentEpoch {} lastVoteEpoch 0\n",
+            cluster.current_epoch
+        ));
+
+        let mut temporary_path = path.clone().into_os_string();
+        temporary_path.push(format!(".tmp-{}", std::process::id()));
+        fs::write(&temporary_path, content)?;
+        File::open(&temporary_path)?.sync_all()?;
+        fs::rename(&temporary_path, &path)?;
+        cluster.is_config_changed = false;
+        Ok(())
+    }
+
+    /// The cluster saved to the nodes file, if there is one
+    pub(super) fn load_cluster_config(
+        &self,
+        output: UnboundedSender<BusOutput>,
+    ) -> anyhow::Result<Option<Cluster>> {
+        let path = self.cluster_config_path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        match parse_nodes_file(&content, output) {
+            Ok(cluster) => {
+                println!("-> Node configuration loaded, I'm {}", cluster.myself);
+                Ok(Some(cluster))
+            }
+            Err(error) => anyhow::bail!(
+                "Unrecoverable error: corrupted cluster config file {}: {error}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Cluster {
+    /// The line telling about a node in CLUSTER NODES and in the nodes file: its id,
+    /// address, flags, master, times of the last ping and pong, config epoch, link state
+    /// and slots, followed by the slots moving from or to this node
+    pub(super) fn describe_node(&self, node: &ClusterNode) -> String {
+        let is_myself = node.id == self.myself;
+        let mut flags = Vec::new();
+        if is_myself {
+            flags.push(FLAG_MYSELF);
+        }
+        flags.push("master");
+        if node.is_pfail {
+            flags.push("fail?");
+        }
+        if node.failed_at.is_some() {
+            flags.push("fail");
+        }
+        if node.is_handshake {
+            flags.push("handshake");
+        }
+        if node.ip.is_empty() {
+            flags.push("noaddr");
+        }
+        let is_connected = is_myself || (node.pong_received > 0 && !node.is_failing());
+
+        let mut line = format!(
+            "{} {}:{}@{} {} - {} {} {} {}",
+            node.id,
+            node.ip,
+            node.port,
+            node.bus_port,
+            flags.join(","),
+            node.ping_sent.unwrap_or_default(),
+            node.pong_received,
+            node.config_epoch,
+            if is_connected {
+                "connected"
+            } else {
+                "disconnected"
+            },
+        );
+        for (start, end) in self.slot_ranges(&node.id) {
+            match start == end {
+                true => line.push_str(&format!(" {start}")),
+                false => line.push_str(&format!(" {start}-{end}")),
+            }
+        }
+        if is_myself {
+            for (slot, target) in &self.migrating {
+                line.push_str(&format!(" [{slot}->-{target}]"));
+            }
+            for (slot, source) in &self.importing {
+                line.push_str(&format!(" [{slot}-<-{source}]"));
+            }
+        }
+        line
+    }
+}
+
+/// Reads the nodes file back. The failure states of the nodes are not kept, the nodes
+/// are pinged again to find out.
+fn parse_nodes_file(content: &str, output: UnboundedSender<BusOutput>) -> anyhow::Result<Cluster> {
+    let mut myself = None;
+    let mut nodes = Vec::new();
+    let mut slots = vec![None; CLUSTER_SLOTS];
+    let mut migrating = BTreeMap::new();
+    let mut importing = BTreeMap::new();
+    let mut current_epoch = 0;
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [] => {}
+            ["vars", ref variables @ ..] => {
+                for variable in variables.chunks(2) {
+                    if let ["currentEpoch", epoch] = variable {
+                        current_epoch = epoch.parse()?;
+                    }
+                }
+            }
+            [id, address, flags, _, _, _, config_epoch, _, ref slot_fields @ ..] => {
+                let (ip, port, bus_port) = parse_node_address(address)?;
+                let mut node = ClusterNode::new(id.to_string(), ip, port, bus_port);
+                node.config_epoch = config_epoch.parse()?;
+                for field in slot_fields {
+                    if let Some(moving) = field.strip_prefix('[').and_then(|f| f.strip_suffix(']'))
+                    {
+                        if let Some((slot, target)) = moving.split_once("->-") {
+                            migrating.insert(parse_slot(slot)?, target.to_string());
+                        } else if let Some((slot, source)) = moving.split_once("-<-") {
+                            importing.insert(parse_slot(slot)?, source.to_string());
+                        } else {
+                            anyhow::bail!("Invalid slot: {field}")
+                        }
+                        continue;
+                    }
+                    let (start, end) = match field.split_once('-') {
+                        Some((start, end)) => (parse_slot(start)?, parse_slot(end)?),
+                        None => (parse_slot(field)?, parse_slot(field)?),
+                    };
+                    for slot in start..=end {
+                        slots[slot as usize] = Some(id.to_string());
+                    }
+                }
+                if flags.split(',').any(|flag| flag == FLAG_MYSELF) {
+                    myself = Some(id.to_string());
+                }
+                nodes.push(node);
+            }
+            _ => anyhow::bail!("Invalid line: {line}"),
+        }
+    }
+    let Some(myself) = myself else {
+        anyhow::bail!("No line for this node")
+    };
+
+    let mut cluster = Cluster::new(ClusterNode::new(myself, String::new(), 0, 0), output);
+    cluster.nodes = nodes;
+    cluster.slots = slots;
+    cluster.migrating = migrating;
+    cluster.importing = importing;
+    cluster.current_epoch = current_epoch;
+    Ok(cluster)
+}
+
+/// Parses an address like 127.0.0.1:7000@17000, optionally followed by a hostname
+fn parse_node_address(address: &str) -> anyhow::Result<(String, u16, u16)> {
+    let address = address.split(',').next().unwrap_or_default();
+    let parsed = address.split_once('@').and_then(|(address, bus_port)| {
+        let (ip, port) = address.rsplit_once(':')?;
+        Some((ip.to_string(), port.parse().ok()?, bus_port.parse().ok()?))
+    });
+    match parsed {
+        Some(parsed) => Ok(parsed),
+        None => anyhow::bail!("Invalid node address: {address}"),
+    }
+}
+
+fn parse_slot(slot: &str) -> anyhow::Result<u16> {
+    match slot.parse::<u16>() {
+        Ok(slot) if (slot as usize) < CLUSTER_SLOTS => Ok(slot),
+        _ => anyhow::bail!("Invalid slot: {slot}"),
+    }
+}
@@ -0,0 +1,348 @@
+#[cfg(test)]
+mod test {
+    use crate::{
+        cli::CliParam,
+        database::{
+            cluster::{crc16::crc16, key_hash_slot, BusOutput, SetSlotAction},
+            migrate::MigrateOptions,
+            Database,
+        },
+    };
+    use std::{
+        env, fs,
+        io::{Read, Write},
+        net::TcpListener,
+        path::PathBuf,
+        thread,
+        time::Duration,
+    };
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    fn cluster_in_temp_dir(
+        name: &str,
+        port: u16,
+    ) -> (Database, UnboundedReceiver<BusOutput>, PathBuf) {
+        let dir = env::temp_dir().join(format!("cluster-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut database = Database::new();
+        let params = vec![
+            CliParam::new("dir", &[dir.display().to_string()]),
+            CliParam::new("bind", &["127.0.0.1".to_string()]),
+            CliParam::new("cluster-enabled", &["yes".to_string()]),
+        ];
+        database.config_setup(None, &params).unwrap();
+        let (_, output) = database.start_cluster(port).unwrap();
+        (database, output, dir)
+    }
+
+    /// The arguments of a message, which hold no line breaks
+    fn arguments(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        let lines: Vec<&str> = text.split("\r\n").collect();
+        lines
+            .windows(2)
+            .filter(|pair| pair[0].starts_with('$'))
+            .map(|pair| pair[1].as_bytes().to_vec())
+            .collect()
+    }
+
+    fn sent(output: &mut UnboundedReceiver<BusOutput>) -> Vec<(BusOutput, Vec<Vec<u8>>)> {
+        let mut messages = Vec::new();
+        while let Ok(message) = output.try_recv() {
+            let arguments = arguments(&message.bytes);
+            messages.push((message, arguments));
+        }
+        messages
+    }
+
+    #[test]
+    fn test_key_hash_slot() {
+        // Given
+        let keys = [
+            "foo",
+            "{user1000}.following",
+            "{user1000}.followers",
+            "{}foo",
+            "a{}{b}",
+        ];
+        // When
+        let slots: Vec<u16> = keys
+            .iter()
+            .map(|key| key_hash_slot(key.as_bytes()))
+            .collect();
+        // Then
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(slots[0], 12182);
+        assert_eq!(slots[1], slots[2]);
+        assert_eq!(slots[1], key_hash_slot(b"user1000"));
+        assert_eq!(slots[3], key_hash_slot(b"{}foo"));
+        assert_eq!(slots[4], key_hash_slot(b"a{}{b}"));
+    }
+
+    #[test]
+    fn test_keys_served_only_by_the_owner_of_their_slot() {
+        // Given
+        let (mut database, _, _) = cluster_in_temp_dir("redirect", 7000);
+        let unserved = database.check_cluster_keys(&["foo"], false);
+        // When
+        database.cluster_add_slots(&[(0, 16383)]).unwrap();
+        let busy = database.cluster_add_slots(&[(5, 5)]);
+        database.cluster_delete_slots(&[(12182, 12182)]).unwrap();
+        let deleted = database.check_cluster_keys(&["foo"], false);
+        database.cluster_add_slots(&[(12182, 12182)]).unwrap();
+        // Then
+        assert_eq!(
+            unserved.unwrap_err().to_string(),
+            "CLUSTERDOWN Hash slot not served"
+        );
+        assert_eq!(busy.unwrap_err().to_string(), "ERR Slot 5 is already busy");
+        assert_eq!(
+            deleted.unwrap_err().to_string(),
+            "CLUSTERDOWN Hash slot not served"
+        );
+        assert!(database
+            .check_cluster_keys(&["foo", "{foo}bar"], false)
+            .is_ok());
+        assert_eq!(
+            database
+                .check_cluster_keys(&["foo", "bar"], false)
+                .unwrap_err()
+                .to_string(),
+            "CROSSSLOT Keys in request don't hash to the same slot"
+        );
+        assert_eq!(
+            database
+                .cluster_add_slots(&[(1, 2), (2, 3)])
+                .unwrap_err()
+                .to_string(),
+            "ERR Slot 2 specified multiple times"
+        );
+    }
+
+    #[test]
+    fn test_nodes_file_is_loaded_on_restart() {
+        // Given
+        let (mut database, _, dir) = cluster_in_temp_dir("restart", 7000);
+        database.cluster_add_slots(&[(0, 100), (200, 200)]).unwrap();
+        let id = database.cluster_my_id().unwrap();
+        let nodes = database.cluster_nodes().unwrap();
+        // When
+        let mut restarted = Database::new();
+        let params = vec![
+            CliParam::new("dir", &[dir.display().to_string()]),
+            CliParam::new("cluster-enabled", &["yes".to_string()]),
+        ];
+        restarted.config_setup(None, &params).unwrap();
+        restarted.start_cluster(7000).unwrap();
+        // Then
+        assert_eq!(restarted.cluster_my_id().unwrap(), id);
+        assert_eq!(restarted.cluster_nodes().unwrap(), nodes);
+        assert!(nodes.starts_with(&format!("{id} 127.0.0.1:7000@17000 myself,master - 0 0 0")));
+        assert!(nodes.ends_with(" 0-100 200\n"));
+    }
+
+    #[test]
+    fn test_nodes_meet_and_learn_the_slots_of_each_other() {
+        // Given
+        let (mut first, mut first_output, _) = cluster_in_temp_dir("meet-first", 7001);
+        let (mut second, mut second_output, _) = cluster_in_temp_dir("meet-second", 7002);
+        first.cluster_add_slots(&[(0, 8191)]).unwrap();
+        second.cluster_add_slots(&[(8192, 16383)]).unwrap();
+        // When
+        first.cluster_meet("127.0.0.1".into(), 7002, 17002).unwrap();
+        first.cluster_cron();
+        let meet = sent(&mut first_output);
+        let pong = second
+            .cluster_receive(&meet[0].1, "127.0.0.1", "127.0.0.1")
+            .unwrap();
+        let pong = arguments(&pong);
+        first.cluster_receive(&pong, "127.0.0.1", "127.0.0.1");
+        first.cluster_cron();
+        second.cluster_cron();
+        // Then
+        assert_eq!(meet.len(), 1);
+        assert_eq!(meet[0].0.address.to_string(), "127.0.0.1:17002");
+        assert_eq!(meet[0].1[0], b"MEET");
+        assert_eq!(pong[0], b"PONG");
+        assert_eq!(sent(&mut second_output)[0].1[0], b"PING");
+        for database in [&first, &second] {
+            assert!(database
+                .cluster_info()
+                .unwrap()
+                .starts_with("cluster_state:ok\r\n"));
+            assert_eq!(database.cluster_slots().unwrap().len(), 2);
+            assert_eq!(database.cluster_shards().unwrap().len(), 2);
+        }
+        assert_eq!(
+            first
+                .check_cluster_keys(&["foo"], false)
+                .unwrap_err()
+                .to_string(),
+            "MOVED 12182 127.0.0.1:7002"
+        );
+        assert!(second.check_cluster_keys(&["foo"], false).is_ok());
+    }
+
+    /// Two nodes of a cluster, the first one serving all the slots
+    fn two_nodes(name: &str) -> (Database, Database) {
+        let (mut first, mut first_output, _) = cluster_in_temp_dir(&format!("{name}-first"), 7001);
+        let (mut second, _, _) = cluster_in_temp_dir(&format!("{name}-second"), 7002);
+        first.cluster_add_slots(&[(0, 16383)]).unwrap();
+        first.cluster_meet("127.0.0.1".into(), 7002, 17002).unwrap();
+        first.cluster_cron();
+        let meet = sent(&mut first_output);
+        let pong = second
+            .cluster_receive(&meet[0].1, "127.0.0.1", "127.0.0.1")
+            .unwrap();
+        first.cluster_receive(&arguments(&pong), "127.0.0.1", "127.0.0.1");
+        first.cluster_cron();
+        second.cluster_cron();
+        (first, second)
+    }
+
+    #[test]
+    fn test_slot_moves_with_ask_redirections() {
+        // Given
+        let (mut first, mut second) = two_nodes("setslot");
+        let first_id = first.cluster_my_id().unwrap();
+        let second_id = second.cluster_my_id().unwrap();
+        let slot = key_hash_slot(b"foo");
+        first
+            .keyspace(0)
+            .set("{foo}moved".into(), b"1".to_vec(), None)
+            .unwrap();
+        first
+            .keyspace(0)
+            .set("{foo}kept".into(), b"1".to_vec(), None)
+            .unwrap();
+        // When
+        let not_owner = second.cluster_set_slot(slot, SetSlotAction::Migrating(first_id.clone()));
+        second
+            .cluster_set_slot(slot, SetSlotAction::Importing(first_id.clone()))
+            .unwrap();
+        first
+            .cluster_set_slot(slot, SetSlotAction::Migrating(second_id.clone()))
+            .unwrap();
+        first.keyspace(0).delete("{foo}moved".into()).unwrap();
+        let held_keys = first.cluster_set_slot(slot, SetSlotAction::Node(second_id.clone()));
+        // Then
+        assert_eq!(
+            not_owner.unwrap_err().to_string(),
+            format!("ERR I'm not the owner of hash slot {slot}")
+        );
+        assert!(first.check_cluster_keys(&["{foo}kept"], false).is_ok());
+        assert_eq!(
+            first
+                .check_cluster_keys(&["{foo}moved"], false)
+                .unwrap_err()
+                .to_string(),
+            format!("ASK {slot} 127.0.0.1:7002")
+        );
+        assert_eq!(
+            first
+                .check_cluster_keys(&["{foo}moved", "{foo}kept"], false)
+                .unwrap_err()
+                .to_string(),
+            "TRYAGAIN Multiple keys request during rehashing of slot"
+        );
+        assert_eq!(
+            second
+                .check_cluster_keys(&["{foo}moved"], false)
+                .unwrap_err()
+                .to_string(),
+            format!("MOVED {slot} 127.0.0.1:7001")
+        );
+        assert!(second.check_cluster_keys(&["{foo}moved"], true).is_ok());
+        assert!(held_keys
+            .unwrap_err()
+            .to_string()
+            .starts_with("ERR Can't assign hashslot"));
+        assert!(first
+            .cluster_nodes()
+            .unwrap()
+            .contains(&format!("[{slot}->-{second_id}]")));
+
+        // When
+        first.keyspace(0).delete("{foo}kept".into()).unwrap();
+        second
+            .cluster_set_slot(slot, SetSlotAction::Node(second_id.clone()))
+            .unwrap();
+        first
+            .cluster_set_slot(slot, SetSlotAction::Node(second_id))
+            .unwrap();
+        // Then
+        assert!(second.check_cluster_keys(&["{foo}moved"], false).is_ok());
+        assert_eq!(
+            first
+                .check_cluster_keys(&["{foo}moved"], false)
+                .unwrap_err()
+                .to_string(),
+            format!("MOVED {slot} 127.0.0.1:7002")
+        );
+        assert!(!first.cluster_nodes().unwrap().contains("->-"));
+    }
+
+    #[test]
+    fn test_migrate_restores_keys_on_the_target_then_deletes_them() {
+        // Given
+        let (mut database, _, _) = cluster_in_temp_dir("migrate", 7000);
+        database.cluster_add_slots(&[(0, 16383)]).unwrap();
+        for key in ["{a}1", "{a}2"] {
+            database
+                .keyspace(0)
+                .set(key.into(), b"value".to_vec(), None)
+                .unwrap();
+        }
+        let target = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = target.local_addr().unwrap().port();
+        // Replies to SELECT and both RESTORE-ASKING
+        let received = thread::spawn(move || {
+            let (mut stream, _) = target.accept().unwrap();
+            let mut bytes = Vec::new();
+            let mut buffer = [0; 1024];
+            while bytes
+                .windows(7)
+                .filter(|window| window == b"RESTORE")
+                .count()
+                < 2
+                || !bytes.ends_with(b"\r\n")
+            {
+                let read_count = stream.read(&mut buffer).unwrap();
+                bytes.extend(&buffer[..read_count]);
+            }
+            stream
+                .write_all(b"+OK\r\n+OK\r\n-BUSYKEY exists\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&bytes).to_string()
+        });
+        let options = MigrateOptions {
+            host: "127.0.0.1".into(),
+            port,
+            keys: vec!["{a}1".into(), "{a}2".into(), "{a}missing".into()],
+            destination_db: 0,
+            timeout: Duration::from_secs(5),
+            copy: false,
+            replace: false,
+            auth: None,
+        };
+        // When
+        let result = database.migrate(0, &options);
+        // Then
+        let received = received.join().unwrap();
+        assert!(received.starts_with("*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n"));
+        assert!(received.contains("$14\r\nRESTORE-ASKING\r\n$4\r\n{a}1\r\n$1\r\n0\r\n"));
+        assert!(!received.contains("missing"));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "ERR Target instance replied with error: BUSYKEY exists"
+        );
+        assert_eq!(database.keyspace(0).get("{a}1".into()).unwrap(), None);
+        assert!(database.keyspace(0).get("{a}2".into()).unwrap().is_some());
+        let missing = MigrateOptions {
+            keys: vec!["{a}missing".into()],
+            ..options
+        };
+        assert!(!database.migrate(0, &missing).unwrap());
+    }
+}
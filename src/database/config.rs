@@ -1,20 +1,361 @@
-use super::{Database, SETTINGS_DBFILENAME_ID, SETTINGS_DIR_ID};
+use super::{
+    aof::AppendFsync,
+    keyspace::Keyspace,
+    pattern::glob_match,
+    replication::{DisklessLoad, MasterAddress},
+    Database, SETTINGS_AOF_LOAD_TRUNCATED_ID, SETTINGS_AOF_USE_RDB_PREAMBLE_ID,
+    SETTINGS_APPENDDIRNAME_ID, SETTINGS_APPENDFILENAME_ID, SETTINGS_APPENDFSYNC_ID,
+    SETTINGS_APPENDONLY_ID, SETTINGS_AUTO_AOF_REWRITE_MIN_SIZE_ID,
+    SETTINGS_AUTO_AOF_REWRITE_PERCENTAGE_ID, SETTINGS_BIND_ID, SETTINGS_CLUSTER_CONFIG_FILE_ID,
+    SETTINGS_CLUSTER_ENABLED_ID, SETTINGS_CLUSTER_NODE_TIMEOUT_ID, SETTINGS_CLUSTER_PORT_ID,
+    SETTINGS_CLUSTER_REQUIRE_FULL_COVERAGE_ID, SETTINGS_DATABASES_ID, SETTINGS_DBFILENAME_ID,
+    SETTINGS_DIR_ID, SETTINGS_HZ_ID, SETTINGS_PIDFILE_ID, SETTINGS_PORT_ID, SETTINGS_REPLICAOF_ID,
+    SETTINGS_REPLICA_READ_ONLY_ID, SETTINGS_REPL_BACKLOG_SIZE_ID, SETTINGS_REPL_DISKLESS_LOAD_ID,
+    SETTINGS_REPL_DISKLESS_SYNC_DELAY_ID, SETTINGS_REPL_DISKLESS_SYNC_ID,
+    SETTINGS_REPL_DISKLESS_SYNC_MAX_REPLICAS_ID, SETTINGS_REPL_PING_REPLICA_PERIOD_ID,
+    SETTINGS_REPL_TIMEOUT_ID, SETTINGS_SAVE_ID, SETTINGS_STOP_WRITES_ON_BGSAVE_ERROR_ID,
+    SETTINGS_UNIXSOCKETPERM_ID, SETTINGS_UNIXSOCKET_ID,
+};
 use crate::cli::CliParam;
+use std::{collections::HashMap, path::PathBuf};
+
+use self::registry::{find_definition, ConfigDefinition, ConfigValue, DEFINITIONS};
+
+pub(super) mod registry;
+mod rewrite;
+
+#[cfg(test)]
+mod tests;
+
+const MIN_HZ: i64 = 1;
+const MAX_HZ: i64 = 500;
+
+/// Every setting with its default value
+pub(super) fn default_config() -> HashMap<&'static str, ConfigValue> {
+    DEFINITIONS
+        .iter()
+        .filter_map(|definition| {
+            let value = definition.config_type.parse(definition.default).ok()?;
+            Some((definition.name, value))
+        })
+        .collect()
+}
 
 impl Database {
-    pub fn config_setup(&mut self, cli_params: &[CliParam]) {
-        cli_params.iter().for_each(|param| match param {
-            CliParam::Dir(dir) => {
-                self.config.insert(SETTINGS_DIR_ID.to_string(), dir.clone());
+    /// Sets the values from the config file and the command line, where later ones win
+    pub fn config_setup(
+        &mut self,
+        config_file: Option<PathBuf>,
+        cli_params: &[CliParam],
+    ) -> anyhow::Result<()> {
+        self.config_file = config_file;
+        let mut save_points = None;
+        for param in cli_params {
+            let Some(definition) = find_definition(&param.name) else {
+                eprintln!("-> Ignored unsupported config '{}'", param.name);
+                continue;
+            };
+            // Like redis-server, each save line adds save points to the ones given before,
+            // and an empty one removes them
+            let mut param_value = param.value.clone();
+            if definition.name == SETTINGS_SAVE_ID {
+                let previous: &mut String = save_points.get_or_insert_with(String::new);
+                if param_value.is_empty() {
+                    previous.clear();
+                } else {
+                    if !previous.is_empty() {
+                        previous.push(' ');
+                    }
+                    previous.push_str(&param_value);
+                }
+                param_value = previous.clone();
             }
-            CliParam::DbFilename(dbfilename) => {
-                self.config
-                    .insert(SETTINGS_DBFILENAME_ID.to_string(), dbfilename.clone());
-            }
-        });
+            let value = match definition.parse(&param_value) {
+                Ok(value) => value,
+                Err(error) => anyhow::bail!(
+                    "Invalid argument '{}' for config '{}': {error}",
+                    param.value,
+                    definition.name
+                ),
+            };
+            self.config.insert(definition.name, value);
+        }
+
+        self.keyspaces.resize_with(self.databases(), Keyspace::new);
+        Ok(())
     }
 
     pub fn config_get(&self, key: &str) -> Option<String> {
-        self.config.get(key).cloned()
+        let definition = find_definition(key)?;
+        let value = self.config.get(definition.name)?;
+        Some(definition.config_type.format(value))
+    }
+
+    /// Returns the settings whose name matches any of the glob patterns, sorted by name
+    pub fn config_get_matching(&self, patterns: &[String]) -> Vec<(String, String)> {
+        let mut settings: Vec<(String, String)> = DEFINITIONS
+            .iter()
+            .filter(|definition| {
+                patterns
+                    .iter()
+                    .any(|pattern| glob_match(pattern, definition.name, true))
+            })
+            .filter_map(|definition| {
+                let value = self.config_get(definition.name)?;
+                Some((definition.name.to_string(), value))
+            })
+            .collect();
+        settings.sort();
+        settings
+    }
+
+    /// Sets all the settings or none of them, when any value is invalid or fails to apply
+    pub fn config_set(&mut self, pairs: &[(String, String)]) -> anyhow::Result<()> {
+        let mut changes: Vec<(&ConfigDefinition, ConfigValue)> = Vec::new();
+        for (name, value) in pairs {
+            let Some(definition) = find_definition(name) else {
+                anyhow::bail!("ERR Unknown option or number of arguments for CONFIG SET - '{name}'")
+            };
+            let failed = |reason: &str| {
+                anyhow::anyhow!(
+                    "ERR CONFIG SET failed (possibly related to argument '{name}') - {reason}"
+                )
+            };
+            if !definition.is_mutable {
+                return Err(failed("can't set immutable config"));
+            }
+            if changes
+                .iter()
+                .any(|(changed, _)| changed.name == definition.name)
+            {
+                return Err(failed("duplicate parameter"));
+            }
+            let value = definition
+                .parse(value)
+                .map_err(|error| failed(&error.to_string()))?;
+            changes.push((definition, value));
+        }
+
+        let mut previous_values = Vec::new();
+        for (definition, value) in &changes {
+            if let Some(previous_value) = self.config.insert(definition.name, value.clone()) {
+                previous_values.push((definition.name, previous_value));
+            }
+        }
+        for (definition, _) in &changes {
+            let Some(apply) = definition.apply else {
+                continue;
+            };
+            if let Err(error) = apply(self) {
+                self.config.extend(previous_values);
+                for apply in changes
+                    .iter()
+                    .filter_map(|(definition, _)| definition.apply)
+                {
+                    let _ = apply(self);
+                }
+                anyhow::bail!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - {error}",
+                    definition.name
+                )
+            }
+        }
+        Ok(())
+    }
+
+    fn config_string(&self, name: &str) -> String {
+        match self.config.get(name) {
+            Some(ConfigValue::String(string)) => string.clone(),
+            _ => String::new(),
+        }
+    }
+
+    fn config_integer(&self, name: &str) -> i64 {
+        match self.config.get(name) {
+            Some(ConfigValue::Integer(integer)) => *integer,
+            _ => 0,
+        }
+    }
+
+    fn config_bool(&self, name: &str) -> bool {
+        matches!(self.config.get(name), Some(ConfigValue::Bool(true)))
+    }
+
+    pub fn dir(&self) -> String {
+        self.config_string(SETTINGS_DIR_ID)
+    }
+
+    pub fn dbfilename(&self) -> String {
+        self.config_string(SETTINGS_DBFILENAME_ID)
+    }
+
+    /// Frequency of the background tasks, like the active expire cycle, per second
+    pub fn hz(&self) -> u64 {
+        self.config_integer(SETTINGS_HZ_ID).clamp(MIN_HZ, MAX_HZ) as u64
+    }
+
+    /// Number of numbered databases, selectable with SELECT
+    pub fn databases(&self) -> usize {
+        self.config_integer(SETTINGS_DATABASES_ID) as usize
+    }
+
+    /// TCP port to listen on, where 0 picks an ephemeral port
+    pub fn port(&self) -> u16 {
+        self.config_integer(SETTINGS_PORT_ID) as u16
+    }
+
+    /// Addresses to listen on. Addresses prefixed with '-' are optional.
+    pub fn bind_addresses(&self) -> Vec<String> {
+        self.config_string(SETTINGS_BIND_ID)
+            .split_whitespace()
+            .map(|address| address.to_string())
+            .collect()
+    }
+
+    /// Path of the Unix socket to listen on, if any
+    pub fn unix_socket(&self) -> Option<String> {
+        Some(self.config_string(SETTINGS_UNIXSOCKET_ID)).filter(|path| !path.is_empty())
+    }
+
+    /// Permissions of the Unix socket file, if set
+    pub fn unix_socket_perm(&self) -> Option<u32> {
+        Some(self.config_integer(SETTINGS_UNIXSOCKETPERM_ID) as u32)
+            .filter(|permissions| *permissions != 0)
+    }
+
+    /// File the process id is written to while running, if any
+    pub fn pidfile(&self) -> Option<String> {
+        Some(self.config_string(SETTINGS_PIDFILE_ID)).filter(|path| !path.is_empty())
+    }
+
+    pub fn appendonly(&self) -> bool {
+        self.config_bool(SETTINGS_APPENDONLY_ID)
+    }
+
+    pub fn appendfilename(&self) -> String {
+        self.config_string(SETTINGS_APPENDFILENAME_ID)
+    }
+
+    pub fn appendfsync(&self) -> AppendFsync {
+        AppendFsync::from_name(&self.config_string(SETTINGS_APPENDFSYNC_ID))
+    }
+
+    /// Whether an AOF whose last command is cut short is loaded anyway
+    pub fn aof_load_truncated(&self) -> bool {
+        self.config_bool(SETTINGS_AOF_LOAD_TRUNCATED_ID)
+    }
+
+    /// Directory holding the AOF files and their manifest, inside dir
+    pub fn appenddirname(&self) -> String {
+        self.config_string(SETTINGS_APPENDDIRNAME_ID)
+    }
+
+    /// Whether an AOF rewrite writes the dataset in the RDB format, faster to load
+    pub fn aof_use_rdb_preamble(&self) -> bool {
+        self.config_bool(SETTINGS_AOF_USE_RDB_PREAMBLE_ID)
+    }
+
+    /// Growth of the AOF since the last rewrite, in percent, triggering a rewrite.
+    /// 0 disables automatic rewrites.
+    pub fn auto_aof_rewrite_percentage(&self) -> u64 {
+        self.config_integer(SETTINGS_AUTO_AOF_REWRITE_PERCENTAGE_ID) as u64
+    }
+
+    /// Pairs of seconds and changes: the RDB file is saved once there were as many
+    /// changes since the last save, at least that many seconds ago
+    pub fn save_points(&self) -> Vec<(u64, u64)> {
+        let numbers: Vec<u64> = self
+            .config_string(SETTINGS_SAVE_ID)
+            .split_whitespace()
+            .filter_map(|number| number.parse().ok())
+            .collect();
+        numbers
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
+
+    pub fn stop_writes_on_bgsave_error(&self) -> bool {
+        self.config_bool(SETTINGS_STOP_WRITES_ON_BGSAVE_ERROR_ID)
+    }
+
+    /// Size in bytes under which the AOF is never rewritten automatically
+    pub fn auto_aof_rewrite_min_size(&self) -> u64 {
+        self.config_integer(SETTINGS_AUTO_AOF_REWRITE_MIN_SIZE_ID) as u64
+    }
+
+    /// The master to replicate from at startup, if any
+    pub fn replicaof(&self) -> Option<MasterAddress> {
+        let master = self.config_string(SETTINGS_REPLICAOF_ID);
+        let (host, port) = master.split_once(' ')?;
+        Some(MasterAddress {
+            host: host.to_string(),
+            port: port.parse().ok()?,
+        })
+    }
+
+    /// Whether replicas reject the writes of their clients
+    pub fn replica_read_only(&self) -> bool {
+        self.config_bool(SETTINGS_REPLICA_READ_ONLY_ID)
+    }
+
+    /// Bytes of the replication stream kept for replicas resyncing partially
+    pub fn repl_backlog_size(&self) -> usize {
+        self.config_integer(SETTINGS_REPL_BACKLOG_SIZE_ID) as usize
+    }
+
+    /// Seconds between the PINGs a master sends to its replicas
+    pub fn repl_ping_replica_period(&self) -> u64 {
+        self.config_integer(SETTINGS_REPL_PING_REPLICA_PERIOD_ID) as u64
+    }
+
+    /// Seconds without data after which a replication link is dropped
+    pub fn repl_timeout(&self) -> u64 {
+        self.config_integer(SETTINGS_REPL_TIMEOUT_ID) as u64
+    }
+
+    /// Whether full resyncs send the RDB straight from memory, without writing it to dir
+    pub fn repl_diskless_sync(&self) -> bool {
+        self.config_bool(SETTINGS_REPL_DISKLESS_SYNC_ID)
+    }
+
+    /// Seconds a diskless sync waits for more replicas to share it
+    pub fn repl_diskless_sync_delay(&self) -> u64 {
+        self.config_integer(SETTINGS_REPL_DISKLESS_SYNC_DELAY_ID) as u64
+    }
+
+    /// Replicas a diskless sync starts without waiting for the delay once reached.
+    /// 0 always waits for it.
+    pub fn repl_diskless_sync_max_replicas(&self) -> usize {
+        self.config_integer(SETTINGS_REPL_DISKLESS_SYNC_MAX_REPLICAS_ID) as usize
+    }
+
+    pub fn repl_diskless_load(&self) -> DisklessLoad {
+        DisklessLoad::from_name(&self.config_string(SETTINGS_REPL_DISKLESS_LOAD_ID))
+    }
+
+    /// Whether the server runs as a node of a cluster, serving the hash slots it owns
+    pub fn cluster_enabled(&self) -> bool {
+        self.config_bool(SETTINGS_CLUSTER_ENABLED_ID)
+    }
+
+    /// File in dir where the node saves the cluster as it sees it, to rejoin it at startup
+    pub fn cluster_config_file(&self) -> String {
+        self.config_string(SETTINGS_CLUSTER_CONFIG_FILE_ID)
+    }
+
+    /// Milliseconds a node may not answer before it is considered failing
+    pub fn cluster_node_timeout(&self) -> u64 {
+        self.config_integer(SETTINGS_CLUSTER_NODE_TIMEOUT_ID) as u64
+    }
+
+    /// TCP port of the cluster bus, where 0 is the port of clients plus 10000
+    pub fn cluster_port(&self) -> u16 {
+        self.config_integer(SETTINGS_CLUSTER_PORT_ID) as u16
+    }
+
+    /// Whether the cluster stops serving keys while some hash slots have no node
+    pub fn cluster_require_full_coverage(&self) -> bool {
+        self.config_bool(SETTINGS_CLUSTER_REQUIRE_FULL_COVERAGE_ID)
     }
 }
@@ -0,0 +1,525 @@
+use std::path::Path;
+
+use crate::database::{
+    aof::APPEND_FSYNC_NAMES, replication::DISKLESS_LOAD_NAMES, Database,
+    SETTINGS_AOF_LOAD_TRUNCATED_ID, SETTINGS_AOF_USE_RDB_PREAMBLE_ID, SETTINGS_APPENDDIRNAME_ID,
+    SETTINGS_APPENDFILENAME_ID, SETTINGS_APPENDFSYNC_ID, SETTINGS_APPENDONLY_ID,
+    SETTINGS_AUTO_AOF_REWRITE_MIN_SIZE_ID, SETTINGS_AUTO_AOF_REWRITE_PERCENTAGE_ID,
+    SETTINGS_BIND_ID, SETTINGS_CLUSTER_CONFIG_FILE_ID, SETTINGS_CLUSTER_ENABLED_ID,
+    SETTINGS_CLUSTER_NODE_TIMEOUT_ID, SETTINGS_CLUSTER_PORT_ID,
+    SETTINGS_CLUSTER_REQUIRE_FULL_COVERAGE_ID, SETTINGS_DATABASES_ID, SETTINGS_DBFILENAME_ID,
+    SETTINGS_DIR_ID, SETTINGS_HZ_ID, SETTINGS_PIDFILE_ID, SETTINGS_PORT_ID, SETTINGS_REPLICAOF_ID,
+    SETTINGS_REPLICA_READ_ONLY_ID, SETTINGS_REPL_BACKLOG_SIZE_ID, SETTINGS_REPL_DISKLESS_LOAD_ID,
+    SETTINGS_REPL_DISKLESS_SYNC_DELAY_ID, SETTINGS_REPL_DISKLESS_SYNC_ID,
+    SETTINGS_REPL_DISKLESS_SYNC_MAX_REPLICAS_ID, SETTINGS_REPL_PING_REPLICA_PERIOD_ID,
+    SETTINGS_REPL_TIMEOUT_ID, SETTINGS_SAVE_ID, SETTINGS_STOP_WRITES_ON_BGSAVE_ERROR_ID,
+    SETTINGS_UNIXSOCKETPERM_ID, SETTINGS_UNIXSOCKET_ID,
+};
+
+const VALUE_YES: &str = "yes";
+const VALUE_NO: &str = "no";
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConfigType {
+    String,
+    /// Values separated by spaces, like the addresses of bind
+    List,
+    Integer {
+        min: i64,
+        max: i64,
+    },
+    /// Integer written in octal, like file permissions
+    Octal {
+        max: i64,
+    },
+    /// Number of bytes, with an optional unit like 64mb or 1gb
+    Memory {
+        max: i64,
+    },
+    /// yes or no
+    Bool,
+    /// One of the names
+    Enum(&'static [&'static str]),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+}
+
+pub struct ConfigDefinition {
+    pub name: &'static str,
+    pub config_type: ConfigType,
+    pub default: &'static str,
+    /// Immutable settings can only be given at startup
+    pub is_mutable: bool,
+    /// Checks the value, both at startup and on CONFIG SET
+    pub validate: Option<fn(&ConfigValue) -> anyhow::Result<()>>,
+    /// Runs once the value is changed by CONFIG SET. When it fails the previous value
+    /// is restored.
+    pub apply: Option<fn(&mut Database) -> anyhow::Result<()>>,
+}
+
+pub const DEFINITIONS: &[ConfigDefinition] = &[
+    ConfigDefinition {
+        name: SETTINGS_DIR_ID,
+        config_type: ConfigType::String,
+        default: ".",
+        is_mutable: true,
+        validate: Some(validate_dir),
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_DBFILENAME_ID,
+        config_type: ConfigType::String,
+        default: "dump.rdb",
+        is_mutable: true,
+        validate: Some(validate_filename),
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_HZ_ID,
+        config_type: ConfigType::Integer {
+            min: 0,
+            max: i32::MAX as i64,
+        },
+        default: "10",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_DATABASES_ID,
+        config_type: ConfigType::Integer {
+            min: 1,
+            max: i32::MAX as i64,
+        },
+        default: "16",
+        is_mutable: false,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_PORT_ID,
+        config_type: ConfigType::Integer {
+            min: 0,
+            max: u16::MAX as i64,
+        },
+        default: "6379",
+        is_mutable: false,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_BIND_ID,
+        config_type: ConfigType::List,
+        default: "127.0.0.1",
+        is_mutable: false,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_UNIXSOCKET_ID,
+        config_type: ConfigType::String,
+        default: "",
+        is_mutable: false,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_UNIXSOCKETPERM_ID,
+        config_type: ConfigType::Octal { max: 0o777 },
+        default: "0",
+        is_mutable: false,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_PIDFILE_ID,
+        config_type: ConfigType::String,
+        default: "",
+        is_mutable: false,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_APPENDONLY_ID,
+        config_type: ConfigType::Bool,
+        default: VALUE_NO,
+        is_mutable: true,
+        validate: None,
+        apply: Some(apply_appendonly),
+    },
+    ConfigDefinition {
+        name: SETTINGS_APPENDFILENAME_ID,
+        config_type: ConfigType::String,
+        default: "appendonly.aof",
+        is_mutable: false,
+        validate: Some(validate_filename),
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_APPENDFSYNC_ID,
+        config_type: ConfigType::Enum(APPEND_FSYNC_NAMES),
+        default: "everysec",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_AOF_LOAD_TRUNCATED_ID,
+        config_type: ConfigType::Bool,
+        default: VALUE_YES,
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_APPENDDIRNAME_ID,
+        config_type: ConfigType::String,
+        default: "appendonlydir",
+        is_mutable: false,
+        validate: Some(validate_filename),
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_AOF_USE_RDB_PREAMBLE_ID,
+        config_type: ConfigType::Bool,
+        default: VALUE_YES,
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_AUTO_AOF_REWRITE_PERCENTAGE_ID,
+        config_type: ConfigType::Integer {
+            min: 0,
+            max: i32::MAX as i64,
+        },
+        default: "100",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_AUTO_AOF_REWRITE_MIN_SIZE_ID,
+        config_type: ConfigType::Memory { max: i64::MAX },
+        default: "64mb",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_SAVE_ID,
+        config_type: ConfigType::List,
+        default: "3600 1 300 100 60 10000",
+        is_mutable: true,
+        validate: Some(validate_save_points),
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_STOP_WRITES_ON_BGSAVE_ERROR_ID,
+        config_type: ConfigType::Bool,
+        default: VALUE_YES,
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPLICAOF_ID,
+        config_type: ConfigType::List,
+        default: "",
+        is_mutable: false,
+        validate: Some(validate_replicaof),
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPLICA_READ_ONLY_ID,
+        config_type: ConfigType::Bool,
+        default: VALUE_YES,
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPL_BACKLOG_SIZE_ID,
+        config_type: ConfigType::Memory { max: i64::MAX },
+        default: "1mb",
+        is_mutable: true,
+        validate: None,
+        apply: Some(apply_repl_backlog_size),
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPL_PING_REPLICA_PERIOD_ID,
+        config_type: ConfigType::Integer {
+            min: 1,
+            max: i32::MAX as i64,
+        },
+        default: "10",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPL_TIMEOUT_ID,
+        config_type: ConfigType::Integer {
+            min: 1,
+            max: i32::MAX as i64,
+        },
+        default: "60",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPL_DISKLESS_SYNC_ID,
+        config_type: ConfigType::Bool,
+        default: VALUE_YES,
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPL_DISKLESS_SYNC_DELAY_ID,
+        config_type: ConfigType::Integer {
+            min: 0,
+            max: i32::MAX as i64,
+        },
+        default: "5",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPL_DISKLESS_SYNC_MAX_REPLICAS_ID,
+        config_type: ConfigType::Integer {
+            min: 0,
+            max: i32::MAX as i64,
+        },
+        default: "0",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_REPL_DISKLESS_LOAD_ID,
+        config_type: ConfigType::Enum(DISKLESS_LOAD_NAMES),
+        default: "disabled",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_CLUSTER_ENABLED_ID,
+        config_type: ConfigType::Bool,
+        default: VALUE_NO,
+        is_mutable: false,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_CLUSTER_CONFIG_FILE_ID,
+        config_type: ConfigType::String,
+        default: "nodes.conf",
+        is_mutable: false,
+        validate: Some(validate_filename),
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_CLUSTER_NODE_TIMEOUT_ID,
+        config_type: ConfigType::Integer {
+            min: 0,
+            max: i64::MAX,
+        },
+        default: "15000",
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_CLUSTER_PORT_ID,
+        config_type: ConfigType::Integer {
+            min: 0,
+            max: u16::MAX as i64,
+        },
+        default: "0",
+        is_mutable: false,
+        validate: None,
+        apply: None,
+    },
+    ConfigDefinition {
+        name: SETTINGS_CLUSTER_REQUIRE_FULL_COVERAGE_ID,
+        config_type: ConfigType::Bool,
+        default: VALUE_YES,
+        is_mutable: true,
+        validate: None,
+        apply: None,
+    },
+];
+
+pub fn find_definition(name: &str) -> Option<&'static ConfigDefinition> {
+    DEFINITIONS
+        .iter()
+        .find(|definition| definition.name.eq_ignore_ascii_case(name))
+}
+
+impl ConfigDefinition {
+    /// Parses and validates a value of the setting
+    pub fn parse(&self, value: &str) -> anyhow::Result<ConfigValue> {
+        let value = self.config_type.parse(value)?;
+        if let Some(validate) = self.validate {
+            validate(&value)?;
+        }
+        Ok(value)
+    }
+}
+
+impl ConfigType {
+    pub fn parse(&self, value: &str) -> anyhow::Result<ConfigValue> {
+        match self {
+            ConfigType::String => Ok(ConfigValue::String(value.to_string())),
+            ConfigType::List => {
+                let values: Vec<&str> = value.split_whitespace().collect();
+                Ok(ConfigValue::String(values.join(" ")))
+            }
+            ConfigType::Integer { min, max } => {
+                let Ok(integer) = value.parse::<i64>() else {
+                    anyhow::bail!("argument couldn't be parsed into an integer")
+                };
+                validate_range(integer, *min, *max)?;
+                Ok(ConfigValue::Integer(integer))
+            }
+            ConfigType::Octal { max } => {
+                let Ok(integer) = i64::from_str_radix(value, 8) else {
+                    anyhow::bail!("argument couldn't be parsed into an octal integer")
+                };
+                validate_range(integer, 0, *max)?;
+                Ok(ConfigValue::Integer(integer))
+            }
+            ConfigType::Memory { max } => {
+                let Some(integer) = parse_memory(value) else {
+                    anyhow::bail!("argument must be a memory value")
+                };
+                validate_range(integer, 0, *max)?;
+                Ok(ConfigValue::Integer(integer))
+            }
+            ConfigType::Bool => match value.to_lowercase().as_str() {
+                VALUE_YES => Ok(ConfigValue::Bool(true)),
+                VALUE_NO => Ok(ConfigValue::Bool(false)),
+                _ => anyhow::bail!("argument must be 'yes' or 'no'"),
+            },
+            ConfigType::Enum(names) => {
+                match names.iter().find(|name| name.eq_ignore_ascii_case(value)) {
+                    Some(name) => Ok(ConfigValue::String(name.to_string())),
+                    None => anyhow::bail!(
+                        "argument(s) must be one of the following: {}",
+                        names.join(", ")
+                    ),
+                }
+            }
+        }
+    }
+
+    pub fn format(&self, value: &ConfigValue) -> String {
+        match (self, value) {
+            (ConfigType::Octal { .. }, ConfigValue::Integer(integer)) => format!("{integer:o}"),
+            (_, ConfigValue::Integer(integer)) => integer.to_string(),
+            (_, ConfigValue::Bool(true)) => VALUE_YES.to_string(),
+            (_, ConfigValue::Bool(false)) => VALUE_NO.to_string(),
+            (_, ConfigValue::String(string)) => string.clone(),
+        }
+    }
+}
+
+/// Parses a number of bytes like redis-server: k, m and g are powers of 1000,
+/// kb, mb and gb powers of 1024
+fn parse_memory(value: &str) -> Option<i64> {
+    let value = value.to_lowercase();
+    let digits_end = value
+        .find(|character: char| !character.is_ascii_digit() && character != '-')
+        .unwrap_or(value.len());
+    let multiplier: i64 = match &value[digits_end..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    value[..digits_end]
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(multiplier)
+}
+
+fn validate_range(integer: i64, min: i64, max: i64) -> anyhow::Result<()> {
+    if integer < min || integer > max {
+        anyhow::bail!("argument must be between {min} and {max} inclusive")
+    }
+    Ok(())
+}
+
+fn validate_dir(value: &ConfigValue) -> anyhow::Result<()> {
+    match value {
+        ConfigValue::String(dir) if Path::new(dir).is_dir() => Ok(()),
+        _ => anyhow::bail!("No such file or directory"),
+    }
+}
+
+fn validate_filename(value: &ConfigValue) -> anyhow::Result<()> {
+    match value {
+        ConfigValue::String(filename) if filename.contains('/') => {
+            anyhow::bail!("File name can't be a path, just a filename")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Save points are pairs of seconds and changes, like `3600 1 300 100`
+fn validate_save_points(value: &ConfigValue) -> anyhow::Result<()> {
+    let ConfigValue::String(save_points) = value else {
+        return Ok(());
+    };
+    let numbers: Vec<&str> = save_points.split_whitespace().collect();
+    if numbers.len() % 2 != 0 {
+        anyhow::bail!("Invalid save parameters")
+    }
+    for pair in numbers.chunks(2) {
+        let seconds = pair[0].parse::<u64>();
+        let changes = pair[1].parse::<u64>();
+        if !matches!((seconds, changes), (Ok(seconds), Ok(_)) if seconds > 0) {
+            anyhow::bail!("Invalid save parameters")
+        }
+    }
+    Ok(())
+}
+
+/// The master is given as a host and a port, like `127.0.0.1 6379`
+fn validate_replicaof(value: &ConfigValue) -> anyhow::Result<()> {
+    let ConfigValue::String(master) = value else {
+        return Ok(());
+    };
+    match master.split_whitespace().collect::<Vec<_>>()[..] {
+        [] => Ok(()),
+        [_, port] if port.parse::<u16>().is_ok() => Ok(()),
+        _ => anyhow::bail!("Invalid master address"),
+    }
+}
+
+fn apply_repl_backlog_size(database: &mut Database) -> anyhow::Result<()> {
+    database.resize_replication_backlog();
+    Ok(())
+}
+
+/// Starts logging to the AOF, from a snapshot of the dataset, or stops it
+fn apply_appendonly(database: &mut Database) -> anyhow::Result<()> {
+    match (database.appendonly(), database.is_aof_on()) {
+        (true, false) => database.create_aof(),
+        (false, true) => database.stop_aof(),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::Write,
+};
+
+use super::registry::{find_definition, ConfigDefinition, ConfigType, DEFINITIONS};
+use crate::{
+    config_file::{quote_argument, split_arguments},
+    database::Database,
+};
+
+/// Marks the settings appended by CONFIG REWRITE, which were not in the file before
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+impl Database {
+    /// Writes the current settings back to the config file. Each setting replaces its
+    /// first line in the file, and the lines left, like comments, are kept as they are.
+    pub fn config_rewrite(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.config_file else {
+            anyhow::bail!("ERR The server is running without a config file")
+        };
+        // Like redis-server, a config file removed in the meantime is created again
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        let rewritten = rewrite_config(&contents, |definition| {
+            let value = self.config.get(definition.name)?;
+            Some(definition.config_type.format(value))
+        });
+
+        // Writes a temporary file first so that a failure never leaves a partial config
+        let mut temporary_path = path.clone().into_os_string();
+        temporary_path.push(format!(".tmp-{}", std::process::id()));
+        let result = File::create(&temporary_path).and_then(|mut file| {
+            file.write_all(rewritten.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temporary_path, path)
+        });
+        if let Err(error) = result {
+            let _ = fs::remove_file(&temporary_path);
+            anyhow::bail!("ERR Rewriting config file: {error}")
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn rewrite_config(
+    contents: &str,
+    current_value: impl Fn(&ConfigDefinition) -> Option<String>,
+) -> String {
+    let mut lines = Vec::new();
+    let mut rewritten_names = HashSet::new();
+    let mut has_signature = false;
+    for line in contents.lines() {
+        has_signature |= line.trim() == REWRITE_SIGNATURE;
+        let definition = split_arguments(line.trim())
+            .ok()
+            .and_then(|arguments| find_definition(arguments.first()?));
+        let Some(definition) = definition else {
+            lines.push(line.to_string());
+            continue;
+        };
+        // Later lines of the same setting would override the rewritten one
+        if rewritten_names.insert(definition.name) {
+            if let Some(value) = current_value(definition) {
+                lines.push(format_line(definition, &value));
+            }
+        }
+    }
+
+    let appended_lines: Vec<String> = DEFINITIONS
+        .iter()
+        .filter(|definition| !rewritten_names.contains(definition.name))
+        .filter_map(|definition| {
+            let value = current_value(definition)?;
+            let default = definition.config_type.parse(definition.default).ok()?;
+            (value != definition.config_type.format(&default))
+                .then(|| format_line(definition, &value))
+        })
+        .collect();
+    if !appended_lines.is_empty() && !has_signature {
+        lines.push(REWRITE_SIGNATURE.to_string());
+    }
+    lines.extend(appended_lines);
+
+    let mut rewritten = lines.join("\n");
+    rewritten.push('\n');
+    rewritten
+}
+
+fn format_line(definition: &ConfigDefinition, value: &str) -> String {
+    let arguments: Vec<String> = match definition.config_type {
+        ConfigType::List => value.split_whitespace().map(quote_argument).collect(),
+        _ => vec![quote_argument(value)],
+    };
+    format!("{} {}", definition.name, arguments.join(" "))
+}
@@ -0,0 +1,225 @@
+#[cfg(test)]
+mod test {
+    use crate::{
+        cli::CliParam,
+        database::{config::rewrite::rewrite_config, Database},
+    };
+    use std::{env, fs};
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_listen_settings_from_cli_params() {
+        // Given
+        let arguments: Vec<String> = [
+            "--port",
+            "0",
+            "--bind",
+            "127.0.0.1",
+            "-::1",
+            "--unixsocket",
+            "/tmp/redis.sock",
+            "--unixsocketperm",
+            "700",
+        ]
+        .iter()
+        .map(|argument| argument.to_string())
+        .collect();
+        let mut database = Database::new();
+        // When
+        database
+            .config_setup(None, &CliParam::from(&arguments))
+            .unwrap();
+        // Then
+        assert_eq!(database.port(), 0);
+        assert_eq!(database.bind_addresses(), ["127.0.0.1", "-::1"]);
+        assert_eq!(database.unix_socket().unwrap(), "/tmp/redis.sock");
+        assert_eq!(database.unix_socket_perm(), Some(0o700));
+    }
+
+    #[test]
+    fn test_defaults() {
+        // Given
+        let database = Database::new();
+        // Then
+        assert_eq!(database.port(), 6379);
+        assert_eq!(database.bind_addresses(), ["127.0.0.1"]);
+        assert_eq!(database.unix_socket(), None);
+        assert_eq!(database.unix_socket_perm(), None);
+        assert_eq!(database.databases(), 16);
+        assert_eq!(database.config_get("DBFILENAME").unwrap(), "dump.rdb");
+    }
+
+    #[test]
+    fn test_config_setup_rejects_invalid_values() {
+        // Given
+        let mut database = Database::new();
+        // When
+        let result = database.config_setup(None, &[CliParam::new("port", &["70000".into()])]);
+        // Then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_config_get_matching_returns_all_matches() {
+        // Given
+        let database = Database::new();
+        // When
+        let settings = database.config_get_matching(&["*name".into(), "UNIX*".into()]);
+        // Then
+        assert_eq!(
+            settings,
+            pairs(&[
+                ("appenddirname", "appendonlydir"),
+                ("appendfilename", "appendonly.aof"),
+                ("dbfilename", "dump.rdb"),
+                ("unixsocket", ""),
+                ("unixsocketperm", "0"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_config_set_is_atomic() {
+        // Given
+        let mut database = Database::new();
+        // When
+        database
+            .config_set(&pairs(&[("hz", "50"), ("dbfilename", "other.rdb")]))
+            .unwrap();
+        let invalid_value = database.config_set(&pairs(&[("hz", "20"), ("hz", "30")]));
+        let failed_apply =
+            database.config_set(&pairs(&[("hz", "20"), ("dir", "/missing/directory")]));
+        let immutable = database.config_set(&pairs(&[("hz", "20"), ("port", "1")]));
+        let unknown = database.config_set(&pairs(&[("hz", "20"), ("unknown", "1")]));
+        // Then
+        assert!(invalid_value.unwrap_err().to_string().contains("duplicate"));
+        assert!(failed_apply.unwrap_err().to_string().contains("'dir'"));
+        assert!(immutable.unwrap_err().to_string().contains("immutable"));
+        assert!(unknown.unwrap_err().to_string().starts_with("ERR Unknown"));
+        assert_eq!(database.hz(), 50);
+        assert_eq!(database.dbfilename(), "other.rdb");
+        assert_eq!(database.dir(), ".");
+    }
+
+    #[test]
+    fn test_save_lines_add_save_points() {
+        // Given
+        let mut database = Database::new();
+        let params = [
+            CliParam::new("save", &["900".into(), "1".into()]),
+            CliParam::new("save", &["300 10".into()]),
+        ];
+        let cleared_params = [
+            CliParam::new("save", &["900 1".into()]),
+            CliParam::new("save", &["".into()]),
+        ];
+        // When
+        let default = database.save_points();
+        database.config_setup(None, &params).unwrap();
+        let added = database.save_points();
+        database.config_setup(None, &cleared_params).unwrap();
+        let cleared = database.save_points();
+        let odd_count = database.config_set(&pairs(&[("save", "900")]));
+        // Then
+        assert_eq!(default, vec![(3600, 1), (300, 100), (60, 10000)]);
+        assert_eq!(added, vec![(900, 1), (300, 10)]);
+        assert!(cleared.is_empty());
+        assert!(odd_count.is_err());
+    }
+
+    #[test]
+    fn test_memory_settings_take_units() {
+        // Given
+        let mut database = Database::new();
+        let min_size = "auto-aof-rewrite-min-size";
+        // When
+        let default = database.config_get(min_size).unwrap();
+        database.config_set(&pairs(&[(min_size, "2KB")])).unwrap();
+        let binary_unit = database.config_get(min_size).unwrap();
+        database.config_set(&pairs(&[(min_size, "3m")])).unwrap();
+        let decimal_unit = database.config_get(min_size).unwrap();
+        let invalid_unit = database.config_set(&pairs(&[(min_size, "4tb")]));
+        let negative = database.config_set(&pairs(&[(min_size, "-1")]));
+        // Then
+        assert_eq!(default, "67108864");
+        assert_eq!(binary_unit, "2048");
+        assert_eq!(decimal_unit, "3000000");
+        assert!(invalid_unit
+            .unwrap_err()
+            .to_string()
+            .contains("memory value"));
+        assert!(negative.is_err());
+        assert_eq!(database.auto_aof_rewrite_min_size(), 3_000_000);
+    }
+
+    #[test]
+    fn test_reset_stats() {
+        // Given
+        let mut database = Database::new();
+        database.record_command(false);
+        database.record_command(true);
+        // When
+        database.reset_stats();
+        // Then
+        assert_eq!(database.stats.total_commands_processed, 0);
+        assert_eq!(database.stats.total_error_replies, 0);
+    }
+
+    #[test]
+    fn test_rewrite_keeps_comments_and_replaces_settings() {
+        // Given
+        let contents =
+            "# Server\nport 6380\ninclude other.conf\n\n# Again\nport 6381\nloglevel notice\n";
+        let values = [
+            ("port", "7000"),
+            ("bind", "127.0.0.1 ::1"),
+            ("dir", "/var/lib/my redis"),
+            ("hz", "10"),
+        ];
+        // When
+        let rewritten = rewrite_config(contents, |definition| {
+            values
+                .iter()
+                .find(|(name, _)| *name == definition.name)
+                .map(|(_, value)| value.to_string())
+        });
+        // Then
+        assert_eq!(
+            rewritten,
+            "# Server\nport 7000\ninclude other.conf\n\n# Again\nloglevel notice\n\
+             # Generated by CONFIG REWRITE\ndir \"/var/lib/my redis\"\nbind 127.0.0.1 ::1\n"
+        );
+    }
+
+    #[test]
+    fn test_config_rewrite_writes_the_config_file() {
+        // Given
+        let path = env::temp_dir().join(format!("{}-rewrite.conf", std::process::id()));
+        fs::write(&path, "# Comment\nhz 20\n").unwrap();
+        let mut database = Database::new();
+        database.config_setup(Some(path.clone()), &[]).unwrap();
+        database.config_set(&pairs(&[("hz", "30")])).unwrap();
+        // When
+        database.config_rewrite().unwrap();
+        // Then
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# Comment\nhz 30\n");
+        database
+            .config_set(&pairs(&[("hz", "40"), ("dbfilename", "x.rdb")]))
+            .unwrap();
+        database.config_rewrite().unwrap();
+        database.config_set(&pairs(&[("dir", "/")])).unwrap();
+        database.config_rewrite().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Comment\nhz 40\n# Generated by CONFIG REWRITE\ndbfilename x.rdb\ndir /\n"
+        );
+        assert!(Database::new().config_rewrite().is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
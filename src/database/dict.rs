@@ -0,0 +1,160 @@
+use super::random::Random;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+};
+
+#[cfg(test)]
+mod tests;
+
+const INITIAL_SIZE: usize = 4;
+/// The table shrinks when fewer than this percentage of its buckets is used
+const MIN_FILL_PERCENTAGE: usize = 10;
+
+/// Chained hash table with a power of two number of buckets.
+/// Unlike `HashMap`, it exposes its buckets, so that it can be iterated with a stateless
+/// cursor (see `scan`) and sampled randomly.
+#[derive(Debug)]
+pub struct Dict<V> {
+    buckets: Vec<Vec<(String, V)>>,
+    len: usize,
+    hash_builder: RandomState,
+}
+
+impl<V> Default for Dict<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> Dict<V> {
+    pub fn new() -> Self {
+        Self {
+            buckets: Self::create_buckets(INITIAL_SIZE),
+            len: 0,
+            hash_builder: RandomState::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        let bucket = &self.buckets[self.bucket_index(key)];
+        bucket
+            .iter()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        let bucket_index = self.bucket_index(key);
+        self.buckets[bucket_index]
+            .iter_mut()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Inserts a value, returning the previous one if the key was present
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        if let Some(existing_value) = self.get_mut(&key) {
+            return Some(std::mem::replace(existing_value, value));
+        }
+
+        if self.len >= self.buckets.len() {
+            self.resize(self.buckets.len() * 2);
+        }
+        let bucket_index = self.bucket_index(&key);
+        self.buckets[bucket_index].push((key, value));
+        self.len += 1;
+        None
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let bucket_index = self.bucket_index(key);
+        let bucket = &mut self.buckets[bucket_index];
+        let position = bucket.iter().position(|(entry_key, _)| entry_key == key)?;
+        let (_, value) = bucket.swap_remove(position);
+        self.len -= 1;
+
+        let size = self.buckets.len();
+        if size > INITIAL_SIZE && self.len * 100 / size < MIN_FILL_PERCENTAGE {
+            self.resize(self.len.max(INITIAL_SIZE));
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.buckets
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key, value))
+    }
+
+    /// Returns a random entry, or None if the dict is empty
+    pub fn random_entry(&self, random: &mut Random) -> Option<(&String, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+        let mask = self.buckets.len() - 1;
+        loop {
+            let bucket = &self.buckets[random.next_u64() as usize & mask];
+            if bucket.is_empty() {
+                continue;
+            }
+            let (key, value) = &bucket[random.next_below(bucket.len())];
+            return Some((key, value));
+        }
+    }
+
+    /// Visits the bucket pointed by the cursor, calling `visit` for each of its entries,
+    /// and returns the cursor of the next bucket, or 0 when the iteration is complete.
+    ///
+    /// The cursor is incremented with its bits reversed, so that the buckets already
+    /// visited are not visited again when the table grows, and every entry present
+    /// for the whole iteration is returned at least once even when the table shrinks.
+    pub fn scan<F>(&self, cursor: u64, mut visit: F) -> u64
+    where
+        F: FnMut(&String, &V),
+    {
+        let mask = (self.buckets.len() - 1) as u64;
+        for (key, value) in &self.buckets[(cursor & mask) as usize] {
+            visit(key, value);
+        }
+
+        // Sets the unmasked bits so that incrementing the reversed cursor
+        // operates on the masked bits only
+        let mut cursor = cursor | !mask;
+        cursor = cursor.reverse_bits();
+        cursor = cursor.wrapping_add(1);
+        cursor.reverse_bits()
+    }
+
+    fn bucket_index(&self, key: &str) -> usize {
+        let mut hasher = self.hash_builder.build_hasher();
+        key.hash(&mut hasher);
+        hasher.finish() as usize & (self.buckets.len() - 1)
+    }
+
+    fn resize(&mut self, size: usize) {
+        let size = size.next_power_of_two().max(INITIAL_SIZE);
+        let old_buckets = std::mem::replace(&mut self.buckets, Self::create_buckets(size));
+        for (key, value) in old_buckets.into_iter().flatten() {
+            let bucket_index = self.bucket_index(&key);
+            self.buckets[bucket_index].push((key, value));
+        }
+    }
+
+    fn create_buckets(size: usize) -> Vec<Vec<(String, V)>> {
+        (0..size).map(|_| Vec::new()).collect()
+    }
+}
@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod test {
+    use crate::database::{dict::Dict, random::Random};
+    use std::collections::HashSet;
+
+    fn scan_all(dict: &Dict<usize>, cursor: u64) -> (HashSet<String>, u64) {
+        let mut keys = HashSet::new();
+        let cursor = dict.scan(cursor, |key, _| {
+            keys.insert(key.clone());
+        });
+        (keys, cursor)
+    }
+
+    #[test]
+    fn test_insert_get_remove() {
+        // Given
+        let mut dict = Dict::new();
+        for index in 0..1000 {
+            assert_eq!(dict.insert(format!("key:{index}"), index), None);
+        }
+        // Then
+        assert_eq!(dict.len(), 1000);
+        assert_eq!(dict.get("key:42"), Some(&42));
+        assert_eq!(dict.insert("key:42".into(), 0), Some(42));
+        assert_eq!(dict.remove("key:42"), Some(0));
+        assert_eq!(dict.remove("key:42"), None);
+        assert_eq!(dict.len(), 999);
+
+        // When
+        for index in 0..1000 {
+            dict.remove(&format!("key:{index}"));
+        }
+        // Then
+        assert!(dict.is_empty());
+        assert_eq!(dict.buckets.len(), 4);
+    }
+
+    #[test]
+    fn test_scan_returns_every_key() {
+        // Given
+        let mut dict = Dict::new();
+        for index in 0..500 {
+            dict.insert(format!("key:{index}"), index);
+        }
+        // When
+        let mut keys = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (scanned_keys, next_cursor) = scan_all(&dict, cursor);
+            keys.extend(scanned_keys);
+            cursor = next_cursor;
+            if cursor == 0 {
+                break;
+            }
+        }
+        // Then
+        assert_eq!(keys.len(), 500);
+    }
+
+    #[test]
+    fn test_scan_returns_stable_keys_while_resizing() {
+        // Given
+        let mut dict = Dict::new();
+        for index in 0..200 {
+            dict.insert(format!("stable:{index}"), index);
+        }
+        // When
+        let mut keys = HashSet::new();
+        let mut cursor = 0;
+        let mut step = 0;
+        loop {
+            let (scanned_keys, next_cursor) = scan_all(&dict, cursor);
+            keys.extend(scanned_keys);
+            cursor = next_cursor;
+            if cursor == 0 {
+                break;
+            }
+
+            // Grows the table in the first half of the iteration, then shrinks it
+            step += 1;
+            if step < 40 {
+                for index in 0..50 {
+                    dict.insert(format!("volatile:{step}:{index}"), index);
+                }
+            } else {
+                let volatile_keys: Vec<String> = dict
+                    .iter()
+                    .map(|(key, _)| key)
+                    .filter(|key| key.starts_with("volatile"))
+                    .take(500)
+                    .cloned()
+                    .collect();
+                for key in volatile_keys {
+                    dict.remove(&key);
+                }
+            }
+        }
+        // Then
+        for index in 0..200 {
+            assert!(keys.contains(&format!("stable:{index}")));
+        }
+    }
+
+    #[test]
+    fn test_random_entry() {
+        // Given
+        let mut dict = Dict::new();
+        let mut random = Random::new();
+        // Then
+        assert!(dict.random_entry(&mut random).is_none());
+
+        // When
+        dict.insert("key".into(), 1);
+        // Then
+        assert_eq!(dict.random_entry(&mut random), Some((&"key".into(), &1)));
+    }
+}
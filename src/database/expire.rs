@@ -0,0 +1,176 @@
+use super::{get_current_time_ms, keyspace::Keyspace, Database};
+use std::time::{Duration, Instant};
+
+pub const TTL_NOT_EXISTING: i128 = -2;
+pub const TTL_NOT_EXPIRING: i128 = -1;
+
+/// Keys with an expiry sampled in each iteration of the active expire cycle
+const ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP: usize = 20;
+/// The cycle keeps going while more than this percentage of the sampled keys is expired
+const ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpireCondition {
+    Nx,
+    Xx,
+    Gt,
+    Lt,
+}
+
+impl ExpireCondition {
+    fn is_satisfied(&self, current: Option<u128>, new: i128) -> bool {
+        match (self, current) {
+            (Self::Nx, current) => current.is_none(),
+            (Self::Xx, current) => current.is_some(),
+            // A key without expiry has an infinite TTL
+            (Self::Gt, None) => false,
+            (Self::Gt, Some(current)) => new > current as i128,
+            (Self::Lt, None) => true,
+            (Self::Lt, Some(current)) => new < current as i128,
+        }
+    }
+}
+
+impl Keyspace {
+    /// Sets the absolute expiry (unix time in ms) of a key.
+    /// Returns true if the expiry was set, or the key deleted because the time is in the past.
+    pub fn expire(
+        &mut self,
+        key: String,
+        expires_at: i128,
+        conditions: &[ExpireCondition],
+    ) -> anyhow::Result<bool> {
+        self.delete_if_expired(&key)?;
+        let Some(entry) = self.data.get_mut(&key) else {
+            return Ok(false);
+        };
+
+        let conditions_satisfied = conditions
+            .iter()
+            .all(|condition| condition.is_satisfied(entry.expires_at, expires_at));
+        if !conditions_satisfied {
+            return Ok(false);
+        }
+
+        let now = get_current_time_ms()?;
+        if expires_at <= now as i128 && !self.is_loading {
+            self.delete(key)?;
+            return Ok(true);
+        }
+
+        // While loading, a negative time is kept as already expired instead of wrapping
+        entry.expires_at = Some(expires_at.max(0) as u128);
+        self.expires.insert(&key);
+        Ok(true)
+    }
+
+    /// Removes the expiry of a key. Returns true if the key had one.
+    pub fn persist(&mut self, key: String) -> anyhow::Result<bool> {
+        self.delete_if_expired(&key)?;
+        let Some(entry) = self.data.get_mut(&key) else {
+            return Ok(false);
+        };
+        let had_expiry = entry.expires_at.take().is_some();
+        self.expires.remove(&key);
+        Ok(had_expiry)
+    }
+
+    /// Returns the remaining time to live of a key in ms,
+    /// or TTL_NOT_EXISTING / TTL_NOT_EXPIRING.
+    pub fn ttl(&mut self, key: String) -> anyhow::Result<i128> {
+        let expire_time = self.expire_time(key)?;
+        if expire_time < 0 {
+            return Ok(expire_time);
+        }
+        let now = get_current_time_ms()?;
+        Ok((expire_time - now as i128).max(0))
+    }
+
+    /// Returns the absolute expiry (unix time in ms) of a key,
+    /// or TTL_NOT_EXISTING / TTL_NOT_EXPIRING.
+    pub fn expire_time(&mut self, key: String) -> anyhow::Result<i128> {
+        self.delete_if_expired(&key)?;
+        let Some(entry) = self.data.get(&key) else {
+            return Ok(TTL_NOT_EXISTING);
+        };
+        match entry.expires_at {
+            Some(expires_at) => Ok(expires_at as i128),
+            None => Ok(TTL_NOT_EXPIRING),
+        }
+    }
+
+    /// Deletes the key if it is expired. Returns true if it was deleted.
+    pub fn delete_if_expired(&mut self, key: &str) -> anyhow::Result<bool> {
+        let Some(entry) = self.data.get(key) else {
+            return Ok(false);
+        };
+        if self.is_loading || !entry.is_expired(get_current_time_ms()?) {
+            return Ok(false);
+        }
+
+        self.delete(key.to_string())?;
+        self.expired_keys.push(key.to_string());
+        Ok(true)
+    }
+}
+
+impl Keyspace {
+    /// Samples keys with an expiry and deletes the expired ones, repeating while many
+    /// of the sampled keys were expired and the time limit is not reached.
+    /// Returns the number of deleted keys.
+    pub fn active_expire_cycle(&mut self, time_limit: Duration) -> anyhow::Result<usize> {
+        let started_at = Instant::now();
+        let mut expired_count = 0;
+
+        loop {
+            let sample_size = self.expires.len().min(ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP);
+            if sample_size == 0 {
+                break;
+            }
+
+            let mut sample_expired_count = 0;
+            for _ in 0..sample_size {
+                let position = self.random.next_below(self.expires.len());
+                let Some(key) = self.expires.get(position).cloned() else {
+                    continue;
+                };
+                if self.delete_if_expired(&key)? {
+                    sample_expired_count += 1;
+                }
+                if self.expires.is_empty() {
+                    break;
+                }
+            }
+            expired_count += sample_expired_count;
+
+            let stale_percentage = sample_expired_count * 100 / sample_size;
+            if stale_percentage <= ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE
+                || started_at.elapsed() >= time_limit
+            {
+                break;
+            }
+        }
+
+        Ok(expired_count)
+    }
+}
+
+impl Database {
+    /// Runs the active expire cycle on every keyspace within the time limit.
+    /// Returns the number of deleted keys. Replicas leave the expired keys to the
+    /// deletions of their master.
+    pub fn active_expire_cycle(&mut self, time_limit: Duration) -> anyhow::Result<usize> {
+        if self.is_replica() {
+            return Ok(0);
+        }
+        let started_at = Instant::now();
+        let mut expired_count = 0;
+        for keyspace in self.keyspaces.iter_mut() {
+            let Some(remaining_time) = time_limit.checked_sub(started_at.elapsed()) else {
+                break;
+            };
+            expired_count += keyspace.active_expire_cycle(remaining_time)?;
+        }
+        Ok(expired_count)
+    }
+}
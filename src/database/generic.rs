@@ -0,0 +1,155 @@
+use super::{get_current_time_ms, keyspace::Keyspace, Database};
+
+impl Keyspace {
+    /// Deletes the keys, returning how many of them existed
+    pub fn delete_keys(&mut self, keys: &[String]) -> anyhow::Result<usize> {
+        let mut deleted_count = 0;
+        for key in keys {
+            self.delete_if_expired(key)?;
+            if self.delete(key.clone())? {
+                deleted_count += 1;
+            }
+        }
+        Ok(deleted_count)
+    }
+
+    /// Returns how many of the keys exist. Keys mentioned multiple times are counted
+    /// multiple times.
+    pub fn exists(&mut self, keys: &[String]) -> anyhow::Result<usize> {
+        let mut existing_count = 0;
+        for key in keys {
+            self.delete_if_expired(key)?;
+            if self.data.contains_key(key) {
+                existing_count += 1;
+            }
+        }
+        Ok(existing_count)
+    }
+
+    pub fn type_name(&mut self, key: &str) -> anyhow::Result<Option<&'static str>> {
+        self.delete_if_expired(key)?;
+        Ok(self.data.get(key).map(|entry| entry.type_name()))
+    }
+
+    /// Renames the key, keeping its expiry. With only_if_new, the rename happens only
+    /// if new_key does not exist. Returns true if the key was renamed.
+    pub fn rename(
+        &mut self,
+        key: String,
+        new_key: String,
+        only_if_new: bool,
+    ) -> anyhow::Result<bool> {
+        self.delete_if_expired(&key)?;
+        if !self.data.contains_key(&key) {
+            anyhow::bail!("ERR no such key")
+        }
+        if key == new_key {
+            return Ok(!only_if_new);
+        }
+
+        self.delete_if_expired(&new_key)?;
+        if only_if_new && self.data.contains_key(&new_key) {
+            return Ok(false);
+        }
+
+        let Some(entry) = self.data.remove(&key) else {
+            return Ok(false);
+        };
+        self.expires.remove(&key);
+        self.insert_entry(new_key, entry);
+        Ok(true)
+    }
+
+    /// Returns how many of the keys exist. Access times are not tracked, so touching
+    /// a key only checks its existence.
+    pub fn touch(&mut self, keys: &[String]) -> anyhow::Result<usize> {
+        self.exists(keys)
+    }
+
+    /// Returns a random key that is not expired, or None if the database is empty
+    pub fn random_key(&mut self) -> anyhow::Result<Option<String>> {
+        let now = get_current_time_ms()?;
+        loop {
+            let Some((key, entry)) = self.data.random_entry(&mut self.random) else {
+                return Ok(None);
+            };
+            if !entry.is_expired(now) {
+                return Ok(Some(key.clone()));
+            }
+
+            let key = key.clone();
+            self.delete(key.clone())?;
+            self.expired_keys.push(key);
+        }
+    }
+}
+
+impl Database {
+    /// Copies the value of source in the db to destination in the destination db,
+    /// keeping its expiry. Returns true if the value was copied.
+    pub fn copy(
+        &mut self,
+        db: usize,
+        source: String,
+        destination: String,
+        destination_db: usize,
+        replace: bool,
+    ) -> anyhow::Result<bool> {
+        if db == destination_db && source == destination {
+            anyhow::bail!("ERR source and destination objects are the same")
+        }
+
+        let keyspace = self.keyspace(db);
+        keyspace.delete_if_expired(&source)?;
+        let Some(entry) = keyspace.data.get(&source).cloned() else {
+            return Ok(false);
+        };
+
+        let destination_keyspace = self.keyspace(destination_db);
+        destination_keyspace.delete_if_expired(&destination)?;
+        if !replace && destination_keyspace.data.contains_key(&destination) {
+            return Ok(false);
+        }
+
+        destination_keyspace.insert_entry(destination, entry);
+        Ok(true)
+    }
+
+    /// Moves the key from the db to the destination db, unless it exists there already.
+    /// Returns true if the key was moved.
+    pub fn move_key(
+        &mut self,
+        db: usize,
+        key: String,
+        destination_db: usize,
+    ) -> anyhow::Result<bool> {
+        if db == destination_db {
+            anyhow::bail!("ERR source and destination objects are the same")
+        }
+
+        let destination_keyspace = self.keyspace(destination_db);
+        destination_keyspace.delete_if_expired(&key)?;
+        if destination_keyspace.data.contains_key(&key) {
+            return Ok(false);
+        }
+
+        let keyspace = self.keyspace(db);
+        keyspace.delete_if_expired(&key)?;
+        let Some(entry) = keyspace.data.remove(&key) else {
+            return Ok(false);
+        };
+        keyspace.expires.remove(&key);
+
+        self.keyspace(destination_db).insert_entry(key, entry);
+        Ok(true)
+    }
+
+    /// Atomically swaps the content of two dbs
+    pub fn swap_db(&mut self, db: usize, other_db: usize) {
+        self.keyspaces.swap(db, other_db);
+    }
+
+    pub fn flush_all(&mut self) {
+        self.keyspaces.iter_mut().for_each(Keyspace::flush);
+    }
+}
@@ -0,0 +1,548 @@
+use super::{keyspace::Keyspace, string::Value, Entry};
+
+// Layout of the Redis HYLL string: a 16 bytes header followed by the registers.
+// The header holds the "HYLL" magic, the encoding, 3 unused bytes and the cached
+// cardinality, little endian, where the most significant bit flags it as invalid.
+const MAGIC: &[u8] = b"HYLL";
+const HEADER_SIZE: usize = 16;
+const ENCODING_POSITION: usize = 4;
+const CARDINALITY_POSITION: usize = 8;
+const ENCODING_DENSE: u8 = 0;
+const ENCODING_SPARSE: u8 = 1;
+
+/// Bits of the hash used to select the register
+const P: u32 = 14;
+/// Bits of the hash used to count the leading zeros
+const Q: u32 = 64 - P;
+const REGISTERS: usize = 1 << P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+const DENSE_SIZE: usize = HEADER_SIZE + (REGISTERS * REGISTER_BITS + 7) / 8;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+const HASH_SEED: u64 = 0xadc8_3b19;
+
+// Sparse opcodes: ZERO is 00xxxxxx, a run of up to 64 zero registers, XZERO is
+// 01xxxxxx yyyyyyyy, a run of up to 16384 zero registers (all of them), and VAL is 1vvvvvxx, a run
+// of up to 4 registers set to a value up to 32.
+const SPARSE_ZERO_MAX_LENGTH: usize = 64;
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LENGTH: usize = 4;
+/// Size above which a sparse HyperLogLog is converted to dense (hll-sparse-max-bytes)
+const SPARSE_MAX_BYTES: usize = 3000;
+
+const WRONG_TYPE_ERROR: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+const CORRUPTED_ERROR: &str = "INVALIDOBJ Corrupted HLL object detected";
+
+impl Keyspace {
+    /// Adds the elements to the HyperLogLog at key, creating it if needed.
+    /// Returns true if the key was created or a register was updated.
+    pub fn pf_add(&mut self, key: String, elements: &[Vec<u8>]) -> anyhow::Result<bool> {
+        self.delete_if_expired(&key)?;
+        let mut is_updated = false;
+        if !self.data.contains_key(&key) {
+            let entry = Entry {
+                value: Value::Raw(create_hll()),
+                expires_at: None,
+            };
+            self.insert_entry(key.clone(), entry);
+            is_updated = true;
+        }
+
+        let hll = self.hll_mut(&key)?;
+        for element in elements {
+            let (index, count) = hash_element(element);
+            if set_register(hll, index, count)? {
+                is_updated = true;
+            }
+        }
+        if is_updated {
+            invalidate_cache(hll);
+        }
+        Ok(is_updated)
+    }
+
+    /// Returns the approximated cardinality of the union of the HyperLogLogs.
+    /// The cardinality of a single key is cached in its header.
+    pub fn pf_count(&mut self, keys: &[String]) -> anyhow::Result<u64> {
+        if let [key] = keys {
+            self.delete_if_expired(key)?;
+            if !self.data.contains_key(key) {
+                return Ok(0);
+            }
+            let hll = self.hll_mut(key)?;
+            if let Some(cardinality) = cached_cardinality(hll) {
+                return Ok(cardinality);
+            }
+            let cardinality = estimate_cardinality(&histogram(hll)?);
+            hll[CARDINALITY_POSITION..HEADER_SIZE].copy_from_slice(&cardinality.to_le_bytes());
+            return Ok(cardinality);
+        }
+
+        let mut registers = vec![0; REGISTERS];
+        for key in keys {
+            if let Some(hll) = self.get(key.clone())? {
+                validate_hll(&hll)?;
+                merge_registers(&mut registers, &hll)?;
+            }
+        }
+        Ok(estimate_cardinality(&registers_histogram(&registers)))
+    }
+
+    /// Merges the HyperLogLogs of the keys and the destination into the destination.
+    /// The destination is dense if any of the HyperLogLogs is.
+    pub fn pf_merge(&mut self, destination: String, keys: &[String]) -> anyhow::Result<()> {
+        let mut registers = vec![0; REGISTERS];
+        let mut is_dense = false;
+        for key in std::iter::once(&destination).chain(keys) {
+            if let Some(hll) = self.get(key.clone())? {
+                validate_hll(&hll)?;
+                is_dense |= hll[ENCODING_POSITION] == ENCODING_DENSE;
+                merge_registers(&mut registers, &hll)?;
+            }
+        }
+
+        if !self.data.contains_key(&destination) {
+            let entry = Entry {
+                value: Value::Raw(create_hll()),
+                expires_at: None,
+            };
+            self.insert_entry(destination.clone(), entry);
+        }
+        let hll = self.hll_mut(&destination)?;
+        if is_dense {
+            sparse_to_dense(hll)?;
+        }
+        for (index, count) in registers.into_iter().enumerate() {
+            if count != 0 {
+                set_register(hll, index, count)?;
+            }
+        }
+        invalidate_cache(hll);
+        Ok(())
+    }
+
+    fn hll_mut(&mut self, key: &str) -> anyhow::Result<&mut Vec<u8>> {
+        let Some(entry) = self.data.get_mut(key) else {
+            anyhow::bail!("Failed to get key {key}")
+        };
+        let hll = entry.value.raw_mut();
+        validate_hll(hll)?;
+        Ok(hll)
+    }
+}
+
+/// Creates an empty sparse HyperLogLog, with a single XZERO opcode for all registers
+fn create_hll() -> Vec<u8> {
+    let mut hll = MAGIC.to_vec();
+    hll.resize(HEADER_SIZE, 0);
+    hll[ENCODING_POSITION] = ENCODING_SPARSE;
+    hll.extend(xzero_opcode(REGISTERS));
+    hll
+}
+
+fn validate_hll(hll: &[u8]) -> anyhow::Result<()> {
+    let is_valid = hll.len() >= HEADER_SIZE
+        && hll.starts_with(MAGIC)
+        && match hll[ENCODING_POSITION] {
+            ENCODING_DENSE => hll.len() == DENSE_SIZE,
+            ENCODING_SPARSE => true,
+            _ => false,
+        };
+    if !is_valid {
+        anyhow::bail!(WRONG_TYPE_ERROR)
+    }
+    Ok(())
+}
+
+fn cached_cardinality(hll: &[u8]) -> Option<u64> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hll[CARDINALITY_POSITION..HEADER_SIZE]);
+    let cardinality = u64::from_le_bytes(bytes);
+    (cardinality >> 63 == 0).then_some(cardinality)
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[HEADER_SIZE - 1] |= 0x80;
+}
+
+/// Returns the register selected by the hash of the element, and the count of
+/// leading zeros plus one of the remaining bits
+fn hash_element(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash_64a(element, HASH_SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // Sets a bit after the Q bits so the count stops at Q + 1
+    let hash = (hash >> P) | (1 << Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+/// MurmurHash2, 64 bit version, reading the blocks as little endian as Redis does
+fn murmur_hash_64a(bytes: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut hash = seed ^ (bytes.len() as u64).wrapping_mul(M);
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut block = [0; 8];
+        block.copy_from_slice(chunk);
+        let mut k = u64::from_le_bytes(block);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        hash ^= k;
+        hash = hash.wrapping_mul(M);
+    }
+
+    let remainder = chunks.remainder();
+    if !remainder.is_empty() {
+        for (index, byte) in remainder.iter().enumerate() {
+            hash ^= (*byte as u64) << (8 * index);
+        }
+        hash = hash.wrapping_mul(M);
+    }
+
+    hash ^= hash >> R;
+    hash = hash.wrapping_mul(M);
+    hash ^= hash >> R;
+    hash
+}
+
+/// Sets the register to the count if it is greater. Returns true if it was updated.
+fn set_register(hll: &mut Vec<u8>, index: usize, count: u8) -> anyhow::Result<bool> {
+    if hll[ENCODING_POSITION] == ENCODING_SPARSE {
+        return sparse_set(hll, index, count);
+    }
+    Ok(dense_set(&mut hll[HEADER_SIZE..], index, count))
+}
+
+// Dense registers are packed as 6 bits integers, starting from the least significant
+// bits of each byte
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let byte = index * REGISTER_BITS / 8;
+    let shift = index * REGISTER_BITS % 8;
+    let low = (registers[byte] >> shift) as u16;
+    let high = registers
+        .get(byte + 1)
+        .map_or(0, |byte| (*byte as u16) << (8 - shift));
+    ((low | high) & REGISTER_MAX as u16) as u8
+}
+
+fn dense_set(registers: &mut [u8], index: usize, count: u8) -> bool {
+    if count <= dense_get(registers, index) {
+        return false;
+    }
+
+    let byte = index * REGISTER_BITS / 8;
+    let shift = index * REGISTER_BITS % 8;
+    registers[byte] &= !(REGISTER_MAX << shift);
+    registers[byte] |= count << shift;
+    if let Some(next_byte) = registers.get_mut(byte + 1) {
+        let shift = 8 - shift as u32;
+        *next_byte &= !REGISTER_MAX.checked_shr(shift).unwrap_or(0);
+        *next_byte |= count.checked_shr(shift).unwrap_or(0);
+    }
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SparseOpcode {
+    Zero(usize),
+    Xzero(usize),
+    Val { value: u8, length: usize },
+}
+
+impl SparseOpcode {
+    fn read(bytes: &[u8]) -> anyhow::Result<Self> {
+        let byte = bytes[0];
+        let opcode = match byte & 0xc0 {
+            0x00 => SparseOpcode::Zero((byte & 0x3f) as usize + 1),
+            0x40 => {
+                let Some(next_byte) = bytes.get(1) else {
+                    anyhow::bail!(CORRUPTED_ERROR)
+                };
+                SparseOpcode::Xzero((((byte & 0x3f) as usize) << 8 | *next_byte as usize) + 1)
+            }
+            _ => SparseOpcode::Val {
+                value: ((byte >> 2) & 0x1f) + 1,
+                length: (byte & 0x03) as usize + 1,
+            },
+        };
+        Ok(opcode)
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            SparseOpcode::Xzero(_) => 2,
+            _ => 1,
+        }
+    }
+
+    fn length(&self) -> usize {
+        match *self {
+            SparseOpcode::Zero(length) | SparseOpcode::Xzero(length) => length,
+            SparseOpcode::Val { length, .. } => length,
+        }
+    }
+}
+
+fn zero_opcode(length: usize) -> Vec<u8> {
+    match length > SPARSE_ZERO_MAX_LENGTH {
+        true => xzero_opcode(length).to_vec(),
+        false => vec![(length - 1) as u8],
+    }
+}
+
+fn xzero_opcode(length: usize) -> [u8; 2] {
+    let length = length - 1;
+    [(length >> 8) as u8 | 0x40, (length & 0xff) as u8]
+}
+
+fn val_opcode(value: u8, length: usize) -> u8 {
+    ((value - 1) << 2) | (length - 1) as u8 | 0x80
+}
+
+/// Reads the sparse opcodes, checking that they cover exactly all the registers
+fn sparse_opcodes(hll: &[u8]) -> anyhow::Result<Vec<SparseOpcode>> {
+    let mut opcodes = Vec::new();
+    let mut position = HEADER_SIZE;
+    let mut register_count = 0;
+    while position < hll.len() {
+        let opcode = SparseOpcode::read(&hll[position..])?;
+        register_count += opcode.length();
+        if register_count > REGISTERS {
+            anyhow::bail!(CORRUPTED_ERROR)
+        }
+        position += opcode.size();
+        opcodes.push(opcode);
+    }
+    if register_count != REGISTERS {
+        anyhow::bail!(CORRUPTED_ERROR)
+    }
+    Ok(opcodes)
+}
+
+/// Updates a sparse register in place, splitting the opcode that covers it and merging
+/// the adjacent VAL opcodes afterwards, exactly as Redis does so both produce the same
+/// bytes. Converts to dense when the value or the size is too big for the sparse
+/// encoding.
+fn sparse_set(hll: &mut Vec<u8>, index: usize, count: u8) -> anyhow::Result<bool> {
+    if count > SPARSE_VAL_MAX_VALUE {
+        return promote_and_set(hll, index, count);
+    }
+
+    // Finds the opcode covering the register
+    let mut position = HEADER_SIZE;
+    let mut previous_position = None;
+    let mut first = 0;
+    let opcode = loop {
+        if position >= hll.len() {
+            anyhow::bail!(CORRUPTED_ERROR)
+        }
+        let opcode = SparseOpcode::read(&hll[position..])?;
+        if index < first + opcode.length() {
+            break opcode;
+        }
+        previous_position = Some(position);
+        position += opcode.size();
+        first += opcode.length();
+    };
+    let last = first + opcode.length() - 1;
+
+    match opcode {
+        SparseOpcode::Val { value, .. } if value >= count => return Ok(false),
+        SparseOpcode::Val { length: 1, .. } | SparseOpcode::Zero(1) => {
+            hll[position] = val_opcode(count, 1);
+        }
+        _ => {
+            let mut sequence = Vec::with_capacity(5);
+            match opcode {
+                SparseOpcode::Val { value, .. } => {
+                    if index != first {
+                        sequence.push(val_opcode(value, index - first));
+                    }
+                    sequence.push(val_opcode(count, 1));
+                    if index != last {
+                        sequence.push(val_opcode(value, last - index));
+                    }
+                }
+                _ => {
+                    if index != first {
+                        sequence.extend(zero_opcode(index - first));
+                    }
+                    sequence.push(val_opcode(count, 1));
+                    if index != last {
+                        sequence.extend(zero_opcode(last - index));
+                    }
+                }
+            }
+
+            if sequence.len() > opcode.size()
+                && hll.len() + sequence.len() - opcode.size() > SPARSE_MAX_BYTES
+            {
+                return promote_and_set(hll, index, count);
+            }
+            hll.splice(position..position + opcode.size(), sequence);
+        }
+    }
+
+    // Merges adjacent VAL opcodes with the same value, scanning up to 5 opcodes
+    // starting from the one before the update
+    let mut position = previous_position.unwrap_or(HEADER_SIZE);
+    let mut scan_count = 5;
+    while position < hll.len() && scan_count > 0 {
+        scan_count -= 1;
+        let opcode = SparseOpcode::read(&hll[position..])?;
+        let SparseOpcode::Val { value, length } = opcode else {
+            position += opcode.size();
+            continue;
+        };
+        let next_opcode = match hll.get(position + 1) {
+            Some(next_byte) if next_byte & 0x80 != 0 => Some(SparseOpcode::read(&[*next_byte])?),
+            _ => None,
+        };
+        if let Some(SparseOpcode::Val {
+            value: next_value,
+            length: next_length,
+        }) = next_opcode
+        {
+            let merged_length = length + next_length;
+            if value == next_value && merged_length <= SPARSE_VAL_MAX_LENGTH {
+                hll[position + 1] = val_opcode(value, merged_length);
+                hll.remove(position);
+                // Tries to merge the merged opcode with the following one
+                continue;
+            }
+        }
+        position += 1;
+    }
+    Ok(true)
+}
+
+fn promote_and_set(hll: &mut Vec<u8>, index: usize, count: u8) -> anyhow::Result<bool> {
+    sparse_to_dense(hll)?;
+    Ok(dense_set(&mut hll[HEADER_SIZE..], index, count))
+}
+
+/// Converts a sparse HyperLogLog to dense, keeping the header
+fn sparse_to_dense(hll: &mut Vec<u8>) -> anyhow::Result<()> {
+    if hll[ENCODING_POSITION] == ENCODING_DENSE {
+        return Ok(());
+    }
+
+    let mut dense = hll[..HEADER_SIZE].to_vec();
+    dense[ENCODING_POSITION] = ENCODING_DENSE;
+    dense.resize(DENSE_SIZE, 0);
+    let mut index = 0;
+    for opcode in sparse_opcodes(hll)? {
+        if let SparseOpcode::Val { value, length } = opcode {
+            for register in index..index + length {
+                dense_set(&mut dense[HEADER_SIZE..], register, value);
+            }
+        }
+        index += opcode.length();
+    }
+    *hll = dense;
+    Ok(())
+}
+
+/// Sets each register to the max between itself and the one of the HyperLogLog
+fn merge_registers(registers: &mut [u8], hll: &[u8]) -> anyhow::Result<()> {
+    if hll[ENCODING_POSITION] == ENCODING_DENSE {
+        for (index, register) in registers.iter_mut().enumerate() {
+            *register = (*register).max(dense_get(&hll[HEADER_SIZE..], index));
+        }
+        return Ok(());
+    }
+
+    let mut index = 0;
+    for opcode in sparse_opcodes(hll)? {
+        if let SparseOpcode::Val { value, length } = opcode {
+            for register in &mut registers[index..index + length] {
+                *register = (*register).max(value);
+            }
+        }
+        index += opcode.length();
+    }
+    Ok(())
+}
+
+/// Counts how many registers hold each value
+fn histogram(hll: &[u8]) -> anyhow::Result<[u32; 64]> {
+    if hll[ENCODING_POSITION] == ENCODING_DENSE {
+        let registers: Vec<u8> = (0..REGISTERS)
+            .map(|index| dense_get(&hll[HEADER_SIZE..], index))
+            .collect();
+        return Ok(registers_histogram(&registers));
+    }
+
+    let mut histogram = [0; 64];
+    for opcode in sparse_opcodes(hll)? {
+        match opcode {
+            SparseOpcode::Zero(length) | SparseOpcode::Xzero(length) => {
+                histogram[0] += length as u32
+            }
+            SparseOpcode::Val { value, length } => histogram[value as usize] += length as u32,
+        }
+    }
+    Ok(histogram)
+}
+
+fn registers_histogram(registers: &[u8]) -> [u32; 64] {
+    let mut histogram = [0; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+    histogram
+}
+
+/// Estimates the cardinality from the registers histogram with the improved estimator
+/// of Otmar Ertl, as Redis does
+fn estimate_cardinality(histogram: &[u32; 64]) -> u64 {
+    let m = REGISTERS as f64;
+    let q = Q as usize;
+    let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+    for count in histogram[1..=q].iter().rev() {
+        z += *count as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous_z = z;
+        z += x * y;
+        y += y;
+        if previous_z == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous_z = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous_z == z {
+            return z / 3.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
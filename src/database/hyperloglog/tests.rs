@@ -0,0 +1,140 @@
+#[cfg(test)]
+mod test {
+    use crate::database::{
+        hyperloglog::{
+            dense_get, sparse_opcodes, sparse_set, SparseOpcode, DENSE_SIZE, ENCODING_DENSE,
+            ENCODING_POSITION, HEADER_SIZE, REGISTERS,
+        },
+        keyspace::Keyspace,
+    };
+
+    fn elements(prefix: &str, count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|index| format!("{prefix}:{index}").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_pf_add_creates_empty_sparse_hyperloglog() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        // When
+        let is_updated = keyspace.pf_add("hll".into(), &[]).unwrap();
+        // Then
+        assert!(is_updated);
+        assert_eq!(
+            keyspace.get("hll".into()).unwrap().unwrap(),
+            b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\x80\x7f\xff"
+        );
+        assert!(!keyspace.pf_add("hll".into(), &[]).unwrap());
+        assert_eq!(keyspace.pf_count(&["hll".into()]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_pf_count_is_exact_for_small_sets_and_cached() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        let elements: Vec<Vec<u8>> = ["a", "b", "c", "d", "e", "f", "g"]
+            .iter()
+            .map(|element| element.as_bytes().to_vec())
+            .collect();
+        // When
+        let is_updated = keyspace.pf_add("hll".into(), &elements).unwrap();
+        let is_updated_again = keyspace.pf_add("hll".into(), &elements[..3]).unwrap();
+        // Then
+        assert!(is_updated);
+        assert!(!is_updated_again);
+        assert_eq!(keyspace.pf_count(&["hll".into()]).unwrap(), 7);
+        let hll = keyspace.get("hll".into()).unwrap().unwrap();
+        assert_eq!(hll[8..16], [7, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_pf_add_promotes_to_dense_with_small_error() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        // When
+        for chunk in elements("element", 100_000).chunks(1000) {
+            keyspace.pf_add("hll".into(), chunk).unwrap();
+        }
+        // Then
+        let hll = keyspace.get("hll".into()).unwrap().unwrap();
+        assert_eq!(hll[ENCODING_POSITION], ENCODING_DENSE);
+        assert_eq!(hll.len(), DENSE_SIZE);
+        let count = keyspace.pf_count(&["hll".into()]).unwrap() as f64;
+        assert!((count - 100_000.0).abs() / 100_000.0 < 0.02);
+    }
+
+    #[test]
+    fn test_pf_merge_and_multiple_key_count_give_the_union() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .pf_add("small".into(), &elements("a", 100))
+            .unwrap();
+        keyspace
+            .pf_add("large".into(), &elements("b", 20_000))
+            .unwrap();
+        keyspace
+            .pf_add("overlap".into(), &elements("a", 50))
+            .unwrap();
+        let keys = ["small".into(), "large".into(), "overlap".into()];
+        // When
+        let union_count = keyspace.pf_count(&keys).unwrap();
+        keyspace.pf_merge("merged".into(), &keys).unwrap();
+        // Then
+        let merged_count = keyspace.pf_count(&["merged".into()]).unwrap();
+        assert_eq!(union_count, merged_count);
+        assert!((union_count as f64 - 20_100.0).abs() / 20_100.0 < 0.02);
+        let merged = keyspace.get("merged".into()).unwrap().unwrap();
+        assert_eq!(merged[ENCODING_POSITION], ENCODING_DENSE);
+        let large = keyspace.get("large".into()).unwrap().unwrap();
+        for index in (0..REGISTERS).step_by(97) {
+            assert!(
+                dense_get(&merged[HEADER_SIZE..], index) >= dense_get(&large[HEADER_SIZE..], index)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sparse_set_splits_and_merges_opcodes_like_redis() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.pf_add("hll".into(), &[]).unwrap();
+        let mut hll = keyspace.get("hll".into()).unwrap().unwrap();
+        // When
+        for index in (0..5).rev() {
+            sparse_set(&mut hll, index, 1).unwrap();
+        }
+        // Then
+        assert_eq!(
+            sparse_opcodes(&hll).unwrap(),
+            vec![
+                SparseOpcode::Val {
+                    value: 1,
+                    length: 1
+                },
+                SparseOpcode::Val {
+                    value: 1,
+                    length: 4
+                },
+                SparseOpcode::Xzero(REGISTERS - 5),
+            ]
+        );
+        assert!(!sparse_set(&mut hll, 2, 1).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_hyperloglog_is_wrong_type() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("string".into(), "value".into(), None).unwrap();
+        // Then
+        assert!(keyspace
+            .pf_add("string".into(), &[])
+            .unwrap_err()
+            .to_string()
+            .starts_with("WRONGTYPE"));
+        assert!(keyspace.pf_count(&["string".into()]).is_err());
+    }
+}
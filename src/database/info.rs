@@ -0,0 +1,34 @@
+use super::Database;
+
+const SECTION_REPLICATION: &str = "replication";
+const SECTION_CLUSTER: &str = "cluster";
+
+/// Sections INFO reports without arguments, and with default, all or everything
+const DEFAULT_SECTIONS: &[&str] = &[SECTION_REPLICATION, SECTION_CLUSTER];
+
+impl Database {
+    /// The INFO report of the sections asked for, lowercase. Unknown sections are left
+    /// out.
+    pub fn info(&self, sections: &[String]) -> String {
+        let is_default = sections.is_empty()
+            || sections
+                .iter()
+                .any(|section| matches!(section.as_str(), "default" | "all" | "everything"));
+        let is_included = |name: &str| {
+            (is_default && DEFAULT_SECTIONS.contains(&name))
+                || sections.iter().any(|section| section == name)
+        };
+
+        let mut reports = Vec::new();
+        if is_included(SECTION_REPLICATION) {
+            reports.push(self.replication_info());
+        }
+        if is_included(SECTION_CLUSTER) {
+            reports.push(format!(
+                "# Cluster\r\ncluster_enabled:{}\r\n",
+                self.is_cluster_enabled() as u8
+            ));
+        }
+        reports.join("\r\n")
+    }
+}
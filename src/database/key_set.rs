@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+/// Set of keys supporting O(1) insert, remove and access by position,
+/// so that random samples can be taken cheaply.
+#[derive(Debug, Default)]
+pub struct KeySet {
+    keys: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl KeySet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        if self.positions.contains_key(key) {
+            return;
+        }
+        self.positions.insert(key.to_string(), self.keys.len());
+        self.keys.push(key.to_string());
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        let Some(position) = self.positions.remove(key) else {
+            return;
+        };
+        self.keys.swap_remove(position);
+        if let Some(moved_key) = self.keys.get(position) {
+            self.positions.insert(moved_key.clone(), position);
+        }
+    }
+
+    pub fn get(&self, position: usize) -> Option<&String> {
+        self.keys.get(position)
+    }
+}
@@ -0,0 +1,91 @@
+use super::{
+    dict::Dict, get_current_time_ms, key_set::KeySet, pattern::glob_match, random::Random, Entry,
+};
+
+const PATTERN_ALL: &str = "*";
+
+/// One of the numbered databases: the keys with their values and expiry
+pub struct Keyspace {
+    pub(super) data: Dict<Entry>,
+    pub(super) expires: KeySet,
+    pub(super) random: Random,
+    /// Keys deleted because they expired, until they are logged to the AOF
+    pub(super) expired_keys: Vec<String>,
+    /// Keys never expire while loading, their deletion being part of what is loaded
+    pub(super) is_loading: bool,
+}
+
+impl Keyspace {
+    pub fn new() -> Self {
+        Keyspace {
+            data: Dict::new(),
+            expires: KeySet::new(),
+            random: Random::new(),
+            expired_keys: Vec::new(),
+            is_loading: false,
+        }
+    }
+}
+
+impl Keyspace {
+    pub fn set(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        expires_at: Option<u128>,
+    ) -> anyhow::Result<()> {
+        let value = value.into();
+        self.insert_entry(key, Entry { value, expires_at });
+        Ok(())
+    }
+
+    /// Sets the value of the key, keeping the expiry it has
+    pub fn set_keep_ttl(&mut self, key: String, value: Vec<u8>) -> anyhow::Result<()> {
+        self.delete_if_expired(&key)?;
+        let expires_at = self.data.get(&key).and_then(|entry| entry.expires_at);
+        self.set(key, value, expires_at)
+    }
+
+    pub(super) fn insert_entry(&mut self, key: String, entry: Entry) {
+        match entry.expires_at {
+            Some(_) => self.expires.insert(&key),
+            None => self.expires.remove(&key),
+        }
+        self.data.insert(key, entry);
+    }
+
+    pub fn get(&mut self, key: String) -> anyhow::Result<Option<Vec<u8>>> {
+        self.delete_if_expired(&key)?;
+        let value = self.data.get(&key).map(|entry| entry.value.to_bytes());
+        Ok(value)
+    }
+
+    /// Deletes the key, returning true if it existed
+    pub fn delete(&mut self, key: String) -> anyhow::Result<bool> {
+        self.expires.remove(&key);
+        Ok(self.data.remove(&key).is_some())
+    }
+
+    pub fn keys(&self, pattern: String) -> anyhow::Result<Vec<String>> {
+        let now = get_current_time_ms()?;
+        let keys = self
+            .data
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .filter(|(key, _)| pattern == PATTERN_ALL || glob_match(&pattern, key, false))
+            .map(|(key, _)| key.clone())
+            .collect();
+        Ok(keys)
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Deletes all the keys. Still loading if it was, as when FLUSHDB is replayed.
+    pub fn flush(&mut self) {
+        let is_loading = self.is_loading;
+        *self = Keyspace::new();
+        self.is_loading = is_loading;
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use super::{aof::encode_command, get_current_time_ms, Database};
+
+/// Credentials MIGRATE authenticates to the target with
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrateAuth {
+    /// Set with AUTH2, the default user being used otherwise
+    pub username: Option<String>,
+    pub password: String,
+}
+
+/// Where MIGRATE moves the keys, and how
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrateOptions {
+    pub host: String,
+    pub port: u16,
+    pub keys: Vec<String>,
+    pub destination_db: i64,
+    /// For connecting, and for each reply of the target
+    pub timeout: Duration,
+    /// Keeps the keys here
+    pub copy: bool,
+    /// Replaces the keys existing in the target
+    pub replace: bool,
+    pub auth: Option<MigrateAuth>,
+}
+
+impl Database {
+    /// Moves the keys of the db to another server, which restores them from their DUMP
+    /// payload. The keys are deleted here once restored there, unless copied. Blocks the
+    /// database until the target replied, so that the keys can't change in between.
+    /// Returns false when none of the keys exist.
+    pub fn migrate(&mut self, db: usize, options: &MigrateOptions) -> anyhow::Result<bool> {
+        let mut dumped = Vec::new();
+        for key in &options.keys {
+            if let Some((payload, expires_at)) = self.dump_entry(db, key)? {
+                dumped.push((key, payload, expires_at));
+            }
+        }
+        if dumped.is_empty() {
+            return Ok(false);
+        }
+
+        let mut commands = Vec::new();
+        if let Some(auth) = &options.auth {
+            let mut arguments = vec![b"AUTH".to_vec()];
+            if let Some(username) = &auth.username {
+                arguments.push(username.as_bytes().to_vec());
+            }
+            arguments.push(auth.password.as_bytes().to_vec());
+            commands.push(arguments);
+        }
+        let db_string = options.destination_db.to_string();
+        commands.push(vec![b"SELECT".to_vec(), db_string.into_bytes()]);
+        // The target serves the keys of a slot it imports only when asked
+        let restore = match self.is_cluster_enabled() {
+            true => b"RESTORE-ASKING".to_vec(),
+            false => b"RESTORE".to_vec(),
+        };
+        let now = get_current_time_ms()?;
+        for (key, payload, expires_at) in &dumped {
+            // A TTL of 0 restores the key without expiry
+            let ttl = expires_at.map_or(0, |expires_at| expires_at.saturating_sub(now).max(1));
+            let mut arguments = vec![
+                restore.clone(),
+                key.as_bytes().to_vec(),
+                ttl.to_string().into_bytes(),
+                payload.clone(),
+            ];
+            if options.replace {
+                arguments.push(b"REPLACE".to_vec());
+            }
+            commands.push(arguments);
+        }
+
+        let replies = send_to_target(options, &commands)?;
+        let (setup_replies, restore_replies) = replies.split_at(commands.len() - dumped.len());
+        if let Some(error) = setup_replies.iter().find_map(|reply| reply.as_ref().err()) {
+            anyhow::bail!("ERR Target instance replied with error: {error}")
+        }
+
+        let mut deleted = vec![b"DEL".to_vec()];
+        let mut first_error = None;
+        for ((key, _, _), reply) in dumped.iter().zip(restore_replies) {
+            match reply {
+                Ok(()) if !options.copy => {
+                    self.keyspace(db).delete(key.to_string())?;
+                    deleted.push(key.as_bytes().to_vec());
+                }
+                Ok(()) => {}
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        // The keys restored are gone even when others failed
+        if deleted.len() > 1 {
+            self.propagate(db, &deleted);
+        }
+        if let Some(error) = first_error {
+            anyhow::bail!("ERR Target instance replied with error: {error}")
+        }
+        Ok(true)
+    }
+}
+
+/// Sends the commands at once, and returns the reply to each one: Ok for a status, the
+/// message of an error otherwise
+fn send_to_target(
+    options: &MigrateOptions,
+    commands: &[Vec<Vec<u8>>],
+) -> anyhow::Result<Vec<Result<(), String>>> {
+    let address = (options.host.as_str(), options.port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next());
+    let stream =
+        address.and_then(|address| TcpStream::connect_timeout(&address, options.timeout).ok());
+    let Some(mut stream) = stream else {
+        anyhow::bail!("IOERR error or timeout connecting to the client")
+    };
+    stream.set_read_timeout(Some(options.timeout))?;
+    stream.set_write_timeout(Some(options.timeout))?;
+
+    let mut bytes = Vec::new();
+    for arguments in commands {
+        encode_command(&mut bytes, arguments);
+    }
+    if stream.write_all(&bytes).is_err() {
+        anyhow::bail!("IOERR error or timeout writing to target instance")
+    }
+
+    let mut reader = BufReader::new(stream);
+    let mut replies = Vec::new();
+    for _ in commands {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(read_count) if read_count > 0 => {}
+            _ => anyhow::bail!("IOERR error or timeout reading to target instance"),
+        }
+        let line = line.trim_end();
+        match line.strip_prefix('-') {
+            Some(error) => replies.push(Err(error.to_string())),
+            None => replies.push(Ok(())),
+        }
+    }
+    Ok(replies)
+}
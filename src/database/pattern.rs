@@ -0,0 +1,145 @@
+#[cfg(test)]
+mod tests;
+
+/// Max recursion depth of `*`, to avoid stack overflows with patterns like "a*a*a*a*..."
+const MAX_NESTING: usize = 1000;
+
+/// Glob-style matching with the same semantics as Redis' `stringmatchlen`:
+/// `*`, `?`, `[abc]`, `[^a]`, `[a-z]` and `\` escapes.
+pub fn glob_match(pattern: &str, string: &str, nocase: bool) -> bool {
+    glob_match_bytes(pattern.as_bytes(), string.as_bytes(), nocase)
+}
+
+pub fn glob_match_bytes(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let mut skip_longer_matches = false;
+    match_impl(pattern, string, nocase, &mut skip_longer_matches, 0)
+}
+
+fn match_impl(
+    mut pattern: &[u8],
+    mut string: &[u8],
+    nocase: bool,
+    skip_longer_matches: &mut bool,
+    nesting: usize,
+) -> bool {
+    if nesting > MAX_NESTING {
+        return false;
+    }
+
+    while !pattern.is_empty() && !string.is_empty() {
+        match pattern[0] {
+            b'*' => {
+                while pattern.len() > 1 && pattern[1] == b'*' {
+                    pattern = &pattern[1..];
+                }
+                if pattern.len() == 1 {
+                    return true;
+                }
+                while !string.is_empty() {
+                    if match_impl(
+                        &pattern[1..],
+                        string,
+                        nocase,
+                        skip_longer_matches,
+                        nesting + 1,
+                    ) {
+                        return true;
+                    }
+                    // The rest of the pattern failed against every suffix tried so far
+                    // and will fail against the shorter ones too
+                    if *skip_longer_matches {
+                        return false;
+                    }
+                    string = &string[1..];
+                }
+                *skip_longer_matches = true;
+                return false;
+            }
+            b'?' => {
+                string = &string[1..];
+            }
+            b'[' => {
+                pattern = &pattern[1..];
+                let negate = pattern.first() == Some(&b'^');
+                if negate {
+                    pattern = &pattern[1..];
+                }
+
+                let mut is_match = false;
+                loop {
+                    if pattern.is_empty() {
+                        break;
+                    }
+                    if pattern[0] == b'\\' && pattern.len() >= 2 {
+                        pattern = &pattern[1..];
+                        if pattern[0] == string[0] {
+                            is_match = true;
+                        }
+                    } else if pattern[0] == b']' {
+                        break;
+                    } else if pattern.len() >= 3 && pattern[1] == b'-' {
+                        let (mut start, mut end) = (pattern[0], pattern[2]);
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        let mut character = string[0];
+                        if nocase {
+                            start = start.to_ascii_lowercase();
+                            end = end.to_ascii_lowercase();
+                            character = character.to_ascii_lowercase();
+                        }
+                        pattern = &pattern[2..];
+                        if character >= start && character <= end {
+                            is_match = true;
+                        }
+                    } else if equals(pattern[0], string[0], nocase) {
+                        is_match = true;
+                    }
+                    pattern = &pattern[1..];
+                }
+
+                // Unterminated class: treat the end of the pattern as the closing bracket
+                if pattern.is_empty() {
+                    pattern = b"]";
+                }
+                if negate {
+                    is_match = !is_match;
+                }
+                if !is_match {
+                    return false;
+                }
+                string = &string[1..];
+            }
+            b'\\' if pattern.len() >= 2 => {
+                pattern = &pattern[1..];
+                if !equals(pattern[0], string[0], nocase) {
+                    return false;
+                }
+                string = &string[1..];
+            }
+            character => {
+                if !equals(character, string[0], nocase) {
+                    return false;
+                }
+                string = &string[1..];
+            }
+        }
+
+        pattern = &pattern[1..];
+        if string.is_empty() {
+            while pattern.first() == Some(&b'*') {
+                pattern = &pattern[1..];
+            }
+            break;
+        }
+    }
+
+    pattern.is_empty() && string.is_empty()
+}
+
+fn equals(a: u8, b: u8, nocase: bool) -> bool {
+    match nocase {
+        true => a.eq_ignore_ascii_case(&b),
+        false => a == b,
+    }
+}
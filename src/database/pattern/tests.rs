@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod test {
+    use crate::database::pattern::glob_match;
+
+    #[test]
+    fn test_glob_match_wildcards() {
+        assert!(!glob_match("*", "", false));
+        assert!(glob_match("*", "anything", false));
+        assert!(glob_match("user:*:session", "user:42:session", false));
+        assert!(!glob_match("user:*:session", "user:42:profile", false));
+        assert!(glob_match("h?llo", "hello", false));
+        assert!(!glob_match("h?llo", "hllo", false));
+        assert!(glob_match("a*b*c", "axxbyyc", false));
+        assert!(!glob_match("a", "banana", false));
+        assert!(glob_match("a", "a", false));
+    }
+
+    #[test]
+    fn test_glob_match_classes() {
+        assert!(glob_match("h[ae]llo", "hello", false));
+        assert!(glob_match("h[ae]llo", "hallo", false));
+        assert!(!glob_match("h[ae]llo", "hillo", false));
+        assert!(glob_match("h[^e]llo", "hallo", false));
+        assert!(!glob_match("h[^e]llo", "hello", false));
+        assert!(glob_match("h[a-b]llo", "hbllo", false));
+        assert!(glob_match("h[b-a]llo", "hallo", false));
+        assert!(!glob_match("h[a-b]llo", "hcllo", false));
+        assert!(glob_match("h[\\]]llo", "h]llo", false));
+        assert!(glob_match("ab[c", "abc", false));
+    }
+
+    #[test]
+    fn test_glob_match_escapes_and_case() {
+        assert!(glob_match("h\\*llo", "h*llo", false));
+        assert!(!glob_match("h\\*llo", "hello", false));
+        assert!(glob_match("a\\", "a\\", false));
+        assert!(glob_match("HELLO", "hello", true));
+        assert!(!glob_match("HELLO", "hello", false));
+        assert!(glob_match("[A-C]x", "bx", true));
+    }
+
+    #[test]
+    fn test_glob_match_pathological_pattern() {
+        let pattern = "a*".repeat(50) + "b";
+        let string = "a".repeat(60);
+        assert!(!glob_match(&pattern, &string, false));
+    }
+}
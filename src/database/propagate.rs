@@ -0,0 +1,34 @@
+use super::Database;
+
+impl Database {
+    /// Records a write command that changed the dataset: it counts towards the save
+    /// points, is logged to the AOF and sent to the replicas
+    pub fn propagate(&mut self, db: usize, arguments: &[Vec<u8>]) {
+        self.save_status.dirty += 1;
+        self.feed_aof(db, arguments);
+        // The stream of a replica is the one of its master, which its writable replica
+        // mode never adds to
+        if !self.is_replica() {
+            self.feed_replicas(Some(db), arguments);
+        }
+    }
+
+    /// Propagates the deletion of the keys that expired since the last call, so that
+    /// loading the AOF never depends on the time it is loaded at, and replicas keep the
+    /// same dataset. Replicas wait for the deletions of their master instead.
+    pub fn propagate_expired_keys(&mut self) {
+        let is_replica = self.is_replica();
+        for db in 0..self.keyspaces.len() {
+            if self.keyspaces[db].expired_keys.is_empty() {
+                continue;
+            }
+            let expired_keys = std::mem::take(&mut self.keyspaces[db].expired_keys);
+            if is_replica {
+                continue;
+            }
+            for key in expired_keys {
+                self.propagate(db, &[b"DEL".to_vec(), key.into_bytes()]);
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Small xorshift64* pseudo random generator, good enough for sampling keys.
+#[derive(Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+        Self { state: seed | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in 0..max. max must be greater than 0.
+    pub fn next_below(&mut self, max: usize) -> usize {
+        (self.next_u64() % max as u64) as usize
+    }
+
+    /// Returns a string of random lowercase hexadecimal digits, like the ids of servers
+    pub fn hex_string(&mut self, length: usize) -> String {
+        (0..length)
+            .map(|_| char::from_digit(self.next_below(16) as u32, 16).unwrap_or('0'))
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+use crate::database::{get_current_time_ms, string::Value, Database, Entry};
+
+use super::{
+    crc64::crc64,
+    read_functions::read_value,
+    write_functions::{write_value, RDB_VERSION, VALUE_TYPE_STRING},
+    CHECKSUM_LENGTH,
+};
+
+const VERSION_LENGTH: usize = 2;
+
+/// Serializes a value like DUMP does: its type and encoding as in RDB files, followed
+/// by the RDB version and the CRC64 of all that, both little endian
+pub fn create_dump_payload(value: &Value) -> Vec<u8> {
+    let mut payload = vec![VALUE_TYPE_STRING];
+    write_value(&mut payload, value);
+    payload.extend(RDB_VERSION.to_le_bytes());
+    let checksum = crc64(0, &payload);
+    payload.extend(checksum.to_le_bytes());
+    payload
+}
+
+/// Reads back a value serialized by DUMP, here or by a server writing the same RDB
+/// version or an older one
+pub fn parse_dump_payload(payload: &[u8]) -> anyhow::Result<Value> {
+    let Some(content_length) = payload.len().checked_sub(VERSION_LENGTH + CHECKSUM_LENGTH) else {
+        anyhow::bail!("ERR DUMP payload version or checksum are wrong")
+    };
+    let (content, footer) = payload.split_at(content_length);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let mut checksum = [0; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&footer[VERSION_LENGTH..]);
+    let checksum = u64::from_le_bytes(checksum);
+    let checked_length = content_length + VERSION_LENGTH;
+    if version > RDB_VERSION || crc64(0, &payload[..checked_length]) != checksum {
+        anyhow::bail!("ERR DUMP payload version or checksum are wrong")
+    }
+
+    match read_value(content) {
+        Ok((value, read_count)) if read_count == content.len() => Ok(value.into()),
+        _ => anyhow::bail!("ERR Bad data format"),
+    }
+}
+
+impl Database {
+    /// The payload of the key with its expiry, None when it doesn't exist
+    pub(in crate::database) fn dump_entry(
+        &mut self,
+        db: usize,
+        key: &str,
+    ) -> anyhow::Result<Option<(Vec<u8>, Option<u128>)>> {
+        let keyspace = self.keyspace(db);
+        keyspace.delete_if_expired(key)?;
+        let dumped = keyspace
+            .data
+            .get(key)
+            .map(|entry| (create_dump_payload(&entry.value), entry.expires_at));
+        Ok(dumped)
+    }
+
+    /// The value of the key serialized like DUMP does, None when it doesn't exist
+    pub fn dump(&mut self, db: usize, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let dumped = self.dump_entry(db, key)?;
+        Ok(dumped.map(|(payload, _)| payload))
+    }
+
+    /// Creates the key with the value serialized by DUMP. An existing key is only
+    /// replaced when asked to, and a key already expired is not created.
+    pub fn restore(
+        &mut self,
+        db: usize,
+        key: String,
+        expires_at: Option<u128>,
+        payload: &[u8],
+        replace: bool,
+    ) -> anyhow::Result<()> {
+        let keyspace = self.keyspace(db);
+        keyspace.delete_if_expired(&key)?;
+        if !replace && keyspace.data.contains_key(&key) {
+            anyhow::bail!("BUSYKEY Target key name already exists.")
+        }
+        let value = parse_dump_payload(payload)?;
+
+        let now = get_current_time_ms()?;
+        let keyspace = self.keyspace(db);
+        if expires_at.is_some_and(|expires_at| expires_at <= now) {
+            keyspace.delete(key)?;
+            return Ok(());
+        }
+        keyspace.insert_entry(key, Entry { value, expires_at });
+        Ok(())
+    }
+}
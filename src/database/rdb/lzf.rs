@@ -0,0 +1,45 @@
+/// Decompresses LZF data, as used by Redis for strings in RDB files
+pub fn decompress(bytes: &[u8], length: usize) -> anyhow::Result<Vec<u8>> {
+    // The length read may be forged, while each input byte gives at most 88 output bytes
+    let mut output = Vec::with_capacity(length.min(bytes.len() * 88));
+    let mut position = 0;
+    while position < bytes.len() {
+        let control = bytes[position] as usize;
+        position += 1;
+
+        // Literal run of control + 1 bytes
+        if control < 32 {
+            let end = position + control + 1;
+            anyhow::ensure!(end <= bytes.len(), "-> Invalid LZF literal run");
+            output.extend_from_slice(&bytes[position..end]);
+            position = end;
+            continue;
+        }
+
+        // Back reference, with a length of 7 meaning the length continues in the next byte
+        let mut reference_length = control >> 5;
+        if reference_length == 7 {
+            anyhow::ensure!(position < bytes.len(), "-> Invalid LZF back reference");
+            reference_length += bytes[position] as usize;
+            position += 1;
+        }
+        anyhow::ensure!(position < bytes.len(), "-> Invalid LZF back reference");
+        let distance = ((control & 0x1f) << 8) + bytes[position] as usize + 1;
+        position += 1;
+        anyhow::ensure!(distance <= output.len(), "-> Invalid LZF back reference");
+
+        // Copied byte by byte since the reference may overlap the output
+        let start = output.len() - distance;
+        for index in 0..reference_length + 2 {
+            output.push(output[start + index]);
+        }
+    }
+
+    anyhow::ensure!(
+        output.len() == length,
+        "-> Invalid LZF length. Expected: {}, got: {}",
+        length,
+        output.len()
+    );
+    Ok(output)
+}
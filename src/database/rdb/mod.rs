@@ -1,6 +1,7 @@
 use self::read_functions::read_key_value_with_ms_expiry;
 
-use super::{Database, SETTINGS_DBFILENAME_ID, SETTINGS_DIR_ID};
+use self::crc64::crc64;
+use super::Database;
 use crate::database::rdb::{
     op_code::OpCode,
     read_functions::{
@@ -9,49 +10,68 @@ use crate::database::rdb::{
 };
 use std::path::PathBuf;
 
+mod crc64;
+pub mod dump;
+mod lzf;
 mod op_code;
 mod read_functions;
+pub mod save;
+mod write_functions;
 
 #[cfg(test)]
 mod tests;
 
+/// RDB files end with a checksum since version 5
+const RDB_CHECKSUM_MIN_VERSION: u32 = 5;
+const CHECKSUM_LENGTH: usize = 8;
+
 impl Database {
+    /// Path of the RDB file, made of the dir and dbfilename settings
+    pub fn rdb_path(&self) -> PathBuf {
+        let mut dbpath = PathBuf::new();
+        dbpath.push(self.dir());
+        dbpath.push(self.dbfilename());
+        dbpath
+    }
+
     pub fn can_load_from_disk(&mut self) -> bool {
-        let dir = self.config_get(SETTINGS_DIR_ID);
-        let dbfilename = self.config_get(SETTINGS_DBFILENAME_ID);
-        dir.is_some() && dbfilename.is_some()
+        self.rdb_path().exists()
     }
 
     pub fn load_from_disk(&mut self) -> anyhow::Result<()> {
-        let Some(dir) = self.config_get(SETTINGS_DIR_ID) else {
-            anyhow::bail!("Failed to get dir")
-        };
-        let Some(dbfilename) = self.config_get(SETTINGS_DBFILENAME_ID) else {
-            anyhow::bail!("Failed to get dbfilename")
-        };
-
-        let mut dbpath = PathBuf::new();
-        dbpath.push(dir);
-        dbpath.push(dbfilename);
-
-        let rdb_bytes = std::fs::read(&dbpath)?;
+        let rdb_bytes = std::fs::read(self.rdb_path())?;
         self.parse_and_restore_rdb(&rdb_bytes)?;
 
         Ok(())
     }
 
-    fn parse_and_restore_rdb(&mut self, rdb_bytes: &[u8]) -> anyhow::Result<()> {
+    /// Loads the RDB at the start of an AOF base file. Returns its length, the commands
+    /// logged after it following.
+    pub fn load_rdb_preamble(&mut self, bytes: &[u8]) -> anyhow::Result<usize> {
+        self.parse_and_restore_rdb(bytes)
+    }
+
+    /// Returns the length of the RDB, up to the end of its checksum
+    #[allow(clippy::needless_borrow)]
+    pub(in crate::database) fn parse_and_restore_rdb(
+        &mut self,
+        rdb_bytes: &[u8],
+    ) -> anyhow::Result<usize> {
         let mut bytes = rdb_bytes;
 
         let (version, read_count) = read_headers(bytes)?;
         bytes = &bytes[read_count..];
         self.metadata.insert("version".into(), version.to_string());
 
+        let mut db_index = 0;
         loop {
-            let Ok(op_code) = OpCode::try_from(bytes[0]) else {
+            let Some(&first) = bytes.first() else {
+                anyhow::bail!("-> RDB cut short before its EOF")
+            };
+            let Ok(op_code) = OpCode::try_from(first) else {
                 let ((key, value), read_count) = read_key_value(&bytes)?;
                 bytes = &bytes[read_count..];
-                self.set(key, value, None)?;
+                self.keyspace(db_index).set(key, value, None)?;
                 continue;
             };
 
@@ -65,8 +85,9 @@ impl Database {
                     self.metadata.insert(key, value);
                 }
                 OpCode::SelectDB => {
-                    let (_db_number, read_count) = read_db_number(&bytes)?;
+                    let (db_number, read_count) = read_db_number(bytes)?;
                     bytes = &bytes[read_count..];
+                    db_index = self.validate_keyspace_index(db_number as i64)?;
                 }
                 OpCode::ResizeDB => {
                     let ((_size_hash_table, _size_expiry_hash_table), read_count) =
@@ -76,7 +97,7 @@ impl Database {
                 OpCode::ExpireTimeMS => {
                     let ((key, entry), read_count) = read_key_value_with_ms_expiry(&bytes)?;
                     bytes = &bytes[read_count..];
-                    self.set(key, entry.value, entry.expires_at)?;
+                    self.keyspace(db_index).insert_entry(key, entry);
                 }
                 _ => {
                     eprintln!("-> Unmanaged op code: {op_code:?}");
@@ -85,8 +106,36 @@ impl Database {
             }
         }
 
-        Ok(())
+        let length = rdb_bytes.len() - bytes.len();
+        if version < RDB_CHECKSUM_MIN_VERSION {
+            return Ok(length);
+        }
+        anyhow::ensure!(bytes.len() >= CHECKSUM_LENGTH, "-> Missing RDB checksum");
+        Ok(length + CHECKSUM_LENGTH)
+    }
+}
+
+/// Checks that the RDB ends with the CRC64 of its content, unless written without a
+/// checksum. Catches an RDB received cut short or corrupted before parsing it.
+pub(in crate::database) fn verify_rdb_checksum(rdb_bytes: &[u8]) -> anyhow::Result<()> {
+    let (version, read_count) = read_headers(rdb_bytes)?;
+    if version < RDB_CHECKSUM_MIN_VERSION {
+        return Ok(());
     }
+    anyhow::ensure!(
+        rdb_bytes.len() >= read_count + CHECKSUM_LENGTH,
+        "Missing RDB checksum"
+    );
+    let (content, checksum) = rdb_bytes.split_at(rdb_bytes.len() - CHECKSUM_LENGTH);
+    let mut expected = [0; CHECKSUM_LENGTH];
+    expected.copy_from_slice(checksum);
+    let expected = u64::from_le_bytes(expected);
+    // A checksum of 0 tells it was not computed
+    anyhow::ensure!(
+        expected == 0 || crc64(0, content) == expected,
+        "Wrong RDB checksum"
+    );
+    Ok(())
 }
 
 /*
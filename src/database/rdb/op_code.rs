@@ -1,10 +1,11 @@
-const OP_CODE_EOF: u8 = 0xff;
-const OP_CODE_SELECTDB: u8 = 0xfe;
-const OP_CODE_EXPIRETIME_S: u8 = 0xfd;
-const OP_CODE_EXPIRETIME_MS: u8 = 0xfc;
-const OP_CODE_RESIZEDB: u8 = 0xfb;
-const OP_CODE_AUX: u8 = 0xfa;
+pub(super) const OP_CODE_EOF: u8 = 0xff;
+pub(super) const OP_CODE_SELECTDB: u8 = 0xfe;
+pub(super) const OP_CODE_EXPIRETIME_S: u8 = 0xfd;
+pub(super) const OP_CODE_EXPIRETIME_MS: u8 = 0xfc;
+pub(super) const OP_CODE_RESIZEDB: u8 = 0xfb;
+pub(super) const OP_CODE_AUX: u8 = 0xfa;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum OpCode {
     EOF,
@@ -1,4 +1,4 @@
-use crate::database::Entry;
+use crate::database::{rdb::lzf, Entry};
 
 use self::value_type::ValueType;
 
@@ -16,78 +16,147 @@ const READ_LENGTH_TYPE_SPECIAL: u8 = 0b11;
 pub enum ReadLength {
     Number(usize),
     Special(usize),
+    /// LZF compressed string
+    Compressed,
 }
 
 type ReadResult<T> = anyhow::Result<(T, usize)>;
 
+/// The first count bytes, which may be missing from data cut short or forged, like the
+/// payloads given to RESTORE
+fn get_bytes(bytes: &[u8], count: usize) -> anyhow::Result<&[u8]> {
+    match bytes.get(..count) {
+        Some(bytes) => Ok(bytes),
+        None => anyhow::bail!(
+            "-> Data cut short. Expected: {}, got: {}",
+            count,
+            bytes.len()
+        ),
+    }
+}
+
+#[allow(clippy::unnecessary_cast)]
 fn read_length(bytes: &[u8]) -> ReadResult<ReadLength> {
-    let kind = bytes[0] >> 6;
-    let b0 = bytes[0] & 0b0011_1111;
+    let first = get_bytes(bytes, 1)?[0];
+    let kind = first >> 6;
+    let b0 = first & 0b0011_1111;
 
     match kind {
         READ_LENGTH_TYPE_6BIT => Ok((ReadLength::Number(b0 as usize), 1)),
-        READ_LENGTH_TYPE_14BIT => Ok((
-            ReadLength::Number(((b0 as usize) << 8) | (bytes[1] as usize)),
-            2,
-        )),
-        READ_LENGTH_TYPE_32BIT => Ok((
-            ReadLength::Number(
-                u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]) as usize
-            ),
-            5,
-        )),
+        READ_LENGTH_TYPE_14BIT => {
+            let b1 = get_bytes(bytes, 2)?[1];
+            Ok((ReadLength::Number(((b0 as usize) << 8) | (b1 as usize)), 2))
+        }
+        READ_LENGTH_TYPE_32BIT => {
+            let (length, read_count) = read_long_length(bytes, b0)?;
+            Ok((ReadLength::Number(length as usize), read_count))
+        }
         READ_LENGTH_TYPE_SPECIAL => match b0 {
             0 => Ok((ReadLength::Special(1 as usize), 1)),
             1 => Ok((ReadLength::Special(2 as usize), 1)),
             2 => Ok((ReadLength::Special(4 as usize), 1)),
+            3 => Ok((ReadLength::Compressed, 1)),
             _ => anyhow::bail!("-> Length encoding not supported. Special kind id: {}", b0),
         },
         _ => anyhow::bail!("-> Length encoding not supported. Kind: {}", kind),
     }
 }
 
+/// Reads a 32 or 64 bit length, which unlike the other integers is big endian
+fn read_long_length(bytes: &[u8], b0: u8) -> ReadResult<u64> {
+    match b0 {
+        0 => {
+            let bytes = get_bytes(bytes, 5)?;
+            let length = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+            Ok((length as u64, 5))
+        }
+        1 => {
+            let mut length = [0; 8];
+            length.copy_from_slice(&get_bytes(bytes, 9)?[1..]);
+            Ok((u64::from_be_bytes(length), 9))
+        }
+        _ => anyhow::bail!("-> Length encoding not supported. Long kind id: {}", b0),
+    }
+}
+
 fn read_string(bytes: &[u8]) -> ReadResult<String> {
+    let (bytes, read_count) = read_bytes(bytes)?;
+    Ok((String::from_utf8_lossy(&bytes).to_string(), read_count))
+}
+
+/// Reads a key, which unlike other strings can't have its invalid bytes replaced
+fn read_key(bytes: &[u8]) -> ReadResult<String> {
+    let (bytes, read_count) = read_bytes(bytes)?;
+    let Ok(key) = String::from_utf8(bytes) else {
+        anyhow::bail!("-> Key is not valid UTF-8")
+    };
+    Ok((key, read_count))
+}
+
+fn read_bytes(bytes: &[u8]) -> ReadResult<Vec<u8>> {
     let (read_length, read_count_length) = read_length(bytes)?;
     let bytes = &bytes[read_count_length..];
 
-    let (string, read_count_string) = match read_length {
-        ReadLength::Number(length) => (
-            String::from_utf8_lossy(&bytes[..length]).to_string(),
-            length,
-        ),
-        ReadLength::Special(length) => match length {
-            1 => (bytes[0].to_string(), 1),
-            2 => (u16::from_le_bytes([bytes[0], bytes[1]]).to_string(), 2),
-            4 => (
-                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_string(),
-                4,
-            ),
-            _ => anyhow::bail!("-> Int length not supported. Length: {}", length),
+    let (value, read_count_value) = match read_length {
+        ReadLength::Number(length) => match bytes.get(..length) {
+            Some(value) => (value.to_vec(), length),
+            None => anyhow::bail!("-> String cut short. Length: {}", length),
         },
+        ReadLength::Special(length) => {
+            let bytes = get_bytes(bytes, length)?;
+            let integer = match length {
+                1 => bytes[0] as i8 as i64,
+                2 => i16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+                4 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64,
+                _ => anyhow::bail!("-> Int length not supported. Length: {}", length),
+            };
+            (integer.to_string().into_bytes(), length)
+        }
+        ReadLength::Compressed => {
+            let (compressed_length, read_count_compressed) = read_number(bytes)?;
+            let (length, read_count_uncompressed) = read_number(&bytes[read_count_compressed..])?;
+            let start = read_count_compressed + read_count_uncompressed;
+            let compressed = get_bytes(&bytes[start..], compressed_length as usize)?;
+            let value = lzf::decompress(compressed, length as usize)?;
+            (value, start + compressed.len())
+        }
     };
 
-    let read_count = read_count_length + read_count_string;
-    Ok((string, read_count))
+    let read_count = read_count_length + read_count_value;
+    Ok((value, read_count))
 }
 
 fn read_number(bytes: &[u8]) -> ReadResult<u32> {
-    let kind = bytes[0] >> 6;
-    let b0 = bytes[0] & 0b0011_1111;
+    let first = get_bytes(bytes, 1)?[0];
+    let kind = first >> 6;
+    let b0 = first & 0b0011_1111;
 
     match kind {
         READ_LENGTH_TYPE_6BIT => Ok((b0 as u32, 1)),
-        READ_LENGTH_TYPE_14BIT => Ok((((b0 as u32) << 8) | (bytes[1] as u32), 2)),
-        READ_LENGTH_TYPE_32BIT => Ok((
-            u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
-            5,
-        )),
+        READ_LENGTH_TYPE_14BIT => {
+            let b1 = get_bytes(bytes, 2)?[1];
+            Ok((((b0 as u32) << 8) | (b1 as u32), 2))
+        }
+        READ_LENGTH_TYPE_32BIT => {
+            let (length, read_count) = read_long_length(bytes, b0)?;
+            let Ok(length) = u32::try_from(length) else {
+                anyhow::bail!("-> Number too large: {}", length)
+            };
+            Ok((length, read_count))
+        }
         READ_LENGTH_TYPE_SPECIAL => match b0 {
-            0 => Ok((bytes[1] as u32, 2)),
-            1 => Ok((u16::from_le_bytes([bytes[1], bytes[2]]) as u32, 3)),
-            2 => Ok((
-                u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
-                5,
-            )),
+            0 => Ok((get_bytes(bytes, 2)?[1] as u32, 2)),
+            1 => {
+                let bytes = get_bytes(bytes, 3)?;
+                Ok((u16::from_le_bytes([bytes[1], bytes[2]]) as u32, 3))
+            }
+            2 => {
+                let bytes = get_bytes(bytes, 5)?;
+                Ok((
+                    u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+                    5,
+                ))
+            }
             _ => anyhow::bail!("-> Length encoding not supported. Special kind id: {}", b0),
         },
         _ => anyhow::bail!("-> Length encoding not supported. Kind: {}", kind),
@@ -96,22 +165,24 @@ fn read_number(bytes: &[u8]) -> ReadResult<u32> {
 
 pub fn read_headers(bytes: &[u8]) -> ReadResult<u32> {
     const MAGIC_STRING_LENGTH: usize = 5;
-    let magic_string = &bytes[..MAGIC_STRING_LENGTH];
+    let magic_string = get_bytes(bytes, MAGIC_STRING_LENGTH)?;
     anyhow::ensure!(magic_string == b"REDIS");
     let bytes = &bytes[MAGIC_STRING_LENGTH..];
 
     const VERSION_LENGTH: usize = 4;
-    let version = String::from_utf8_lossy(&bytes[..VERSION_LENGTH]).to_string();
+    let version = String::from_utf8_lossy(get_bytes(bytes, VERSION_LENGTH)?).to_string();
     let version = version.parse::<u32>()?;
 
     let read_count = MAGIC_STRING_LENGTH + VERSION_LENGTH;
     Ok((version, read_count))
 }
 
+#[allow(clippy::needless_borrow, clippy::needless_question_mark)]
 pub fn read_db_number(bytes: &[u8]) -> ReadResult<u32> {
     Ok(read_number(&bytes)?)
 }
 
+#[allow(clippy::needless_borrow)]
 pub fn read_auxiliary(bytes: &[u8]) -> ReadResult<(String, String)> {
     let (key, read_count_key) = read_string(&bytes)?;
     let bytes = &bytes[read_count_key..];
@@ -120,6 +191,7 @@ pub fn read_auxiliary(bytes: &[u8]) -> ReadResult<(String, String)> {
     Ok(((key, value), read_count))
 }
 
+#[allow(clippy::needless_borrow)]
 pub fn read_resize_db(bytes: &[u8]) -> ReadResult<(u32, u32)> {
     let (size_hash_table, read_count_hash_table) = read_number(&bytes)?;
     let bytes = &bytes[read_count_hash_table..];
@@ -128,32 +200,46 @@ pub fn read_resize_db(bytes: &[u8]) -> ReadResult<(u32, u32)> {
     Ok(((size_hash_table, size_expiry_hash_table), read_count))
 }
 
-pub fn read_key_value(bytes: &[u8]) -> ReadResult<(String, String)> {
-    let value_type = ValueType::try_from(bytes[0])?;
+pub fn read_key_value(bytes: &[u8]) -> ReadResult<(String, Vec<u8>)> {
+    let value_type = ValueType::try_from(get_bytes(bytes, 1)?[0])?;
     let bytes = &bytes[1..];
 
     match value_type {
         ValueType::String => {
-            let (key, read_count_key) = read_string(&bytes)?;
+            let (key, read_count_key) = read_key(bytes)?;
             let bytes = &bytes[read_count_key..];
-            let (value, read_count_value) = read_string(&bytes)?;
+            let (value, read_count_value) = read_bytes(bytes)?;
             let read_count = read_count_key + read_count_value + 1;
             Ok(((key, value), read_count))
         }
     }
 }
 
+/// Reads a value preceded by its type, as in DUMP payloads where there is no key
+pub fn read_value(bytes: &[u8]) -> ReadResult<Vec<u8>> {
+    let Some(value_type) = bytes.first() else {
+        anyhow::bail!("-> Missing value type")
+    };
+    match ValueType::try_from(*value_type)? {
+        ValueType::String => {
+            let (value, read_count) = read_bytes(&bytes[1..])?;
+            Ok((value, read_count + 1))
+        }
+    }
+}
+
+#[allow(clippy::needless_borrow)]
 pub fn read_key_value_with_ms_expiry(bytes: &[u8]) -> ReadResult<(String, Entry)> {
-    let expiry_ms = u64::from_le_bytes([
-        bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
-    ]);
+    let mut expiry_ms = [0; 8];
+    expiry_ms.copy_from_slice(get_bytes(bytes, 8)?);
+    let expiry_ms = u64::from_le_bytes(expiry_ms);
     let bytes = &bytes[8..];
 
     let ((key, value), read_count) = read_key_value(&bytes)?;
     let read_count = read_count + 8;
 
     let entry = Entry {
-        value,
+        value: value.into(),
         expires_at: Some(expiry_ms as u128),
     };
 
@@ -127,7 +127,7 @@ mod test {
         // Then
         assert_eq!(read_count, 13);
         assert_eq!(key, "mykey");
-        assert_eq!(value, "myval");
+        assert_eq!(value, b"myval");
     }
 
     // ----------------------------
@@ -187,4 +187,14 @@ mod test {
         assert_eq!(size_hash_table, 1);
         assert_eq!(size_expiry_hash_table, 0);
     }
+
+    #[test]
+    fn test_read_key_value_rejects_keys_not_utf8() {
+        // Given
+        let bytes = &[0x00, 0x02, 0x6b, 0xff, 0x02, 0x76, 0xff];
+        // When
+        let result = read_key_value(bytes);
+        // Then
+        assert_eq!(result.unwrap_err().to_string(), "-> Key is not valid UTF-8");
+    }
 }
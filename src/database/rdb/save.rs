@@ -0,0 +1,207 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::database::{get_current_time_ms, snapshot::Snapshot, Database};
+
+/// After a failed background save, save points wait this long before trying again
+const BGSAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Changes made since the last save, and how the last background save went
+pub struct SaveStatus {
+    /// Writes to the dataset since the last successful save
+    pub(in crate::database) dirty: u64,
+    /// Unix time in seconds of the last successful save, or of the start
+    pub(in crate::database) last_save: u64,
+    last_bgsave_failed: bool,
+    last_bgsave_attempt: Option<Instant>,
+}
+
+impl SaveStatus {
+    pub fn new() -> Self {
+        SaveStatus {
+            dirty: 0,
+            last_save: unix_time_seconds(),
+            last_bgsave_failed: false,
+            last_bgsave_attempt: None,
+        }
+    }
+
+    pub(in crate::database) fn saved(&mut self, dirty_saved: u64) {
+        self.dirty = self.dirty.saturating_sub(dirty_saved);
+        self.last_save = unix_time_seconds();
+        self.last_bgsave_failed = false;
+    }
+}
+
+/// A save of the RDB file, writing the dataset as of its start on a background thread
+pub struct RdbSave {
+    thread: JoinHandle<anyhow::Result<()>>,
+    temporary_path: PathBuf,
+    /// Writes made before the snapshot, which the file holds once saved
+    dirty_saved: u64,
+    started_at: Instant,
+}
+
+impl Database {
+    /// Writes the dataset to the RDB file, blocking every client until it is done
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if self.rdb_save.is_some() {
+            anyhow::bail!("ERR Background save already in progress")
+        }
+        let dirty_saved = self.save_status.dirty;
+        let snapshot = self.snapshot()?;
+        if let Err(error) = write_rdb_file(&snapshot, &self.rdb_temporary_path(), &self.rdb_path())
+        {
+            eprintln!("-> Failed saving the DB: {error}");
+            anyhow::bail!("ERR {error}")
+        }
+        self.save_status.saved(dirty_saved);
+        println!("-> DB saved on disk");
+        Ok(())
+    }
+
+    /// Starts writing the dataset to the RDB file on a background thread
+    pub fn start_bgsave(&mut self) -> anyhow::Result<()> {
+        if self.rdb_save.is_some() {
+            anyhow::bail!("ERR Background save already in progress")
+        }
+        self.save_status.last_bgsave_attempt = Some(Instant::now());
+        let snapshot = self.snapshot()?;
+        let temporary_path = self.rdb_temporary_path();
+        let rdb_path = self.rdb_path();
+        let thread_temporary_path = temporary_path.clone();
+        let thread = thread::Builder::new()
+            .name("rdb-bgsave".into())
+            .spawn(move || write_rdb_file(&snapshot, &thread_temporary_path, &rdb_path))?;
+
+        self.rdb_save = Some(RdbSave {
+            thread,
+            temporary_path,
+            dirty_saved: self.save_status.dirty,
+            started_at: Instant::now(),
+        });
+        println!("-> Background saving started");
+        Ok(())
+    }
+
+    /// Unix time in seconds of the last successful save
+    pub fn last_save(&self) -> u64 {
+        self.save_status.last_save
+    }
+
+    /// Handles the end of a background save, and starts one when a save point is
+    /// reached. Runs on every background tick.
+    pub fn rdb_cron(&mut self) {
+        let is_bgsave_done = self
+            .rdb_save
+            .as_ref()
+            .is_some_and(|save| save.thread.is_finished());
+        if is_bgsave_done {
+            self.finish_bgsave();
+        }
+        if self.rdb_save.is_some() {
+            return;
+        }
+
+        let is_retry_delayed = self.save_status.last_bgsave_failed
+            && self
+                .save_status
+                .last_bgsave_attempt
+                .is_some_and(|attempt| attempt.elapsed() < BGSAVE_RETRY_DELAY);
+        if is_retry_delayed {
+            return;
+        }
+        let elapsed = unix_time_seconds().saturating_sub(self.save_status.last_save);
+        let reached_save_point = self
+            .save_points()
+            .into_iter()
+            .find(|(seconds, changes)| self.save_status.dirty >= *changes && elapsed >= *seconds);
+        if let Some((seconds, changes)) = reached_save_point {
+            println!("-> {changes} changes in {seconds} seconds. Saving...");
+            if let Err(error) = self.start_bgsave() {
+                eprintln!("-> Can't save in background. Error: {error}");
+                self.save_status.last_bgsave_failed = true;
+            }
+        }
+    }
+
+    /// Fails write commands while saving to disk fails, so that clients notice the
+    /// writes would be lost, unless stop-writes-on-bgsave-error is off
+    pub fn check_writes_allowed(&self) -> anyhow::Result<()> {
+        if self.save_status.last_bgsave_failed
+            && self.stop_writes_on_bgsave_error()
+            && !self.save_points().is_empty()
+        {
+            anyhow::bail!(
+                "MISCONF Errors writing the RDB snapshot to disk. Commands that may modify \
+                 the data set are disabled, because stop-writes-on-bgsave-error is on. \
+                 Please check the server logs for details about the error."
+            )
+        }
+        Ok(())
+    }
+
+    /// Waits for the background save in progress and drops what it wrote
+    pub(in crate::database) fn discard_bgsave(&mut self) {
+        if let Some(save) = self.rdb_save.take() {
+            let _ = save.thread.join();
+            let _ = fs::remove_file(&save.temporary_path);
+        }
+    }
+
+    fn finish_bgsave(&mut self) {
+        let Some(save) = self.rdb_save.take() else {
+            return;
+        };
+        let result = match save.thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("The save thread panicked")),
+        };
+        let is_saved = result.is_ok();
+        match result {
+            Ok(()) => {
+                self.save_status.saved(save.dirty_saved);
+                println!(
+                    "-> Background saving terminated with success in {} ms",
+                    save.started_at.elapsed().as_millis()
+                );
+            }
+            Err(error) => {
+                let _ = fs::remove_file(&save.temporary_path);
+                self.save_status.last_bgsave_failed = true;
+                eprintln!("-> Background saving failed. Error: {error}");
+            }
+        }
+        self.bgsave_done_for_replicas(is_saved);
+    }
+
+    fn rdb_temporary_path(&self) -> PathBuf {
+        PathBuf::from(self.dir()).join(format!("temp-{}.rdb", std::process::id()))
+    }
+}
+
+/// Writes the snapshot to a temporary file, then moves it to the RDB path, so that the
+/// RDB file is always complete
+fn write_rdb_file(snapshot: &Snapshot, temporary_path: &Path, path: &Path) -> anyhow::Result<()> {
+    let mut bytes = Vec::new();
+    snapshot.write_rdb(&mut bytes, false)?;
+    let result = File::create(temporary_path).and_then(|mut file| {
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(temporary_path, path)
+    });
+    if let Err(error) = result {
+        let _ = fs::remove_file(temporary_path);
+        anyhow::bail!("Failed to write the RDB file {}: {error}", path.display())
+    }
+    Ok(())
+}
+
+fn unix_time_seconds() -> u64 {
+    (get_current_time_ms().unwrap_or_default() / 1000) as u64
+}
@@ -1,7 +1,18 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use crate::database::Database;
-    use std::collections::HashMap;
+    use crate::database::{
+        get_current_time_ms,
+        rdb::{
+            crc64::crc64,
+            dump::{create_dump_payload, parse_dump_payload},
+            lzf,
+        },
+        string::Value,
+        test_helpers::{database_in_temp_dir, wait_for_bgsave},
+        Database,
+    };
+    use std::{collections::HashMap, fs};
 
     const TEST_BYTES: &[u8] = &[
         0x52, 0x45, 0x44, 0x49, 0x53, 0x30, 0x30, 0x31, 0x31, 0xfa, 0x09, 0x72, 0x65, 0x64, 0x69,
@@ -14,6 +25,22 @@ mod tests {
         0x9a,
     ];
 
+    /// Same RDB, with a key expired and a key expiring long after
+    const EXPIRY_TEST_BYTES: &[u8] = &[
+        0x52, 0x45, 0x44, 0x49, 0x53, 0x30, 0x30, 0x31, 0x31, 0xfa, 0x09, 0x72, 0x65, 0x64, 0x69,
+        0x73, 0x2d, 0x76, 0x65, 0x72, 0x05, 0x37, 0x2e, 0x32, 0x2e, 0x34, 0xfa, 0x0a, 0x72, 0x65,
+        0x64, 0x69, 0x73, 0x2d, 0x62, 0x69, 0x74, 0x73, 0xc0, 0x40, 0xfa, 0x05, 0x63, 0x74, 0x69,
+        0x6d, 0x65, 0xc2, 0x27, 0xcb, 0xb3, 0x65, 0xfa, 0x08, 0x75, 0x73, 0x65, 0x64, 0x2d, 0x6d,
+        0x65, 0x6d, 0xc2, 0xa0, 0x86, 0x11, 0x00, 0xfa, 0x08, 0x61, 0x6f, 0x66, 0x2d, 0x62, 0x61,
+        0x73, 0x65, 0xc0, 0x00, 0xfe, 0x00, 0xfb, 0x03, 0x02, 0x00, 0x05, 0x6d, 0x79, 0x6b, 0x65,
+        0x79, 0x05, 0x6d, 0x79, 0x76, 0x61, 0x6c, 0xfc, 0x00, 0x9c, 0xef, 0x12, 0x7e, 0x01, 0x00,
+        0x00, 0x00, 0x07, 0x65, 0x78, 0x70, 0x5f, 0x6b, 0x65, 0x79, 0x0d, 0x65, 0x78, 0x70, 0x69,
+        0x72, 0x65, 0x64, 0x5f, 0x76, 0x61, 0x6c, 0x75, 0x65, 0xfc, 0x00, 0xd8, 0xc3, 0x2c, 0xbb,
+        0x03, 0x00, 0x00, 0x00, 0x0b, 0x6e, 0x6f, 0x74, 0x5f, 0x65, 0x78, 0x70, 0x5f, 0x6b, 0x65,
+        0x79, 0x11, 0x6e, 0x6f, 0x74, 0x5f, 0x65, 0x78, 0x70, 0x69, 0x72, 0x65, 0x64, 0x5f, 0x76,
+        0x61, 0x6c, 0x75, 0x65, 0xff, 0x3d, 0x30, 0xa8, 0x7a, 0xcf, 0x3e, 0x03, 0x9a,
+    ];
+
     #[test]
     fn test_parse_and_restore_rdb() {
         // Given
@@ -29,11 +56,340 @@ mod tests {
         expected_metadata.insert("ctime".into(), "1706281767".into());
         expected_metadata.insert("used-mem".into(), "1148576".into());
         assert_eq!(database.metadata, expected_metadata);
-        assert_eq!(database.get("mykey".into()).unwrap(), Some("myval".into()));
-        assert_eq!(database.get("exp_key".into()).unwrap(), None);
         assert_eq!(
-            database.get("not_exp_key".into()).unwrap(),
+            database.keyspace(0).get("mykey".into()).unwrap(),
+            Some("myval".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_and_restore_rdb_with_expiry() {
+        // Given
+        let mut database = Database::new();
+        // When
+        database.parse_and_restore_rdb(EXPIRY_TEST_BYTES).unwrap();
+        // Then
+        assert_eq!(
+            database.keyspace(0).get("mykey".into()).unwrap(),
+            Some("myval".into())
+        );
+        assert_eq!(database.keyspace(0).get("exp_key".into()).unwrap(), None);
+        assert_eq!(
+            database.keyspace(0).get("not_exp_key".into()).unwrap(),
             Some("not_expired_value".into())
         );
     }
+
+    #[test]
+    fn test_parse_and_restore_rdb_with_multiple_dbs() {
+        // Given
+        let bytes: &[u8] = &[
+            0x52, 0x45, 0x44, 0x49, 0x53, 0x30, 0x30, 0x31, 0x31, 0xfe, 0x00, 0x00, 0x01, 0x61,
+            0x01, 0x30, 0xfe, 0x03, 0x00, 0x01, 0x62, 0x01, 0x33, 0xff, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut database = Database::new();
+        // When
+        database.parse_and_restore_rdb(bytes).unwrap();
+        // Then
+        assert_eq!(
+            database.keyspace(0).get("a".into()).unwrap(),
+            Some("0".into())
+        );
+        assert_eq!(database.keyspace(0).get("b".into()).unwrap(), None);
+        assert_eq!(
+            database.keyspace(3).get("b".into()).unwrap(),
+            Some("3".into())
+        );
+    }
+
+    #[test]
+    fn test_lzf_decompress_with_overlapping_back_reference() {
+        // Given
+        let bytes = [0x00, b'a', 0xe0, 0x00, 0x00, 0x01, b'b', b'c'];
+        // When
+        let decompressed = lzf::decompress(&bytes, 12).unwrap();
+        // Then
+        assert_eq!(decompressed, b"aaaaaaaaaabc");
+        assert!(lzf::decompress(&bytes, 11).is_err());
+    }
+
+    #[test]
+    fn test_parse_and_restore_rdb_with_compressed_hyperloglog() {
+        // Given
+        let mut source = Database::new();
+        let elements: Vec<Vec<u8>> = (0..100).map(|index| format!("{index}").into()).collect();
+        source.keyspace(0).pf_add("hll".into(), &elements).unwrap();
+        let hll = source.keyspace(0).get("hll".into()).unwrap().unwrap();
+        // Compressed as LZF literal runs of up to 32 bytes
+        let compressed: Vec<u8> = hll
+            .chunks(32)
+            .flat_map(|chunk| [&[chunk.len() as u8 - 1], chunk].concat())
+            .collect();
+        let mut bytes = b"REDIS0011".to_vec();
+        bytes.extend([0x00, 0x03]);
+        bytes.extend(b"hll");
+        bytes.push(0xc3);
+        bytes.extend([0x40 | (compressed.len() >> 8) as u8, compressed.len() as u8]);
+        bytes.extend([0x40 | (hll.len() >> 8) as u8, hll.len() as u8]);
+        bytes.extend(&compressed);
+        bytes.extend([0xff, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut database = Database::new();
+        // When
+        database.parse_and_restore_rdb(&bytes).unwrap();
+        // Then
+        assert_eq!(database.keyspace(0).get("hll".into()).unwrap(), Some(hll));
+        assert_eq!(
+            database.keyspace(0).pf_count(&["hll".into()]).unwrap(),
+            source.keyspace(0).pf_count(&["hll".into()]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_crc64() {
+        // Given
+        let bytes = b"123456789";
+        // When
+        let checksum = crc64(0, bytes);
+        // Then
+        assert_eq!(checksum, 0xe9c6_d914_c4b8_d9ca);
+        assert_eq!(crc64(crc64(0, &bytes[..4]), &bytes[4..]), checksum);
+    }
+
+    #[test]
+    fn test_write_rdb_is_read_back() {
+        // Given
+        let mut source = Database::new();
+        let expires_at = get_current_time_ms().unwrap() + 60_000;
+        let long_value = vec![b'x'; 20_000];
+        source
+            .keyspace(0)
+            .set("short".into(), "value".into(), Some(expires_at))
+            .unwrap();
+        source
+            .keyspace(2)
+            .set("long".into(), long_value.clone(), None)
+            .unwrap();
+        let mut bytes = Vec::new();
+        // When
+        source
+            .snapshot()
+            .unwrap()
+            .write_rdb(&mut bytes, false)
+            .unwrap();
+        let mut database = Database::new();
+        let length = database.parse_and_restore_rdb(&bytes).unwrap();
+        // Then
+        assert_eq!(length, bytes.len());
+        let (contents, checksum) = bytes.split_at(bytes.len() - 8);
+        assert_eq!(checksum, crc64(0, contents).to_le_bytes());
+        assert_eq!(
+            database.keyspace(0).get("short".into()).unwrap(),
+            Some("value".into())
+        );
+        assert_eq!(
+            database.keyspace(0).expire_time("short".into()).unwrap(),
+            expires_at as i128
+        );
+        assert_eq!(
+            database.keyspace(2).get("long".into()).unwrap(),
+            Some(long_value)
+        );
+        assert_eq!(database.metadata.get("aof-base"), Some(&"0".to_string()));
+    }
+
+    #[test]
+    fn test_save_point_starts_bgsave() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("save-point", &[("save", "10 2")]);
+        database
+            .keyspace(0)
+            .set("key".into(), "value".into(), None)
+            .unwrap();
+        database.propagate(0, &[b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]);
+        database.save_status.last_save -= 10;
+        database.rdb_cron();
+        let is_started_before_enough_changes = database.rdb_save.is_some();
+        // When
+        database.propagate(0, &[b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]);
+        database.rdb_cron();
+        let is_started = database.rdb_save.is_some();
+        wait_for_bgsave(&mut database);
+        // Then
+        assert!(!is_started_before_enough_changes);
+        assert!(is_started);
+        assert_eq!(database.save_status.dirty, 0);
+        assert!(database.last_save() >= database.save_status.last_save);
+        let mut loaded = Database::new();
+        loaded
+            .parse_and_restore_rdb(&fs::read(dir.join("dump.rdb")).unwrap())
+            .unwrap();
+        assert_eq!(
+            loaded.keyspace(0).get("key".into()).unwrap(),
+            Some("value".into())
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_bgsave_stops_writes() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("misconf", &[]);
+        fs::remove_dir_all(dir).unwrap();
+        // When
+        database.start_bgsave().unwrap();
+        wait_for_bgsave(&mut database);
+        let stopped = database.check_writes_allowed();
+        database
+            .config_set(&[("stop-writes-on-bgsave-error".into(), "no".into())])
+            .unwrap();
+        let allowed = database.check_writes_allowed();
+        // Then
+        assert!(stopped.unwrap_err().to_string().starts_with("MISCONF"));
+        assert!(allowed.is_ok());
+    }
+
+    #[test]
+    fn test_dump_payload_round_trip_and_redis_payload() {
+        // Given
+        let value = Value::Raw(b"some value".to_vec());
+        // Written by redis-server with RDB version 9, the integer 10 encoded as such
+        let redis_payload = b"\x00\xc0\x0a\x09\x00\xbe\x6d\x06\x89\x5a\x28\x00\x0a";
+        // When
+        let payload = create_dump_payload(&value);
+        let mut corrupted = payload.clone();
+        corrupted[2] ^= 1;
+        // Then
+        assert_eq!(&payload[..12], b"\x00\x0asome value");
+        assert_eq!(&payload[12..14], &[11, 0]);
+        assert_eq!(parse_dump_payload(&payload).unwrap(), value);
+        assert_eq!(
+            parse_dump_payload(redis_payload).unwrap(),
+            Value::Integer(10)
+        );
+        assert_eq!(
+            parse_dump_payload(&corrupted).unwrap_err().to_string(),
+            "ERR DUMP payload version or checksum are wrong"
+        );
+        assert!(parse_dump_payload(b"short").is_err());
+    }
+
+    #[test]
+    fn test_dump_payload_encodes_integers_like_redis() {
+        // Given
+        // DUMP of the integer 10 as redis-server 7.2 writes it: the RDB version 9 payload
+        // of the round trip test, with the version 11 and its checksum
+        let redis_payload = b"\x00\xc0\x0a\x0b\x00\x07\x40\xea\x36\xf3\x31\x8a\x32";
+        let values = [
+            (Value::Integer(-1), &b"\xc0\xff"[..]),
+            (Value::Integer(300), b"\xc1\x2c\x01"),
+            (Value::Raw(b"70000".to_vec()), b"\xc2\x70\x11\x01\x00"),
+            (Value::Integer(i32::MIN as i64), b"\xc2\x00\x00\x00\x80"),
+            (Value::Integer(1 << 31), b"\x0a2147483648"),
+            (Value::Raw(b"007".to_vec()), b"\x03007"),
+        ];
+        // When
+        let payload = create_dump_payload(&Value::Integer(10));
+        let raw_payload = create_dump_payload(&Value::Raw(b"10".to_vec()));
+        // Then
+        assert_eq!(payload, redis_payload);
+        assert_eq!(raw_payload, redis_payload);
+        for (value, encoded) in values {
+            let payload = create_dump_payload(&value);
+            assert_eq!(&payload[1..payload.len() - 10], encoded);
+            assert_eq!(
+                parse_dump_payload(&payload).unwrap().to_bytes(),
+                value.to_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_dump_and_restore_keys() {
+        // Given
+        let mut database = Database::new();
+        let expires_at = get_current_time_ms().unwrap() + 100_000;
+        database
+            .keyspace(0)
+            .set("key".into(), b"12".to_vec(), Some(expires_at))
+            .unwrap();
+        // When
+        let payload = database.dump(0, "key").unwrap().unwrap();
+        let missing = database.dump(0, "missing").unwrap();
+        let busy = database.restore(0, "key".into(), None, &payload, false);
+        database
+            .restore(0, "copy".into(), Some(expires_at), &payload, false)
+            .unwrap();
+        database
+            .restore(0, "key".into(), None, &payload, true)
+            .unwrap();
+        database
+            .restore(0, "expired".into(), Some(1), &payload, false)
+            .unwrap();
+        let corrupted = database.restore(
+            0,
+            "other".into(),
+            None,
+            &payload[..payload.len() - 1],
+            false,
+        );
+        // Then
+        assert_eq!(missing, None);
+        assert_eq!(
+            busy.unwrap_err().to_string(),
+            "BUSYKEY Target key name already exists."
+        );
+        assert_eq!(
+            database.keyspace(0).get("copy".into()).unwrap(),
+            Some(b"12".to_vec())
+        );
+        assert!(database.keyspace(0).ttl("copy".into()).unwrap() > 0);
+        assert_eq!(database.keyspace(0).ttl("key".into()).unwrap(), -1);
+        assert_eq!(database.keyspace(0).get("expired".into()).unwrap(), None);
+        assert_eq!(
+            corrupted.unwrap_err().to_string(),
+            "ERR DUMP payload version or checksum are wrong"
+        );
+    }
+
+    #[test]
+    fn test_restore_rejects_truncated_and_forged_payloads() {
+        // Given
+        let mut database = Database::new();
+        let contents: &[&[u8]] = &[
+            b"",
+            b"\x05",
+            b"\x00",
+            b"\x00\x40",
+            b"\x00\x80\x00",
+            b"\x00\x81\x00\x00",
+            b"\x00\x05abc",
+            b"\x00\xc0",
+            b"\x00\xc1\x01",
+            b"\x00\xc2\x01\x02",
+            b"\x00\xc3",
+            b"\x00\xc3\x05\x0a\x01",
+            b"\x00\xc3\x40",
+            b"\x00\xc3\x02\x80\xff\xff\xff\xff\x00a",
+            b"\x00\xc3\x02\x05\xe0\xff",
+        ];
+        for content in contents {
+            // A client can compute the checksum of any payload
+            let mut payload = content.to_vec();
+            payload.extend(11u16.to_le_bytes());
+            payload.extend(crc64(0, &payload).to_le_bytes());
+            // When
+            let result = database.restore(0, "key".into(), None, &payload, true);
+            // Then
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "ERR Bad data format",
+                "{content:?}"
+            );
+        }
+        assert!(database
+            .restore(0, "key".into(), None, b"\x00\x40", true)
+            .is_err());
+        assert_eq!(database.keyspace(0).size(), 0);
+    }
 }
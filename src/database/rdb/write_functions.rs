@@ -0,0 +1,127 @@
+use crate::database::{
+    get_current_time_ms,
+    snapshot::Snapshot,
+    string::{parse_canonical_integer, Value},
+};
+
+use super::{
+    crc64::crc64,
+    op_code::{
+        OP_CODE_AUX, OP_CODE_EOF, OP_CODE_EXPIRETIME_MS, OP_CODE_RESIZEDB, OP_CODE_SELECTDB,
+    },
+};
+
+const RDB_HEADER: &[u8] = b"REDIS0011";
+/// Version of RDB_HEADER, written at the end of DUMP payloads
+pub(super) const RDB_VERSION: u16 = 11;
+pub(super) const VALUE_TYPE_STRING: u8 = 0;
+
+const WRITE_LENGTH_6BIT_MAX: usize = (1 << 6) - 1;
+const WRITE_LENGTH_14BIT_MAX: usize = (1 << 14) - 1;
+const WRITE_LENGTH_14BIT: u8 = 0x40;
+const WRITE_LENGTH_32BIT: u8 = 0x80;
+const WRITE_LENGTH_64BIT: u8 = 0x81;
+const WRITE_ENCODING_INT8: u8 = 0xc0;
+const WRITE_ENCODING_INT16: u8 = 0xc1;
+const WRITE_ENCODING_INT32: u8 = 0xc2;
+
+impl Snapshot {
+    /// Writes the dataset in the RDB format, ending with the CRC64 of the file.
+    /// An RDB written as the base of an AOF is marked with the aof-base field.
+    pub fn write_rdb(&self, bytes: &mut Vec<u8>, is_aof_base: bool) -> anyhow::Result<()> {
+        let start = bytes.len();
+        bytes.extend(RDB_HEADER);
+        let ctime = (get_current_time_ms()? / 1000).to_string();
+        write_auxiliary(bytes, "redis-bits", "64");
+        write_auxiliary(bytes, "ctime", &ctime);
+        write_auxiliary(bytes, "aof-base", if is_aof_base { "1" } else { "0" });
+
+        for (db, entries) in self.keyspaces.iter().enumerate() {
+            if entries.is_empty() {
+                continue;
+            }
+            bytes.push(OP_CODE_SELECTDB);
+            write_length(bytes, db);
+            let expires_count = entries
+                .iter()
+                .filter(|(_, entry)| entry.expires_at.is_some())
+                .count();
+            bytes.push(OP_CODE_RESIZEDB);
+            write_length(bytes, entries.len());
+            write_length(bytes, expires_count);
+
+            for (key, entry) in entries {
+                if let Some(expires_at) = entry.expires_at {
+                    bytes.push(OP_CODE_EXPIRETIME_MS);
+                    bytes.extend((expires_at as u64).to_le_bytes());
+                }
+                bytes.push(VALUE_TYPE_STRING);
+                write_bytes(bytes, key.as_bytes());
+                write_value(bytes, &entry.value);
+            }
+        }
+
+        bytes.push(OP_CODE_EOF);
+        let checksum = crc64(0, &bytes[start..]);
+        bytes.extend(checksum.to_le_bytes());
+        Ok(())
+    }
+}
+
+fn write_auxiliary(bytes: &mut Vec<u8>, key: &str, value: &str) {
+    bytes.push(OP_CODE_AUX);
+    write_bytes(bytes, key.as_bytes());
+    write_bytes(bytes, value.as_bytes());
+}
+
+/// Writes the value in the encoding of its type, which the type byte comes before
+pub(super) fn write_value(bytes: &mut Vec<u8>, value: &Value) {
+    write_bytes(bytes, &value.to_bytes());
+}
+
+/// Writes a string, as an integer when it is the canonical form of one fitting 32 bits,
+/// like Redis does for values, keys and auxiliary fields
+fn write_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    if let Some(integer) = parse_canonical_integer(value) {
+        if write_integer(bytes, integer) {
+            return;
+        }
+    }
+    write_length(bytes, value.len());
+    bytes.extend(value);
+}
+
+/// Writes the integer in the shortest little endian encoding, returning false when it
+/// doesn't fit 32 bits
+fn write_integer(bytes: &mut Vec<u8>, integer: i64) -> bool {
+    if let Ok(integer) = i8::try_from(integer) {
+        bytes.push(WRITE_ENCODING_INT8);
+        bytes.extend(integer.to_le_bytes());
+    } else if let Ok(integer) = i16::try_from(integer) {
+        bytes.push(WRITE_ENCODING_INT16);
+        bytes.extend(integer.to_le_bytes());
+    } else if let Ok(integer) = i32::try_from(integer) {
+        bytes.push(WRITE_ENCODING_INT32);
+        bytes.extend(integer.to_le_bytes());
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Writes the length in the shortest encoding. Unlike the integers elsewhere in the
+/// file, 32 and 64 bit lengths are big endian.
+fn write_length(bytes: &mut Vec<u8>, length: usize) {
+    if length <= WRITE_LENGTH_6BIT_MAX {
+        bytes.push(length as u8);
+    } else if length <= WRITE_LENGTH_14BIT_MAX {
+        bytes.push(WRITE_LENGTH_14BIT | (length >> 8) as u8);
+        bytes.push(length as u8);
+    } else if let Ok(length) = u32::try_from(length) {
+        bytes.push(WRITE_LENGTH_32BIT);
+        bytes.extend(length.to_be_bytes());
+    } else {
+        bytes.push(WRITE_LENGTH_64BIT);
+        bytes.extend((length as u64).to_be_bytes());
+    }
+}
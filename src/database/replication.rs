@@ -0,0 +1,754 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    oneshot, watch,
+};
+
+use self::{
+    backlog::ReplicationBacklog,
+    diskless::DisklessSync,
+    wait::{Acknowledgements, WaitCondition, Waiter},
+};
+use super::{aof::encode_command, keyspace::Keyspace, random::Random, Database};
+
+pub use self::diskless::{DisklessLoad, DISKLESS_LOAD_NAMES};
+
+mod backlog;
+mod diskless;
+pub mod wait;
+
+#[cfg(test)]
+mod tests;
+
+const REPLICATION_ID_LENGTH: usize = 40;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MasterAddress {
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for MasterAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// What the connection of a replica sends to it, in order
+#[derive(Debug)]
+pub enum ReplicaOutput {
+    Bytes(Vec<u8>),
+    /// The RDB file of a full resync, sent as a bulk string without the final CRLF
+    RdbFile(File),
+}
+
+/// The address a replica announced with REPLCONF, to tell it apart in INFO
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaInfo {
+    pub ip: String,
+    pub listening_port: u16,
+}
+
+#[derive(Debug)]
+enum ReplicaState {
+    /// Waits for the background save in progress, started before it connected, or for
+    /// the delay of a diskless sync
+    WaitBgsaveStart {
+        since: Instant,
+    },
+    /// Waits for the RDB of the background save started for it, written to dir or in
+    /// memory when diskless, while the writes made since are buffered
+    WaitBgsaveEnd {
+        buffer: Vec<u8>,
+        offset: u64,
+        is_diskless: bool,
+    },
+    Online,
+}
+
+impl ReplicaState {
+    fn name(&self) -> &'static str {
+        match self {
+            ReplicaState::WaitBgsaveStart { .. } | ReplicaState::WaitBgsaveEnd { .. } => {
+                "wait_bgsave"
+            }
+            ReplicaState::Online => "online",
+        }
+    }
+}
+
+struct Replica {
+    /// Tells the replica apart when its connection forwards its acknowledgements
+    id: u64,
+    info: ReplicaInfo,
+    state: ReplicaState,
+    output: UnboundedSender<ReplicaOutput>,
+    /// Replication offset the replica received, from REPLCONF ACK
+    ack_offset: u64,
+    /// Replication offset the replica fsynced to its AOF, when it has one
+    aof_ack_offset: u64,
+    last_ack: Instant,
+}
+
+/// Replication state, on both sides: a master streams its writes to its replicas, and a
+/// replica applies the stream of its master and passes it on to its own replicas
+pub struct Replication {
+    /// Names the history of the dataset. Replicas take the id of their master.
+    replid: String,
+    /// The previous id with the offset it ends at, so that the replicas of a promoted
+    /// replica can resync partially with it
+    replid2: Option<(String, u64)>,
+    /// Bytes of the replication stream produced, or received from the master
+    master_repl_offset: u64,
+    /// Created when the first replica connects
+    backlog: Option<ReplicationBacklog>,
+    /// The db of the last SELECT sent to the replicas
+    selected_db: Option<usize>,
+    replicas: Vec<Replica>,
+    last_ping: Instant,
+    /// The master when this server is a replica, followed by the replication link
+    master: watch::Sender<Option<MasterAddress>>,
+    /// The db selected by the stream of the master
+    master_selected_db: usize,
+    /// Whether the stream of the master is applied, once synced with it
+    master_link_up: bool,
+    /// When the last bytes were received from the master
+    master_last_io: Instant,
+    next_replica_id: u64,
+    /// Clients blocked by WAIT and WAITAOF
+    waiters: Vec<Waiter>,
+    diskless_sync: Option<DisklessSync>,
+}
+
+impl Replication {
+    pub fn new() -> Self {
+        Replication {
+            replid: random_replication_id(),
+            replid2: None,
+            master_repl_offset: 0,
+            backlog: None,
+            selected_db: None,
+            replicas: Vec::new(),
+            last_ping: Instant::now(),
+            master: watch::channel(None).0,
+            master_selected_db: 0,
+            master_link_up: false,
+            master_last_io: Instant::now(),
+            next_replica_id: 0,
+            waiters: Vec::new(),
+            diskless_sync: None,
+        }
+    }
+}
+
+impl Database {
+    pub fn is_replica(&self) -> bool {
+        self.replication.master.borrow().is_some()
+    }
+
+    /// Receives the master to replicate from each time it changes
+    pub fn subscribe_master(&self) -> watch::Receiver<Option<MasterAddress>> {
+        self.replication.master.subscribe()
+    }
+
+    /// Replicates the master from now on, or stops replicating with None. Returns
+    /// whether the master changed.
+    pub fn replica_of(&mut self, master: Option<MasterAddress>) -> bool {
+        if *self.replication.master.borrow() == master {
+            return false;
+        }
+        match &master {
+            Some(master) => println!("-> Connecting to MASTER {master}"),
+            None => {
+                // Replicas of this server resync partially with the new id, up to here
+                let replid =
+                    std::mem::replace(&mut self.replication.replid, random_replication_id());
+                self.replication.replid2 = Some((replid, self.replication.master_repl_offset));
+                println!("-> MASTER MODE enabled");
+            }
+        }
+        // The stream of a master never goes on from the one of another server
+        self.replication.selected_db = None;
+        self.replication.master_selected_db = 0;
+        self.replication.master_link_up = false;
+        // They resync with the new master or the new replication id
+        self.disconnect_replicas();
+        self.replication.master.send_replace(master);
+        true
+    }
+
+    /// Rejects the writes of clients on replicas, which only apply the master's writes
+    pub fn check_replica_writable(&self) -> anyhow::Result<()> {
+        if self.is_replica() && self.replica_read_only() {
+            anyhow::bail!("READONLY You can't write against a read only replica.")
+        }
+        Ok(())
+    }
+
+    /// The replication id and offset to continue from with PSYNC
+    pub fn psync_position(&self) -> (String, u64) {
+        (
+            self.replication.replid.clone(),
+            self.replication.master_repl_offset,
+        )
+    }
+
+    /// Bytes of the replication stream produced, or received from the master
+    pub fn replication_offset(&self) -> u64 {
+        self.replication.master_repl_offset
+    }
+
+    /// Answers the PSYNC of a replica, which wants the stream from the offset. Replies
+    /// +CONTINUE with the missed bytes when they are in the backlog, or +FULLRESYNC
+    /// followed by an RDB file of the dataset otherwise. Returns the id of the replica,
+    /// to acknowledge offsets with, and what to send it from now on.
+    pub fn psync(
+        &mut self,
+        replid: &str,
+        offset: i64,
+        info: &ReplicaInfo,
+    ) -> (u64, UnboundedReceiver<ReplicaOutput>) {
+        let (output, receiver) = mpsc::unbounded_channel();
+        let id = self.replication.next_replica_id;
+        self.replication.next_replica_id += 1;
+        let mut replica = Replica {
+            id,
+            info: info.clone(),
+            state: ReplicaState::WaitBgsaveStart {
+                since: Instant::now(),
+            },
+            output,
+            ack_offset: 0,
+            aof_ack_offset: 0,
+            last_ack: Instant::now(),
+        };
+
+        if let Some(missed) = self.missed_bytes(replid, offset) {
+            println!(
+                "-> Partial resynchronization request from {}:{} accepted, sending {} bytes \
+                 of backlog",
+                info.ip,
+                info.listening_port,
+                missed.len()
+            );
+            let reply = format!("+CONTINUE {}\r\n", self.replication.replid);
+            let _ = replica
+                .output
+                .send(ReplicaOutput::Bytes(reply.into_bytes()));
+            let _ = replica.output.send(ReplicaOutput::Bytes(missed));
+            replica.state = ReplicaState::Online;
+            self.replication.replicas.push(replica);
+            return (id, receiver);
+        }
+
+        println!(
+            "-> Full resync requested by replica {}:{}",
+            info.ip, info.listening_port
+        );
+        if self.replication.backlog.is_none() {
+            self.create_replication_backlog();
+        }
+        // Another replica waiting for the save in progress shares it, with the writes
+        // buffered since
+        let is_diskless = self.repl_diskless_sync();
+        let attached = self
+            .replication
+            .replicas
+            .iter()
+            .find_map(|other| match &other.state {
+                ReplicaState::WaitBgsaveEnd {
+                    buffer,
+                    offset,
+                    is_diskless: other_is_diskless,
+                } if *other_is_diskless == is_diskless => Some((buffer.clone(), *offset)),
+                _ => None,
+            });
+        if let Some((buffer, offset)) = attached {
+            send_full_resync(&mut replica, &self.replication.replid, offset);
+            replica.state = ReplicaState::WaitBgsaveEnd {
+                buffer,
+                offset,
+                is_diskless,
+            };
+        }
+        self.replication.replicas.push(replica);
+        self.start_full_resyncs();
+        (id, receiver)
+    }
+
+    /// Records the offsets a replica acknowledged with REPLCONF ACK, which may unblock
+    /// clients waiting for them
+    pub fn replica_ack(&mut self, id: u64, offset: u64, aof_offset: Option<u64>) {
+        let Some(replica) = self
+            .replication
+            .replicas
+            .iter_mut()
+            .find(|replica| replica.id == id)
+        else {
+            return;
+        };
+        replica.ack_offset = replica.ack_offset.max(offset);
+        if let Some(aof_offset) = aof_offset {
+            replica.aof_ack_offset = replica.aof_ack_offset.max(aof_offset);
+        }
+        replica.last_ack = Instant::now();
+        self.unblock_waiters();
+    }
+
+    /// The offsets a replica acknowledges to its master: the one received, and the one
+    /// fsynced to its AOF when it has one
+    pub fn replication_ack(&self) -> (u64, Option<u64>) {
+        (
+            self.replication.master_repl_offset,
+            self.aof_fsynced_offset(),
+        )
+    }
+
+    /// Blocks until the writes made so far are acknowledged as the condition tells.
+    /// Returns their offset and the receiver of the acknowledgements once the condition
+    /// is met, which the caller stops waiting for on timeout.
+    pub fn wait_for_acknowledgements(
+        &mut self,
+        condition: WaitCondition,
+    ) -> anyhow::Result<(u64, oneshot::Receiver<Acknowledgements>)> {
+        if self.is_replica() {
+            match condition {
+                WaitCondition::Replicas(_) => anyhow::bail!(
+                    "ERR WAIT cannot be used with replica instances. Please also note that \
+                     writes to replicas are just local and are not propagated."
+                ),
+                WaitCondition::Fsynced { .. } => anyhow::bail!(
+                    "ERR WAITAOF cannot be used with replica instances. Please also note \
+                     that writes to replicas are just local and are not propagated."
+                ),
+            }
+        }
+        if matches!(condition, WaitCondition::Fsynced { local: true, .. }) && !self.is_aof_on() {
+            anyhow::bail!(
+                "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled."
+            )
+        }
+
+        let offset = self.replication.master_repl_offset;
+        let (reply, receiver) = oneshot::channel();
+        let acknowledgements = self.acknowledgements(offset, condition);
+        if condition.is_met(acknowledgements) {
+            let _ = reply.send(acknowledgements);
+            return Ok((offset, receiver));
+        }
+        self.replication.waiters.push(Waiter {
+            offset,
+            condition,
+            reply,
+        });
+        // Replicas acknowledge once per second on their own otherwise
+        let waits_for_replicas = match condition {
+            WaitCondition::Replicas(replicas) | WaitCondition::Fsynced { replicas, .. } => {
+                replicas > 0
+            }
+        };
+        if waits_for_replicas {
+            let getack = ["REPLCONF", "GETACK", "*"].map(|argument| argument.as_bytes().to_vec());
+            self.feed_replicas(None, &getack);
+        }
+        Ok((offset, receiver))
+    }
+
+    /// Who acknowledged the offset, in the way the condition waits for
+    pub fn acknowledgements(&self, offset: u64, condition: WaitCondition) -> Acknowledgements {
+        let replicas = self.replication.replicas.iter();
+        match condition {
+            WaitCondition::Replicas(_) => Acknowledgements {
+                local: false,
+                replicas: replicas
+                    .filter(|replica| replica.ack_offset >= offset)
+                    .count(),
+            },
+            WaitCondition::Fsynced { .. } => Acknowledgements {
+                local: self
+                    .aof_fsynced_offset()
+                    .is_some_and(|fsynced_offset| fsynced_offset >= offset),
+                replicas: replicas
+                    .filter(|replica| replica.aof_ack_offset >= offset)
+                    .count(),
+            },
+        }
+    }
+
+    /// Records a write command run in the db on the replication stream
+    pub(in crate::database) fn feed_replicas(&mut self, db: Option<usize>, arguments: &[Vec<u8>]) {
+        if self.replication.backlog.is_none() {
+            // WAITAOF follows the writes fsynced to the AOF by their offset
+            if !self.is_aof_on() {
+                return;
+            }
+            self.create_replication_backlog();
+        }
+        let mut bytes = Vec::new();
+        if let Some(db) = db {
+            if self.replication.selected_db != Some(db) {
+                encode_command(&mut bytes, &[b"SELECT".to_vec(), db.to_string().into()]);
+                self.replication.selected_db = Some(db);
+            }
+        }
+        encode_command(&mut bytes, arguments);
+        self.feed_replication_stream(&bytes);
+    }
+
+    /// The db the stream of the master runs its commands in
+    pub fn master_selected_db(&self) -> usize {
+        self.replication.master_selected_db
+    }
+
+    /// Records a command of the master's stream, once run: its bytes count towards the
+    /// replication offset and go on to the replicas of this server as they are. A write,
+    /// run in db, counts towards the save points and is logged to the AOF.
+    pub fn propagate_from_master(
+        &mut self,
+        db: usize,
+        selected_db: usize,
+        write_arguments: Option<&[Vec<u8>]>,
+        bytes: &[u8],
+    ) {
+        self.replication.master_selected_db = selected_db;
+        self.replication.master_last_io = Instant::now();
+        if let Some(arguments) = write_arguments {
+            self.save_status.dirty += 1;
+            self.feed_aof(db, arguments);
+        }
+        self.feed_replication_stream(bytes);
+    }
+
+    /// Replaces the dataset with the RDB file received from the master on a full resync.
+    /// The file becomes the RDB file of this server.
+    pub fn load_master_rdb(
+        &mut self,
+        received_path: &Path,
+        replid: String,
+        offset: u64,
+    ) -> anyhow::Result<()> {
+        // Its snapshot is of the dataset replaced below
+        self.discard_bgsave();
+        fs::rename(received_path, self.rdb_path())?;
+        self.keyspaces
+            .iter_mut()
+            .for_each(|keyspace| *keyspace = Keyspace::new());
+        self.metadata.clear();
+        println!("-> MASTER <-> REPLICA sync: Loading DB in memory");
+        self.load_from_disk()?;
+        self.save_status.saved(self.save_status.dirty);
+        self.synced_with_master(replid, offset)
+    }
+
+    /// Continues the stream of the master from the dataset loaded on a full resync
+    fn synced_with_master(&mut self, replid: String, offset: u64) -> anyhow::Result<()> {
+        self.replication.replid = replid;
+        self.replication.replid2 = None;
+        self.replication.master_repl_offset = offset;
+        self.replication.master_selected_db = 0;
+        self.replication.backlog = Some(ReplicationBacklog::new(self.repl_backlog_size(), offset));
+        // Their dataset is of the previous history
+        self.disconnect_replicas();
+        if self.is_aof_on() {
+            self.create_aof()?;
+        }
+        self.replication.master_link_up = true;
+        self.replication.master_last_io = Instant::now();
+        println!("-> MASTER <-> REPLICA sync: Finished with success");
+        Ok(())
+    }
+
+    /// Continues the stream of the master after a partial resync, which may have a new
+    /// replication id when it was promoted
+    pub fn continue_from_master(&mut self, replid: Option<String>) {
+        self.replication.master_link_up = true;
+        self.replication.master_last_io = Instant::now();
+        let Some(replid) = replid.filter(|replid| *replid != self.replication.replid) else {
+            return;
+        };
+        let previous = std::mem::replace(&mut self.replication.replid, replid);
+        self.replication.replid2 = Some((previous, self.replication.master_repl_offset));
+        // They resync partially with the new id
+        self.disconnect_replicas();
+    }
+
+    /// The link with the master broke, until it syncs again
+    pub fn master_link_down(&mut self) {
+        self.replication.master_link_up = false;
+    }
+
+    /// Pings the replicas so that they notice when the master is gone, starts the save
+    /// replicas wait for, and unblocks the clients waiting for the AOF fsyncs. Runs on
+    /// every background tick.
+    pub fn replication_cron(&mut self) {
+        let output_closed = |replica: &Replica| replica.output.is_closed();
+        if self.replication.replicas.iter().any(output_closed) {
+            self.replication
+                .replicas
+                .retain(|replica| !output_closed(replica));
+        }
+
+        let ping_period = Duration::from_secs(self.repl_ping_replica_period());
+        if !self.is_replica()
+            && !self.replication.replicas.is_empty()
+            && self.replication.last_ping.elapsed() >= ping_period
+        {
+            self.feed_replicas(None, &[b"PING".to_vec()]);
+            self.replication.last_ping = Instant::now();
+        }
+
+        self.finish_diskless_sync();
+        self.start_full_resyncs();
+        self.unblock_waiters();
+    }
+
+    /// The replication section of INFO
+    pub fn replication_info(&self) -> String {
+        let replication = &self.replication;
+        let mut info = String::from("# Replication\r\n");
+        let master = replication.master.borrow().clone();
+        match master {
+            None => info.push_str("role:master\r\n"),
+            Some(master) => {
+                let link_status = if replication.master_link_up {
+                    "up"
+                } else {
+                    "down"
+                };
+                info.push_str(&format!(
+                    "role:slave\r\n\
+                     master_host:{}\r\n\
+                     master_port:{}\r\n\
+                     master_link_status:{link_status}\r\n\
+                     master_last_io_seconds_ago:{}\r\n\
+                     slave_repl_offset:{}\r\n\
+                     slave_read_only:{}\r\n",
+                    master.host,
+                    master.port,
+                    replication.master_last_io.elapsed().as_secs(),
+                    replication.master_repl_offset,
+                    u8::from(self.replica_read_only()),
+                ));
+            }
+        }
+
+        info.push_str(&format!(
+            "connected_slaves:{}\r\n",
+            replication.replicas.len()
+        ));
+        for (index, replica) in replication.replicas.iter().enumerate() {
+            info.push_str(&format!(
+                "slave{index}:ip={},port={},state={},offset={},lag={}\r\n",
+                replica.info.ip,
+                replica.info.listening_port,
+                replica.state.name(),
+                replica.ack_offset,
+                replica.last_ack.elapsed().as_secs()
+            ));
+        }
+
+        let (replid2, second_offset) = match &replication.replid2 {
+            Some((replid2, end_offset)) => (replid2.clone(), *end_offset as i64 + 1),
+            None => ("0".repeat(REPLICATION_ID_LENGTH), -1),
+        };
+        info.push_str(&format!(
+            "master_replid:{}\r\n\
+             master_replid2:{replid2}\r\n\
+             master_repl_offset:{}\r\n\
+             second_repl_offset:{second_offset}\r\n",
+            replication.replid, replication.master_repl_offset
+        ));
+        match &replication.backlog {
+            // Offsets count from 1 in INFO, like in PSYNC
+            Some(backlog) => info.push_str(&format!(
+                "repl_backlog_active:1\r\n\
+                 repl_backlog_size:{}\r\n\
+                 repl_backlog_first_byte_offset:{}\r\n\
+                 repl_backlog_histlen:{}\r\n",
+                self.repl_backlog_size(),
+                backlog.start_offset() + 1,
+                replication.master_repl_offset - backlog.start_offset()
+            )),
+            None => info.push_str(
+                "repl_backlog_active:0\r\n\
+                 repl_backlog_size:0\r\n\
+                 repl_backlog_first_byte_offset:0\r\n\
+                 repl_backlog_histlen:0\r\n",
+            ),
+        }
+        info
+    }
+
+    /// Sends the RDB file to the replicas waiting for the background save that ended,
+    /// then the writes made since. They are dropped when it failed.
+    pub(in crate::database) fn bgsave_done_for_replicas(&mut self, is_saved: bool) {
+        let rdb_path = self.rdb_path();
+        self.replication.replicas.retain_mut(|replica| {
+            let ReplicaState::WaitBgsaveEnd {
+                buffer,
+                is_diskless: false,
+                ..
+            } = &mut replica.state
+            else {
+                return true;
+            };
+            if !is_saved {
+                return false;
+            }
+            let file = match File::open(&rdb_path) {
+                Ok(file) => file,
+                Err(error) => {
+                    eprintln!("-> Can't open the RDB file for the replicas: {error}");
+                    return false;
+                }
+            };
+            let buffer = std::mem::take(buffer);
+            replica.state = ReplicaState::Online;
+            replica.output.send(ReplicaOutput::RdbFile(file)).is_ok()
+                && replica.output.send(ReplicaOutput::Bytes(buffer)).is_ok()
+        });
+    }
+
+    /// Applies a new repl-backlog-size
+    pub fn resize_replication_backlog(&mut self) {
+        let size = self.repl_backlog_size();
+        if let Some(backlog) = &mut self.replication.backlog {
+            backlog.resize(size);
+        }
+    }
+
+    fn create_replication_backlog(&mut self) {
+        // A new backlog starts a new history, which no replica can continue
+        self.replication.replid = random_replication_id();
+        self.replication.replid2 = None;
+        self.replication.backlog = Some(ReplicationBacklog::new(
+            self.repl_backlog_size(),
+            self.replication.master_repl_offset,
+        ));
+    }
+
+    /// Starts the save the replicas waiting for a full resync need, to disk or diskless
+    /// as repl-diskless-sync tells
+    fn start_full_resyncs(&mut self) {
+        let is_waiting = self
+            .replication
+            .replicas
+            .iter()
+            .any(|replica| matches!(replica.state, ReplicaState::WaitBgsaveStart { .. }));
+        if !is_waiting {
+            return;
+        }
+        if self.repl_diskless_sync() {
+            self.start_diskless_sync_when_due();
+        } else if self.rdb_save.is_none() {
+            self.start_bgsave_for_replication();
+        }
+    }
+
+    fn start_bgsave_for_replication(&mut self) {
+        if let Err(error) = self.start_bgsave() {
+            eprintln!("-> Can't start the background save for the replicas: {error}");
+            self.replication
+                .replicas
+                .retain(|replica| !matches!(replica.state, ReplicaState::WaitBgsaveStart { .. }));
+            return;
+        }
+        self.wait_for_rdb(false);
+    }
+
+    /// Sends +FULLRESYNC to the replicas waiting for a save, which was just started.
+    /// They get its RDB, then the stream goes on from there.
+    fn wait_for_rdb(&mut self, is_diskless: bool) {
+        self.replication.selected_db = None;
+        let replid = self.replication.replid.clone();
+        let offset = self.replication.master_repl_offset;
+        for replica in &mut self.replication.replicas {
+            if matches!(replica.state, ReplicaState::WaitBgsaveStart { .. }) {
+                send_full_resync(replica, &replid, offset);
+                replica.state = ReplicaState::WaitBgsaveEnd {
+                    buffer: Vec::new(),
+                    offset,
+                    is_diskless,
+                };
+            }
+        }
+    }
+
+    /// The bytes a replica missed, when it continues the same history and the backlog
+    /// still has them
+    fn missed_bytes(&self, replid: &str, offset: i64) -> Option<Vec<u8>> {
+        let backlog = self.replication.backlog.as_ref()?;
+        // PSYNC asks for the offset of the next byte, counting from 1
+        let offset = u64::try_from(offset).ok()?.checked_sub(1)?;
+        let is_same_history = replid == self.replication.replid
+            || self
+                .replication
+                .replid2
+                .as_ref()
+                .is_some_and(|(replid2, end_offset)| replid == replid2 && offset <= *end_offset);
+        if !is_same_history {
+            return None;
+        }
+        backlog.since(offset)
+    }
+
+    /// Replies to the waiters whose condition is met, and forgets the ones that gave up
+    fn unblock_waiters(&mut self) {
+        if self.replication.waiters.is_empty() {
+            return;
+        }
+        let waiters = std::mem::take(&mut self.replication.waiters);
+        for waiter in waiters {
+            if waiter.reply.is_closed() {
+                continue;
+            }
+            let acknowledgements = self.acknowledgements(waiter.offset, waiter.condition);
+            if waiter.condition.is_met(acknowledgements) {
+                let _ = waiter.reply.send(acknowledgements);
+            } else {
+                self.replication.waiters.push(waiter);
+            }
+        }
+    }
+
+    fn feed_replication_stream(&mut self, bytes: &[u8]) {
+        self.replication.master_repl_offset += bytes.len() as u64;
+        if let Some(backlog) = &mut self.replication.backlog {
+            backlog.append(bytes);
+        }
+        self.replication
+            .replicas
+            .retain_mut(|replica| match &mut replica.state {
+                ReplicaState::WaitBgsaveStart { .. } => true,
+                ReplicaState::WaitBgsaveEnd { buffer, .. } => {
+                    buffer.extend(bytes);
+                    true
+                }
+                ReplicaState::Online => replica
+                    .output
+                    .send(ReplicaOutput::Bytes(bytes.to_vec()))
+                    .is_ok(),
+            });
+    }
+
+    fn disconnect_replicas(&mut self) {
+        self.replication.replicas.clear();
+    }
+}
+
+fn send_full_resync(replica: &mut Replica, replid: &str, offset: u64) {
+    let reply = format!("+FULLRESYNC {replid} {offset}\r\n");
+    let _ = replica
+        .output
+        .send(ReplicaOutput::Bytes(reply.into_bytes()));
+}
+
+fn random_replication_id() -> String {
+    Random::new().hex_string(REPLICATION_ID_LENGTH)
+}
@@ -0,0 +1,50 @@
+use std::collections::VecDeque;
+
+/// The end of the replication stream, kept so that a replica that was disconnected for
+/// a short while only needs the bytes it missed. Older bytes drop off as new ones come.
+pub struct ReplicationBacklog {
+    bytes: VecDeque<u8>,
+    size: usize,
+    /// Replication offset of the byte after the last one kept
+    end_offset: u64,
+}
+
+impl ReplicationBacklog {
+    /// Creates an empty backlog continuing the stream at the offset
+    pub fn new(size: usize, end_offset: u64) -> Self {
+        ReplicationBacklog {
+            bytes: VecDeque::new(),
+            size,
+            end_offset,
+        }
+    }
+
+    pub fn append(&mut self, bytes: &[u8]) {
+        self.end_offset += bytes.len() as u64;
+        let kept = &bytes[bytes.len().saturating_sub(self.size)..];
+        let overflow = (self.bytes.len() + kept.len()).saturating_sub(self.size);
+        self.bytes.drain(..overflow);
+        self.bytes.extend(kept);
+    }
+
+    /// Replication offset of the first byte kept
+    pub fn start_offset(&self) -> u64 {
+        self.end_offset - self.bytes.len() as u64
+    }
+
+    /// Returns the bytes from the offset on, or None when they are not all kept
+    pub fn since(&self, offset: u64) -> Option<Vec<u8>> {
+        if offset < self.start_offset() || offset > self.end_offset {
+            return None;
+        }
+        let skipped = (offset - self.start_offset()) as usize;
+        Some(self.bytes.range(skipped..).copied().collect())
+    }
+
+    /// Keeps at most size bytes, dropping the oldest ones
+    pub fn resize(&mut self, size: usize) {
+        self.size = size;
+        let overflow = self.bytes.len().saturating_sub(size);
+        self.bytes.drain(..overflow);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{ReplicaOutput, ReplicaState};
+use crate::database::{keyspace::Keyspace, rdb::verify_rdb_checksum, Database};
+
+const DISKLESS_LOAD_DISABLED: &str = "disabled";
+const DISKLESS_LOAD_ON_EMPTY_DB: &str = "on-empty-db";
+const DISKLESS_LOAD_SWAPDB: &str = "swapdb";
+pub const DISKLESS_LOAD_NAMES: &[&str] = &[
+    DISKLESS_LOAD_DISABLED,
+    DISKLESS_LOAD_ON_EMPTY_DB,
+    DISKLESS_LOAD_SWAPDB,
+];
+
+/// How a replica loads the RDB of a full resync
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisklessLoad {
+    /// Saved to dir first, then loaded from there
+    Disabled,
+    /// Loaded from the socket when the dataset is empty, saved to dir first otherwise
+    OnEmptyDb,
+    /// Loaded from the socket, keeping the current dataset in memory until then so that
+    /// a failed load leaves it as it was
+    SwapDb,
+}
+
+impl DisklessLoad {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            DISKLESS_LOAD_ON_EMPTY_DB => DisklessLoad::OnEmptyDb,
+            DISKLESS_LOAD_SWAPDB => DisklessLoad::SwapDb,
+            _ => DisklessLoad::Disabled,
+        }
+    }
+}
+
+/// The RDB of a diskless full resync, written in memory on a background thread
+pub struct DisklessSync {
+    thread: JoinHandle<anyhow::Result<Vec<u8>>>,
+}
+
+impl Database {
+    /// Whether the RDB of the full resync in progress is loaded straight from the socket
+    pub fn loads_master_rdb_from_socket(&self) -> bool {
+        match self.repl_diskless_load() {
+            DisklessLoad::Disabled => false,
+            DisklessLoad::OnEmptyDb => self.keyspaces.iter().all(|keyspace| keyspace.size() == 0),
+            DisklessLoad::SwapDb => true,
+        }
+    }
+
+    /// Replaces the dataset with the RDB received from the master on a full resync,
+    /// without writing it to dir. The current dataset is back when the RDB fails to load.
+    pub fn load_master_rdb_bytes(
+        &mut self,
+        rdb_bytes: &[u8],
+        replid: String,
+        offset: u64,
+    ) -> anyhow::Result<()> {
+        println!("-> MASTER <-> REPLICA sync: Loading DB in memory from the socket");
+        verify_rdb_checksum(rdb_bytes)?;
+        // Its snapshot is of the dataset replaced below
+        self.discard_bgsave();
+        let empty_keyspaces = self.keyspaces.iter().map(|_| Keyspace::new()).collect();
+        let previous_keyspaces = std::mem::replace(&mut self.keyspaces, empty_keyspaces);
+        let previous_metadata = std::mem::take(&mut self.metadata);
+        if let Err(error) = self.parse_and_restore_rdb(rdb_bytes) {
+            self.keyspaces = previous_keyspaces;
+            self.metadata = previous_metadata;
+            anyhow::bail!("Failed to load the RDB received from the MASTER: {error}")
+        }
+        drop(previous_keyspaces);
+        self.synced_with_master(replid, offset)
+    }
+
+    /// Starts the diskless sync the replicas wait for once they had the delay to share
+    /// it, or once enough of them wait
+    pub(super) fn start_diskless_sync_when_due(&mut self) {
+        if self.replication.diskless_sync.is_some() {
+            return;
+        }
+        let waiting_since = self
+            .replication
+            .replicas
+            .iter()
+            .filter_map(|replica| match replica.state {
+                ReplicaState::WaitBgsaveStart { since } => Some(since),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let Some(first_since) = waiting_since.iter().min() else {
+            return;
+        };
+        let max_replicas = self.repl_diskless_sync_max_replicas();
+        let delay = Duration::from_secs(self.repl_diskless_sync_delay());
+        let is_due = first_since.elapsed() >= delay
+            || (max_replicas > 0 && waiting_since.len() >= max_replicas);
+        if !is_due {
+            return;
+        }
+
+        let snapshot = match self.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                eprintln!("-> Can't start the diskless sync for the replicas: {error}");
+                return;
+            }
+        };
+        let spawned = thread::Builder::new()
+            .name("repl-diskless-sync".into())
+            .spawn(move || {
+                let mut bytes = Vec::new();
+                snapshot.write_rdb(&mut bytes, false)?;
+                Ok(bytes)
+            });
+        let thread = match spawned {
+            Ok(thread) => thread,
+            Err(error) => {
+                eprintln!("-> Can't start the diskless sync for the replicas: {error}");
+                return;
+            }
+        };
+        self.replication.diskless_sync = Some(DisklessSync { thread });
+        println!(
+            "-> Starting diskless sync for {} replicas",
+            waiting_since.len()
+        );
+        self.wait_for_rdb(true);
+    }
+
+    /// Sends the RDB written in memory to the replicas waiting for it, then the writes
+    /// made since. They are dropped when writing it failed.
+    pub(super) fn finish_diskless_sync(&mut self) {
+        let is_done = self
+            .replication
+            .diskless_sync
+            .as_ref()
+            .is_some_and(|sync| sync.thread.is_finished());
+        if !is_done {
+            return;
+        }
+        let Some(sync) = self.replication.diskless_sync.take() else {
+            return;
+        };
+        let result = match sync.thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("The diskless sync thread panicked")),
+        };
+        let rdb = match result {
+            Ok(rdb) => Some(rdb),
+            Err(error) => {
+                eprintln!("-> Diskless sync for the replicas failed: {error}");
+                None
+            }
+        };
+        self.replication.replicas.retain_mut(|replica| {
+            let ReplicaState::WaitBgsaveEnd {
+                buffer,
+                is_diskless: true,
+                ..
+            } = &mut replica.state
+            else {
+                return true;
+            };
+            let Some(rdb) = &rdb else {
+                return false;
+            };
+            // Sent as a bulk string without the final CRLF, like an RDB file
+            let mut bytes = format!("${}\r\n", rdb.len()).into_bytes();
+            bytes.extend(rdb);
+            let buffer = std::mem::take(buffer);
+            replica.state = ReplicaState::Online;
+            replica.output.send(ReplicaOutput::Bytes(bytes)).is_ok()
+                && replica.output.send(ReplicaOutput::Bytes(buffer)).is_ok()
+        });
+        if rdb.is_some() {
+            println!("-> Diskless sync for the replicas terminated with success");
+        }
+    }
+}
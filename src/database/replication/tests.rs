@@ -0,0 +1,322 @@
+#[cfg(test)]
+mod test {
+    use crate::database::{
+        replication::{
+            backlog::ReplicationBacklog,
+            wait::{Acknowledgements, WaitCondition},
+            MasterAddress, ReplicaInfo, ReplicaOutput,
+        },
+        test_helpers::{database_in_temp_dir, wait_for_bgsave},
+        Database,
+    };
+    use std::{fs, io::Read, thread, time::Duration};
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    const DISK_SYNC: &[(&str, &str)] = &[("repl-diskless-sync", "no")];
+
+    fn wait_for_diskless_sync(database: &mut Database) {
+        while database.replication.diskless_sync.is_some() {
+            database.replication_cron();
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn rdb_bytes(database: &Database) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        database
+            .snapshot()
+            .unwrap()
+            .write_rdb(&mut bytes, false)
+            .unwrap();
+        bytes
+    }
+
+    fn set(key: &str) -> Vec<Vec<u8>> {
+        vec![b"SET".to_vec(), key.as_bytes().to_vec(), b"value".to_vec()]
+    }
+
+    /// Returns the bytes sent, with the RDB file as its length, and the ones that are not
+    /// UTF-8 as their length
+    fn sent(output: &mut UnboundedReceiver<ReplicaOutput>) -> Vec<String> {
+        let mut sent = Vec::new();
+        while let Ok(output) = output.try_recv() {
+            match output {
+                ReplicaOutput::Bytes(bytes) => match String::from_utf8(bytes) {
+                    Ok(string) => sent.push(string),
+                    Err(error) => sent.push(format!("bytes {}", error.as_bytes().len())),
+                },
+                ReplicaOutput::RdbFile(mut file) => {
+                    let mut bytes = Vec::new();
+                    file.read_to_end(&mut bytes).unwrap();
+                    sent.push(format!("rdb {}", bytes.len()));
+                }
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn test_backlog_keeps_the_last_bytes() {
+        // Given
+        let mut backlog = ReplicationBacklog::new(8, 100);
+        // When
+        backlog.append(b"12345");
+        backlog.append(b"6789");
+        let kept = backlog.since(101);
+        let all_missed = backlog.since(105);
+        let dropped = backlog.since(100);
+        let beyond_end = backlog.since(110);
+        backlog.resize(2);
+        // Then
+        assert_eq!(backlog.start_offset(), 107);
+        assert_eq!(kept, Some(b"23456789".to_vec()));
+        assert_eq!(all_missed, Some(b"6789".to_vec()));
+        assert_eq!(dropped, None);
+        assert_eq!(beyond_end, None);
+        assert_eq!(backlog.since(109), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_full_resync_then_partial_resync() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("psync", DISK_SYNC);
+        database.propagate(0, &set("before"));
+        let info = ReplicaInfo::default();
+        // When
+        let (_, mut full) = database.psync("?", -1, &info);
+        let (replid, offset) = database.psync_position();
+        database.propagate(1, &set("during"));
+        wait_for_bgsave(&mut database);
+        let full_sent = sent(&mut full);
+        let (_, end_offset) = database.psync_position();
+        let (_, mut partial) = database.psync(&replid, offset as i64 + 1, &info);
+        database.propagate(1, &set("after"));
+        let (_, mut too_old) = database.psync("other", end_offset as i64 + 1, &info);
+        // Then
+        let rdb_length = fs::metadata(dir.join("dump.rdb")).unwrap().len();
+        let during =
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n1\r\n*3\r\n$3\r\nSET\r\n$6\r\nduring\r\n$5\r\nvalue\r\n";
+        assert_eq!(
+            full_sent,
+            vec![
+                format!("+FULLRESYNC {replid} {offset}\r\n"),
+                format!("rdb {rdb_length}"),
+                during.to_string()
+            ]
+        );
+        assert_eq!(end_offset, offset + during.len() as u64);
+        let after = "*3\r\n$3\r\nSET\r\n$5\r\nafter\r\n$5\r\nvalue\r\n";
+        assert_eq!(
+            sent(&mut partial),
+            vec![
+                format!("+CONTINUE {replid}\r\n"),
+                during.to_string(),
+                after.to_string()
+            ]
+        );
+        assert!(sent(&mut too_old)[0].starts_with("+FULLRESYNC"));
+        wait_for_bgsave(&mut database);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_promoted_replica_accepts_the_previous_id() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("promoted", DISK_SYNC);
+        let _ = database.psync("?", -1, &ReplicaInfo::default());
+        wait_for_bgsave(&mut database);
+        let master = MasterAddress {
+            host: "127.0.0.1".into(),
+            port: 6379,
+        };
+        database.replica_of(Some(master));
+        let (replid, offset) = database.psync_position();
+        let bytes = b"*1\r\n$4\r\nPING\r\n";
+        database.propagate_from_master(0, 0, None, bytes);
+        // When
+        database.replica_of(None);
+        let (new_replid, new_offset) = database.psync_position();
+        let (_, mut output) = database.psync(&replid, offset as i64 + 1, &ReplicaInfo::default());
+        // Then
+        assert_ne!(new_replid, replid);
+        assert_eq!(new_offset, offset + bytes.len() as u64);
+        assert_eq!(
+            sent(&mut output),
+            vec![
+                format!("+CONTINUE {new_replid}\r\n"),
+                String::from_utf8(bytes.to_vec()).unwrap()
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_replica_rejects_writes_and_keeps_expired_keys() {
+        // Given
+        let mut database = Database::new();
+        database
+            .keyspace(0)
+            .set("key".into(), "value".into(), Some(0))
+            .unwrap();
+        let master = MasterAddress {
+            host: "127.0.0.1".into(),
+            port: 6379,
+        };
+        // When
+        database.replica_of(Some(master));
+        let read_only = database.check_replica_writable();
+        let expired_count = database
+            .active_expire_cycle(Duration::from_secs(1))
+            .unwrap();
+        database
+            .config_set(&[("replica-read-only".into(), "no".into())])
+            .unwrap();
+        let writable = database.check_replica_writable();
+        // Then
+        assert!(read_only.unwrap_err().to_string().starts_with("READONLY"));
+        assert_eq!(expired_count, 0);
+        assert!(writable.is_ok());
+    }
+
+    #[test]
+    fn test_wait_unblocks_once_replicas_acknowledge() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("wait", DISK_SYNC);
+        let (first, mut first_output) = database.psync("?", -1, &ReplicaInfo::default());
+        let (second, _second_output) = database.psync("?", -1, &ReplicaInfo::default());
+        wait_for_bgsave(&mut database);
+        sent(&mut first_output);
+        database.propagate(0, &set("key"));
+        // When
+        let (offset, mut receiver) = database
+            .wait_for_acknowledgements(WaitCondition::Replicas(2))
+            .unwrap();
+        let is_blocked = receiver.try_recv().is_err();
+        database.replica_ack(first, offset, None);
+        let is_blocked_by_one = receiver.try_recv().is_err();
+        database.replica_ack(second, offset + 100, None);
+        // Then
+        assert!(is_blocked);
+        assert!(is_blocked_by_one);
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            Acknowledgements {
+                local: false,
+                replicas: 2
+            }
+        );
+        let getack = "*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+        assert_eq!(sent(&mut first_output).last().unwrap(), getack);
+        let info = database.replication_info();
+        assert!(info.contains("connected_slaves:2\r\n"));
+        assert!(info.contains(&format!("state=online,offset={offset},lag=0\r\n")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_waitaof_counts_the_local_fsync() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("waitaof", DISK_SYNC);
+        let without_aof = database.wait_for_acknowledgements(WaitCondition::Fsynced {
+            local: true,
+            replicas: 0,
+        });
+        database
+            .config_set(&[("appendfsync".into(), "always".into())])
+            .unwrap();
+        database.create_aof().unwrap();
+        database.propagate(0, &set("key"));
+        let condition = WaitCondition::Fsynced {
+            local: true,
+            replicas: 1,
+        };
+        // When
+        let offset = database.replication_offset();
+        let not_flushed = database.acknowledgements(offset, condition);
+        database.flush_aof().unwrap();
+        let flushed = database.acknowledgements(offset, condition);
+        let (_, mut receiver) = database.wait_for_acknowledgements(condition).unwrap();
+        // Then
+        assert!(without_aof
+            .unwrap_err()
+            .to_string()
+            .starts_with("ERR WAITAOF cannot be used when numlocal is set"));
+        assert!(!not_flushed.local);
+        assert!(flushed.local);
+        assert_eq!(database.aof_fsynced_offset(), Some(offset));
+        assert!(receiver.try_recv().is_err());
+        database.stop_aof().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_diskless_sync_sends_the_rdb_from_memory() {
+        // Given
+        let settings = [
+            ("repl-diskless-sync-delay", "60"),
+            ("repl-diskless-sync-max-replicas", "2"),
+        ];
+        let (mut database, dir) = database_in_temp_dir("diskless", &settings);
+        database
+            .keyspace(0)
+            .set("key".into(), "value".into(), None)
+            .unwrap();
+        let expected_rdb = rdb_bytes(&database);
+        let info = ReplicaInfo::default();
+        // When
+        let (_, mut first) = database.psync("?", -1, &info);
+        database.replication_cron();
+        let sent_before_delay = sent(&mut first);
+        let (_, mut second) = database.psync("?", -1, &info);
+        let (replid, offset) = database.psync_position();
+        database.propagate(0, &set("during"));
+        wait_for_diskless_sync(&mut database);
+        // Then
+        assert!(sent_before_delay.is_empty());
+        let rdb_length = format!("${}\r\n", expected_rdb.len()).len() + expected_rdb.len();
+        let during =
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*3\r\n$3\r\nSET\r\n$6\r\nduring\r\n$5\r\nvalue\r\n";
+        for output in [&mut first, &mut second] {
+            assert_eq!(
+                sent(output),
+                vec![
+                    format!("+FULLRESYNC {replid} {offset}\r\n"),
+                    format!("bytes {rdb_length}"),
+                    during.to_string()
+                ]
+            );
+        }
+        assert!(!dir.join("dump.rdb").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_diskless_load_keeps_the_dataset_when_failing() {
+        // Given
+        let mut master = Database::new();
+        master
+            .keyspace(1)
+            .set("new".into(), "value".into(), None)
+            .unwrap();
+        let rdb = rdb_bytes(&master);
+        let mut database = Database::new();
+        database
+            .keyspace(0)
+            .set("old".into(), "value".into(), None)
+            .unwrap();
+        // When
+        let truncated = database.load_master_rdb_bytes(&rdb[..rdb.len() - 12], "a".repeat(40), 7);
+        let old_kept = database.keyspace(0).get("old".into()).unwrap();
+        let loaded = database.load_master_rdb_bytes(&rdb, "b".repeat(40), 9);
+        // Then
+        assert!(truncated.is_err());
+        assert_eq!(old_kept, Some(b"value".to_vec()));
+        assert!(loaded.is_ok());
+        assert_eq!(database.keyspace(0).size(), 0);
+        assert_eq!(
+            database.keyspace(1).get("new".into()).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(database.psync_position(), ("b".repeat(40), 9));
+    }
+}
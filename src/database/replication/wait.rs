@@ -0,0 +1,36 @@
+use tokio::sync::oneshot;
+
+/// What WAIT and WAITAOF block for, once the writes made so far are acknowledged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitCondition {
+    /// Replicas that received the writes
+    Replicas(usize),
+    /// The writes fsynced to the local AOF when local is set, and to the AOF of replicas
+    Fsynced { local: bool, replicas: usize },
+}
+
+/// Who acknowledged an offset, counting the kind of acknowledgement of a condition
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Acknowledgements {
+    pub local: bool,
+    pub replicas: usize,
+}
+
+impl WaitCondition {
+    pub fn is_met(&self, acknowledgements: Acknowledgements) -> bool {
+        match *self {
+            WaitCondition::Replicas(replicas) => acknowledgements.replicas >= replicas,
+            WaitCondition::Fsynced { local, replicas } => {
+                (!local || acknowledgements.local) && acknowledgements.replicas >= replicas
+            }
+        }
+    }
+}
+
+/// A client blocked by WAIT or WAITAOF, replied to once the condition is met. It gives
+/// up on its own when the timeout is reached.
+pub struct Waiter {
+    pub offset: u64,
+    pub condition: WaitCondition,
+    pub reply: oneshot::Sender<Acknowledgements>,
+}
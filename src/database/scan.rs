@@ -0,0 +1,53 @@
+use super::{get_current_time_ms, keyspace::Keyspace, pattern::glob_match};
+
+/// Buckets visited for each requested key before giving up on filling the reply,
+/// to bound the time spent on sparse tables
+const SCAN_MAX_ITERATIONS_PER_COUNT: usize = 10;
+
+impl Keyspace {
+    /// Iterates the keyspace from the cursor, returning the next cursor (0 when the
+    /// iteration is complete) and the keys matching the optional pattern and type.
+    /// Every key present for the whole iteration is returned at least once.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+        type_name: Option<&str>,
+    ) -> anyhow::Result<(u64, Vec<String>)> {
+        let now = get_current_time_ms()?;
+        let mut keys = Vec::new();
+        let mut cursor = cursor;
+        let mut max_iterations = count.saturating_mul(SCAN_MAX_ITERATIONS_PER_COUNT);
+
+        loop {
+            cursor = self.data.scan(cursor, |key, entry| {
+                let is_matching = !entry.is_expired(now)
+                    && pattern.map_or(true, |pattern| glob_match(pattern, key, false))
+                    && type_name.map_or(true, |type_name| {
+                        entry.type_name().eq_ignore_ascii_case(type_name)
+                    });
+                if is_matching {
+                    keys.push(key.clone());
+                }
+            });
+            max_iterations -= 1;
+            if cursor == 0 || max_iterations == 0 || keys.len() >= count {
+                break;
+            }
+        }
+
+        Ok((cursor, keys))
+    }
+
+    /// Iterates the elements of the collection stored at key (HSCAN, SSCAN and ZSCAN).
+    /// Only string values can be stored for now, so an existing key always holds
+    /// the wrong type and a missing key is an empty collection.
+    pub fn scan_collection(&mut self, key: &str) -> anyhow::Result<(u64, Vec<String>)> {
+        self.delete_if_expired(key)?;
+        if self.data.contains_key(key) {
+            anyhow::bail!("WRONGTYPE Operation against a key holding the wrong kind of value")
+        }
+        Ok((0, Vec::new()))
+    }
+}
@@ -0,0 +1,80 @@
+use std::{fs, process};
+
+use super::Database;
+
+#[cfg(test)]
+mod tests;
+
+/// How SHUTDOWN saves the dataset before exiting
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ShutdownOptions {
+    /// Saves the RDB file even without save points when true, never when false, and
+    /// when save points are set otherwise
+    pub save: Option<bool>,
+    /// Exits right away instead of waiting for lagging replicas
+    pub now: bool,
+    /// Exits even when saving the dataset fails
+    pub force: bool,
+}
+
+impl Database {
+    /// Makes the dataset durable before exiting: the AOF is flushed and fsynced, and the
+    /// RDB file saved as the options tell. The server keeps running if saving fails,
+    /// unless forced.
+    pub fn prepare_shutdown(&mut self, options: ShutdownOptions) -> anyhow::Result<()> {
+        // Their snapshots are older than the final state written below
+        self.discard_bgsave();
+        self.discard_aof_rewrite();
+
+        let save = options
+            .save
+            .unwrap_or_else(|| !self.save_points().is_empty());
+        if save {
+            println!("-> Saving the final RDB snapshot before exiting");
+            if let Err(error) = self.save() {
+                if !options.force {
+                    anyhow::bail!("Error trying to save the DB, can't exit: {error}")
+                }
+                eprintln!("-> Error trying to save the DB, exiting anyway: {error}");
+            }
+        }
+        if let Err(error) = self.stop_aof() {
+            if !options.force {
+                anyhow::bail!("Error flushing the AOF, can't exit: {error}")
+            }
+            eprintln!("-> Error flushing the AOF, exiting anyway: {error}");
+        }
+
+        self.remove_pidfile();
+        self.is_shut_down = true;
+        println!("-> Redis is now ready to exit, bye bye...");
+        Ok(())
+    }
+
+    /// Whether the dataset was saved for exiting, after which no command may run
+    pub fn is_shut_down(&self) -> bool {
+        self.is_shut_down
+    }
+
+    /// Shutdowns never wait, so there is none in progress to abort
+    pub fn abort_shutdown(&self) -> anyhow::Result<()> {
+        eprintln!("-> Failed to abort shutdown: No shutdown in progress.");
+        anyhow::bail!("ERR Errors trying to abort SHUTDOWN. Check logs.")
+    }
+
+    /// Writes the process id to the pidfile, if set. Failing to is not fatal.
+    pub fn create_pidfile(&self) {
+        let Some(path) = self.pidfile() else {
+            return;
+        };
+        if let Err(error) = fs::write(&path, format!("{}\n", process::id())) {
+            eprintln!("-> Failed to write PID file {path}: {error}");
+        }
+    }
+
+    fn remove_pidfile(&self) {
+        if let Some(path) = self.pidfile() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod test {
+    use crate::database::{shutdown::ShutdownOptions, test_helpers::database_in_temp_dir};
+    use std::{env, fs};
+
+    #[test]
+    fn test_shutdown_saves_as_told() {
+        // Given
+        let (mut with_save_points, with_save_points_dir) = database_in_temp_dir("points", &[]);
+        let (mut nosave, nosave_dir) = database_in_temp_dir("nosave", &[]);
+        let (mut save, save_dir) = database_in_temp_dir("save", &[("save", "")]);
+        // When
+        with_save_points
+            .prepare_shutdown(ShutdownOptions::default())
+            .unwrap();
+        nosave
+            .prepare_shutdown(ShutdownOptions {
+                save: Some(false),
+                ..Default::default()
+            })
+            .unwrap();
+        save.prepare_shutdown(ShutdownOptions {
+            save: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+        // Then
+        assert!(with_save_points.is_shut_down());
+        assert!(with_save_points_dir.join("dump.rdb").exists());
+        assert!(!nosave_dir.join("dump.rdb").exists());
+        assert!(save_dir.join("dump.rdb").exists());
+        for dir in [with_save_points_dir, nosave_dir, save_dir] {
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_shutdown_fails_when_saving_fails_unless_forced() {
+        // Given
+        let (mut database, dir) = database_in_temp_dir("force", &[]);
+        fs::remove_dir_all(dir).unwrap();
+        // When
+        let not_forced = database.prepare_shutdown(ShutdownOptions::default());
+        let is_shut_down_after_failure = database.is_shut_down();
+        let forced = database.prepare_shutdown(ShutdownOptions {
+            force: true,
+            ..Default::default()
+        });
+        // Then
+        assert!(not_forced.unwrap_err().to_string().contains("can't exit"));
+        assert!(!is_shut_down_after_failure);
+        assert!(forced.is_ok());
+        assert!(database.is_shut_down());
+    }
+
+    #[test]
+    fn test_pidfile_is_removed_on_shutdown() {
+        // Given
+        let dir = env::temp_dir().join(format!("database-{}-pidfile", std::process::id()));
+        let pidfile = dir.join("redis.pid");
+        let (mut database, dir) = database_in_temp_dir(
+            "pidfile",
+            &[("save", ""), ("pidfile", &pidfile.display().to_string())],
+        );
+        // When
+        database.create_pidfile();
+        let contents = fs::read_to_string(&pidfile).unwrap();
+        database
+            .prepare_shutdown(ShutdownOptions::default())
+            .unwrap();
+        // Then
+        assert_eq!(contents, format!("{}\n", std::process::id()));
+        assert!(!pidfile.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
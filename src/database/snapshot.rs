@@ -0,0 +1,27 @@
+use super::{get_current_time_ms, Database, Entry};
+
+/// A copy of the dataset at one point in time, which background threads can write
+/// to disk while the database keeps changing
+pub struct Snapshot {
+    /// The keys of each numbered database, without the expired ones
+    pub(super) keyspaces: Vec<Vec<(String, Entry)>>,
+}
+
+impl Database {
+    pub fn snapshot(&self) -> anyhow::Result<Snapshot> {
+        let now = get_current_time_ms()?;
+        let keyspaces = self
+            .keyspaces
+            .iter()
+            .map(|keyspace| {
+                keyspace
+                    .data
+                    .iter()
+                    .filter(|(_, entry)| !entry.is_expired(now))
+                    .map(|(key, entry)| (key.clone(), entry.clone()))
+                    .collect()
+            })
+            .collect();
+        Ok(Snapshot { keyspaces })
+    }
+}
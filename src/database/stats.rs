@@ -0,0 +1,21 @@
+use super::Database;
+
+/// Counters of the server activity, reset by CONFIG RESETSTAT
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    pub total_commands_processed: u64,
+    pub total_error_replies: u64,
+}
+
+impl Database {
+    pub fn record_command(&mut self, is_error: bool) {
+        self.stats.total_commands_processed += 1;
+        if is_error {
+            self.stats.total_error_replies += 1;
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+}
@@ -0,0 +1,377 @@
+use super::{keyspace::Keyspace, Entry};
+
+/// Max size of a string value (proto-max-bulk-len)
+pub const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+/// Longest string representation of an i64
+const MAX_INTEGER_LENGTH: usize = 20;
+
+/// A string value. Values that are the canonical representation of an i64 are stored
+/// as integers, so counters are not reparsed on every increment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Raw(Vec<u8>),
+    Integer(i64),
+}
+
+impl Value {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Raw(bytes) => bytes.clone(),
+            Value::Integer(integer) => integer.to_string().into_bytes(),
+        }
+    }
+
+    /// Returns the bytes for in place modification, converting an integer first
+    pub fn raw_mut(&mut self) -> &mut Vec<u8> {
+        if let Value::Integer(integer) = self {
+            *self = Value::Raw(integer.to_string().into_bytes());
+        }
+        match self {
+            Value::Raw(bytes) => bytes,
+            Value::Integer(_) => unreachable!("integer values were converted above"),
+        }
+    }
+
+    fn to_integer(&self) -> Option<i64> {
+        match self {
+            Value::Raw(bytes) => parse_canonical_integer(bytes),
+            Value::Integer(integer) => Some(*integer),
+        }
+    }
+
+    fn to_float(&self) -> Option<f64> {
+        match self {
+            Value::Raw(bytes) => parse_float(bytes),
+            Value::Integer(integer) => Some(*integer as f64),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        match parse_canonical_integer(&bytes) {
+            Some(integer) => Value::Integer(integer),
+            None => Value::Raw(bytes),
+        }
+    }
+}
+
+/// A range of matching bytes found by LCS, with inclusive start and end positions
+#[derive(Debug, PartialEq)]
+pub struct LcsMatch {
+    pub range_1: (usize, usize),
+    pub range_2: (usize, usize),
+    pub length: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Lcs {
+    pub value: Vec<u8>,
+    pub matches: Vec<LcsMatch>,
+}
+
+impl Keyspace {
+    /// Appends the value to the string stored at key, creating it if needed.
+    /// Returns the new length.
+    pub fn append(&mut self, key: String, value: &[u8]) -> anyhow::Result<usize> {
+        self.delete_if_expired(&key)?;
+        let Some(entry) = self.data.get_mut(&key) else {
+            let length = value.len();
+            self.set(key, value.to_vec(), None)?;
+            return Ok(length);
+        };
+
+        let bytes = entry.value.raw_mut();
+        validate_length(bytes.len() + value.len())?;
+        bytes.extend_from_slice(value);
+        Ok(bytes.len())
+    }
+
+    pub fn strlen(&mut self, key: String) -> anyhow::Result<usize> {
+        Ok(self.get(key)?.map_or(0, |value| value.len()))
+    }
+
+    /// Returns the substring between start and end (both inclusive).
+    /// Negative positions are offsets from the end of the string.
+    pub fn get_range(&mut self, key: String, start: i64, end: i64) -> anyhow::Result<Vec<u8>> {
+        let Some(value) = self.get(key)? else {
+            return Ok(Vec::new());
+        };
+        if start < 0 && end < 0 && start > end {
+            return Ok(Vec::new());
+        }
+
+        let length = value.len() as i64;
+        let start = if start < 0 { length + start } else { start }.max(0);
+        let end = if end < 0 { length + end } else { end }
+            .max(0)
+            .min(length - 1);
+        if start > end || length == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(value[start as usize..=end as usize].to_vec())
+    }
+
+    /// Overwrites the string stored at key from the offset, padding it with zeros
+    /// if it is shorter than the offset. Returns the new length.
+    pub fn set_range(&mut self, key: String, offset: usize, value: &[u8]) -> anyhow::Result<usize> {
+        self.delete_if_expired(&key)?;
+        let Some(entry) = self.data.get_mut(&key) else {
+            if value.is_empty() {
+                return Ok(0);
+            }
+            validate_length(offset + value.len())?;
+            let mut new_value = vec![0; offset];
+            new_value.extend_from_slice(value);
+            let length = new_value.len();
+            self.set(key, new_value, None)?;
+            return Ok(length);
+        };
+
+        let bytes = entry.value.raw_mut();
+        if value.is_empty() {
+            return Ok(bytes.len());
+        }
+        let end = offset + value.len();
+        validate_length(end)?;
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset..end].copy_from_slice(value);
+        Ok(bytes.len())
+    }
+
+    pub fn get_del(&mut self, key: String) -> anyhow::Result<Option<Vec<u8>>> {
+        let value = self.get(key.clone())?;
+        if value.is_some() {
+            self.delete(key)?;
+        }
+        Ok(value)
+    }
+
+    /// Sets the value, removing any expiry, and returns the previous value
+    pub fn get_set(&mut self, key: String, value: Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+        let previous_value = self.get(key.clone())?;
+        self.set(key, value, None)?;
+        Ok(previous_value)
+    }
+
+    /// Sets the value only if the key does not exist. Returns true if it was set.
+    pub fn set_nx(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        expires_at: Option<u128>,
+    ) -> anyhow::Result<bool> {
+        self.delete_if_expired(&key)?;
+        if self.data.contains_key(&key) {
+            return Ok(false);
+        }
+        let value = value.into();
+        self.insert_entry(key, Entry { value, expires_at });
+        Ok(true)
+    }
+
+    /// Finds the longest common subsequence of the strings stored at two keys, with
+    /// the ranges of the matches from the last to the first one. Missing keys are
+    /// treated as empty strings.
+    pub fn lcs(&mut self, key_1: String, key_2: String) -> anyhow::Result<Lcs> {
+        let value_1 = self.get(key_1)?.unwrap_or_default();
+        let value_2 = self.get(key_2)?.unwrap_or_default();
+        // Like Redis, bounds the table to the maximum length of a string
+        let table_size = (value_1.len() as u64 + 1)
+            .checked_mul(value_2.len() as u64 + 1)
+            .and_then(|cells| cells.checked_mul(std::mem::size_of::<u32>() as u64));
+        if table_size.map_or(true, |size| size > MAX_STRING_LENGTH as u64) {
+            anyhow::bail!(
+                "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+            )
+        }
+        Ok(compute_lcs(&value_1, &value_2))
+    }
+}
+
+// Multiple keys
+impl Keyspace {
+    /// Returns the values of the keys, with None for missing keys
+    pub fn multi_get(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.get(key.clone())).collect()
+    }
+
+    /// Sets all the values, or none of them if only_if_new is set and one of the keys
+    /// exists. Returns true if the values were set.
+    pub fn multi_set(
+        &mut self,
+        pairs: &[(String, Vec<u8>)],
+        only_if_new: bool,
+    ) -> anyhow::Result<bool> {
+        if only_if_new {
+            for (key, _) in pairs {
+                self.delete_if_expired(key)?;
+                if self.data.contains_key(key) {
+                    return Ok(false);
+                }
+            }
+        }
+
+        for (key, value) in pairs {
+            self.set(key.clone(), value.clone(), None)?;
+        }
+        Ok(true)
+    }
+}
+
+// Counters
+impl Keyspace {
+    /// Increments the integer stored at key, starting from 0 if the key does not exist.
+    /// Keeps the expiry of the key. Returns the new value.
+    pub fn increment_by(&mut self, key: String, increment: i64) -> anyhow::Result<i64> {
+        self.delete_if_expired(&key)?;
+        let Some(entry) = self.data.get_mut(&key) else {
+            self.insert_entry(
+                key,
+                Entry {
+                    value: Value::Integer(increment),
+                    expires_at: None,
+                },
+            );
+            return Ok(increment);
+        };
+
+        let Some(integer) = entry.value.to_integer() else {
+            anyhow::bail!("ERR value is not an integer or out of range")
+        };
+        let Some(integer) = integer.checked_add(increment) else {
+            anyhow::bail!("ERR increment or decrement would overflow")
+        };
+        entry.value = Value::Integer(integer);
+        Ok(integer)
+    }
+
+    /// Increments the float stored at key, starting from 0 if the key does not exist.
+    /// Keeps the expiry of the key. Returns the new value formatted as it is stored.
+    pub fn increment_by_float(&mut self, key: String, increment: f64) -> anyhow::Result<Vec<u8>> {
+        self.delete_if_expired(&key)?;
+        let float = match self.data.get(&key) {
+            Some(entry) => match entry.value.to_float() {
+                Some(float) => float,
+                None => anyhow::bail!("ERR value is not a valid float"),
+            },
+            None => 0.0,
+        };
+
+        let float = float + increment;
+        if !float.is_finite() {
+            anyhow::bail!("ERR increment would produce NaN or Infinity")
+        }
+        let bytes = format_float(float);
+        match self.data.get_mut(&key) {
+            Some(entry) => entry.value = bytes.clone().into(),
+            None => self.set(key, bytes.clone(), None)?,
+        }
+        Ok(bytes)
+    }
+}
+
+/// Parses an i64 only if the bytes are its canonical representation: no sign other
+/// than a leading '-', no leading zeros and no spaces, as Redis string2ll does
+pub fn parse_canonical_integer(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() || bytes.len() > MAX_INTEGER_LENGTH {
+        return None;
+    }
+    let string = std::str::from_utf8(bytes).ok()?;
+    let integer = string.parse::<i64>().ok()?;
+    (integer.to_string() == string).then_some(integer)
+}
+
+/// Parses a float, rejecting surrounding spaces and NaN
+pub fn parse_float(bytes: &[u8]) -> Option<f64> {
+    let string = std::str::from_utf8(bytes).ok()?;
+    if string.is_empty() || string.trim() != string {
+        return None;
+    }
+    string.parse::<f64>().ok().filter(|float| !float.is_nan())
+}
+
+/// Formats a float in the shortest form parsed back to the same float, so that no
+/// precision is lost, with no exponent and no trailing zeros
+pub fn format_float(float: f64) -> Vec<u8> {
+    // Avoids printing -0 for a negative zero result
+    let float = if float == 0.0 { 0.0 } else { float };
+    float.to_string().into_bytes()
+}
+
+pub(super) fn validate_length(length: usize) -> anyhow::Result<()> {
+    if length > MAX_STRING_LENGTH {
+        anyhow::bail!("ERR string exceeds maximum allowed size (proto-max-bulk-len)")
+    }
+    Ok(())
+}
+
+/// Dynamic programming LCS, walking the table backwards to collect the matching ranges
+/// in the same order as Redis
+fn compute_lcs(a: &[u8], b: &[u8]) -> Lcs {
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut length = table[a.len() * width + b.len()] as usize;
+    let mut value = vec![0; length];
+    let mut matches = Vec::new();
+
+    let (mut i, mut j) = (a.len(), b.len());
+    // None when no range is being tracked
+    let mut range: Option<((usize, usize), (usize, usize))> = None;
+    while i > 0 && j > 0 {
+        let mut emit_range = false;
+        if a[i - 1] == b[j - 1] {
+            value[length - 1] = a[i - 1];
+            match range {
+                None => range = Some(((i - 1, i - 1), (j - 1, j - 1))),
+                // Extends the range backward since it is contiguous
+                Some(((start_1, end_1), (start_2, end_2))) if start_1 == i && start_2 == j => {
+                    range = Some(((start_1 - 1, end_1), (start_2 - 1, end_2)))
+                }
+                Some(_) => emit_range = true,
+            }
+            if let Some(((start_1, _), (start_2, _))) = range {
+                if start_1 == 0 || start_2 == 0 {
+                    emit_range = true;
+                }
+            }
+            length -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit_range = range.is_some();
+        }
+
+        if emit_range {
+            if let Some((range_1, range_2)) = range.take() {
+                matches.push(LcsMatch {
+                    range_1,
+                    range_2,
+                    length: range_1.1 - range_1.0 + 1,
+                });
+            }
+        }
+    }
+
+    Lcs { value, matches }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,265 @@
+#[cfg(test)]
+mod test {
+    use crate::database::{
+        keyspace::Keyspace,
+        string::{
+            format_float, parse_canonical_integer, parse_float, LcsMatch, Value, MAX_STRING_LENGTH,
+        },
+    };
+
+    #[test]
+    fn test_append_and_strlen() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        // When
+        let first_length = keyspace.append("key".into(), b"Hello").unwrap();
+        let second_length = keyspace.append("key".into(), b" World").unwrap();
+        // Then
+        assert_eq!(first_length, 5);
+        assert_eq!(second_length, 11);
+        assert_eq!(keyspace.strlen("key".into()).unwrap(), 11);
+        assert_eq!(keyspace.strlen("missing".into()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_range_with_negative_offsets() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .set("key".into(), "This is a string".into(), None)
+            .unwrap();
+        // Then
+        assert_eq!(keyspace.get_range("key".into(), 0, 3).unwrap(), b"This");
+        assert_eq!(keyspace.get_range("key".into(), -3, -1).unwrap(), b"ing");
+        assert_eq!(
+            keyspace.get_range("key".into(), 0, -1).unwrap(),
+            b"This is a string"
+        );
+        assert_eq!(
+            keyspace.get_range("key".into(), 10, 100).unwrap(),
+            b"string"
+        );
+        assert!(keyspace.get_range("key".into(), 5, 3).unwrap().is_empty());
+        assert!(keyspace.get_range("key".into(), -1, -5).unwrap().is_empty());
+        assert!(keyspace
+            .get_range("missing".into(), 0, -1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_set_range_pads_with_zeros() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .set("key".into(), "Hello World".into(), None)
+            .unwrap();
+        // When
+        let length = keyspace.set_range("key".into(), 6, b"Redis").unwrap();
+        let padded_length = keyspace.set_range("padded".into(), 3, b"ab").unwrap();
+        let empty_length = keyspace.set_range("empty".into(), 3, b"").unwrap();
+        // Then
+        assert_eq!(length, 11);
+        assert_eq!(keyspace.get("key".into()).unwrap().unwrap(), b"Hello Redis");
+        assert_eq!(padded_length, 5);
+        assert_eq!(keyspace.get("padded".into()).unwrap().unwrap(), b"\0\0\0ab");
+        assert_eq!(empty_length, 0);
+        assert_eq!(keyspace.get("empty".into()).unwrap(), None);
+        assert!(keyspace
+            .set_range("key".into(), MAX_STRING_LENGTH, b"x")
+            .is_err());
+    }
+
+    #[test]
+    fn test_get_set_clears_expiry_and_set_nx_keeps_value() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .set("key".into(), "old".into(), Some(u64::MAX as u128))
+            .unwrap();
+        // When
+        let previous_value = keyspace.get_set("key".into(), "new".into()).unwrap();
+        let is_set = keyspace.set_nx("key".into(), "other".into(), None).unwrap();
+        // Then
+        assert_eq!(previous_value, Some("old".into()));
+        assert!(!is_set);
+        assert_eq!(keyspace.get("key".into()).unwrap(), Some("new".into()));
+        assert_eq!(keyspace.ttl("key".into()).unwrap(), -1);
+        assert_eq!(keyspace.get_del("key".into()).unwrap(), Some("new".into()));
+        assert_eq!(keyspace.get("key".into()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_lcs_value_and_matches() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .set("key1".into(), "ohmytext".into(), None)
+            .unwrap();
+        keyspace
+            .set("key2".into(), "mynewtext".into(), None)
+            .unwrap();
+        // When
+        let lcs = keyspace.lcs("key1".into(), "key2".into()).unwrap();
+        // Then
+        assert_eq!(lcs.value, b"mytext");
+        assert_eq!(
+            lcs.matches,
+            vec![
+                LcsMatch {
+                    range_1: (4, 7),
+                    range_2: (5, 8),
+                    length: 4
+                },
+                LcsMatch {
+                    range_1: (2, 3),
+                    range_2: (0, 1),
+                    length: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lcs_rejects_tables_larger_than_a_string() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .set("key1".into(), vec![b'a'; 12_000], None)
+            .unwrap();
+        keyspace
+            .set("key2".into(), vec![b'b'; 12_000], None)
+            .unwrap();
+        // When
+        let result = keyspace.lcs("key1".into(), "key2".into());
+        // Then
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+        );
+    }
+
+    #[test]
+    fn test_canonical_integers_are_integer_encoded() {
+        // Then
+        assert_eq!(Value::from(b"123".to_vec()), Value::Integer(123));
+        assert_eq!(Value::from(b"-5".to_vec()), Value::Integer(-5));
+        assert_eq!(Value::from(b"0123".to_vec()), Value::Raw(b"0123".to_vec()));
+        assert_eq!(Value::from(b"+1".to_vec()), Value::Raw(b"+1".to_vec()));
+        assert_eq!(Value::from(b" 1".to_vec()), Value::Raw(b" 1".to_vec()));
+        assert_eq!(Value::from(b"-0".to_vec()), Value::Raw(b"-0".to_vec()));
+        assert_eq!(parse_canonical_integer(b"9223372036854775808"), None);
+    }
+
+    #[test]
+    fn test_increment_by_keeps_expiry_and_checks_overflow() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .set("counter".into(), "10".into(), Some(u64::MAX as u128))
+            .unwrap();
+        keyspace.set("text".into(), "ten".into(), None).unwrap();
+        // When
+        let value = keyspace.increment_by("counter".into(), 5).unwrap();
+        let new_value = keyspace.increment_by("new".into(), -3).unwrap();
+        // Then
+        assert_eq!(value, 15);
+        assert_eq!(keyspace.get("counter".into()).unwrap(), Some("15".into()));
+        assert!(keyspace.ttl("counter".into()).unwrap() > 0);
+        assert_eq!(new_value, -3);
+        assert_eq!(
+            keyspace
+                .increment_by("text".into(), 1)
+                .unwrap_err()
+                .to_string(),
+            "ERR value is not an integer or out of range"
+        );
+        assert_eq!(
+            keyspace
+                .increment_by("counter".into(), i64::MAX)
+                .unwrap_err()
+                .to_string(),
+            "ERR increment or decrement would overflow"
+        );
+        assert_eq!(keyspace.append("counter".into(), b"0").unwrap(), 3);
+        assert_eq!(keyspace.increment_by("counter".into(), 1).unwrap(), 151);
+    }
+
+    #[test]
+    fn test_increment_by_float_formats_like_redis() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("key".into(), "10.50".into(), None).unwrap();
+        keyspace.set("exp".into(), "5.0e3".into(), None).unwrap();
+        // When
+        let value = keyspace.increment_by_float("key".into(), 0.1).unwrap();
+        let exp_value = keyspace.increment_by_float("exp".into(), 2.0e2).unwrap();
+        // Then
+        assert_eq!(value, b"10.6");
+        assert_eq!(exp_value, b"5200");
+        assert_eq!(format_float(-0.0), b"0");
+        assert_eq!(format_float(1e21), b"1000000000000000000000");
+        assert!(keyspace
+            .increment_by_float("key".into(), f64::INFINITY)
+            .is_err());
+    }
+
+    #[test]
+    fn test_increment_by_float_keeps_the_exact_result() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("key".into(), "0.1".into(), None).unwrap();
+        let increment = parse_float(b"0.1234567890123456789").unwrap();
+        // When
+        let value = keyspace.increment_by_float("key".into(), 0.2).unwrap();
+        let precise_value = keyspace
+            .increment_by_float("precise".into(), increment)
+            .unwrap();
+        let exp_value = keyspace
+            .increment_by_float("exp".into(), parse_float(b"5.0e3").unwrap())
+            .unwrap();
+        // Then
+        assert_eq!(value, b"0.30000000000000004");
+        assert_eq!(parse_float(&value), Some(0.1 + 0.2));
+        assert_eq!(precise_value, b"0.12345678901234568");
+        assert_eq!(parse_float(&precise_value), Some(increment));
+        assert_eq!(keyspace.get("precise".into()).unwrap(), Some(precise_value));
+        assert_eq!(exp_value, b"5000");
+        assert_eq!(format_float(1.5e-7), b"0.00000015");
+        assert_eq!(format_float(-1.0 / 3.0), b"-0.3333333333333333");
+    }
+
+    #[test]
+    fn test_multi_set_only_if_new_is_all_or_nothing() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace
+            .set("existing".into(), "value".into(), None)
+            .unwrap();
+        let pairs = vec![
+            ("new".to_string(), b"1".to_vec()),
+            ("existing".to_string(), b"2".to_vec()),
+        ];
+        // When
+        let is_set = keyspace.multi_set(&pairs, true).unwrap();
+        // Then
+        assert!(!is_set);
+        assert_eq!(
+            keyspace
+                .multi_get(&["new".into(), "existing".into()])
+                .unwrap(),
+            vec![None, Some("value".into())]
+        );
+
+        // When
+        let is_set = keyspace.multi_set(&pairs, false).unwrap();
+        // Then
+        assert!(is_set);
+        assert_eq!(
+            keyspace
+                .multi_get(&["new".into(), "existing".into()])
+                .unwrap(),
+            vec![Some("1".into()), Some("2".into())]
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use std::{env, fs, path::PathBuf, thread, time::Duration};
+
+use crate::cli::CliParam;
+
+use super::Database;
+
+/// A database set up with the settings, its dir being a new temporary dir
+pub(super) fn database_in_temp_dir(name: &str, settings: &[(&str, &str)]) -> (Database, PathBuf) {
+    let dir = env::temp_dir().join(format!("database-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let mut database = Database::new();
+    let mut params = vec![CliParam::new("dir", &[dir.display().to_string()])];
+    for (name, value) in settings {
+        params.push(CliParam::new(name, &[value.to_string()]));
+    }
+    database.config_setup(None, &params).unwrap();
+    (database, dir)
+}
+
+pub(super) fn wait_for_bgsave(database: &mut Database) {
+    while database.rdb_save.is_some() {
+        database.rdb_cron();
+        thread::sleep(Duration::from_millis(10));
+    }
+}
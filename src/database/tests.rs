@@ -0,0 +1,250 @@
+#[cfg(test)]
+mod test {
+    use crate::database::{
+        expire::{ExpireCondition, TTL_NOT_EXISTING, TTL_NOT_EXPIRING},
+        get_current_time_ms,
+        keyspace::Keyspace,
+        Database,
+    };
+    use std::time::Duration;
+
+    const HOUR_MS: i128 = 60 * 60 * 1000;
+
+    fn now() -> i128 {
+        get_current_time_ms().unwrap() as i128
+    }
+
+    #[test]
+    fn test_ttl_returns_codes_for_missing_and_persistent_keys() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("key".into(), "value".into(), None).unwrap();
+        // When
+        let ttl_missing = keyspace.ttl("missing".into()).unwrap();
+        let ttl_persistent = keyspace.ttl("key".into()).unwrap();
+        // Then
+        assert_eq!(ttl_missing, TTL_NOT_EXISTING);
+        assert_eq!(ttl_persistent, TTL_NOT_EXPIRING);
+    }
+
+    #[test]
+    fn test_expire_sets_and_persist_removes_expiry() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("key".into(), "value".into(), None).unwrap();
+        let expires_at = now() + HOUR_MS;
+        // When
+        let is_set = keyspace.expire("key".into(), expires_at, &[]).unwrap();
+        // Then
+        assert!(is_set);
+        assert_eq!(keyspace.expire_time("key".into()).unwrap(), expires_at);
+        assert!(keyspace.ttl("key".into()).unwrap() > HOUR_MS - 1000);
+
+        // When
+        let is_persisted = keyspace.persist("key".into()).unwrap();
+        // Then
+        assert!(is_persisted);
+        assert!(!keyspace.persist("key".into()).unwrap());
+        assert_eq!(keyspace.ttl("key".into()).unwrap(), TTL_NOT_EXPIRING);
+    }
+
+    #[test]
+    fn test_expire_in_the_past_deletes_key() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("key".into(), "value".into(), None).unwrap();
+        // When
+        let is_set = keyspace.expire("key".into(), -1, &[]).unwrap();
+        // Then
+        assert!(is_set);
+        assert_eq!(keyspace.get("key".into()).unwrap(), None);
+        assert!(!keyspace.expire("key".into(), now() + HOUR_MS, &[]).unwrap());
+    }
+
+    #[test]
+    fn test_negative_expiry_while_loading_is_already_expired() {
+        // Given
+        let mut database = Database::new();
+        database
+            .keyspace(0)
+            .set("key".into(), "value".into(), None)
+            .unwrap();
+        // When
+        database.set_loading(true);
+        let is_set = database.keyspace(0).expire("key".into(), -1, &[]).unwrap();
+        let loading_expire_time = database.keyspace(0).expire_time("key".into()).unwrap();
+        database.set_loading(false);
+        // Then
+        assert!(is_set);
+        assert_eq!(loading_expire_time, 0);
+        assert_eq!(database.keyspace(0).get("key".into()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_expire_conditions() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("key".into(), "value".into(), None).unwrap();
+        let expires_at = now() + HOUR_MS;
+        // Then
+        assert!(!keyspace
+            .expire("key".into(), expires_at, &[ExpireCondition::Xx])
+            .unwrap());
+        assert!(!keyspace
+            .expire("key".into(), expires_at, &[ExpireCondition::Gt])
+            .unwrap());
+        assert!(keyspace
+            .expire("key".into(), expires_at, &[ExpireCondition::Nx])
+            .unwrap());
+        assert!(!keyspace
+            .expire("key".into(), expires_at, &[ExpireCondition::Nx])
+            .unwrap());
+        assert!(!keyspace
+            .expire("key".into(), expires_at + 1, &[ExpireCondition::Lt])
+            .unwrap());
+        assert!(keyspace
+            .expire(
+                "key".into(),
+                expires_at + 1,
+                &[ExpireCondition::Xx, ExpireCondition::Gt]
+            )
+            .unwrap());
+        assert!(keyspace
+            .expire("key".into(), expires_at, &[ExpireCondition::Lt])
+            .unwrap());
+        assert_eq!(keyspace.expire_time("key".into()).unwrap(), expires_at);
+    }
+
+    #[test]
+    fn test_active_expire_cycle_deletes_only_expired_keys() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        let future = (now() + HOUR_MS) as u128;
+        for index in 0..200 {
+            let key = format!("expired:{index}");
+            keyspace.set(key, "value".into(), Some(1)).unwrap();
+        }
+        for index in 0..10 {
+            let key = format!("valid:{index}");
+            keyspace.set(key, "value".into(), Some(future)).unwrap();
+        }
+        keyspace
+            .set("persistent".into(), "value".into(), None)
+            .unwrap();
+        // When
+        let expired_count = keyspace
+            .active_expire_cycle(Duration::from_secs(10))
+            .unwrap();
+        // Then
+        assert!(expired_count > 180);
+        assert!(keyspace.data.iter().count() >= 11);
+        assert_eq!(keyspace.ttl("persistent".into()).unwrap(), TTL_NOT_EXPIRING);
+        assert!(keyspace.ttl("valid:0".into()).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_rename_and_copy_keep_expiry() {
+        // Given
+        let mut database = Database::new();
+        let expires_at = now() + HOUR_MS;
+        database
+            .keyspace(0)
+            .set("key".into(), "value".into(), Some(expires_at as u128))
+            .unwrap();
+        // When
+        database
+            .keyspace(0)
+            .rename("key".into(), "renamed".into(), false)
+            .unwrap();
+        let is_copied = database
+            .copy(0, "renamed".into(), "copied".into(), 0, false)
+            .unwrap();
+        // Then
+        let keyspace = database.keyspace(0);
+        assert!(is_copied);
+        assert_eq!(keyspace.exists(&["key".into()]).unwrap(), 0);
+        assert_eq!(keyspace.expire_time("renamed".into()).unwrap(), expires_at);
+        assert_eq!(keyspace.expire_time("copied".into()).unwrap(), expires_at);
+        assert_eq!(
+            keyspace
+                .delete_keys(&["renamed".into(), "copied".into()])
+                .unwrap(),
+            2
+        );
+        assert!(keyspace
+            .rename("key".into(), "other".into(), false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_move_copy_and_swap_between_dbs() {
+        // Given
+        let mut database = Database::new();
+        database
+            .keyspace(0)
+            .set("key".into(), "0".into(), None)
+            .unwrap();
+        database
+            .keyspace(1)
+            .set("other".into(), "1".into(), None)
+            .unwrap();
+        // When
+        let is_copied = database
+            .copy(0, "key".into(), "copied".into(), 2, false)
+            .unwrap();
+        let is_moved = database.move_key(0, "key".into(), 1).unwrap();
+        database.swap_db(1, 2);
+        // Then
+        assert!(is_copied);
+        assert!(is_moved);
+        assert_eq!(database.keyspace(0).size(), 0);
+        assert_eq!(
+            database.keyspace(1).get("copied".into()).unwrap(),
+            Some("0".into())
+        );
+        assert_eq!(
+            database.keyspace(2).get("key".into()).unwrap(),
+            Some("0".into())
+        );
+        assert_eq!(
+            database.keyspace(2).get("other".into()).unwrap(),
+            Some("1".into())
+        );
+        assert!(database.move_key(1, "copied".into(), 1).is_err());
+    }
+
+    #[test]
+    fn test_scan_with_the_largest_count() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        keyspace.set("key".into(), "value".into(), None).unwrap();
+        // When
+        let (cursor, keys) = keyspace.scan(0, None, i64::MAX as usize, None).unwrap();
+        // Then
+        assert_eq!(cursor, 0);
+        assert_eq!(keys, vec!["key".to_string()]);
+    }
+
+    #[test]
+    fn test_set_keep_ttl_keeps_the_expiry() {
+        // Given
+        let mut keyspace = Keyspace::new();
+        let expires_at = get_current_time_ms().unwrap() + HOUR_MS as u128;
+        keyspace
+            .set("key".into(), "value".into(), Some(expires_at))
+            .unwrap();
+        // When
+        keyspace.set_keep_ttl("key".into(), "new".into()).unwrap();
+        keyspace.set_keep_ttl("other".into(), "new".into()).unwrap();
+        // Then
+        assert_eq!(keyspace.get("key".into()).unwrap(), Some("new".into()));
+        assert_eq!(
+            keyspace.expire_time("key".into()).unwrap(),
+            expires_at as i128
+        );
+        assert_eq!(
+            keyspace.expire_time("other".into()).unwrap(),
+            TTL_NOT_EXPIRING
+        );
+    }
+}
@@ -1,17 +1,36 @@
 use cli::CliParam;
 use server::start_database;
-use std::env;
+use std::{env, path::PathBuf, process};
 mod cli;
+mod config_file;
 mod database;
 mod server;
 
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = env::args().collect();
-    let cli_params = CliParam::from(&args[1..]);
 
-    match start_database(cli_params).await {
-        Ok(_) => {}
-        Err(e) => eprintln!("-> Error: {e}"),
+    // An optional config file comes first, and the command line params override it
+    let mut cli_params = Vec::new();
+    let mut cli_args = &args[1..];
+    let config_file = cli_args
+        .first()
+        .filter(|arg| !arg.starts_with("--"))
+        .cloned();
+    if let Some(path) = &config_file {
+        match config_file::load(path) {
+            Ok(params) => cli_params.extend(params),
+            Err(e) => {
+                eprintln!("-> Error: {e}");
+                process::exit(1);
+            }
+        }
+        cli_args = &cli_args[1..];
+    }
+    cli_params.extend(CliParam::from(cli_args));
+
+    if let Err(e) = start_database(config_file.map(PathBuf::from), cli_params).await {
+        eprintln!("-> Error: {e}");
+        process::exit(1);
     }
 }
@@ -1,128 +1,1197 @@
-use std::sync::{Arc, Mutex};
-
-use crate::{cli::CliParam, database::Database};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
+use crate::{
+    cli::CliParam,
+    database::{
+        bitmap::{BitIndexUnit, BitOperation, BitfieldOperation},
+        cluster::{NodeEndpoint, Shard, SlotRange},
+        expire::ExpireCondition,
+        keyspace::Keyspace,
+        migrate::MigrateOptions,
+        replication::{
+            wait::{Acknowledgements, WaitCondition},
+            MasterAddress, ReplicaInfo, ReplicaOutput,
+        },
+        shutdown::ShutdownOptions,
+        Database, TYPE_NAME_NONE,
+    },
 };
+use std::{fs, path::PathBuf, time::Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time;
 
-use self::inbound_message::{config_message::ConfigMessage, InboundMessage};
+use self::database_thread::{Command, DatabaseHandle};
+use self::inbound_message::{
+    cluster_message::ClusterMessage, config_message::ConfigMessage, InboundMessage, LcsMode,
+    TimeUnit,
+};
+use self::listener::{Listener, Stream};
 use self::outbound_message::OutboundMessage;
+use self::resp::parse_command;
 
-const DEFAULT_IP: &str = "127.0.0.1";
-const DEFAULT_PORT: u32 = 6379;
 const MB: usize = 1024 * 1024;
 
+mod aof_loader;
+mod cluster_bus;
+mod database_thread;
 mod inbound_message;
+mod listener;
 mod outbound_message;
+mod replica_link;
 mod resp;
 
-pub async fn start_database(cli_params: Vec<CliParam>) -> anyhow::Result<()> {
+pub async fn start_database(
+    config_file: Option<PathBuf>,
+    cli_params: Vec<CliParam>,
+) -> anyhow::Result<()> {
     let mut database = Database::new();
-    database.config_setup(&cli_params);
+    database.config_setup(config_file, &cli_params)?;
+
+    if database.appendonly() {
+        database.upgrade_aof()?;
+    }
+    if database.appendonly() && database.aof_exists() {
+        // The AOF logs every write, so it is more up to date than the RDB
+        aof_loader::load_aof(&mut database)?;
+        database.open_aof()?;
+    } else {
+        if database.can_load_from_disk() {
+            let result = database.load_from_disk();
+            if let Err(error) = result {
+                eprintln!("-> Failed to load database from disk. Error: {error}");
+            }
+        }
+        if database.appendonly() {
+            database.create_aof()?;
+        }
+    }
+
+    if let Some(master) = database.replicaof() {
+        database.replica_of(Some(master));
+    }
 
-    if database.can_load_from_disk() {
-        let result = database.load_from_disk();
-        if let Err(error) = result {
-            eprintln!("-> Failed to load database from disk. Error: {error}");
+    database.create_pidfile();
+    let listeners = Listener::bind_all(&database).await?;
+    let unix_socket = database.unix_socket();
+    let master = database.subscribe_master();
+    // Replicas announce the port their master can tell them apart with
+    let listening_port = listeners
+        .iter()
+        .find_map(|listener| listener.local_port())
+        .unwrap_or(database.port());
+    let cluster_bus = match database.cluster_enabled() {
+        true => {
+            let (bus_port, output) = database.start_cluster(listening_port)?;
+            let listener = Listener::bind_cluster_bus(&database, bus_port).await?;
+            Some((listener, output))
         }
+        false => None,
+    };
+    let (database, stopped) = DatabaseHandle::spawn(database)?;
+    if let Some((listener, output)) = cluster_bus {
+        println!("-> Cluster bus listening at {}", listener.local_addr()?);
+        tokio::spawn(cluster_bus::serve(database.clone(), listener, output));
     }
+    tokio::spawn(replica_link::follow_master(
+        database.clone(),
+        master,
+        listening_port,
+    ));
 
-    let database = Arc::new(Mutex::new(database));
+    let mut accept_tasks = Vec::new();
+    for listener in listeners {
+        println!(
+            "-> Started database server at {}",
+            listener.local_address()?
+        );
+        let listener_database = database.clone();
+        accept_tasks.push(tokio::spawn(async move {
+            accept_streams(&listener_database, listener).await
+        }));
+    }
+    let accept_all = async {
+        for accept_task in &mut accept_tasks {
+            accept_task.await??;
+        }
+        anyhow::Ok(())
+    };
 
-    let listener = TcpListener::bind(format!("{DEFAULT_IP}:{DEFAULT_PORT}")).await?;
-    println!("-> Started database server at {DEFAULT_IP}:{DEFAULT_PORT}");
+    // Runs until SHUTDOWN or a signal stops the database thread, once the dataset is saved
+    let result = tokio::select! {
+        result = accept_all => result,
+        result = handle_shutdown_signals(&database) => result,
+        result = stopped => match result {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("Database thread panicked")),
+        },
+    };
+    for accept_task in accept_tasks {
+        accept_task.abort();
+    }
+    if let Some(path) = unix_socket {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
 
+/// Shuts down on SIGTERM or SIGINT. Returns only when the handlers can't be set up.
+async fn handle_shutdown_signals(database: &DatabaseHandle) -> anyhow::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
     loop {
-        let (mut stream, _) = listener.accept().await?;
-        let task_database = Arc::clone(&database);
-        tokio::spawn(async move { handle_stream(&task_database, &mut stream).await });
+        tokio::select! {
+            _ = terminate.recv() => println!("-> Received SIGTERM scheduling shutdown..."),
+            _ = interrupt.recv() => println!("-> Received SIGINT scheduling shutdown..."),
+        }
+        // The database thread stops once ready to exit, and keeps running when saving fails
+        if let Err(error) = database.shutdown(ShutdownOptions::default()).await {
+            eprintln!("-> {error}");
+        }
     }
 }
 
-async fn handle_stream(
-    database: &Arc<Mutex<Database>>,
-    stream: &mut TcpStream,
-) -> anyhow::Result<()> {
+async fn accept_streams(database: &DatabaseHandle, listener: Listener) -> anyhow::Result<()> {
     loop {
-        let mut buffer: Vec<u8> = Vec::with_capacity(1 * MB);
-        let bytes_read = stream.read_buf(&mut buffer).await?;
-        let inbound_message = InboundMessage::try_from(&buffer[..bytes_read])?;
-        println!("-> Inbound message: {inbound_message:?}");
-
-        let outbound_message = handle_message(&database, &inbound_message)?;
-        println!("-> Outbound message: {outbound_message:?}");
-        let outbound_message_bytes: Vec<u8> = outbound_message.into();
+        let stream = listener.accept().await?;
+        let task_database = database.clone();
+        tokio::spawn(async move {
+            match stream {
+                Stream::Tcp(mut stream) => {
+                    let ip = match stream.peer_addr() {
+                        Ok(address) => address.ip().to_string(),
+                        Err(_) => String::new(),
+                    };
+                    handle_stream(&task_database, &mut stream, ip).await
+                }
+                Stream::Unix(mut stream) => {
+                    handle_stream(&task_database, &mut stream, String::new()).await
+                }
+            }
+        });
+    }
+}
+
+/// Serves a client connection. The ip is the one of the client, empty on Unix sockets.
+async fn handle_stream<S>(
+    database: &DatabaseHandle,
+    stream: &mut S,
+    ip: String,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut selected_db = 0;
+    let mut replica_info = ReplicaInfo {
+        ip,
+        listening_port: 0,
+    };
+    let mut buffer: Vec<u8> = Vec::with_capacity(MB);
+    // Commands pipelined after a WAIT are still buffered once it is replied to
+    let mut is_buffer_parsed = true;
+    // ASKING applies to the command right after it only
+    let mut is_previous_asking = false;
+    loop {
+        if is_buffer_parsed {
+            let bytes_read = stream.read_buf(&mut buffer).await?;
+            if bytes_read == 0 {
+                return Ok(());
+            }
+        }
+
+        // A read may hold several pipelined commands, or only part of one.
+        // The complete ones are run together and replied to with a single write.
+        let mut commands = Vec::new();
+        let mut protocol_error = None;
+        let mut psync = None;
+        let mut wait = None;
+        loop {
+            let (arguments, read_count) = match parse_command(&buffer) {
+                Ok(Some(command)) => command,
+                Ok(None) => break,
+                Err(error) => {
+                    protocol_error = Some(error);
+                    break;
+                }
+            };
+            buffer.drain(..read_count);
+            if arguments.is_empty() {
+                continue;
+            }
+
+            let inbound_message = InboundMessage::try_from(&arguments[..]);
+            if let Ok(inbound_message) = &inbound_message {
+                println!("-> Inbound message: {inbound_message:?}");
+            }
+            match &inbound_message {
+                Ok(InboundMessage::ReplConf {
+                    listening_port: Some(port),
+                }) => replica_info.listening_port = *port,
+                // The connection becomes the one of a replica, once the commands before
+                // are replied to
+                Ok(InboundMessage::Psync { replid, offset }) => {
+                    psync = Some((replid.clone(), *offset));
+                    break;
+                }
+                // Blocks this connection only, once the commands before are replied to
+                Ok(InboundMessage::Wait { condition, timeout }) => {
+                    wait = Some((*condition, *timeout));
+                    break;
+                }
+                _ => {}
+            }
+            let is_asking = is_previous_asking;
+            is_previous_asking = matches!(inbound_message, Ok(InboundMessage::Asking));
+            commands.push(Command {
+                arguments,
+                message: inbound_message,
+                is_asking,
+            });
+        }
+
+        let mut outbound_messages = Vec::new();
+        if !commands.is_empty() {
+            (outbound_messages, selected_db) = database.run(commands, selected_db).await?;
+        }
+        let is_protocol_error = protocol_error.is_some();
+        if let Some(error) = protocol_error {
+            outbound_messages.push(OutboundMessage::Error(error.to_string()));
+        }
+
+        let mut outbound_message_bytes = Vec::new();
+        for outbound_message in outbound_messages {
+            println!("-> Outbound message: {outbound_message:?}");
+            let bytes: Vec<u8> = outbound_message.into();
+            outbound_message_bytes.extend(bytes);
+        }
         stream.write_all(&outbound_message_bytes).await?;
+        if is_protocol_error {
+            return Ok(());
+        }
+
+        if let Some((replid, offset)) = psync {
+            let info = replica_info.clone();
+            let (id, output) = database
+                .call(move |database| database.psync(&replid, offset, &info))
+                .await?;
+            return serve_replica(database, stream, id, output).await;
+        }
+
+        is_buffer_parsed = wait.is_none();
+        if let Some((condition, timeout)) = wait {
+            let outbound_message = wait_for_acknowledgements(database, condition, timeout).await?;
+            println!("-> Outbound message: {outbound_message:?}");
+            let bytes: Vec<u8> = outbound_message.into();
+            stream.write_all(&bytes).await?;
+        }
+    }
+}
+
+/// Replies to WAIT or WAITAOF once the writes made so far are acknowledged as the
+/// condition tells, or with the acknowledgements so far on timeout
+async fn wait_for_acknowledgements(
+    database: &DatabaseHandle,
+    condition: WaitCondition,
+    timeout: Option<Duration>,
+) -> anyhow::Result<OutboundMessage> {
+    let result = database
+        .call(move |database| database.wait_for_acknowledgements(condition))
+        .await?;
+    let (offset, receiver) = match result {
+        Ok(waiting) => waiting,
+        Err(error) => return Ok(OutboundMessage::Error(error.to_string())),
+    };
+    let acknowledgements = match timeout {
+        Some(timeout) => time::timeout(timeout, receiver).await.ok(),
+        None => Some(receiver.await),
+    };
+    let acknowledgements = match acknowledgements {
+        Some(Ok(acknowledgements)) => acknowledgements,
+        _ => {
+            database
+                .call(move |database| database.acknowledgements(offset, condition))
+                .await?
+        }
+    };
+    Ok(wait_reply(condition, acknowledgements))
+}
+
+fn wait_reply(condition: WaitCondition, acknowledgements: Acknowledgements) -> OutboundMessage {
+    let replicas = acknowledgements.replicas as i64;
+    match condition {
+        WaitCondition::Replicas(_) => OutboundMessage::Integer(replicas),
+        WaitCondition::Fsynced { .. } => OutboundMessage::Integers(vec![
+            Some(i64::from(acknowledgements.local)),
+            Some(replicas),
+        ]),
+    }
+}
+
+/// Sends the replication stream to a replica, until either side drops the link. The
+/// replica acknowledges the offsets it reached on the same connection.
+async fn serve_replica<S>(
+    database: &DatabaseHandle,
+    stream: &mut S,
+    id: u64,
+    mut output: UnboundedReceiver<ReplicaOutput>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer: Vec<u8> = Vec::new();
+    loop {
+        tokio::select! {
+            output = output.recv() => match output {
+                // The replica was disconnected, to resync
+                None => return Ok(()),
+                Some(ReplicaOutput::Bytes(bytes)) => stream.write_all(&bytes).await?,
+                Some(ReplicaOutput::RdbFile(file)) => {
+                    let length = file.metadata()?.len();
+                    stream.write_all(format!("${length}\r\n").as_bytes()).await?;
+                    tokio::io::copy(&mut tokio::fs::File::from_std(file), stream).await?;
+                }
+            },
+            read = stream.read_buf(&mut buffer) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+                while let Some((arguments, read_count)) = parse_command(&buffer)? {
+                    buffer.drain(..read_count);
+                    if let Ok(InboundMessage::ReplConfAck { offset, aof_offset }) =
+                        InboundMessage::try_from(&arguments[..])
+                    {
+                        database
+                            .call(move |database| database.replica_ack(id, offset, aof_offset))
+                            .await?;
+                    }
+                }
+            }
+        }
     }
 }
 
 fn handle_message(
-    database: &Arc<Mutex<Database>>,
+    database: &mut Database,
+    selected_db: &mut usize,
     message: &InboundMessage,
 ) -> anyhow::Result<OutboundMessage> {
+    let db = *selected_db;
+
     match message {
-        &InboundMessage::Config(ref config_message) => {
+        InboundMessage::Config(config_message) => {
             handle_action_config(database, config_message.clone())
         }
-        &InboundMessage::Ping => Ok(OutboundMessage::Pong),
-        &InboundMessage::Echo(ref string) => Ok(OutboundMessage::Echo(string.into())),
-        &InboundMessage::Set {
-            ref key,
-            ref value,
-            ref expires_at,
-        } => handle_action_set(database, key.into(), value.into(), *expires_at),
-        &InboundMessage::Get { ref key } => handle_action_get(database, key.into()),
-        &InboundMessage::Keys { ref pattern } => handle_action_keys(database, pattern.into()),
+        InboundMessage::Ping => Ok(OutboundMessage::Pong),
+        InboundMessage::Echo(string) => Ok(OutboundMessage::Echo(string.into())),
+        InboundMessage::Set {
+            key,
+            value,
+            expires_at,
+            keep_ttl,
+        } => handle_action_set(
+            database.keyspace(db),
+            key.into(),
+            value.clone(),
+            *expires_at,
+            *keep_ttl,
+        ),
+        InboundMessage::Get { key } => handle_action_get(database.keyspace(db), key.into()),
+        InboundMessage::Keys { pattern } => {
+            handle_action_keys(database.keyspace(db), pattern.into())
+        }
+        InboundMessage::Expire {
+            key,
+            expires_at,
+            conditions,
+        } => handle_action_expire(database.keyspace(db), key.into(), *expires_at, conditions),
+        InboundMessage::Ttl { key, unit } => {
+            handle_action_ttl(database.keyspace(db), key.into(), *unit)
+        }
+        InboundMessage::ExpireTime { key, unit } => {
+            handle_action_expire_time(database.keyspace(db), key.into(), *unit)
+        }
+        InboundMessage::Persist { key } => handle_action_persist(database.keyspace(db), key.into()),
+        InboundMessage::Scan {
+            cursor,
+            pattern,
+            count,
+            type_name,
+        } => handle_action_scan(
+            database.keyspace(db),
+            *cursor,
+            pattern.as_deref(),
+            *count,
+            type_name.as_deref(),
+        ),
+        InboundMessage::CollectionScan { key } => {
+            handle_action_collection_scan(database.keyspace(db), key.into())
+        }
+        InboundMessage::Delete { keys } => handle_action_delete(database.keyspace(db), keys),
+        InboundMessage::Exists { keys } => handle_action_exists(database.keyspace(db), keys),
+        InboundMessage::Type { key } => handle_action_type(database.keyspace(db), key),
+        InboundMessage::Rename {
+            key,
+            new_key,
+            only_if_new,
+        } => handle_action_rename(
+            database.keyspace(db),
+            key.into(),
+            new_key.into(),
+            *only_if_new,
+        ),
+        InboundMessage::Copy {
+            source,
+            destination,
+            destination_db,
+            replace,
+        } => handle_action_copy(
+            database,
+            db,
+            source.into(),
+            destination.into(),
+            *destination_db,
+            *replace,
+        ),
+        InboundMessage::Touch { keys } => handle_action_touch(database.keyspace(db), keys),
+        InboundMessage::RandomKey => handle_action_random_key(database.keyspace(db)),
+        InboundMessage::DbSize => handle_action_db_size(database.keyspace(db)),
+        InboundMessage::Select { db } => handle_action_select(database, selected_db, *db),
+        InboundMessage::SwapDb { db, other_db } => handle_action_swap_db(database, *db, *other_db),
+        InboundMessage::Move {
+            key,
+            db: destination_db,
+        } => handle_action_move(database, db, key.into(), *destination_db),
+        InboundMessage::FlushDb => handle_action_flush_db(database.keyspace(db)),
+        InboundMessage::FlushAll => handle_action_flush_all(database),
+        InboundMessage::Append { key, value } => {
+            handle_action_append(database.keyspace(db), key.into(), value)
+        }
+        InboundMessage::Strlen { key } => handle_action_strlen(database.keyspace(db), key.into()),
+        InboundMessage::GetRange { key, start, end } => {
+            handle_action_get_range(database.keyspace(db), key.into(), *start, *end)
+        }
+        InboundMessage::SetRange { key, offset, value } => {
+            handle_action_set_range(database.keyspace(db), key.into(), *offset, value)
+        }
+        InboundMessage::GetDel { key } => handle_action_get_del(database.keyspace(db), key.into()),
+        InboundMessage::GetEx {
+            key,
+            expires_at,
+            persist,
+        } => handle_action_get_ex(database.keyspace(db), key.into(), *expires_at, *persist),
+        InboundMessage::GetSet { key, value } => {
+            handle_action_get_set(database.keyspace(db), key.into(), value.clone())
+        }
+        InboundMessage::SetNx { key, value } => {
+            handle_action_set_nx(database.keyspace(db), key.into(), value.clone())
+        }
+        InboundMessage::SetEx {
+            key,
+            expires_at,
+            value,
+        } => handle_action_set(
+            database.keyspace(db),
+            key.into(),
+            value.clone(),
+            Some(*expires_at),
+            false,
+        ),
+        InboundMessage::Lcs { key_1, key_2, mode } => {
+            handle_action_lcs(database.keyspace(db), key_1.into(), key_2.into(), mode)
+        }
+        InboundMessage::IncrementBy { key, increment } => {
+            handle_action_increment_by(database.keyspace(db), key.into(), *increment)
+        }
+        InboundMessage::IncrementByFloat { key, increment } => {
+            handle_action_increment_by_float(database.keyspace(db), key.into(), *increment)
+        }
+        InboundMessage::MultiGet { keys } => handle_action_multi_get(database.keyspace(db), keys),
+        InboundMessage::MultiSet { pairs, only_if_new } => {
+            handle_action_multi_set(database.keyspace(db), pairs, *only_if_new)
+        }
+        InboundMessage::SetBit { key, offset, bit } => {
+            handle_action_set_bit(database.keyspace(db), key.into(), *offset, *bit)
+        }
+        InboundMessage::GetBit { key, offset } => {
+            handle_action_get_bit(database.keyspace(db), key.into(), *offset)
+        }
+        InboundMessage::BitCount { key, range } => {
+            handle_action_bit_count(database.keyspace(db), key.into(), *range)
+        }
+        InboundMessage::BitPos {
+            key,
+            bit,
+            start,
+            end,
+            unit,
+        } => handle_action_bit_pos(database.keyspace(db), key.into(), *bit, *start, *end, *unit),
+        InboundMessage::BitOp {
+            operation,
+            destination,
+            keys,
+        } => handle_action_bit_op(database.keyspace(db), *operation, destination.into(), keys),
+        InboundMessage::Bitfield { key, operations } => {
+            handle_action_bitfield(database.keyspace(db), key.into(), operations)
+        }
+        InboundMessage::PfAdd { key, elements } => {
+            handle_action_pf_add(database.keyspace(db), key.into(), elements)
+        }
+        InboundMessage::PfCount { keys } => handle_action_pf_count(database.keyspace(db), keys),
+        InboundMessage::PfMerge { destination, keys } => {
+            handle_action_pf_merge(database.keyspace(db), destination.into(), keys)
+        }
+        InboundMessage::BgRewriteAof => handle_action_bg_rewrite_aof(database),
+        InboundMessage::Save => handle_action_save(database),
+        InboundMessage::BgSave => handle_action_bg_save(database),
+        InboundMessage::LastSave => Ok(OutboundMessage::Integer(database.last_save() as i64)),
+        InboundMessage::Shutdown(options) => handle_action_shutdown(database, *options),
+        InboundMessage::ReplicaOf { master } => handle_action_replica_of(database, master.clone()),
+        InboundMessage::ReplConf { .. } => Ok(OutboundMessage::Ok),
+        // Answered by the replication link, after the stream of the master ran it
+        InboundMessage::ReplConfGetAck => Ok(OutboundMessage::Ok),
+        InboundMessage::ReplConfAck { .. } => {
+            anyhow::bail!("ERR REPLCONF ACK is only valid on the connection of a replica")
+        }
+        InboundMessage::Psync { .. } => {
+            anyhow::bail!("ERR PSYNC is only valid as the command of a replica connection")
+        }
+        InboundMessage::Wait { .. } => {
+            anyhow::bail!("ERR WAIT is only valid as the command of a client connection")
+        }
+        InboundMessage::Info { sections } => Ok(OutboundMessage::Info(database.info(sections))),
+        InboundMessage::Cluster(cluster_message) => {
+            handle_action_cluster(database, cluster_message.clone())
+        }
+        InboundMessage::Asking => {
+            database.check_asking()?;
+            Ok(OutboundMessage::Ok)
+        }
+        InboundMessage::Restore {
+            key,
+            expires_at,
+            payload,
+            replace,
+            ..
+        } => {
+            database.restore(db, key.into(), *expires_at, payload, *replace)?;
+            Ok(OutboundMessage::Ok)
+        }
+        InboundMessage::Migrate(options) => handle_action_migrate(database, db, options),
+        InboundMessage::Dump { key } => Ok(OutboundMessage::Get(database.dump(db, key)?)),
+        InboundMessage::ShutdownAbort => {
+            database.abort_shutdown()?;
+            Ok(OutboundMessage::Ok)
+        }
     }
 }
 
 fn handle_action_config(
-    database: &Arc<Mutex<Database>>,
+    database: &mut Database,
     config_message: ConfigMessage,
 ) -> anyhow::Result<OutboundMessage> {
-    let Ok(database) = database.lock() else {
-        anyhow::bail!("Failed to lock database");
-    };
     match config_message {
-        ConfigMessage::Get { key } => {
-            let value = database.config_get(&key);
-            Ok(OutboundMessage::ConfigGet { key, value })
+        ConfigMessage::Get { patterns } => {
+            let settings = database.config_get_matching(&patterns);
+            Ok(OutboundMessage::ConfigGet(settings))
+        }
+        ConfigMessage::Set { pairs } => {
+            database.config_set(&pairs)?;
+            Ok(OutboundMessage::Ok)
+        }
+        ConfigMessage::ResetStat => {
+            database.reset_stats();
+            Ok(OutboundMessage::Ok)
+        }
+        ConfigMessage::Rewrite => {
+            database.config_rewrite()?;
+            Ok(OutboundMessage::Ok)
         }
     }
 }
 
+fn handle_action_cluster(
+    database: &mut Database,
+    cluster_message: ClusterMessage,
+) -> anyhow::Result<OutboundMessage> {
+    match cluster_message {
+        ClusterMessage::Info => Ok(OutboundMessage::BulkString(database.cluster_info()?)),
+        ClusterMessage::MyId => Ok(OutboundMessage::BulkString(database.cluster_my_id()?)),
+        ClusterMessage::Nodes => Ok(OutboundMessage::BulkString(database.cluster_nodes()?)),
+        ClusterMessage::Slots => {
+            let ranges = database.cluster_slots()?;
+            Ok(OutboundMessage::Array(
+                ranges.into_iter().map(create_slot_range_reply).collect(),
+            ))
+        }
+        ClusterMessage::Shards => {
+            let shards = database.cluster_shards()?;
+            Ok(OutboundMessage::Array(
+                shards.into_iter().map(create_shard_reply).collect(),
+            ))
+        }
+        ClusterMessage::KeySlot { key } => {
+            let slot = database.cluster_key_slot(&key)?;
+            Ok(OutboundMessage::Integer(slot as i64))
+        }
+        ClusterMessage::CountKeysInSlot { slot } => {
+            let count = database.cluster_count_keys_in_slot(slot)?;
+            Ok(OutboundMessage::Integer(count as i64))
+        }
+        ClusterMessage::GetKeysInSlot { slot, count } => {
+            let keys = database.cluster_get_keys_in_slot(slot, count)?;
+            Ok(OutboundMessage::Keys(keys))
+        }
+        ClusterMessage::Meet { ip, port, bus_port } => {
+            database.cluster_meet(ip, port, bus_port)?;
+            Ok(OutboundMessage::Ok)
+        }
+        ClusterMessage::AddSlots { ranges } => {
+            database.cluster_add_slots(&ranges)?;
+            Ok(OutboundMessage::Ok)
+        }
+        ClusterMessage::DelSlots { ranges } => {
+            database.cluster_delete_slots(&ranges)?;
+            Ok(OutboundMessage::Ok)
+        }
+        ClusterMessage::SetSlot { slot, action } => {
+            database.cluster_set_slot(slot, action)?;
+            Ok(OutboundMessage::Ok)
+        }
+    }
+}
+
+fn handle_action_migrate(
+    database: &mut Database,
+    db: usize,
+    options: &MigrateOptions,
+) -> anyhow::Result<OutboundMessage> {
+    match database.migrate(db, options)? {
+        true => Ok(OutboundMessage::Ok),
+        false => Ok(OutboundMessage::Status("NOKEY".into())),
+    }
+}
+
+/// An entry of CLUSTER SLOTS: the range, then the node serving it
+fn create_slot_range_reply(range: SlotRange) -> OutboundMessage {
+    let NodeEndpoint { id, ip, port } = range.node;
+    OutboundMessage::Array(vec![
+        OutboundMessage::Integer(range.start as i64),
+        OutboundMessage::Integer(range.end as i64),
+        OutboundMessage::Array(vec![
+            OutboundMessage::BulkString(ip),
+            OutboundMessage::Integer(port as i64),
+            OutboundMessage::BulkString(id),
+            OutboundMessage::Array(Vec::new()),
+        ]),
+    ])
+}
+
+/// An entry of CLUSTER SHARDS: a map with the slots, given as pairs of start and end,
+/// and the nodes of the shard
+fn create_shard_reply(shard: Shard) -> OutboundMessage {
+    let slots = shard
+        .ranges
+        .iter()
+        .flat_map(|(start, end)| [*start, *end])
+        .map(|slot| OutboundMessage::Integer(slot as i64))
+        .collect();
+    let nodes = shard
+        .nodes
+        .into_iter()
+        .map(|node| {
+            let NodeEndpoint { id, ip, port } = node.endpoint;
+            OutboundMessage::Array(vec![
+                OutboundMessage::BulkString("id".into()),
+                OutboundMessage::BulkString(id),
+                OutboundMessage::BulkString("port".into()),
+                OutboundMessage::Integer(port as i64),
+                OutboundMessage::BulkString("ip".into()),
+                OutboundMessage::BulkString(ip.clone()),
+                OutboundMessage::BulkString("endpoint".into()),
+                OutboundMessage::BulkString(ip),
+                OutboundMessage::BulkString("role".into()),
+                OutboundMessage::BulkString("master".into()),
+                OutboundMessage::BulkString("replication-offset".into()),
+                OutboundMessage::Integer(node.replication_offset as i64),
+                OutboundMessage::BulkString("health".into()),
+                OutboundMessage::BulkString(node.health.into()),
+            ])
+        })
+        .collect();
+    OutboundMessage::Array(vec![
+        OutboundMessage::BulkString("slots".into()),
+        OutboundMessage::Array(slots),
+        OutboundMessage::BulkString("nodes".into()),
+        OutboundMessage::Array(nodes),
+    ])
+}
+
 fn handle_action_set(
-    database: &Arc<Mutex<Database>>,
+    keyspace: &mut Keyspace,
     key: String,
-    value: String,
+    value: Vec<u8>,
     expires_at: Option<u128>,
+    keep_ttl: bool,
 ) -> anyhow::Result<OutboundMessage> {
-    let Ok(mut database) = database.lock() else {
-        anyhow::bail!("Failed to lock database");
-    };
-    database.set(key, value, expires_at)?;
+    match keep_ttl {
+        true => keyspace.set_keep_ttl(key, value)?,
+        false => keyspace.set(key, value, expires_at)?,
+    }
     Ok(OutboundMessage::Ok)
 }
 
-fn handle_action_get(
-    database: &Arc<Mutex<Database>>,
+fn handle_action_get(keyspace: &mut Keyspace, key: String) -> anyhow::Result<OutboundMessage> {
+    let value = keyspace.get(key)?;
+    Ok(OutboundMessage::Get(value))
+}
+
+fn handle_action_keys(keyspace: &mut Keyspace, pattern: String) -> anyhow::Result<OutboundMessage> {
+    let value = keyspace.keys(pattern)?;
+    Ok(OutboundMessage::Keys(value))
+}
+
+fn handle_action_expire(
+    keyspace: &mut Keyspace,
+    key: String,
+    expires_at: i128,
+    conditions: &[ExpireCondition],
+) -> anyhow::Result<OutboundMessage> {
+    let is_set = keyspace.expire(key, expires_at, conditions)?;
+    Ok(OutboundMessage::Integer(is_set as i64))
+}
+
+fn handle_action_ttl(
+    keyspace: &mut Keyspace,
     key: String,
+    unit: TimeUnit,
 ) -> anyhow::Result<OutboundMessage> {
-    let Ok(mut database) = database.lock() else {
-        anyhow::bail!("Failed to lock database");
+    let ttl = keyspace.ttl(key)?;
+    let ttl = match unit {
+        TimeUnit::Milliseconds => ttl,
+        TimeUnit::Seconds if ttl < 0 => ttl,
+        TimeUnit::Seconds => (ttl + 500) / 1000,
     };
-    let value = database.get(key)?;
-    Ok(OutboundMessage::Get(value))
+    Ok(OutboundMessage::Integer(ttl as i64))
 }
 
-fn handle_action_keys(
-    database: &Arc<Mutex<Database>>,
-    pattern: String,
+fn handle_action_expire_time(
+    keyspace: &mut Keyspace,
+    key: String,
+    unit: TimeUnit,
 ) -> anyhow::Result<OutboundMessage> {
-    let Ok(database) = database.lock() else {
-        anyhow::bail!("Failed to lock database");
+    let expire_time = keyspace.expire_time(key)?;
+    let expire_time = match unit {
+        TimeUnit::Milliseconds => expire_time,
+        TimeUnit::Seconds if expire_time < 0 => expire_time,
+        TimeUnit::Seconds => expire_time / 1000,
     };
-    let value = database.keys(pattern)?;
-    Ok(OutboundMessage::Keys(value))
+    Ok(OutboundMessage::Integer(expire_time as i64))
+}
+
+fn handle_action_persist(keyspace: &mut Keyspace, key: String) -> anyhow::Result<OutboundMessage> {
+    let is_persisted = keyspace.persist(key)?;
+    Ok(OutboundMessage::Integer(is_persisted as i64))
+}
+
+fn handle_action_scan(
+    keyspace: &mut Keyspace,
+    cursor: u64,
+    pattern: Option<&str>,
+    count: usize,
+    type_name: Option<&str>,
+) -> anyhow::Result<OutboundMessage> {
+    let (cursor, keys) = keyspace.scan(cursor, pattern, count, type_name)?;
+    Ok(OutboundMessage::Scan { cursor, keys })
+}
+
+fn handle_action_collection_scan(
+    keyspace: &mut Keyspace,
+    key: String,
+) -> anyhow::Result<OutboundMessage> {
+    let (cursor, elements) = keyspace.scan_collection(&key)?;
+    Ok(OutboundMessage::Scan {
+        cursor,
+        keys: elements,
+    })
+}
+
+fn handle_action_delete(
+    keyspace: &mut Keyspace,
+    keys: &[String],
+) -> anyhow::Result<OutboundMessage> {
+    let deleted_count = keyspace.delete_keys(keys)?;
+    Ok(OutboundMessage::Integer(deleted_count as i64))
+}
+
+fn handle_action_exists(
+    keyspace: &mut Keyspace,
+    keys: &[String],
+) -> anyhow::Result<OutboundMessage> {
+    let existing_count = keyspace.exists(keys)?;
+    Ok(OutboundMessage::Integer(existing_count as i64))
+}
+
+fn handle_action_type(keyspace: &mut Keyspace, key: &str) -> anyhow::Result<OutboundMessage> {
+    let type_name = keyspace.type_name(key)?.unwrap_or(TYPE_NAME_NONE);
+    Ok(OutboundMessage::Type(type_name.into()))
+}
+
+fn handle_action_rename(
+    keyspace: &mut Keyspace,
+    key: String,
+    new_key: String,
+    only_if_new: bool,
+) -> anyhow::Result<OutboundMessage> {
+    let is_renamed = keyspace.rename(key, new_key, only_if_new)?;
+    match only_if_new {
+        true => Ok(OutboundMessage::Integer(is_renamed as i64)),
+        false => Ok(OutboundMessage::Ok),
+    }
+}
+
+fn handle_action_copy(
+    database: &mut Database,
+    db: usize,
+    source: String,
+    destination: String,
+    destination_db: Option<i64>,
+    replace: bool,
+) -> anyhow::Result<OutboundMessage> {
+    let destination_db = match destination_db {
+        Some(destination_db) => database.validate_keyspace_index(destination_db)?,
+        None => db,
+    };
+    let is_copied = database.copy(db, source, destination, destination_db, replace)?;
+    Ok(OutboundMessage::Integer(is_copied as i64))
+}
+
+fn handle_action_touch(
+    keyspace: &mut Keyspace,
+    keys: &[String],
+) -> anyhow::Result<OutboundMessage> {
+    let touched_count = keyspace.touch(keys)?;
+    Ok(OutboundMessage::Integer(touched_count as i64))
+}
+
+fn handle_action_random_key(keyspace: &mut Keyspace) -> anyhow::Result<OutboundMessage> {
+    let key = keyspace.random_key()?;
+    Ok(OutboundMessage::Get(key.map(String::into_bytes)))
+}
+
+fn handle_action_db_size(keyspace: &mut Keyspace) -> anyhow::Result<OutboundMessage> {
+    Ok(OutboundMessage::Integer(keyspace.size() as i64))
+}
+
+fn handle_action_flush_db(keyspace: &mut Keyspace) -> anyhow::Result<OutboundMessage> {
+    keyspace.flush();
+    Ok(OutboundMessage::Ok)
+}
+
+fn handle_action_select(
+    database: &mut Database,
+    selected_db: &mut usize,
+    db: i64,
+) -> anyhow::Result<OutboundMessage> {
+    if database.is_cluster_enabled() && db != 0 {
+        anyhow::bail!("ERR SELECT is not allowed in cluster mode")
+    }
+    *selected_db = database.validate_keyspace_index(db)?;
+    Ok(OutboundMessage::Ok)
+}
+
+fn handle_action_swap_db(
+    database: &mut Database,
+    db: i64,
+    other_db: i64,
+) -> anyhow::Result<OutboundMessage> {
+    if database.is_cluster_enabled() {
+        anyhow::bail!("ERR SWAPDB is not allowed in cluster mode")
+    }
+    let db = database.validate_keyspace_index(db)?;
+    let other_db = database.validate_keyspace_index(other_db)?;
+    database.swap_db(db, other_db);
+    Ok(OutboundMessage::Ok)
+}
+
+fn handle_action_move(
+    database: &mut Database,
+    db: usize,
+    key: String,
+    destination_db: i64,
+) -> anyhow::Result<OutboundMessage> {
+    let destination_db = database.validate_keyspace_index(destination_db)?;
+    let is_moved = database.move_key(db, key, destination_db)?;
+    Ok(OutboundMessage::Integer(is_moved as i64))
+}
+
+fn handle_action_flush_all(database: &mut Database) -> anyhow::Result<OutboundMessage> {
+    database.flush_all();
+    Ok(OutboundMessage::Ok)
+}
+
+fn handle_action_append(
+    keyspace: &mut Keyspace,
+    key: String,
+    value: &[u8],
+) -> anyhow::Result<OutboundMessage> {
+    let length = keyspace.append(key, value)?;
+    Ok(OutboundMessage::Integer(length as i64))
+}
+
+fn handle_action_strlen(keyspace: &mut Keyspace, key: String) -> anyhow::Result<OutboundMessage> {
+    let length = keyspace.strlen(key)?;
+    Ok(OutboundMessage::Integer(length as i64))
+}
+
+fn handle_action_get_range(
+    keyspace: &mut Keyspace,
+    key: String,
+    start: i64,
+    end: i64,
+) -> anyhow::Result<OutboundMessage> {
+    let value = keyspace.get_range(key, start, end)?;
+    Ok(OutboundMessage::Get(Some(value)))
+}
+
+fn handle_action_set_range(
+    keyspace: &mut Keyspace,
+    key: String,
+    offset: usize,
+    value: &[u8],
+) -> anyhow::Result<OutboundMessage> {
+    let length = keyspace.set_range(key, offset, value)?;
+    Ok(OutboundMessage::Integer(length as i64))
+}
+
+fn handle_action_get_del(keyspace: &mut Keyspace, key: String) -> anyhow::Result<OutboundMessage> {
+    let value = keyspace.get_del(key)?;
+    Ok(OutboundMessage::Get(value))
+}
+
+fn handle_action_get_ex(
+    keyspace: &mut Keyspace,
+    key: String,
+    expires_at: Option<i128>,
+    persist: bool,
+) -> anyhow::Result<OutboundMessage> {
+    let value = keyspace.get(key.clone())?;
+    if value.is_some() {
+        if let Some(expires_at) = expires_at {
+            keyspace.expire(key, expires_at, &[])?;
+        } else if persist {
+            keyspace.persist(key)?;
+        }
+    }
+    Ok(OutboundMessage::Get(value))
+}
+
+fn handle_action_get_set(
+    keyspace: &mut Keyspace,
+    key: String,
+    value: Vec<u8>,
+) -> anyhow::Result<OutboundMessage> {
+    let previous_value = keyspace.get_set(key, value)?;
+    Ok(OutboundMessage::Get(previous_value))
+}
+
+fn handle_action_set_nx(
+    keyspace: &mut Keyspace,
+    key: String,
+    value: Vec<u8>,
+) -> anyhow::Result<OutboundMessage> {
+    let is_set = keyspace.set_nx(key, value, None)?;
+    Ok(OutboundMessage::Integer(is_set as i64))
+}
+
+fn handle_action_lcs(
+    keyspace: &mut Keyspace,
+    key_1: String,
+    key_2: String,
+    mode: &LcsMode,
+) -> anyhow::Result<OutboundMessage> {
+    let lcs = keyspace.lcs(key_1, key_2)?;
+    match *mode {
+        LcsMode::Value => Ok(OutboundMessage::Get(Some(lcs.value))),
+        LcsMode::Length => Ok(OutboundMessage::Integer(lcs.value.len() as i64)),
+        LcsMode::Indexes {
+            min_match_length,
+            with_match_length,
+        } => {
+            let length = lcs.value.len();
+            let matches = lcs
+                .matches
+                .into_iter()
+                .filter(|lcs_match| lcs_match.length >= min_match_length)
+                .collect();
+            Ok(OutboundMessage::LcsIndexes {
+                matches,
+                length,
+                with_match_length,
+            })
+        }
+    }
+}
+
+fn handle_action_increment_by(
+    keyspace: &mut Keyspace,
+    key: String,
+    increment: i64,
+) -> anyhow::Result<OutboundMessage> {
+    let value = keyspace.increment_by(key, increment)?;
+    Ok(OutboundMessage::Integer(value))
+}
+
+fn handle_action_increment_by_float(
+    keyspace: &mut Keyspace,
+    key: String,
+    increment: f64,
+) -> anyhow::Result<OutboundMessage> {
+    let value = keyspace.increment_by_float(key, increment)?;
+    Ok(OutboundMessage::Get(Some(value)))
+}
+
+fn handle_action_multi_get(
+    keyspace: &mut Keyspace,
+    keys: &[String],
+) -> anyhow::Result<OutboundMessage> {
+    let values = keyspace.multi_get(keys)?;
+    Ok(OutboundMessage::MultiGet(values))
+}
+
+fn handle_action_multi_set(
+    keyspace: &mut Keyspace,
+    pairs: &[(String, Vec<u8>)],
+    only_if_new: bool,
+) -> anyhow::Result<OutboundMessage> {
+    let is_set = keyspace.multi_set(pairs, only_if_new)?;
+    match only_if_new {
+        true => Ok(OutboundMessage::Integer(is_set as i64)),
+        false => Ok(OutboundMessage::Ok),
+    }
+}
+
+fn handle_action_set_bit(
+    keyspace: &mut Keyspace,
+    key: String,
+    offset: u64,
+    bit: bool,
+) -> anyhow::Result<OutboundMessage> {
+    let previous_bit = keyspace.set_bit(key, offset, bit)?;
+    Ok(OutboundMessage::Integer(previous_bit as i64))
+}
+
+fn handle_action_get_bit(
+    keyspace: &mut Keyspace,
+    key: String,
+    offset: u64,
+) -> anyhow::Result<OutboundMessage> {
+    let bit = keyspace.get_bit(key, offset)?;
+    Ok(OutboundMessage::Integer(bit as i64))
+}
+
+fn handle_action_bit_count(
+    keyspace: &mut Keyspace,
+    key: String,
+    range: Option<(i64, i64, BitIndexUnit)>,
+) -> anyhow::Result<OutboundMessage> {
+    let count = keyspace.bit_count(key, range)?;
+    Ok(OutboundMessage::Integer(count as i64))
+}
+
+fn handle_action_bit_pos(
+    keyspace: &mut Keyspace,
+    key: String,
+    bit: bool,
+    start: Option<i64>,
+    end: Option<i64>,
+    unit: BitIndexUnit,
+) -> anyhow::Result<OutboundMessage> {
+    let position = keyspace.bit_pos(key, bit, start, end, unit)?;
+    Ok(OutboundMessage::Integer(position))
+}
+
+fn handle_action_bit_op(
+    keyspace: &mut Keyspace,
+    operation: BitOperation,
+    destination: String,
+    keys: &[String],
+) -> anyhow::Result<OutboundMessage> {
+    let length = keyspace.bit_op(operation, destination, keys)?;
+    Ok(OutboundMessage::Integer(length as i64))
+}
+
+fn handle_action_bitfield(
+    keyspace: &mut Keyspace,
+    key: String,
+    operations: &[BitfieldOperation],
+) -> anyhow::Result<OutboundMessage> {
+    let results = keyspace.bitfield(key, operations)?;
+    Ok(OutboundMessage::Integers(results))
+}
+
+fn handle_action_pf_add(
+    keyspace: &mut Keyspace,
+    key: String,
+    elements: &[Vec<u8>],
+) -> anyhow::Result<OutboundMessage> {
+    let is_updated = keyspace.pf_add(key, elements)?;
+    Ok(OutboundMessage::Integer(is_updated as i64))
+}
+
+fn handle_action_pf_count(
+    keyspace: &mut Keyspace,
+    keys: &[String],
+) -> anyhow::Result<OutboundMessage> {
+    let cardinality = keyspace.pf_count(keys)?;
+    Ok(OutboundMessage::Integer(cardinality as i64))
+}
+
+fn handle_action_pf_merge(
+    keyspace: &mut Keyspace,
+    destination: String,
+    keys: &[String],
+) -> anyhow::Result<OutboundMessage> {
+    keyspace.pf_merge(destination, keys)?;
+    Ok(OutboundMessage::Ok)
+}
+
+fn handle_action_bg_rewrite_aof(database: &mut Database) -> anyhow::Result<OutboundMessage> {
+    database.start_aof_rewrite()?;
+    Ok(OutboundMessage::Status(
+        "Background append only file rewriting started".into(),
+    ))
+}
+
+fn handle_action_save(database: &mut Database) -> anyhow::Result<OutboundMessage> {
+    database.save()?;
+    Ok(OutboundMessage::Ok)
+}
+
+fn handle_action_replica_of(
+    database: &mut Database,
+    master: Option<MasterAddress>,
+) -> anyhow::Result<OutboundMessage> {
+    let is_replicating = master.is_some();
+    if !database.replica_of(master) && is_replicating {
+        return Ok(OutboundMessage::Status(
+            "OK Already connected to specified master".into(),
+        ));
+    }
+    Ok(OutboundMessage::Ok)
+}
+
+fn handle_action_shutdown(
+    database: &mut Database,
+    options: ShutdownOptions,
+) -> anyhow::Result<OutboundMessage> {
+    if let Err(error) = database.prepare_shutdown(options) {
+        eprintln!("-> {error}");
+        anyhow::bail!("ERR Errors trying to SHUTDOWN. Check logs.")
+    }
+    // Never sent: the server exits once the database thread sees the shutdown
+    Ok(OutboundMessage::Ok)
+}
+
+fn handle_action_bg_save(database: &mut Database) -> anyhow::Result<OutboundMessage> {
+    database.start_bgsave()?;
+    Ok(OutboundMessage::Status("Background saving started".into()))
 }
@@ -0,0 +1,109 @@
+use std::fs::{self, OpenOptions};
+
+use crate::database::{aof::manifest::AofManifest, Database};
+
+use super::{handle_message, inbound_message::InboundMessage, resp::parse_command};
+
+const RDB_MAGIC_STRING: &[u8] = b"REDIS";
+
+/// Rebuilds the dataset from the files listed by the AOF manifest: the base file, in the
+/// RDB format or made of commands, then the commands of the incremental files. A last
+/// command cut short, like after a crash in the middle of a write, is dropped when
+/// aof-load-truncated is on.
+pub fn load_aof(database: &mut Database) -> anyhow::Result<()> {
+    let manifest = database.load_aof_manifest()?;
+
+    database.set_loading(true);
+    let result = load_files(database, &manifest);
+    database.set_loading(false);
+    let command_count = result?;
+
+    println!("-> Loaded {command_count} commands from the append only file");
+    Ok(())
+}
+
+/// Returns the number of commands run
+fn load_files(database: &mut Database, manifest: &AofManifest) -> anyhow::Result<usize> {
+    let file_count = manifest.files().count();
+    let mut command_count = 0;
+    for (index, file) in manifest.files().enumerate() {
+        command_count += load_file(database, &file.name, index == file_count - 1)?;
+    }
+    Ok(command_count)
+}
+
+/// Returns the number of commands run
+fn load_file(database: &mut Database, name: &str, is_last: bool) -> anyhow::Result<usize> {
+    let path = database.aof_file_path(name);
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(error) => anyhow::bail!(
+            "Failed to read the append only file {}: {error}",
+            path.display()
+        ),
+    };
+
+    // Base files rewritten with aof-use-rdb-preamble start with the dataset as an RDB
+    let mut position = 0;
+    if bytes.starts_with(RDB_MAGIC_STRING) {
+        position = database.load_rdb_preamble(&bytes)?;
+    }
+    let (command_count, loaded_length) = run_commands(database, &bytes, position)?;
+
+    if loaded_length < bytes.len() {
+        // Only the file written last may have been cut short by a crash
+        if !is_last || !database.aof_load_truncated() {
+            anyhow::bail!(
+                "Unexpected end of file reading the append only file {}. Set \
+                 aof-load-truncated to yes to load it anyway.",
+                path.display()
+            )
+        }
+        eprintln!(
+            "-> The append only file {} is truncated, dropping its last {} bytes",
+            path.display(),
+            bytes.len() - loaded_length
+        );
+        // Following commands must not be appended to the partial one
+        OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(loaded_length as u64)?;
+    }
+    Ok(command_count)
+}
+
+/// Runs the commands from the position. Returns the number of commands run, and the
+/// length of the bytes read up to the end of the last one.
+fn run_commands(
+    database: &mut Database,
+    bytes: &[u8],
+    mut position: usize,
+) -> anyhow::Result<(usize, usize)> {
+    let mut selected_db = 0;
+    let mut command_count = 0;
+    loop {
+        let (arguments, read_count) = match parse_command(&bytes[position..]) {
+            Ok(Some(command)) => command,
+            Ok(None) => return Ok((command_count, position)),
+            Err(error) => anyhow::bail!(
+                "Bad file format reading the append only file at byte {position}: {error}"
+            ),
+        };
+        if arguments.is_empty() {
+            position += read_count;
+            continue;
+        }
+
+        let message = match InboundMessage::try_from(&arguments[..]) {
+            Ok(message) => message,
+            Err(error) => anyhow::bail!(
+                "Bad command reading the append only file at byte {position}: {error}"
+            ),
+        };
+        // Like when the command was first run, an error reply leaves the dataset as it is
+        let _ = handle_message(database, &mut selected_db, &message);
+        command_count += 1;
+        position += read_count;
+    }
+}
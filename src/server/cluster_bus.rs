@@ -0,0 +1,149 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time,
+};
+
+use crate::database::cluster::{BusOutput, NodeAddress};
+
+use super::{database_thread::DatabaseHandle, resp::parse_command};
+
+/// Time to connect to the bus of another node, which is pinged again later when it fails
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+const BUFFER_CAPACITY: usize = 16 * 1024;
+
+/// Serves the cluster bus: accepts the links of the other nodes, and sends the
+/// messages of this node over links to them, opened when first needed and again once
+/// they break
+pub async fn serve(
+    database: DatabaseHandle,
+    listener: TcpListener,
+    mut output: UnboundedReceiver<BusOutput>,
+) {
+    let mut links: HashMap<NodeAddress, UnboundedSender<Vec<u8>>> = HashMap::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_inbound_link(database.clone(), stream));
+                }
+                Err(error) => eprintln!("-> Error accepting a cluster bus connection: {error}"),
+            },
+            message = output.recv() => {
+                let Some(BusOutput { address, bytes }) = message else {
+                    return;
+                };
+                let link = links
+                    .entry(address.clone())
+                    .or_insert_with(|| open_link(&database, address.clone()));
+                if link.is_closed() {
+                    *link = open_link(&database, address);
+                }
+                let _ = link.send(bytes);
+            }
+        }
+    }
+}
+
+/// Answers the messages another node sends over the link it opened
+async fn serve_inbound_link(database: DatabaseHandle, mut stream: TcpStream) {
+    let (Ok(peer), Ok(local)) = (stream.peer_addr(), stream.local_addr()) else {
+        return;
+    };
+    let mut buffer = Vec::with_capacity(BUFFER_CAPACITY);
+    loop {
+        match stream.read_buf(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let replies = match receive(&database, &mut buffer, peer, local).await {
+            Ok(replies) => replies,
+            Err(error) => {
+                eprintln!("-> Closing the cluster bus link of {peer}: {error}");
+                return;
+            }
+        };
+        if !replies.is_empty() && stream.write_all(&replies).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Opens a link to the bus of another node, and returns where to queue the messages
+/// for it. The link closes when it breaks, dropping the messages queued.
+fn open_link(database: &DatabaseHandle, address: NodeAddress) -> UnboundedSender<Vec<u8>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let database = database.clone();
+    tokio::spawn(async move {
+        if let Err(error) = serve_outbound_link(&database, &address, receiver).await {
+            eprintln!("-> Cluster bus link to {address} closed: {error}");
+        }
+    });
+    sender
+}
+
+async fn serve_outbound_link(
+    database: &DatabaseHandle,
+    address: &NodeAddress,
+    mut messages: UnboundedReceiver<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let connect = TcpStream::connect((address.ip.as_str(), address.port));
+    let Ok(stream) = time::timeout(CONNECT_TIMEOUT, connect).await else {
+        anyhow::bail!("Timeout connecting")
+    };
+    let mut stream = stream?;
+    stream.set_nodelay(true)?;
+    let (peer, local) = (stream.peer_addr()?, stream.local_addr()?);
+    let mut buffer = Vec::with_capacity(BUFFER_CAPACITY);
+    loop {
+        tokio::select! {
+            message = messages.recv() => {
+                let Some(bytes) = message else {
+                    return Ok(());
+                };
+                stream.write_all(&bytes).await?;
+            }
+            bytes_read = stream.read_buf(&mut buffer) => {
+                if bytes_read? == 0 {
+                    anyhow::bail!("Connection closed")
+                }
+                let replies = receive(database, &mut buffer, peer, local).await?;
+                stream.write_all(&replies).await?;
+            }
+        }
+    }
+}
+
+/// Hands the complete messages of the buffer to the database, and returns the bytes
+/// of the replies
+async fn receive(
+    database: &DatabaseHandle,
+    buffer: &mut Vec<u8>,
+    peer: SocketAddr,
+    local: SocketAddr,
+) -> anyhow::Result<Vec<u8>> {
+    let mut messages = Vec::new();
+    while let Some((arguments, read_count)) = parse_command(buffer)? {
+        buffer.drain(..read_count);
+        if !arguments.is_empty() {
+            messages.push(arguments);
+        }
+    }
+    if messages.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (peer_ip, local_ip) = (peer.ip().to_string(), local.ip().to_string());
+    database
+        .call(move |database| {
+            messages
+                .iter()
+                .filter_map(|arguments| database.cluster_receive(arguments, &peer_ip, &local_ip))
+                .flatten()
+                .collect()
+        })
+        .await
+}
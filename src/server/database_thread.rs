@@ -0,0 +1,225 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::oneshot;
+
+use crate::database::{shutdown::ShutdownOptions, Database};
+
+use super::{handle_message, inbound_message::InboundMessage, outbound_message::OutboundMessage};
+
+/// Percentage of each background tick the active expire cycle may use
+const ACTIVE_EXPIRE_CYCLE_TIME_PERCENTAGE: u32 = 25;
+
+/// A command read from a connection: its arguments, logged to the AOF when it writes,
+/// and the message parsed from them
+pub struct Command {
+    pub arguments: Vec<Vec<u8>>,
+    pub message: anyhow::Result<InboundMessage>,
+    /// Follows ASKING, which lets it use a slot this node imports
+    pub is_asking: bool,
+}
+
+enum DatabaseRequest {
+    /// The commands read at once from a connection, with the database it has selected
+    Run {
+        commands: Vec<Command>,
+        selected_db: usize,
+        reply: oneshot::Sender<(Vec<OutboundMessage>, usize)>,
+    },
+    /// Stops the thread once the dataset is saved. The reply is the error that
+    /// prevented saving, in which case the thread keeps running.
+    Shutdown {
+        options: ShutdownOptions,
+        reply: oneshot::Sender<anyhow::Result<()>>,
+    },
+    /// Runs a function with the database, like applying the stream of the master
+    Call(Box<dyn FnOnce(&mut Database) + Send>),
+}
+
+/// Sends commands to the thread owning the database. The database is never shared:
+/// commands run one after the other on its thread, so multi-key commands are atomic
+/// without locks and a slow command never blocks the async runtime.
+#[derive(Clone)]
+pub struct DatabaseHandle {
+    requests: mpsc::Sender<DatabaseRequest>,
+}
+
+impl DatabaseHandle {
+    /// Starts the database thread, which stops on shutdown or once all the handles are
+    /// dropped. The receiver gets why it stopped.
+    pub fn spawn(
+        database: Database,
+    ) -> anyhow::Result<(Self, oneshot::Receiver<anyhow::Result<()>>)> {
+        let (requests, receiver) = mpsc::channel();
+        let (stopped, stopped_receiver) = oneshot::channel();
+        thread::Builder::new()
+            .name("database".into())
+            .spawn(move || {
+                let _ = stopped.send(run_database(database, receiver));
+            })?;
+        Ok((DatabaseHandle { requests }, stopped_receiver))
+    }
+
+    /// Runs the commands in order without interleaving commands of other connections.
+    /// Returns the replies and the database selected after them.
+    pub async fn run(
+        &self,
+        commands: Vec<Command>,
+        selected_db: usize,
+    ) -> anyhow::Result<(Vec<OutboundMessage>, usize)> {
+        let (reply, reply_receiver) = oneshot::channel();
+        let request = DatabaseRequest::Run {
+            commands,
+            selected_db,
+            reply,
+        };
+        if self.requests.send(request).is_err() {
+            anyhow::bail!("Database thread stopped")
+        }
+        Ok(reply_receiver.await?)
+    }
+
+    /// Runs the function on the database thread, between the commands of connections,
+    /// and returns its result
+    pub async fn call<T, F>(&self, function: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Database) -> T + Send + 'static,
+    {
+        let (reply, reply_receiver) = oneshot::channel();
+        let request = DatabaseRequest::Call(Box::new(move |database| {
+            let _ = reply.send(function(database));
+        }));
+        if self.requests.send(request).is_err() {
+            anyhow::bail!("Database thread stopped")
+        }
+        Ok(reply_receiver.await?)
+    }
+
+    /// Saves the dataset and stops the database thread
+    pub async fn shutdown(&self, options: ShutdownOptions) -> anyhow::Result<()> {
+        let (reply, reply_receiver) = oneshot::channel();
+        if self
+            .requests
+            .send(DatabaseRequest::Shutdown { options, reply })
+            .is_err()
+        {
+            anyhow::bail!("Database thread stopped")
+        }
+        reply_receiver.await?
+    }
+}
+
+fn run_database(
+    mut database: Database,
+    receiver: mpsc::Receiver<DatabaseRequest>,
+) -> anyhow::Result<()> {
+    let mut next_cycle = Instant::now();
+    loop {
+        // Runs the background cycle on time even when requests keep coming
+        let now = Instant::now();
+        if now >= next_cycle {
+            let tick = Duration::from_millis(1000 / database.hz());
+            let time_limit = tick * ACTIVE_EXPIRE_CYCLE_TIME_PERCENTAGE / 100;
+            database.active_expire_cycle(time_limit)?;
+            database.propagate_expired_keys();
+            database.flush_aof()?;
+            database.aof_cron();
+            database.rdb_cron();
+            database.replication_cron();
+            database.cluster_cron();
+            next_cycle = now + tick;
+        }
+
+        let request = match receiver.recv_timeout(next_cycle - now) {
+            Ok(request) => request,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+
+        match request {
+            DatabaseRequest::Run {
+                commands,
+                mut selected_db,
+                reply,
+            } => {
+                let mut outbound_messages = Vec::new();
+                for command in commands {
+                    outbound_messages.push(run_command(&mut database, &mut selected_db, command));
+                    if database.is_shut_down() {
+                        // Like redis-server, SHUTDOWN closes the connection without a
+                        // reply, and the commands after it never run
+                        outbound_messages.pop();
+                        break;
+                    }
+                }
+                // Replies only once the writes are logged, and fsynced with appendfsync always
+                database.flush_aof()?;
+                // The connection may have been closed in the meantime
+                let _ = reply.send((outbound_messages, selected_db));
+                if database.is_shut_down() {
+                    return Ok(());
+                }
+            }
+            DatabaseRequest::Shutdown { options, reply } => {
+                let result = database.prepare_shutdown(options);
+                let is_ready = result.is_ok();
+                let _ = reply.send(result);
+                if is_ready {
+                    return Ok(());
+                }
+            }
+            DatabaseRequest::Call(function) => {
+                function(&mut database);
+                database.flush_aof()?;
+            }
+        }
+    }
+}
+
+fn run_command(
+    database: &mut Database,
+    selected_db: &mut usize,
+    command: Command,
+) -> OutboundMessage {
+    let db = *selected_db;
+    let result = command.message.and_then(|message| {
+        // MIGRATE finds out itself which of the keys are still here
+        let is_migrate = matches!(message, InboundMessage::Migrate(_));
+        if database.is_cluster_enabled() && !is_migrate {
+            let is_asking = command.is_asking
+                || matches!(
+                    message,
+                    InboundMessage::Restore {
+                        is_asking: true,
+                        ..
+                    }
+                );
+            database.check_cluster_keys(&message.keys(), is_asking)?;
+        }
+        if message.is_write() {
+            database.check_replica_writable()?;
+            database.check_writes_allowed()?;
+        }
+        let outbound_message = handle_message(database, selected_db, &message)?;
+        Ok((message, outbound_message))
+    });
+    database.record_command(result.is_err());
+    // Keys expired while running the command are deleted before its own write
+    database.propagate_expired_keys();
+
+    match result {
+        Ok((message, outbound_message)) => {
+            if let Some(arguments) =
+                message.propagated_arguments(&command.arguments, &outbound_message)
+            {
+                database.propagate(db, &arguments);
+            }
+            outbound_message
+        }
+        Err(error) => OutboundMessage::Error(error.to_string()),
+    }
+}
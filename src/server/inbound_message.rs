@@ -1,7 +1,23 @@
-use self::config_message::ConfigMessage;
-use std::time::SystemTime;
+use std::time::Duration;
 
+use self::{cluster_message::ClusterMessage, config_message::ConfigMessage};
+use crate::database::{
+    bitmap::{
+        validate_bit_offset, BitIndexUnit, BitOperation, BitfieldOperation, BitfieldOverflow,
+        BitfieldType,
+    },
+    expire::ExpireCondition,
+    get_current_time_ms,
+    migrate::{MigrateAuth, MigrateOptions},
+    replication::{wait::WaitCondition, MasterAddress},
+    shutdown::ShutdownOptions,
+    string::{parse_canonical_integer, parse_float},
+};
+
+pub mod cluster_message;
 pub mod config_message;
+mod keys;
+mod propagation;
 
 const ID_CONFIG: &str = "CONFIG";
 const ID_PING: &str = "PING";
@@ -9,6 +25,99 @@ const ID_ECHO: &str = "ECHO";
 const ID_SET: &str = "SET";
 const ID_GET: &str = "GET";
 const ID_KEYS: &str = "KEYS";
+const ID_EXPIRE: &str = "EXPIRE";
+const ID_PEXPIRE: &str = "PEXPIRE";
+const ID_EXPIREAT: &str = "EXPIREAT";
+const ID_PEXPIREAT: &str = "PEXPIREAT";
+const ID_TTL: &str = "TTL";
+const ID_PTTL: &str = "PTTL";
+const ID_EXPIRETIME: &str = "EXPIRETIME";
+const ID_PEXPIRETIME: &str = "PEXPIRETIME";
+const ID_PERSIST: &str = "PERSIST";
+const ID_SCAN: &str = "SCAN";
+const ID_HSCAN: &str = "HSCAN";
+const ID_SSCAN: &str = "SSCAN";
+const ID_ZSCAN: &str = "ZSCAN";
+const ID_DEL: &str = "DEL";
+const ID_UNLINK: &str = "UNLINK";
+const ID_EXISTS: &str = "EXISTS";
+const ID_TYPE: &str = "TYPE";
+const ID_RENAME: &str = "RENAME";
+const ID_RENAMENX: &str = "RENAMENX";
+const ID_COPY: &str = "COPY";
+const ID_TOUCH: &str = "TOUCH";
+const ID_RANDOMKEY: &str = "RANDOMKEY";
+const ID_DBSIZE: &str = "DBSIZE";
+const ID_SELECT: &str = "SELECT";
+const ID_SWAPDB: &str = "SWAPDB";
+const ID_MOVE: &str = "MOVE";
+const ID_FLUSHDB: &str = "FLUSHDB";
+const ID_FLUSHALL: &str = "FLUSHALL";
+const ID_APPEND: &str = "APPEND";
+const ID_STRLEN: &str = "STRLEN";
+const ID_GETRANGE: &str = "GETRANGE";
+const ID_SETRANGE: &str = "SETRANGE";
+const ID_GETDEL: &str = "GETDEL";
+const ID_GETEX: &str = "GETEX";
+const ID_GETSET: &str = "GETSET";
+const ID_SETNX: &str = "SETNX";
+const ID_SETEX: &str = "SETEX";
+const ID_PSETEX: &str = "PSETEX";
+const ID_LCS: &str = "LCS";
+const ID_INCR: &str = "INCR";
+const ID_DECR: &str = "DECR";
+const ID_INCRBY: &str = "INCRBY";
+const ID_DECRBY: &str = "DECRBY";
+const ID_INCRBYFLOAT: &str = "INCRBYFLOAT";
+const ID_MGET: &str = "MGET";
+const ID_MSET: &str = "MSET";
+const ID_MSETNX: &str = "MSETNX";
+const ID_SETBIT: &str = "SETBIT";
+const ID_GETBIT: &str = "GETBIT";
+const ID_BITCOUNT: &str = "BITCOUNT";
+const ID_BITPOS: &str = "BITPOS";
+const ID_BITOP: &str = "BITOP";
+const ID_BITFIELD: &str = "BITFIELD";
+const ID_BITFIELD_RO: &str = "BITFIELD_RO";
+const ID_PFADD: &str = "PFADD";
+const ID_PFCOUNT: &str = "PFCOUNT";
+const ID_PFMERGE: &str = "PFMERGE";
+const ID_BGREWRITEAOF: &str = "BGREWRITEAOF";
+const ID_SAVE: &str = "SAVE";
+const ID_BGSAVE: &str = "BGSAVE";
+const ID_LASTSAVE: &str = "LASTSAVE";
+const ID_SHUTDOWN: &str = "SHUTDOWN";
+const ID_REPLICAOF: &str = "REPLICAOF";
+const ID_SLAVEOF: &str = "SLAVEOF";
+const ID_REPLCONF: &str = "REPLCONF";
+const ID_PSYNC: &str = "PSYNC";
+const ID_WAIT: &str = "WAIT";
+const ID_WAITAOF: &str = "WAITAOF";
+const ID_INFO: &str = "INFO";
+const ID_CLUSTER: &str = "CLUSTER";
+const ID_ASKING: &str = "ASKING";
+const ID_RESTORE: &str = "RESTORE";
+const ID_RESTORE_ASKING: &str = "RESTORE-ASKING";
+const ID_MIGRATE: &str = "MIGRATE";
+const ID_DUMP: &str = "DUMP";
+
+const SCAN_DEFAULT_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+#[derive(Debug)]
+pub enum LcsMode {
+    Value,
+    Length,
+    Indexes {
+        min_match_length: usize,
+        with_match_length: bool,
+    },
+}
 
 #[derive(Debug)]
 pub enum InboundMessage {
@@ -17,8 +126,10 @@ pub enum InboundMessage {
     Echo(String),
     Set {
         key: String,
-        value: String,
+        value: Vec<u8>,
         expires_at: Option<u128>,
+        /// Keeps the expiry the key has, with KEEPTTL
+        keep_ttl: bool,
     },
     Get {
         key: String,
@@ -26,46 +137,352 @@ pub enum InboundMessage {
     Keys {
         pattern: String,
     },
+    Expire {
+        key: String,
+        expires_at: i128,
+        conditions: Vec<ExpireCondition>,
+    },
+    Ttl {
+        key: String,
+        unit: TimeUnit,
+    },
+    ExpireTime {
+        key: String,
+        unit: TimeUnit,
+    },
+    Persist {
+        key: String,
+    },
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+        type_name: Option<String>,
+    },
+    CollectionScan {
+        key: String,
+    },
+    Delete {
+        keys: Vec<String>,
+    },
+    Exists {
+        keys: Vec<String>,
+    },
+    Type {
+        key: String,
+    },
+    Rename {
+        key: String,
+        new_key: String,
+        only_if_new: bool,
+    },
+    Copy {
+        source: String,
+        destination: String,
+        destination_db: Option<i64>,
+        replace: bool,
+    },
+    Touch {
+        keys: Vec<String>,
+    },
+    RandomKey,
+    DbSize,
+    Select {
+        db: i64,
+    },
+    SwapDb {
+        db: i64,
+        other_db: i64,
+    },
+    Move {
+        key: String,
+        db: i64,
+    },
+    FlushDb,
+    FlushAll,
+    Append {
+        key: String,
+        value: Vec<u8>,
+    },
+    Strlen {
+        key: String,
+    },
+    GetRange {
+        key: String,
+        start: i64,
+        end: i64,
+    },
+    SetRange {
+        key: String,
+        offset: usize,
+        value: Vec<u8>,
+    },
+    GetDel {
+        key: String,
+    },
+    GetEx {
+        key: String,
+        expires_at: Option<i128>,
+        persist: bool,
+    },
+    GetSet {
+        key: String,
+        value: Vec<u8>,
+    },
+    SetNx {
+        key: String,
+        value: Vec<u8>,
+    },
+    SetEx {
+        key: String,
+        expires_at: u128,
+        value: Vec<u8>,
+    },
+    Lcs {
+        key_1: String,
+        key_2: String,
+        mode: LcsMode,
+    },
+    IncrementBy {
+        key: String,
+        increment: i64,
+    },
+    IncrementByFloat {
+        key: String,
+        increment: f64,
+    },
+    MultiGet {
+        keys: Vec<String>,
+    },
+    MultiSet {
+        pairs: Vec<(String, Vec<u8>)>,
+        only_if_new: bool,
+    },
+    SetBit {
+        key: String,
+        offset: u64,
+        bit: bool,
+    },
+    GetBit {
+        key: String,
+        offset: u64,
+    },
+    BitCount {
+        key: String,
+        range: Option<(i64, i64, BitIndexUnit)>,
+    },
+    BitPos {
+        key: String,
+        bit: bool,
+        start: Option<i64>,
+        end: Option<i64>,
+        unit: BitIndexUnit,
+    },
+    BitOp {
+        operation: BitOperation,
+        destination: String,
+        keys: Vec<String>,
+    },
+    Bitfield {
+        key: String,
+        operations: Vec<BitfieldOperation>,
+    },
+    PfAdd {
+        key: String,
+        elements: Vec<Vec<u8>>,
+    },
+    PfCount {
+        keys: Vec<String>,
+    },
+    PfMerge {
+        destination: String,
+        keys: Vec<String>,
+    },
+    BgRewriteAof,
+    Save,
+    BgSave,
+    LastSave,
+    Shutdown(ShutdownOptions),
+    ShutdownAbort,
+    /// None stops replicating
+    ReplicaOf {
+        master: Option<MasterAddress>,
+    },
+    /// Options a replica sets before PSYNC
+    ReplConf {
+        listening_port: Option<u16>,
+    },
+    /// The offsets a replica received, and fsynced to its AOF when it has one
+    ReplConfAck {
+        offset: u64,
+        aof_offset: Option<u64>,
+    },
+    /// Sent by the master in its stream, for the replicas to acknowledge their offsets
+    ReplConfGetAck,
+    Psync {
+        replid: String,
+        offset: i64,
+    },
+    /// WAIT and WAITAOF, where a None timeout blocks forever
+    Wait {
+        condition: WaitCondition,
+        timeout: Option<Duration>,
+    },
+    /// Lowercase names of the sections to report
+    Info {
+        sections: Vec<String>,
+    },
+    Cluster(ClusterMessage),
+    /// Lets the next command use a slot this node imports
+    Asking,
+    /// RESTORE, and RESTORE-ASKING which is asking too
+    Restore {
+        key: String,
+        expires_at: Option<u128>,
+        payload: Vec<u8>,
+        replace: bool,
+        is_asking: bool,
+    },
+    Migrate(MigrateOptions),
+    Dump {
+        key: String,
+    },
 }
 
-impl TryFrom<&[u8]> for InboundMessage {
+impl TryFrom<&[Vec<u8>]> for InboundMessage {
     type Error = anyhow::Error;
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let message_string = String::from_utf8_lossy(value).to_string();
-        let lines: Vec<&str> = message_string
-            .lines()
-            .filter(|line| {
-                *line == "$" || *line == "*" || (!line.starts_with("$") && !line.starts_with("*"))
-            })
+    fn try_from(arguments: &[Vec<u8>]) -> Result<Self, Self::Error> {
+        let strings: Vec<String> = arguments
+            .iter()
+            .map(|argument| String::from_utf8_lossy(argument).to_string())
             .collect();
+        let lines: Vec<&str> = strings.iter().map(|string| string.as_str()).collect();
 
         if lines.is_empty() {
-            anyhow::bail!(format!(
-                "-> Failed to parse inbound message:\n'{}'",
-                message_string
-            ))
+            anyhow::bail!("ERR Protocol error: empty inbound message")
         }
 
         let message_id = lines[0].to_uppercase();
-        match message_id.as_str() {
+        let message = match message_id.as_str() {
             ID_CONFIG => parse_config(&lines[1..]),
             ID_PING => parse_ping(),
             ID_ECHO => parse_echo(&lines[1..]),
-            ID_SET => parse_set(&lines[1..]),
+            ID_SET => parse_set(&lines[1..], &arguments[1..]),
             ID_GET => parse_get(&lines[1..]),
             ID_KEYS => parse_keys(&lines[1..]),
-            _ => anyhow::bail!(format!(
-                "-> Failed to parse inbound message:\n'{}'",
-                message_string
-            )),
+            ID_EXPIRE => parse_expire(&lines[1..], ID_EXPIRE, TimeUnit::Seconds, false),
+            ID_PEXPIRE => parse_expire(&lines[1..], ID_PEXPIRE, TimeUnit::Milliseconds, false),
+            ID_EXPIREAT => parse_expire(&lines[1..], ID_EXPIREAT, TimeUnit::Seconds, true),
+            ID_PEXPIREAT => parse_expire(&lines[1..], ID_PEXPIREAT, TimeUnit::Milliseconds, true),
+            ID_TTL => parse_ttl(&lines[1..], ID_TTL, TimeUnit::Seconds),
+            ID_PTTL => parse_ttl(&lines[1..], ID_PTTL, TimeUnit::Milliseconds),
+            ID_EXPIRETIME => parse_expire_time(&lines[1..], ID_EXPIRETIME, TimeUnit::Seconds),
+            ID_PEXPIRETIME => {
+                parse_expire_time(&lines[1..], ID_PEXPIRETIME, TimeUnit::Milliseconds)
+            }
+            ID_PERSIST => parse_persist(&lines[1..]),
+            ID_SCAN => parse_scan(&lines[1..]),
+            ID_HSCAN => parse_collection_scan(&lines[1..], ID_HSCAN),
+            ID_SSCAN => parse_collection_scan(&lines[1..], ID_SSCAN),
+            ID_ZSCAN => parse_collection_scan(&lines[1..], ID_ZSCAN),
+            ID_DEL => parse_delete(&lines[1..], ID_DEL),
+            ID_UNLINK => parse_delete(&lines[1..], ID_UNLINK),
+            ID_EXISTS => parse_exists(&lines[1..]),
+            ID_TYPE => parse_type(&lines[1..]),
+            ID_RENAME => parse_rename(&lines[1..], ID_RENAME, false),
+            ID_RENAMENX => parse_rename(&lines[1..], ID_RENAMENX, true),
+            ID_COPY => parse_copy(&lines[1..]),
+            ID_TOUCH => parse_touch(&lines[1..]),
+            ID_RANDOMKEY => Ok(InboundMessage::RandomKey),
+            ID_DBSIZE => Ok(InboundMessage::DbSize),
+            ID_SELECT => parse_select(&lines[1..]),
+            ID_SWAPDB => parse_swap_db(&lines[1..]),
+            ID_MOVE => parse_move(&lines[1..]),
+            ID_FLUSHDB => parse_flush(&lines[1..], InboundMessage::FlushDb),
+            ID_FLUSHALL => parse_flush(&lines[1..], InboundMessage::FlushAll),
+            ID_APPEND => parse_append(&lines[1..], &arguments[1..]),
+            ID_STRLEN => parse_strlen(&lines[1..]),
+            ID_GETRANGE => parse_get_range(&lines[1..]),
+            ID_SETRANGE => parse_set_range(&lines[1..], &arguments[1..]),
+            ID_GETDEL => parse_get_del(&lines[1..]),
+            ID_GETEX => parse_get_ex(&lines[1..]),
+            ID_GETSET => parse_get_set(&lines[1..], &arguments[1..]),
+            ID_SETNX => parse_set_nx(&lines[1..], &arguments[1..]),
+            ID_SETEX => parse_set_ex(&lines[1..], &arguments[1..], ID_SETEX, TimeUnit::Seconds),
+            ID_PSETEX => parse_set_ex(
+                &lines[1..],
+                &arguments[1..],
+                ID_PSETEX,
+                TimeUnit::Milliseconds,
+            ),
+            ID_LCS => parse_lcs(&lines[1..]),
+            ID_INCR => parse_increment(&lines[1..], ID_INCR, Some(1)),
+            ID_DECR => parse_increment(&lines[1..], ID_DECR, Some(-1)),
+            ID_INCRBY => parse_increment(&lines[1..], ID_INCRBY, None),
+            ID_DECRBY => parse_decrement_by(&lines[1..]),
+            ID_INCRBYFLOAT => parse_increment_by_float(&lines[1..]),
+            ID_MGET => parse_multi_get(&lines[1..]),
+            ID_MSET => parse_multi_set(&lines[1..], &arguments[1..], ID_MSET, false),
+            ID_MSETNX => parse_multi_set(&lines[1..], &arguments[1..], ID_MSETNX, true),
+            ID_SETBIT => parse_set_bit(&lines[1..]),
+            ID_GETBIT => parse_get_bit(&lines[1..]),
+            ID_BITCOUNT => parse_bit_count(&lines[1..]),
+            ID_BITPOS => parse_bit_pos(&lines[1..]),
+            ID_BITOP => parse_bit_op(&lines[1..]),
+            ID_BITFIELD => parse_bitfield(&lines[1..], ID_BITFIELD, false),
+            ID_BITFIELD_RO => parse_bitfield(&lines[1..], ID_BITFIELD_RO, true),
+            ID_PFADD => parse_pf_add(&lines[1..], &arguments[1..]),
+            ID_PFCOUNT => parse_pf_count(&lines[1..]),
+            ID_PFMERGE => parse_pf_merge(&lines[1..]),
+            ID_BGREWRITEAOF => Ok(InboundMessage::BgRewriteAof),
+            ID_SAVE => Ok(InboundMessage::Save),
+            ID_BGSAVE => Ok(InboundMessage::BgSave),
+            ID_LASTSAVE => Ok(InboundMessage::LastSave),
+            ID_SHUTDOWN => parse_shutdown(&lines[1..]),
+            ID_REPLICAOF => parse_replica_of(&lines[1..], ID_REPLICAOF),
+            ID_SLAVEOF => parse_replica_of(&lines[1..], ID_SLAVEOF),
+            ID_REPLCONF => parse_repl_conf(&lines[1..]),
+            ID_PSYNC => parse_psync(&lines[1..]),
+            ID_WAIT => parse_wait(&lines[1..]),
+            ID_WAITAOF => parse_wait_aof(&lines[1..]),
+            ID_INFO => Ok(InboundMessage::Info {
+                sections: lines[1..].iter().map(|line| line.to_lowercase()).collect(),
+            }),
+            ID_CLUSTER => parse_cluster(&lines[1..]),
+            ID_ASKING => Ok(InboundMessage::Asking),
+            ID_RESTORE => parse_restore(&lines[1..], &arguments[1..], ID_RESTORE, false),
+            ID_RESTORE_ASKING => {
+                parse_restore(&lines[1..], &arguments[1..], ID_RESTORE_ASKING, true)
+            }
+            ID_MIGRATE => parse_migrate(&lines[1..]),
+            ID_DUMP => parse_dump(&lines[1..]),
+            _ => anyhow::bail!("ERR unknown command '{}'", lines[0]),
+        }?;
+
+        // Keys are strings, which would replace the invalid bytes of a binary key and
+        // then store it under another name, possibly the one of another key
+        let is_key_invalid = message
+            .key_positions(arguments)
+            .into_iter()
+            .any(|position| {
+                arguments
+                    .get(position)
+                    .is_some_and(|argument| std::str::from_utf8(argument).is_err())
+            });
+        if is_key_invalid {
+            anyhow::bail!("ERR invalid key, keys must be valid UTF-8")
         }
+        Ok(message)
     }
 }
 
 pub fn validate(lines: &[&str], min_length: usize, message_id: &str) -> anyhow::Result<()> {
     if lines.len() < min_length {
-        anyhow::bail!("-> Failed to parse inbound message {message_id}")
+        anyhow::bail!(
+            "ERR wrong number of arguments for '{}' command",
+            message_id.to_lowercase()
+        )
     }
     Ok(())
 }
@@ -89,11 +506,17 @@ fn get_option(lines: &[&str], key: &str) -> Option<String> {
 }
 
 fn parse_config(lines: &[&str]) -> anyhow::Result<InboundMessage> {
-    validate(lines, 2, ID_CONFIG)?;
+    validate(lines, 1, ID_CONFIG)?;
     let config_message = ConfigMessage::try_from(lines)?;
     Ok(InboundMessage::Config(config_message))
 }
 
+fn parse_cluster(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_CLUSTER)?;
+    let cluster_message = ClusterMessage::try_from(lines)?;
+    Ok(InboundMessage::Cluster(cluster_message))
+}
+
 fn parse_ping() -> anyhow::Result<InboundMessage> {
     Ok(InboundMessage::Ping)
 }
@@ -103,24 +526,31 @@ fn parse_echo(lines: &[&str]) -> anyhow::Result<InboundMessage> {
     Ok(InboundMessage::Echo(lines[0].to_string()))
 }
 
-fn parse_set(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+fn parse_set(lines: &[&str], arguments: &[Vec<u8>]) -> anyhow::Result<InboundMessage> {
     validate(lines, 2, ID_SET)?;
     let key = lines[0].to_string();
-    let value = lines[1].to_string();
+    let value = arguments[1].clone();
 
     let mut expires_at: Option<u128> = None;
     if let Some(expires_at_string) = get_option(&lines[2..], "PX") {
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)?
-            .as_millis();
+        let now = get_current_time_ms()?;
         let expires_in = expires_at_string.parse::<u128>()?;
         expires_at = Some(now + expires_in);
+    } else if let Some(expires_at_string) = get_option(&lines[2..], "PXAT") {
+        expires_at = Some(expires_at_string.parse::<u128>()?);
+    }
+    let keep_ttl = lines[2..]
+        .iter()
+        .any(|option| option.eq_ignore_ascii_case("KEEPTTL"));
+    if keep_ttl && expires_at.is_some() {
+        anyhow::bail!("ERR syntax error")
     }
 
     Ok(InboundMessage::Set {
         key,
         value,
         expires_at,
+        keep_ttl,
     })
 }
 
@@ -135,3 +565,1010 @@ fn parse_keys(lines: &[&str]) -> anyhow::Result<InboundMessage> {
     let pattern = lines[0].to_string();
     Ok(InboundMessage::Keys { pattern })
 }
+
+fn parse_integer(string: &str) -> anyhow::Result<i64> {
+    let Some(integer) = parse_canonical_integer(string.as_bytes()) else {
+        anyhow::bail!("ERR value is not an integer or out of range")
+    };
+    Ok(integer)
+}
+
+fn parse_expire_conditions(lines: &[&str]) -> anyhow::Result<Vec<ExpireCondition>> {
+    let mut conditions = Vec::new();
+    for line in lines {
+        let condition = match line.to_uppercase().as_str() {
+            "NX" => ExpireCondition::Nx,
+            "XX" => ExpireCondition::Xx,
+            "GT" => ExpireCondition::Gt,
+            "LT" => ExpireCondition::Lt,
+            _ => anyhow::bail!("ERR Unsupported option {line}"),
+        };
+        if !conditions.contains(&condition) {
+            conditions.push(condition);
+        }
+    }
+
+    let has = |condition| conditions.contains(&condition);
+    if has(ExpireCondition::Nx)
+        && (has(ExpireCondition::Xx) || has(ExpireCondition::Gt) || has(ExpireCondition::Lt))
+    {
+        anyhow::bail!("ERR NX and XX, GT or LT options at the same time are not compatible")
+    }
+    if has(ExpireCondition::Gt) && has(ExpireCondition::Lt) {
+        anyhow::bail!("ERR GT and LT options at the same time are not compatible")
+    }
+
+    Ok(conditions)
+}
+
+fn parse_expire(
+    lines: &[&str],
+    message_id: &str,
+    unit: TimeUnit,
+    is_absolute: bool,
+) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, message_id)?;
+    let key = lines[0].to_string();
+    let time = parse_integer(lines[1])? as i128;
+    let conditions = parse_expire_conditions(&lines[2..])?;
+
+    let mut expires_at = match unit {
+        TimeUnit::Seconds => time * 1000,
+        TimeUnit::Milliseconds => time,
+    };
+    if !is_absolute {
+        expires_at += get_current_time_ms()? as i128;
+    }
+    if expires_at > i64::MAX as i128 || expires_at < i64::MIN as i128 {
+        anyhow::bail!(
+            "ERR invalid expire time in '{}' command",
+            message_id.to_lowercase()
+        )
+    }
+
+    Ok(InboundMessage::Expire {
+        key,
+        expires_at,
+        conditions,
+    })
+}
+
+fn parse_ttl(lines: &[&str], message_id: &str, unit: TimeUnit) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, message_id)?;
+    let key = lines[0].to_string();
+    Ok(InboundMessage::Ttl { key, unit })
+}
+
+fn parse_expire_time(
+    lines: &[&str],
+    message_id: &str,
+    unit: TimeUnit,
+) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, message_id)?;
+    let key = lines[0].to_string();
+    Ok(InboundMessage::ExpireTime { key, unit })
+}
+
+fn parse_persist(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_PERSIST)?;
+    let key = lines[0].to_string();
+    Ok(InboundMessage::Persist { key })
+}
+
+struct ScanOptions {
+    cursor: u64,
+    pattern: Option<String>,
+    count: usize,
+    type_name: Option<String>,
+}
+
+fn parse_scan_options(
+    lines: &[&str],
+    is_type_allowed: bool,
+    is_novalues_allowed: bool,
+) -> anyhow::Result<ScanOptions> {
+    let Ok(cursor) = lines[0].parse::<u64>() else {
+        anyhow::bail!("ERR invalid cursor")
+    };
+    let mut options = ScanOptions {
+        cursor,
+        pattern: None,
+        count: SCAN_DEFAULT_COUNT,
+        type_name: None,
+    };
+
+    let mut lines = &lines[1..];
+    while !lines.is_empty() {
+        let option = lines[0].to_uppercase();
+        match (option.as_str(), lines.get(1)) {
+            ("MATCH", Some(pattern)) => options.pattern = Some(pattern.to_string()),
+            ("COUNT", Some(count)) => {
+                let count = parse_integer(count)?;
+                if count < 1 {
+                    anyhow::bail!("ERR syntax error")
+                }
+                options.count = count as usize;
+            }
+            ("TYPE", Some(type_name)) if is_type_allowed => {
+                options.type_name = Some(type_name.to_string())
+            }
+            ("NOVALUES", _) if is_novalues_allowed => {
+                lines = &lines[1..];
+                continue;
+            }
+            _ => anyhow::bail!("ERR syntax error"),
+        }
+        lines = &lines[2..];
+    }
+
+    Ok(options)
+}
+
+fn parse_scan(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_SCAN)?;
+    let options = parse_scan_options(lines, true, false)?;
+    Ok(InboundMessage::Scan {
+        cursor: options.cursor,
+        pattern: options.pattern,
+        count: options.count,
+        type_name: options.type_name,
+    })
+}
+
+fn parse_collection_scan(lines: &[&str], message_id: &str) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, message_id)?;
+    let key = lines[0].to_string();
+    parse_scan_options(&lines[1..], false, message_id == ID_HSCAN)?;
+    Ok(InboundMessage::CollectionScan { key })
+}
+
+fn to_strings(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|line| line.to_string()).collect()
+}
+
+fn parse_delete(lines: &[&str], message_id: &str) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, message_id)?;
+    Ok(InboundMessage::Delete {
+        keys: to_strings(lines),
+    })
+}
+
+fn parse_exists(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_EXISTS)?;
+    Ok(InboundMessage::Exists {
+        keys: to_strings(lines),
+    })
+}
+
+fn parse_type(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_TYPE)?;
+    let key = lines[0].to_string();
+    Ok(InboundMessage::Type { key })
+}
+
+fn parse_rename(
+    lines: &[&str],
+    message_id: &str,
+    only_if_new: bool,
+) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, message_id)?;
+    Ok(InboundMessage::Rename {
+        key: lines[0].to_string(),
+        new_key: lines[1].to_string(),
+        only_if_new,
+    })
+}
+
+fn parse_copy(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_COPY)?;
+    let source = lines[0].to_string();
+    let destination = lines[1].to_string();
+
+    let mut destination_db = None;
+    let mut replace = false;
+    let mut lines = &lines[2..];
+    while !lines.is_empty() {
+        let option = lines[0].to_uppercase();
+        match (option.as_str(), lines.get(1)) {
+            ("REPLACE", _) => {
+                replace = true;
+                lines = &lines[1..];
+            }
+            ("DB", Some(db)) => {
+                destination_db = Some(parse_integer(db)?);
+                lines = &lines[2..];
+            }
+            _ => anyhow::bail!("ERR syntax error"),
+        }
+    }
+
+    Ok(InboundMessage::Copy {
+        source,
+        destination,
+        destination_db,
+        replace,
+    })
+}
+
+fn parse_touch(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_TOUCH)?;
+    Ok(InboundMessage::Touch {
+        keys: to_strings(lines),
+    })
+}
+
+fn parse_select(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_SELECT)?;
+    let db = parse_integer(lines[0])?;
+    Ok(InboundMessage::Select { db })
+}
+
+fn parse_swap_db(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_SWAPDB)?;
+    let Ok(db) = lines[0].parse::<i64>() else {
+        anyhow::bail!("ERR invalid first DB index")
+    };
+    let Ok(other_db) = lines[1].parse::<i64>() else {
+        anyhow::bail!("ERR invalid second DB index")
+    };
+    Ok(InboundMessage::SwapDb { db, other_db })
+}
+
+fn parse_move(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_MOVE)?;
+    let key = lines[0].to_string();
+    let db = parse_integer(lines[1])?;
+    Ok(InboundMessage::Move { key, db })
+}
+
+/// Flushing is always synchronous, the ASYNC and SYNC modes are accepted for compatibility
+fn parse_flush(lines: &[&str], message: InboundMessage) -> anyhow::Result<InboundMessage> {
+    match lines {
+        [] => Ok(message),
+        [mode] if mode.eq_ignore_ascii_case("ASYNC") || mode.eq_ignore_ascii_case("SYNC") => {
+            Ok(message)
+        }
+        _ => anyhow::bail!("ERR syntax error"),
+    }
+}
+
+fn parse_shutdown(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    let mut options = ShutdownOptions::default();
+    let mut abort = false;
+    for option in lines {
+        match option.to_uppercase().as_str() {
+            "NOSAVE" if options.save.is_none() => options.save = Some(false),
+            "SAVE" if options.save.is_none() => options.save = Some(true),
+            "NOW" => options.now = true,
+            "FORCE" => options.force = true,
+            "ABORT" => abort = true,
+            _ => anyhow::bail!("ERR syntax error"),
+        }
+    }
+    match abort {
+        true if lines.len() > 1 => anyhow::bail!("ERR syntax error"),
+        true => Ok(InboundMessage::ShutdownAbort),
+        false => Ok(InboundMessage::Shutdown(options)),
+    }
+}
+
+fn parse_restore(
+    lines: &[&str],
+    arguments: &[Vec<u8>],
+    message_id: &str,
+    is_asking: bool,
+) -> anyhow::Result<InboundMessage> {
+    validate(lines, 3, message_id)?;
+    let ttl = parse_integer(lines[1])?;
+    if ttl < 0 {
+        anyhow::bail!("ERR Invalid TTL value, must be >= 0")
+    }
+    let mut replace = false;
+    let mut absolute_ttl = false;
+    // Keys have neither an idle time nor an access frequency here, both are only checked
+    let mut has_idle_time = false;
+    let mut has_frequency = false;
+    let mut index = 3;
+    while index < lines.len() {
+        match lines[index].to_uppercase().as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absolute_ttl = true,
+            "IDLETIME" if index + 1 < lines.len() && !has_frequency => {
+                index += 1;
+                if parse_integer(lines[index])? < 0 {
+                    anyhow::bail!("ERR Invalid IDLETIME value, must be >= 0")
+                }
+                has_idle_time = true;
+            }
+            "FREQ" if index + 1 < lines.len() && !has_idle_time => {
+                index += 1;
+                if !(0..=255).contains(&parse_integer(lines[index])?) {
+                    anyhow::bail!("ERR Invalid FREQ value, must be >= 0 and <= 255")
+                }
+                has_frequency = true;
+            }
+            _ => anyhow::bail!("ERR syntax error"),
+        }
+        index += 1;
+    }
+    // A TTL of 0 restores the key without expiry
+    let expires_at = match ttl {
+        0 => None,
+        ttl if absolute_ttl => Some(ttl as u128),
+        ttl => Some(get_current_time_ms()? + ttl as u128),
+    };
+    Ok(InboundMessage::Restore {
+        key: lines[0].to_string(),
+        expires_at,
+        payload: arguments[2].clone(),
+        replace,
+        is_asking,
+    })
+}
+
+fn parse_dump(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_DUMP)?;
+    let key = lines[0].to_string();
+    Ok(InboundMessage::Dump { key })
+}
+
+fn parse_migrate(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 5, ID_MIGRATE)?;
+    let Ok(port) = u16::try_from(parse_integer(lines[1])?) else {
+        anyhow::bail!("ERR Invalid port")
+    };
+    let destination_db = parse_integer(lines[3])?;
+    let timeout = match parse_integer(lines[4])? {
+        timeout if timeout <= 0 => 1000,
+        timeout => timeout as u64,
+    };
+    let mut options = MigrateOptions {
+        host: lines[0].to_string(),
+        port,
+        keys: vec![lines[2].to_string()],
+        destination_db,
+        timeout: Duration::from_millis(timeout),
+        copy: false,
+        replace: false,
+        auth: None,
+    };
+
+    let mut index = 5;
+    while index < lines.len() {
+        let remaining = lines.len() - index - 1;
+        match lines[index].to_uppercase().as_str() {
+            "COPY" => options.copy = true,
+            "REPLACE" => options.replace = true,
+            "AUTH" if remaining >= 1 => {
+                options.auth = Some(MigrateAuth {
+                    username: None,
+                    password: lines[index + 1].to_string(),
+                });
+                index += 1;
+            }
+            "AUTH2" if remaining >= 2 => {
+                options.auth = Some(MigrateAuth {
+                    username: Some(lines[index + 1].to_string()),
+                    password: lines[index + 2].to_string(),
+                });
+                index += 2;
+            }
+            "KEYS" => {
+                if !lines[2].is_empty() {
+                    anyhow::bail!(
+                        "ERR When using MIGRATE KEYS option, the key argument must be set to \
+                         the empty string"
+                    )
+                }
+                options.keys = lines[index + 1..]
+                    .iter()
+                    .map(|key| key.to_string())
+                    .collect();
+                break;
+            }
+            _ => anyhow::bail!("ERR syntax error"),
+        }
+        index += 1;
+    }
+    Ok(InboundMessage::Migrate(options))
+}
+
+fn parse_replica_of(lines: &[&str], message_id: &str) -> anyhow::Result<InboundMessage> {
+    match lines {
+        [no, one] if no.eq_ignore_ascii_case("NO") && one.eq_ignore_ascii_case("ONE") => {
+            Ok(InboundMessage::ReplicaOf { master: None })
+        }
+        [host, port] => {
+            let Ok(port) = u16::try_from(parse_integer(port)?) else {
+                anyhow::bail!("ERR Invalid master port")
+            };
+            let master = MasterAddress {
+                host: host.to_string(),
+                port,
+            };
+            Ok(InboundMessage::ReplicaOf {
+                master: Some(master),
+            })
+        }
+        _ => anyhow::bail!(
+            "ERR wrong number of arguments for '{}' command",
+            message_id.to_lowercase()
+        ),
+    }
+}
+
+fn parse_repl_conf(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    match lines.first().map(|option| option.to_lowercase()).as_deref() {
+        Some("ack") => return parse_repl_conf_ack(&lines[1..]),
+        Some("getack") => return Ok(InboundMessage::ReplConfGetAck),
+        _ => {}
+    }
+    if lines.len() % 2 != 0 {
+        anyhow::bail!("ERR syntax error")
+    }
+    let mut listening_port = None;
+    for option in lines.chunks(2) {
+        match option[0].to_lowercase().as_str() {
+            "listening-port" => {
+                let Ok(port) = u16::try_from(parse_integer(option[1])?) else {
+                    anyhow::bail!("ERR value is out of range")
+                };
+                listening_port = Some(port);
+            }
+            // The capabilities of the replica, which this master doesn't depend on
+            "capa" | "ip-address" => {}
+            _ => anyhow::bail!("ERR Unrecognized REPLCONF option: {}", option[0]),
+        }
+    }
+    Ok(InboundMessage::ReplConf { listening_port })
+}
+
+fn parse_repl_conf_ack(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    let parse_offset = |line: &str| match u64::try_from(parse_integer(line)?) {
+        Ok(offset) => Ok(offset),
+        Err(_) => anyhow::bail!("ERR value is out of range"),
+    };
+    match lines {
+        [offset] => Ok(InboundMessage::ReplConfAck {
+            offset: parse_offset(offset)?,
+            aof_offset: None,
+        }),
+        [offset, fack, aof_offset] if fack.eq_ignore_ascii_case("FACK") => {
+            Ok(InboundMessage::ReplConfAck {
+                offset: parse_offset(offset)?,
+                aof_offset: Some(parse_offset(aof_offset)?),
+            })
+        }
+        _ => anyhow::bail!("ERR syntax error"),
+    }
+}
+
+fn parse_wait(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    if lines.len() != 2 {
+        anyhow::bail!("ERR wrong number of arguments for 'wait' command")
+    }
+    Ok(InboundMessage::Wait {
+        condition: WaitCondition::Replicas(parse_wait_count(lines[0])?),
+        timeout: parse_wait_timeout(lines[1])?,
+    })
+}
+
+fn parse_wait_aof(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    if lines.len() != 3 {
+        anyhow::bail!("ERR wrong number of arguments for 'waitaof' command")
+    }
+    Ok(InboundMessage::Wait {
+        condition: WaitCondition::Fsynced {
+            local: parse_wait_count(lines[0])? > 0,
+            replicas: parse_wait_count(lines[1])?,
+        },
+        timeout: parse_wait_timeout(lines[2])?,
+    })
+}
+
+/// A count of acknowledgements to wait for, where a negative one is always reached
+fn parse_wait_count(line: &str) -> anyhow::Result<usize> {
+    Ok(usize::try_from(parse_integer(line)?).unwrap_or(0))
+}
+
+/// A timeout in milliseconds, where 0 blocks forever
+fn parse_wait_timeout(line: &str) -> anyhow::Result<Option<Duration>> {
+    let Some(timeout) = parse_canonical_integer(line.as_bytes()) else {
+        anyhow::bail!("ERR timeout is not an integer or out of range")
+    };
+    match u64::try_from(timeout) {
+        Ok(0) => Ok(None),
+        Ok(timeout) => Ok(Some(Duration::from_millis(timeout))),
+        Err(_) => anyhow::bail!("ERR timeout is negative"),
+    }
+}
+
+fn parse_psync(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_PSYNC)?;
+    Ok(InboundMessage::Psync {
+        replid: lines[0].to_string(),
+        offset: parse_integer(lines[1])?,
+    })
+}
+
+fn parse_append(lines: &[&str], arguments: &[Vec<u8>]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_APPEND)?;
+    Ok(InboundMessage::Append {
+        key: lines[0].to_string(),
+        value: arguments[1].clone(),
+    })
+}
+
+fn parse_strlen(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_STRLEN)?;
+    let key = lines[0].to_string();
+    Ok(InboundMessage::Strlen { key })
+}
+
+fn parse_get_range(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 3, ID_GETRANGE)?;
+    Ok(InboundMessage::GetRange {
+        key: lines[0].to_string(),
+        start: parse_integer(lines[1])?,
+        end: parse_integer(lines[2])?,
+    })
+}
+
+fn parse_set_range(lines: &[&str], arguments: &[Vec<u8>]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 3, ID_SETRANGE)?;
+    let offset = parse_integer(lines[1])?;
+    if offset < 0 {
+        anyhow::bail!("ERR offset is out of range")
+    }
+    Ok(InboundMessage::SetRange {
+        key: lines[0].to_string(),
+        offset: offset as usize,
+        value: arguments[2].clone(),
+    })
+}
+
+fn parse_get_del(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_GETDEL)?;
+    let key = lines[0].to_string();
+    Ok(InboundMessage::GetDel { key })
+}
+
+/// Parses the expiry options shared by GETEX and SET: EX, PX, EXAT and PXAT.
+/// Returns the absolute expiry in ms, or None if the option is not an expiry.
+fn parse_expiry_option(option: &str, time: &str, message_id: &str) -> anyhow::Result<Option<i128>> {
+    let (unit, is_absolute) = match option.to_uppercase().as_str() {
+        "EX" => (TimeUnit::Seconds, false),
+        "PX" => (TimeUnit::Milliseconds, false),
+        "EXAT" => (TimeUnit::Seconds, true),
+        "PXAT" => (TimeUnit::Milliseconds, true),
+        _ => return Ok(None),
+    };
+
+    let time = parse_integer(time)?;
+    let invalid_time_error = || {
+        anyhow::anyhow!(
+            "ERR invalid expire time in '{}' command",
+            message_id.to_lowercase()
+        )
+    };
+    if time <= 0 {
+        return Err(invalid_time_error());
+    }
+
+    let mut expires_at = match unit {
+        TimeUnit::Seconds => time as i128 * 1000,
+        TimeUnit::Milliseconds => time as i128,
+    };
+    if !is_absolute {
+        expires_at += get_current_time_ms()? as i128;
+    }
+    if expires_at > i64::MAX as i128 {
+        return Err(invalid_time_error());
+    }
+    Ok(Some(expires_at))
+}
+
+fn parse_get_ex(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_GETEX)?;
+    let key = lines[0].to_string();
+
+    let (expires_at, persist) = match &lines[1..] {
+        [] => (None, false),
+        [option] if option.eq_ignore_ascii_case("PERSIST") => (None, true),
+        [option, time] => match parse_expiry_option(option, time, ID_GETEX)? {
+            Some(expires_at) => (Some(expires_at), false),
+            None => anyhow::bail!("ERR syntax error"),
+        },
+        _ => anyhow::bail!("ERR syntax error"),
+    };
+
+    Ok(InboundMessage::GetEx {
+        key,
+        expires_at,
+        persist,
+    })
+}
+
+fn parse_get_set(lines: &[&str], arguments: &[Vec<u8>]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_GETSET)?;
+    Ok(InboundMessage::GetSet {
+        key: lines[0].to_string(),
+        value: arguments[1].clone(),
+    })
+}
+
+fn parse_set_nx(lines: &[&str], arguments: &[Vec<u8>]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_SETNX)?;
+    Ok(InboundMessage::SetNx {
+        key: lines[0].to_string(),
+        value: arguments[1].clone(),
+    })
+}
+
+fn parse_set_ex(
+    lines: &[&str],
+    arguments: &[Vec<u8>],
+    message_id: &str,
+    unit: TimeUnit,
+) -> anyhow::Result<InboundMessage> {
+    validate(lines, 3, message_id)?;
+    let option = match unit {
+        TimeUnit::Seconds => "EX",
+        TimeUnit::Milliseconds => "PX",
+    };
+    let Some(expires_at) = parse_expiry_option(option, lines[1], message_id)? else {
+        anyhow::bail!("ERR syntax error")
+    };
+    Ok(InboundMessage::SetEx {
+        key: lines[0].to_string(),
+        expires_at: expires_at as u128,
+        value: arguments[2].clone(),
+    })
+}
+
+fn parse_lcs(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_LCS)?;
+    let key_1 = lines[0].to_string();
+    let key_2 = lines[1].to_string();
+
+    let mut is_length = false;
+    let mut is_indexes = false;
+    let mut min_match_length = 0;
+    let mut with_match_length = false;
+    let mut lines = &lines[2..];
+    while !lines.is_empty() {
+        let option = lines[0].to_uppercase();
+        match (option.as_str(), lines.get(1)) {
+            ("LEN", _) => is_length = true,
+            ("IDX", _) => is_indexes = true,
+            ("WITHMATCHLEN", _) => with_match_length = true,
+            ("MINMATCHLEN", Some(length)) => {
+                min_match_length = parse_integer(length)?.max(0) as usize;
+                lines = &lines[1..];
+            }
+            _ => anyhow::bail!("ERR syntax error"),
+        }
+        lines = &lines[1..];
+    }
+
+    let mode = match (is_length, is_indexes) {
+        (true, true) => {
+            anyhow::bail!("ERR If you want both the length and indexes, please just use IDX.")
+        }
+        (true, false) => LcsMode::Length,
+        (false, true) => LcsMode::Indexes {
+            min_match_length,
+            with_match_length,
+        },
+        (false, false) => LcsMode::Value,
+    };
+
+    Ok(InboundMessage::Lcs { key_1, key_2, mode })
+}
+
+/// Parses INCR/DECR with their fixed increment, or INCRBY when the increment is None
+fn parse_increment(
+    lines: &[&str],
+    message_id: &str,
+    increment: Option<i64>,
+) -> anyhow::Result<InboundMessage> {
+    let increment = match increment {
+        Some(increment) => {
+            validate(lines, 1, message_id)?;
+            increment
+        }
+        None => {
+            validate(lines, 2, message_id)?;
+            parse_integer(lines[1])?
+        }
+    };
+    Ok(InboundMessage::IncrementBy {
+        key: lines[0].to_string(),
+        increment,
+    })
+}
+
+fn parse_decrement_by(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_DECRBY)?;
+    let decrement = parse_integer(lines[1])?;
+    let Some(increment) = decrement.checked_neg() else {
+        anyhow::bail!("ERR decrement would overflow")
+    };
+    Ok(InboundMessage::IncrementBy {
+        key: lines[0].to_string(),
+        increment,
+    })
+}
+
+fn parse_increment_by_float(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_INCRBYFLOAT)?;
+    let Some(increment) = parse_float(lines[1].as_bytes()) else {
+        anyhow::bail!("ERR value is not a valid float")
+    };
+    Ok(InboundMessage::IncrementByFloat {
+        key: lines[0].to_string(),
+        increment,
+    })
+}
+
+fn parse_multi_get(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_MGET)?;
+    Ok(InboundMessage::MultiGet {
+        keys: to_strings(lines),
+    })
+}
+
+fn parse_multi_set(
+    lines: &[&str],
+    arguments: &[Vec<u8>],
+    message_id: &str,
+    only_if_new: bool,
+) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, message_id)?;
+    if lines.len() % 2 != 0 {
+        anyhow::bail!(
+            "ERR wrong number of arguments for '{}' command",
+            message_id.to_lowercase()
+        )
+    }
+
+    let pairs = lines
+        .chunks(2)
+        .zip(arguments.chunks(2))
+        .map(|(strings, bytes)| (strings[0].to_string(), bytes[1].clone()))
+        .collect();
+    Ok(InboundMessage::MultiSet { pairs, only_if_new })
+}
+
+fn parse_bit_offset(string: &str) -> anyhow::Result<u64> {
+    let Some(offset) = parse_canonical_integer(string.as_bytes()) else {
+        anyhow::bail!("ERR bit offset is not an integer or out of range")
+    };
+    validate_bit_offset(offset)
+}
+
+fn parse_set_bit(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 3, ID_SETBIT)?;
+    let offset = parse_bit_offset(lines[1])?;
+    let bit = match lines[2] {
+        "0" => false,
+        "1" => true,
+        _ => anyhow::bail!("ERR bit is not an integer or out of range"),
+    };
+    Ok(InboundMessage::SetBit {
+        key: lines[0].to_string(),
+        offset,
+        bit,
+    })
+}
+
+fn parse_get_bit(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_GETBIT)?;
+    Ok(InboundMessage::GetBit {
+        key: lines[0].to_string(),
+        offset: parse_bit_offset(lines[1])?,
+    })
+}
+
+fn parse_bit_index_unit(lines: &[&str]) -> anyhow::Result<BitIndexUnit> {
+    match lines {
+        [] => Ok(BitIndexUnit::Byte),
+        [unit] if unit.eq_ignore_ascii_case("BYTE") => Ok(BitIndexUnit::Byte),
+        [unit] if unit.eq_ignore_ascii_case("BIT") => Ok(BitIndexUnit::Bit),
+        _ => anyhow::bail!("ERR syntax error"),
+    }
+}
+
+fn parse_bit_count(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_BITCOUNT)?;
+    let range = match lines.len() {
+        1 => None,
+        3 | 4 => Some((
+            parse_integer(lines[1])?,
+            parse_integer(lines[2])?,
+            parse_bit_index_unit(&lines[3..])?,
+        )),
+        _ => anyhow::bail!("ERR syntax error"),
+    };
+    Ok(InboundMessage::BitCount {
+        key: lines[0].to_string(),
+        range,
+    })
+}
+
+fn parse_bit_pos(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 2, ID_BITPOS)?;
+    let bit = match parse_integer(lines[1])? {
+        0 => false,
+        1 => true,
+        _ => anyhow::bail!("ERR The bit argument must be 1 or 0."),
+    };
+    if lines.len() > 5 {
+        anyhow::bail!("ERR syntax error")
+    }
+    let start = lines.get(2).map(|start| parse_integer(start)).transpose()?;
+    let end = lines.get(3).map(|end| parse_integer(end)).transpose()?;
+    let unit = parse_bit_index_unit(lines.get(4..).unwrap_or_default())?;
+
+    Ok(InboundMessage::BitPos {
+        key: lines[0].to_string(),
+        bit,
+        start,
+        end,
+        unit,
+    })
+}
+
+fn parse_bit_op(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 3, ID_BITOP)?;
+    let operation_name = lines[0].to_uppercase();
+    let operation = match operation_name.as_str() {
+        "AND" => BitOperation::And,
+        "OR" => BitOperation::Or,
+        "XOR" => BitOperation::Xor,
+        "NOT" => BitOperation::Not,
+        "DIFF" => BitOperation::Diff,
+        "DIFF1" => BitOperation::Diff1,
+        "ANDOR" => BitOperation::AndOr,
+        "ONE" => BitOperation::One,
+        _ => anyhow::bail!("ERR syntax error"),
+    };
+
+    let keys = to_strings(&lines[2..]);
+    match operation {
+        BitOperation::Not if keys.len() != 1 => {
+            anyhow::bail!("ERR BITOP NOT must be called with a single source key.")
+        }
+        BitOperation::Diff | BitOperation::Diff1 | BitOperation::AndOr if keys.len() < 2 => {
+            anyhow::bail!(
+                "ERR BITOP {operation_name} must be called with at least two source keys."
+            )
+        }
+        _ => {}
+    }
+
+    Ok(InboundMessage::BitOp {
+        operation,
+        destination: lines[1].to_string(),
+        keys,
+    })
+}
+
+/// Parses a type such as i16 or u8, where u64 is not supported
+fn parse_bitfield_type(string: &str) -> anyhow::Result<BitfieldType> {
+    let invalid_type_error = || {
+        anyhow::anyhow!(
+            "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+        )
+    };
+    let (is_signed, max_bits) = match string.as_bytes().first() {
+        Some(b'i' | b'I') => (true, 64),
+        Some(b'u' | b'U') => (false, 63),
+        _ => return Err(invalid_type_error()),
+    };
+    match parse_canonical_integer(&string.as_bytes()[1..]) {
+        Some(bits) if (1..=max_bits).contains(&bits) => Ok(BitfieldType {
+            is_signed,
+            bits: bits as u32,
+        }),
+        _ => Err(invalid_type_error()),
+    }
+}
+
+/// Parses an offset in bits, or in multiples of the type width when prefixed by #
+fn parse_bitfield_offset(string: &str, field_type: BitfieldType) -> anyhow::Result<u64> {
+    let Some(index) = string.strip_prefix('#') else {
+        return parse_bit_offset(string);
+    };
+    let offset = parse_canonical_integer(index.as_bytes())
+        .and_then(|index| index.checked_mul(field_type.bits as i64));
+    let Some(offset) = offset else {
+        anyhow::bail!("ERR bit offset is not an integer or out of range")
+    };
+    validate_bit_offset(offset)
+}
+
+fn parse_bitfield(
+    lines: &[&str],
+    message_id: &str,
+    is_read_only: bool,
+) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, message_id)?;
+    let key = lines[0].to_string();
+
+    let mut operations = Vec::new();
+    let mut overflow = BitfieldOverflow::Wrap;
+    let mut lines = &lines[1..];
+    while !lines.is_empty() {
+        let subcommand = lines[0].to_uppercase();
+        if is_read_only && subcommand != "GET" {
+            anyhow::bail!("ERR BITFIELD_RO only supports the GET subcommand")
+        }
+
+        let argument_count = match subcommand.as_str() {
+            "GET" => 2,
+            "SET" | "INCRBY" => 3,
+            "OVERFLOW" => 1,
+            _ => anyhow::bail!("ERR syntax error"),
+        };
+        if lines.len() <= argument_count {
+            anyhow::bail!("ERR syntax error")
+        }
+        let arguments = &lines[1..=argument_count];
+        lines = &lines[argument_count + 1..];
+
+        if subcommand == "OVERFLOW" {
+            overflow = match arguments[0].to_uppercase().as_str() {
+                "WRAP" => BitfieldOverflow::Wrap,
+                "SAT" => BitfieldOverflow::Sat,
+                "FAIL" => BitfieldOverflow::Fail,
+                _ => anyhow::bail!("ERR Invalid OVERFLOW type specified"),
+            };
+            continue;
+        }
+
+        let field_type = parse_bitfield_type(arguments[0])?;
+        let offset = parse_bitfield_offset(arguments[1], field_type)?;
+        let operation = match subcommand.as_str() {
+            "GET" => BitfieldOperation::Get { field_type, offset },
+            "SET" => BitfieldOperation::Set {
+                field_type,
+                offset,
+                value: parse_integer(arguments[2])?,
+                overflow,
+            },
+            _ => BitfieldOperation::IncrBy {
+                field_type,
+                offset,
+                increment: parse_integer(arguments[2])?,
+                overflow,
+            },
+        };
+        operations.push(operation);
+    }
+
+    Ok(InboundMessage::Bitfield { key, operations })
+}
+
+fn parse_pf_add(lines: &[&str], arguments: &[Vec<u8>]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_PFADD)?;
+    Ok(InboundMessage::PfAdd {
+        key: lines[0].to_string(),
+        elements: arguments[1..].to_vec(),
+    })
+}
+
+fn parse_pf_count(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_PFCOUNT)?;
+    Ok(InboundMessage::PfCount {
+        keys: to_strings(lines),
+    })
+}
+
+fn parse_pf_merge(lines: &[&str]) -> anyhow::Result<InboundMessage> {
+    validate(lines, 1, ID_PFMERGE)?;
+    Ok(InboundMessage::PfMerge {
+        destination: lines[0].to_string(),
+        keys: to_strings(&lines[1..]),
+    })
+}
@@ -0,0 +1,229 @@
+use std::net::IpAddr;
+
+use super::{parse_integer, validate};
+use crate::database::cluster::{SetSlotAction, CLUSTER_SLOTS};
+
+const ID_INFO: &str = "INFO";
+const ID_MYID: &str = "MYID";
+const ID_NODES: &str = "NODES";
+const ID_SLOTS: &str = "SLOTS";
+const ID_SHARDS: &str = "SHARDS";
+const ID_KEYSLOT: &str = "KEYSLOT";
+const ID_COUNTKEYSINSLOT: &str = "COUNTKEYSINSLOT";
+const ID_GETKEYSINSLOT: &str = "GETKEYSINSLOT";
+const ID_MEET: &str = "MEET";
+const ID_ADDSLOTS: &str = "ADDSLOTS";
+const ID_ADDSLOTSRANGE: &str = "ADDSLOTSRANGE";
+const ID_DELSLOTS: &str = "DELSLOTS";
+const ID_DELSLOTSRANGE: &str = "DELSLOTSRANGE";
+const ID_SETSLOT: &str = "SETSLOT";
+
+/// Offset of the cluster bus port from the port of clients, when MEET doesn't give it
+const BUS_PORT_OFFSET: u16 = 10000;
+
+#[derive(Debug, Clone)]
+pub enum ClusterMessage {
+    Info,
+    MyId,
+    Nodes,
+    Slots,
+    Shards,
+    KeySlot {
+        key: String,
+    },
+    CountKeysInSlot {
+        slot: u16,
+    },
+    GetKeysInSlot {
+        slot: u16,
+        count: usize,
+    },
+    Meet {
+        ip: String,
+        port: u16,
+        bus_port: u16,
+    },
+    /// ADDSLOTS and ADDSLOTSRANGE, the slots given as ranges
+    AddSlots {
+        ranges: Vec<(u16, u16)>,
+    },
+    /// DELSLOTS and DELSLOTSRANGE
+    DelSlots {
+        ranges: Vec<(u16, u16)>,
+    },
+    SetSlot {
+        slot: u16,
+        action: SetSlotAction,
+    },
+}
+
+impl TryFrom<&[&str]> for ClusterMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(lines: &[&str]) -> Result<Self, Self::Error> {
+        let message_id = lines[0].to_uppercase();
+        let arguments = &lines[1..];
+        match message_id.as_str() {
+            ID_INFO => parse_without_arguments(arguments, ID_INFO, ClusterMessage::Info),
+            ID_MYID => parse_without_arguments(arguments, ID_MYID, ClusterMessage::MyId),
+            ID_NODES => parse_without_arguments(arguments, ID_NODES, ClusterMessage::Nodes),
+            ID_SLOTS => parse_without_arguments(arguments, ID_SLOTS, ClusterMessage::Slots),
+            ID_SHARDS => parse_without_arguments(arguments, ID_SHARDS, ClusterMessage::Shards),
+            ID_KEYSLOT => parse_key_slot(arguments),
+            ID_COUNTKEYSINSLOT => parse_count_keys_in_slot(arguments),
+            ID_GETKEYSINSLOT => parse_get_keys_in_slot(arguments),
+            ID_MEET => parse_meet(arguments),
+            ID_ADDSLOTS => Ok(ClusterMessage::AddSlots {
+                ranges: parse_slots(arguments, ID_ADDSLOTS)?,
+            }),
+            ID_ADDSLOTSRANGE => Ok(ClusterMessage::AddSlots {
+                ranges: parse_slot_ranges(arguments, ID_ADDSLOTSRANGE)?,
+            }),
+            ID_DELSLOTS => Ok(ClusterMessage::DelSlots {
+                ranges: parse_slots(arguments, ID_DELSLOTS)?,
+            }),
+            ID_DELSLOTSRANGE => Ok(ClusterMessage::DelSlots {
+                ranges: parse_slot_ranges(arguments, ID_DELSLOTSRANGE)?,
+            }),
+            ID_SETSLOT => parse_set_slot(arguments),
+            _ => anyhow::bail!("ERR unknown subcommand '{}'", lines[0]),
+        }
+    }
+}
+
+fn subcommand_id(id: &str) -> String {
+    format!("CLUSTER|{id}")
+}
+
+/// Checks the exact number of arguments of a subcommand
+fn validate_exact(lines: &[&str], length: usize, id: &str) -> anyhow::Result<()> {
+    if lines.len() != length {
+        anyhow::bail!(
+            "ERR wrong number of arguments for '{}' command",
+            subcommand_id(id).to_lowercase()
+        )
+    }
+    Ok(())
+}
+
+fn parse_without_arguments(
+    lines: &[&str],
+    id: &str,
+    message: ClusterMessage,
+) -> anyhow::Result<ClusterMessage> {
+    validate_exact(lines, 0, id)?;
+    Ok(message)
+}
+
+fn parse_slot(line: &str) -> anyhow::Result<u16> {
+    match parse_integer(line) {
+        Ok(slot) if (0..CLUSTER_SLOTS as i64).contains(&slot) => Ok(slot as u16),
+        _ => anyhow::bail!("ERR Invalid or out of range slot"),
+    }
+}
+
+fn parse_key_slot(lines: &[&str]) -> anyhow::Result<ClusterMessage> {
+    validate_exact(lines, 1, ID_KEYSLOT)?;
+    Ok(ClusterMessage::KeySlot {
+        key: lines[0].to_string(),
+    })
+}
+
+fn parse_count_keys_in_slot(lines: &[&str]) -> anyhow::Result<ClusterMessage> {
+    validate_exact(lines, 1, ID_COUNTKEYSINSLOT)?;
+    let slot = match parse_integer(lines[0]) {
+        Ok(slot) if (0..CLUSTER_SLOTS as i64).contains(&slot) => slot as u16,
+        _ => anyhow::bail!("ERR Invalid slot"),
+    };
+    Ok(ClusterMessage::CountKeysInSlot { slot })
+}
+
+fn parse_get_keys_in_slot(lines: &[&str]) -> anyhow::Result<ClusterMessage> {
+    validate_exact(lines, 2, ID_GETKEYSINSLOT)?;
+    let (Ok(slot), Ok(count)) = (parse_integer(lines[0]), parse_integer(lines[1])) else {
+        anyhow::bail!("ERR Invalid slot or number of keys")
+    };
+    if !(0..CLUSTER_SLOTS as i64).contains(&slot) || count < 0 {
+        anyhow::bail!("ERR Invalid slot or number of keys")
+    }
+    Ok(ClusterMessage::GetKeysInSlot {
+        slot: slot as u16,
+        count: count as usize,
+    })
+}
+
+fn parse_meet(lines: &[&str]) -> anyhow::Result<ClusterMessage> {
+    validate(lines, 2, &subcommand_id(ID_MEET))?;
+    if lines.len() > 3 {
+        validate_exact(lines, 3, ID_MEET)?;
+    }
+    let Ok(port) = lines[1].parse::<u16>() else {
+        anyhow::bail!("ERR Invalid base port specified: {}", lines[1])
+    };
+    let bus_port = match lines.get(2) {
+        Some(bus_port) => match bus_port.parse::<u16>() {
+            Ok(bus_port) => bus_port,
+            Err(_) => anyhow::bail!("ERR Invalid bus port specified: {bus_port}"),
+        },
+        None => match port.checked_add(BUS_PORT_OFFSET) {
+            Some(bus_port) => bus_port,
+            None => anyhow::bail!("ERR Invalid node address specified: {}:{port}", lines[0]),
+        },
+    };
+    if lines[0].parse::<IpAddr>().is_err() {
+        anyhow::bail!("ERR Invalid node address specified: {}:{port}", lines[0])
+    }
+    Ok(ClusterMessage::Meet {
+        ip: lines[0].to_string(),
+        port,
+        bus_port,
+    })
+}
+
+/// Parses slots given one by one, as single slot ranges
+fn parse_slots(lines: &[&str], id: &str) -> anyhow::Result<Vec<(u16, u16)>> {
+    validate(lines, 1, &subcommand_id(id))?;
+    lines
+        .iter()
+        .map(|line| {
+            let slot = parse_slot(line)?;
+            Ok((slot, slot))
+        })
+        .collect()
+}
+
+/// Parses pairs of start and end slots
+fn parse_slot_ranges(lines: &[&str], id: &str) -> anyhow::Result<Vec<(u16, u16)>> {
+    validate(lines, 2, &subcommand_id(id))?;
+    if lines.len() % 2 != 0 {
+        anyhow::bail!(
+            "ERR wrong number of arguments for '{}' command",
+            subcommand_id(id).to_lowercase()
+        )
+    }
+    lines
+        .chunks(2)
+        .map(|pair| {
+            let (start, end) = (parse_slot(pair[0])?, parse_slot(pair[1])?);
+            if start > end {
+                anyhow::bail!("ERR start slot number {start} is greater than end slot number {end}")
+            }
+            Ok((start, end))
+        })
+        .collect()
+}
+
+fn parse_set_slot(lines: &[&str]) -> anyhow::Result<ClusterMessage> {
+    validate(lines, 2, &subcommand_id(ID_SETSLOT))?;
+    let slot = parse_slot(lines[0])?;
+    let action = match (lines[1].to_uppercase().as_str(), &lines[2..]) {
+        ("MIGRATING", [id]) => SetSlotAction::Migrating(id.to_string()),
+        ("IMPORTING", [id]) => SetSlotAction::Importing(id.to_string()),
+        ("STABLE", []) => SetSlotAction::Stable,
+        ("NODE", [id]) => SetSlotAction::Node(id.to_string()),
+        _ => anyhow::bail!(
+            "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP"
+        ),
+    };
+    Ok(ClusterMessage::SetSlot { slot, action })
+}
@@ -1,10 +1,16 @@
 use super::validate;
 
 const ID_GET: &str = "GET";
+const ID_SET: &str = "SET";
+const ID_RESETSTAT: &str = "RESETSTAT";
+const ID_REWRITE: &str = "REWRITE";
 
 #[derive(Debug, Clone)]
 pub enum ConfigMessage {
-    Get { key: String },
+    Get { patterns: Vec<String> },
+    Set { pairs: Vec<(String, String)> },
+    ResetStat,
+    Rewrite,
 }
 
 impl TryFrom<&[&str]> for ConfigMessage {
@@ -14,17 +20,47 @@ impl TryFrom<&[&str]> for ConfigMessage {
         let message_id = lines[0].to_uppercase();
         match message_id.as_str() {
             ID_GET => parse_get(&lines[1..]),
-            _ => anyhow::bail!(format!(
-                "-> Failed to parse inbound config message:\n'{:?}'",
-                lines
-            )),
+            ID_SET => parse_set(&lines[1..]),
+            ID_RESETSTAT => parse_without_arguments(&lines[1..], ID_RESETSTAT),
+            ID_REWRITE => parse_without_arguments(&lines[1..], ID_REWRITE),
+            _ => anyhow::bail!("ERR unknown subcommand '{}'", lines[0]),
         }
     }
 }
 
+fn subcommand_id(id: &str) -> String {
+    format!("CONFIG|{id}")
+}
+
 fn parse_get(lines: &[&str]) -> anyhow::Result<ConfigMessage> {
-    validate(lines, 1, ID_GET)?;
+    validate(lines, 1, &subcommand_id(ID_GET))?;
     Ok(ConfigMessage::Get {
-        key: lines[0].to_string(),
+        patterns: lines.iter().map(|line| line.to_string()).collect(),
+    })
+}
+
+fn parse_set(lines: &[&str]) -> anyhow::Result<ConfigMessage> {
+    validate(lines, 2, &subcommand_id(ID_SET))?;
+    if lines.len() % 2 != 0 {
+        anyhow::bail!("ERR wrong number of arguments for 'config|set' command")
+    }
+    Ok(ConfigMessage::Set {
+        pairs: lines
+            .chunks(2)
+            .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+            .collect(),
     })
 }
+
+fn parse_without_arguments(lines: &[&str], id: &str) -> anyhow::Result<ConfigMessage> {
+    if !lines.is_empty() {
+        anyhow::bail!(
+            "ERR wrong number of arguments for '{}' command",
+            subcommand_id(id).to_lowercase()
+        )
+    }
+    match id {
+        ID_RESETSTAT => Ok(ConfigMessage::ResetStat),
+        _ => Ok(ConfigMessage::Rewrite),
+    }
+}
@@ -0,0 +1,86 @@
+use super::InboundMessage;
+
+impl InboundMessage {
+    /// The keys the command reads or writes, which cluster mode serves only from the
+    /// node owning their slot
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            InboundMessage::Set { key, .. }
+            | InboundMessage::Get { key }
+            | InboundMessage::Expire { key, .. }
+            | InboundMessage::Ttl { key, .. }
+            | InboundMessage::ExpireTime { key, .. }
+            | InboundMessage::Persist { key }
+            | InboundMessage::CollectionScan { key }
+            | InboundMessage::Type { key }
+            | InboundMessage::Move { key, .. }
+            | InboundMessage::Append { key, .. }
+            | InboundMessage::Strlen { key }
+            | InboundMessage::GetRange { key, .. }
+            | InboundMessage::SetRange { key, .. }
+            | InboundMessage::GetDel { key }
+            | InboundMessage::GetEx { key, .. }
+            | InboundMessage::GetSet { key, .. }
+            | InboundMessage::SetNx { key, .. }
+            | InboundMessage::SetEx { key, .. }
+            | InboundMessage::IncrementBy { key, .. }
+            | InboundMessage::IncrementByFloat { key, .. }
+            | InboundMessage::SetBit { key, .. }
+            | InboundMessage::GetBit { key, .. }
+            | InboundMessage::BitCount { key, .. }
+            | InboundMessage::BitPos { key, .. }
+            | InboundMessage::Bitfield { key, .. }
+            | InboundMessage::PfAdd { key, .. }
+            | InboundMessage::Restore { key, .. }
+            | InboundMessage::Dump { key } => vec![key],
+            InboundMessage::Delete { keys }
+            | InboundMessage::Exists { keys }
+            | InboundMessage::Touch { keys }
+            | InboundMessage::MultiGet { keys }
+            | InboundMessage::PfCount { keys } => keys.iter().map(String::as_str).collect(),
+            InboundMessage::Rename { key, new_key, .. } => vec![key, new_key],
+            InboundMessage::Copy {
+                source,
+                destination,
+                ..
+            } => vec![source, destination],
+            InboundMessage::Lcs { key_1, key_2, .. } => vec![key_1, key_2],
+            InboundMessage::MultiSet { pairs, .. } => {
+                pairs.iter().map(|(key, _)| key.as_str()).collect()
+            }
+            InboundMessage::BitOp {
+                destination, keys, ..
+            }
+            | InboundMessage::PfMerge { destination, keys } => {
+                let mut all_keys = vec![destination.as_str()];
+                all_keys.extend(keys.iter().map(String::as_str));
+                all_keys
+            }
+            InboundMessage::Migrate(options) => options.keys.iter().map(String::as_str).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The indexes of the arguments holding the keys, in the order of keys()
+    pub fn key_positions(&self, arguments: &[Vec<u8>]) -> Vec<usize> {
+        match self {
+            InboundMessage::Delete { keys }
+            | InboundMessage::Exists { keys }
+            | InboundMessage::Touch { keys }
+            | InboundMessage::MultiGet { keys }
+            | InboundMessage::PfCount { keys } => (1..=keys.len()).collect(),
+            InboundMessage::MultiSet { pairs, .. } => (0..pairs.len()).map(|i| 1 + 2 * i).collect(),
+            // BITOP operation destination key...
+            InboundMessage::BitOp { keys, .. } => (2..=2 + keys.len()).collect(),
+            InboundMessage::PfMerge { keys, .. } => (1..=1 + keys.len()).collect(),
+            // MIGRATE host port key db timeout, or with an empty key and KEYS key... last
+            InboundMessage::Migrate(options)
+                if arguments.get(3).is_some_and(Vec::is_empty) && options.keys != [""] =>
+            {
+                (arguments.len() - options.keys.len()..arguments.len()).collect()
+            }
+            InboundMessage::Migrate(_) => vec![3],
+            _ => (1..=self.keys().len()).collect(),
+        }
+    }
+}
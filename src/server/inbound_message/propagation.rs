@@ -0,0 +1,170 @@
+use crate::{
+    database::{
+        bitmap::BitfieldOperation, expire::ExpireCondition, get_current_time_ms,
+        rdb::dump::parse_dump_payload,
+    },
+    server::outbound_message::OutboundMessage,
+};
+
+use super::InboundMessage;
+
+impl InboundMessage {
+    /// Returns the command to propagate once the message ran with the reply, or None
+    /// when it did not write. Commands with a relative expiry log an absolute one, and an
+    /// expiry in the past logs the deletion, so that loading gives the same dataset later.
+    pub fn propagated_arguments(
+        &self,
+        arguments: &[Vec<u8>],
+        reply: &OutboundMessage,
+    ) -> Option<Vec<Vec<u8>>> {
+        let now = get_current_time_ms().unwrap_or_default() as i128;
+        match self {
+            InboundMessage::Set {
+                key,
+                value,
+                keep_ttl: true,
+                ..
+            } => Some(set_keep_ttl_arguments(key, value)),
+            InboundMessage::Set {
+                key,
+                value,
+                expires_at,
+                ..
+            } => Some(set_arguments(key, value, *expires_at)),
+            // The result is logged, so that loading does not redo the float arithmetic
+            InboundMessage::IncrementByFloat { key, .. } => match reply {
+                OutboundMessage::Get(Some(value)) => Some(set_keep_ttl_arguments(key, value)),
+                _ => None,
+            },
+            InboundMessage::SetEx {
+                key,
+                expires_at,
+                value,
+            } => Some(set_arguments(key, value, Some(*expires_at))),
+            InboundMessage::Expire { .. } if matches!(reply, OutboundMessage::Integer(0)) => None,
+            InboundMessage::Expire {
+                key, expires_at, ..
+            } if *expires_at <= now => Some(command(&["DEL", key])),
+            InboundMessage::Expire {
+                key,
+                expires_at,
+                conditions,
+            } => {
+                let expires_at = expires_at.to_string();
+                let mut arguments = command(&["PEXPIREAT", key, &expires_at]);
+                arguments.extend(conditions.iter().map(|condition| {
+                    let name = match condition {
+                        ExpireCondition::Nx => "NX",
+                        ExpireCondition::Xx => "XX",
+                        ExpireCondition::Gt => "GT",
+                        ExpireCondition::Lt => "LT",
+                    };
+                    name.as_bytes().to_vec()
+                }));
+                Some(arguments)
+            }
+            InboundMessage::GetEx { .. } if matches!(reply, OutboundMessage::Get(None)) => None,
+            InboundMessage::GetEx {
+                key,
+                expires_at,
+                persist,
+            } => match expires_at {
+                Some(expires_at) if *expires_at <= now => Some(command(&["DEL", key])),
+                Some(expires_at) => Some(command(&["PEXPIREAT", key, &expires_at.to_string()])),
+                None if *persist => Some(command(&["PERSIST", key])),
+                None => None,
+            },
+            InboundMessage::Restore {
+                key, expires_at, ..
+            } if expires_at.is_some_and(|expires_at| expires_at as i128 <= now) => {
+                Some(command(&["DEL", key]))
+            }
+            // The expiry is logged absolute, and the value decoded once more to log it
+            InboundMessage::Restore {
+                key,
+                expires_at,
+                payload,
+                ..
+            } => {
+                let value = parse_dump_payload(payload).ok()?;
+                Some(set_arguments(key, &value.to_bytes(), *expires_at))
+            }
+            // Logs the deletion of the keys moved, as the migration goes
+            InboundMessage::Migrate(_) => None,
+            // Replies of 0 tell these did not change anything
+            InboundMessage::Delete { .. }
+            | InboundMessage::Persist { .. }
+            | InboundMessage::Copy { .. }
+            | InboundMessage::Move { .. }
+            | InboundMessage::SetNx { .. }
+            | InboundMessage::MultiSet { .. }
+            | InboundMessage::Rename { .. }
+            | InboundMessage::PfAdd { .. }
+                if matches!(reply, OutboundMessage::Integer(0)) =>
+            {
+                None
+            }
+            _ if self.is_write() => Some(arguments.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Whether the command may change the dataset
+    pub fn is_write(&self) -> bool {
+        if let InboundMessage::Bitfield { operations, .. } = self {
+            return operations
+                .iter()
+                .any(|operation| !matches!(operation, BitfieldOperation::Get { .. }));
+        }
+        matches!(
+            self,
+            InboundMessage::Set { .. }
+                | InboundMessage::SetEx { .. }
+                | InboundMessage::Expire { .. }
+                | InboundMessage::GetEx { .. }
+                | InboundMessage::Persist { .. }
+                | InboundMessage::Delete { .. }
+                | InboundMessage::Rename { .. }
+                | InboundMessage::Copy { .. }
+                | InboundMessage::SwapDb { .. }
+                | InboundMessage::Move { .. }
+                | InboundMessage::FlushDb
+                | InboundMessage::FlushAll
+                | InboundMessage::Append { .. }
+                | InboundMessage::SetRange { .. }
+                | InboundMessage::GetDel { .. }
+                | InboundMessage::GetSet { .. }
+                | InboundMessage::SetNx { .. }
+                | InboundMessage::IncrementBy { .. }
+                | InboundMessage::IncrementByFloat { .. }
+                | InboundMessage::MultiSet { .. }
+                | InboundMessage::SetBit { .. }
+                | InboundMessage::BitOp { .. }
+                | InboundMessage::PfAdd { .. }
+                | InboundMessage::PfMerge { .. }
+                | InboundMessage::Restore { .. }
+        ) || matches!(self, InboundMessage::Migrate(options) if !options.copy)
+    }
+}
+
+fn command(arguments: &[&str]) -> Vec<Vec<u8>> {
+    arguments
+        .iter()
+        .map(|argument| argument.as_bytes().to_vec())
+        .collect()
+}
+
+fn set_keep_ttl_arguments(key: &str, value: &[u8]) -> Vec<Vec<u8>> {
+    let mut arguments = set_arguments(key, value, None);
+    arguments.push(b"KEEPTTL".to_vec());
+    arguments
+}
+
+fn set_arguments(key: &str, value: &[u8], expires_at: Option<u128>) -> Vec<Vec<u8>> {
+    let mut arguments = vec![b"SET".to_vec(), key.as_bytes().to_vec(), value.to_vec()];
+    if let Some(expires_at) = expires_at {
+        arguments.push(b"PXAT".to_vec());
+        arguments.push(expires_at.to_string().into_bytes());
+    }
+    arguments
+}
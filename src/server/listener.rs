@@ -0,0 +1,120 @@
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+use crate::database::Database;
+
+/// Prefix of the bind addresses that may fail to bind without stopping the server
+const OPTIONAL_ADDRESS_PREFIX: char = '-';
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Binds every configured TCP address and the Unix socket, if any
+    pub async fn bind_all(database: &Database) -> anyhow::Result<Vec<Self>> {
+        let mut port = database.port();
+        let mut listeners = Vec::new();
+        for address in database.bind_addresses() {
+            let (address, is_optional) = match address.strip_prefix(OPTIONAL_ADDRESS_PREFIX) {
+                Some(address) => (address.to_string(), true),
+                None => (address, false),
+            };
+            let host = match address.as_str() {
+                "*" => "0.0.0.0",
+                "::*" => "::",
+                host => host,
+            };
+            match TcpListener::bind((host, port)).await {
+                Ok(listener) => {
+                    // With port 0 the other addresses reuse the port picked by the first one
+                    port = listener.local_addr()?.port();
+                    listeners.push(Listener::Tcp(listener));
+                }
+                Err(error) if is_optional => {
+                    eprintln!("-> Skipped optional address {address}:{port}. Error: {error}");
+                }
+                Err(error) => anyhow::bail!("Failed to listen on {address}:{port}: {error}"),
+            }
+        }
+
+        if let Some(path) = database.unix_socket() {
+            // A socket file left over by a previous run would make the bind fail
+            if Path::new(&path).exists() {
+                fs::remove_file(&path)?;
+            }
+            let listener = UnixListener::bind(&path)?;
+            if let Some(permissions) = database.unix_socket_perm() {
+                fs::set_permissions(&path, fs::Permissions::from_mode(permissions))?;
+            }
+            listeners.push(Listener::Unix(listener));
+        }
+
+        if listeners.is_empty() {
+            anyhow::bail!("Failed to listen on any address");
+        }
+        Ok(listeners)
+    }
+
+    /// Binds the cluster bus port on the first bind address
+    pub async fn bind_cluster_bus(database: &Database, port: u16) -> anyhow::Result<TcpListener> {
+        let address = database
+            .bind_addresses()
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let address = address.trim_start_matches(OPTIONAL_ADDRESS_PREFIX);
+        let host = match address {
+            "*" => "0.0.0.0",
+            "::*" => "::",
+            host => host,
+        };
+        match TcpListener::bind((host, port)).await {
+            Ok(listener) => Ok(listener),
+            Err(error) => {
+                anyhow::bail!("Failed to listen on the cluster bus {address}:{port}: {error}")
+            }
+        }
+    }
+
+    pub async fn accept(&self) -> anyhow::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream))
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Stream::Unix(stream))
+            }
+        }
+    }
+
+    /// TCP port actually bound
+    pub fn local_port(&self) -> Option<u16> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok().map(|address| address.port()),
+            Listener::Unix(_) => None,
+        }
+    }
+
+    /// Address actually bound, which tells the port picked when listening on port 0
+    pub fn local_address(&self) -> anyhow::Result<String> {
+        match self {
+            Listener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            Listener::Unix(listener) => {
+                let address = listener.local_addr()?;
+                let path = address.as_pathname().unwrap_or(Path::new(""));
+                Ok(format!("unix:{}", path.display()))
+            }
+        }
+    }
+}
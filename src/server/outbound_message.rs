@@ -1,44 +1,152 @@
+use crate::database::string::LcsMatch;
+
 use super::resp::{
-    create_array_reply, create_bulk_strings_reply, create_null_bulk_strings_reply,
-    create_simple_string_reply, NULL_BULK_STRING,
+    create_array_reply, create_bulk_bytes_reply, create_bulk_strings_reply, create_error_reply,
+    create_integer_reply, create_null_bulk_strings_reply, create_simple_string_reply, END_OF_LINE,
 };
 
 #[derive(Debug)]
 pub enum OutboundMessage {
     Ok,
-    ConfigGet { key: String, value: Option<String> },
+    /// A simple string reply other than OK
+    Status(String),
+    Error(String),
+    Integer(i64),
+    /// An array of integers, where None is a null
+    Integers(Vec<Option<i64>>),
+    ConfigGet(Vec<(String, String)>),
     Pong,
     Echo(String),
-    Get(Option<String>),
+    Get(Option<Vec<u8>>),
+    MultiGet(Vec<Option<Vec<u8>>>),
     Keys(Vec<String>),
+    Type(String),
+    /// The report of INFO, as a bulk string
+    Info(String),
+    Scan {
+        cursor: u64,
+        keys: Vec<String>,
+    },
+    LcsIndexes {
+        matches: Vec<LcsMatch>,
+        length: usize,
+        with_match_length: bool,
+    },
+    BulkString(String),
+    /// A nested reply, like the ones of CLUSTER SLOTS
+    Array(Vec<OutboundMessage>),
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<u8>> for OutboundMessage {
+    fn into(self) -> Vec<u8> {
+        let string = match self {
+            OutboundMessage::Ok => create_simple_string_reply("OK"),
+            OutboundMessage::Status(string) => create_simple_string_reply(&string),
+            OutboundMessage::Error(string) => create_error_reply(&string),
+            OutboundMessage::Integer(integer) => create_integer_reply(integer),
+            OutboundMessage::Integers(integers) => create_integers_string(integers),
+            OutboundMessage::ConfigGet(settings) => create_config_string(settings),
+            OutboundMessage::Pong => create_simple_string_reply("PONG"),
+            OutboundMessage::Echo(string) => create_simple_string_reply(&string),
+            OutboundMessage::Get(None) => create_null_bulk_strings_reply(),
+            OutboundMessage::Get(Some(value)) => return create_bulk_bytes_reply(&value),
+            OutboundMessage::MultiGet(values) => return create_multi_get_bytes(values),
+            OutboundMessage::Keys(values) => create_array_reply(values),
+            OutboundMessage::Type(type_name) => create_simple_string_reply(&type_name),
+            OutboundMessage::Info(info) => return create_bulk_bytes_reply(info.as_bytes()),
+            OutboundMessage::Scan { cursor, keys } => create_scan_string(cursor, keys),
+            OutboundMessage::LcsIndexes {
+                matches,
+                length,
+                with_match_length,
+            } => create_lcs_indexes_string(matches, length, with_match_length),
+            OutboundMessage::BulkString(string) => {
+                return create_bulk_bytes_reply(string.as_bytes())
+            }
+            OutboundMessage::Array(messages) => return create_nested_array_bytes(messages),
+        };
+        string.into_bytes()
+    }
+}
+
+fn create_config_string(settings: Vec<(String, String)>) -> String {
+    let lines = settings
+        .into_iter()
+        .flat_map(|(key, value)| [key, value])
+        .collect();
+    create_array_reply(lines)
+}
+
+fn create_scan_string(cursor: u64, keys: Vec<String>) -> String {
+    format!(
+        "*2{END_OF_LINE}{}{}",
+        create_bulk_strings_reply(vec![cursor.to_string()]),
+        create_array_reply(keys)
+    )
 }
 
-impl Into<String> for OutboundMessage {
-    fn into(self) -> String {
-        match self {
-            Self::Ok => create_simple_string_reply("OK"),
-            Self::ConfigGet { key, value } => create_config_string(key, value),
-            Self::Pong => create_simple_string_reply("PONG"),
-            Self::Echo(string) => create_simple_string_reply(&string),
-            Self::Get(None) => create_null_bulk_strings_reply(),
-            Self::Get(Some(value)) => create_bulk_strings_reply(vec![value]),
-            Self::Keys(values) => create_array_reply(values),
+/// Creates the IDX reply of LCS: a map with the matches and the length of the LCS, where
+/// each match is an array with the ranges in both strings and optionally its length
+fn create_lcs_indexes_string(
+    matches: Vec<LcsMatch>,
+    length: usize,
+    with_match_length: bool,
+) -> String {
+    let range_string = |(start, end): (usize, usize)| {
+        format!(
+            "*2{END_OF_LINE}{}{}",
+            create_integer_reply(start as i64),
+            create_integer_reply(end as i64)
+        )
+    };
+
+    let mut matches_string = format!("*{}{END_OF_LINE}", matches.len());
+    for lcs_match in matches {
+        let element_count = if with_match_length { 3 } else { 2 };
+        matches_string.push_str(&format!("*{element_count}{END_OF_LINE}"));
+        matches_string.push_str(&range_string(lcs_match.range_1));
+        matches_string.push_str(&range_string(lcs_match.range_2));
+        if with_match_length {
+            matches_string.push_str(&create_integer_reply(lcs_match.length as i64));
         }
     }
+
+    format!(
+        "*4{END_OF_LINE}{}{matches_string}{}{}",
+        create_bulk_strings_reply(vec!["matches".into()]),
+        create_bulk_strings_reply(vec!["len".into()]),
+        create_integer_reply(length as i64)
+    )
 }
 
-impl Into<Vec<u8>> for OutboundMessage {
-    fn into(self) -> Vec<u8> {
-        let string: String = self.into();
-        string.into_bytes()
+fn create_multi_get_bytes(values: Vec<Option<Vec<u8>>>) -> Vec<u8> {
+    let mut bytes = format!("*{}{END_OF_LINE}", values.len()).into_bytes();
+    for value in values {
+        match value {
+            Some(value) => bytes.extend(create_bulk_bytes_reply(&value)),
+            None => bytes.extend(create_null_bulk_strings_reply().into_bytes()),
+        }
     }
+    bytes
 }
 
-fn create_config_string(key: String, value: Option<String>) -> String {
-    let mut lines = vec![key];
-    match value {
-        Some(ref value) => lines.push(value.into()),
-        None => lines.push(NULL_BULK_STRING.into()),
+fn create_integers_string(integers: Vec<Option<i64>>) -> String {
+    let mut string = format!("*{}{END_OF_LINE}", integers.len());
+    for integer in integers {
+        match integer {
+            Some(integer) => string.push_str(&create_integer_reply(integer)),
+            None => string.push_str(&create_null_bulk_strings_reply()),
+        }
     }
-    create_array_reply(lines)
+    string
+}
+
+fn create_nested_array_bytes(messages: Vec<OutboundMessage>) -> Vec<u8> {
+    let mut bytes = format!("*{}{END_OF_LINE}", messages.len()).into_bytes();
+    for message in messages {
+        let message_bytes: Vec<u8> = message.into();
+        bytes.extend(message_bytes);
+    }
+    bytes
 }
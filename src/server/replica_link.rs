@@ -0,0 +1,357 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::watch,
+    time,
+};
+
+use crate::database::{
+    aof::encode_command, get_current_time_ms, replication::MasterAddress, Database,
+};
+
+use super::{
+    database_thread::DatabaseHandle, handle_message, inbound_message::InboundMessage,
+    resp::parse_command, MB,
+};
+
+/// Time to wait before connecting again after the link with the master broke
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// How often the offset reached is acknowledged to the master, besides on GETACK
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+
+/// How often the offset fsynced to the AOF is checked, to acknowledge it once it moves
+const ACK_CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// A command of the master's stream, with its bytes as received
+struct MasterCommand {
+    bytes: Vec<u8>,
+    arguments: Vec<Vec<u8>>,
+    message: anyhow::Result<InboundMessage>,
+}
+
+/// Replicates the master this server is set to follow, if any: syncs with it, then
+/// applies its stream of writes. Reconnects when the link breaks, and switches when
+/// REPLICAOF changes the master.
+pub async fn follow_master(
+    database: DatabaseHandle,
+    mut master: watch::Receiver<Option<MasterAddress>>,
+    listening_port: u16,
+) {
+    loop {
+        let address = master.borrow_and_update().clone();
+        let Some(address) = address else {
+            if master.changed().await.is_err() {
+                return;
+            }
+            continue;
+        };
+        tokio::select! {
+            _ = replicate_until_changed(&database, &address, listening_port) => {}
+            changed = master.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+async fn replicate_until_changed(
+    database: &DatabaseHandle,
+    address: &MasterAddress,
+    listening_port: u16,
+) {
+    loop {
+        if let Err(error) = replicate(database, address, listening_port).await {
+            eprintln!("-> Replication with MASTER {address} stopped: {error}");
+        }
+        let _ = database.call(|database| database.master_link_down()).await;
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn replicate(
+    database: &DatabaseHandle,
+    address: &MasterAddress,
+    listening_port: u16,
+) -> anyhow::Result<()> {
+    let timeout = Duration::from_secs(database.call(|database| database.repl_timeout()).await?);
+    let connect = TcpStream::connect((address.host.as_str(), address.port));
+    let Ok(stream) = time::timeout(timeout, connect).await else {
+        anyhow::bail!("Timeout connecting to the MASTER")
+    };
+    let mut link = MasterLink {
+        stream: stream?,
+        buffer: Vec::with_capacity(MB),
+        timeout,
+        last_ack: Instant::now(),
+        last_ack_aof_offset: None,
+    };
+    println!("-> MASTER <-> REPLICA sync started");
+
+    link.command(&["PING"]).await?;
+    link.command(&["REPLCONF", "listening-port", &listening_port.to_string()])
+        .await?;
+    link.command(&["REPLCONF", "capa", "psync2"]).await?;
+
+    let (replid, offset) = database.call(|database| database.psync_position()).await?;
+    let reply = link
+        .command(&["PSYNC", &replid, &(offset + 1).to_string()])
+        .await?;
+    if let Some(position) = reply.strip_prefix("+FULLRESYNC ") {
+        let (replid, offset) = match position.split_once(' ') {
+            Some((replid, offset)) => (replid.to_string(), offset.parse::<u64>()?),
+            None => anyhow::bail!("Bad FULLRESYNC reply from master: {reply}"),
+        };
+        println!("-> Full resync from master: {replid}:{offset}");
+        let is_diskless = database
+            .call(|database| database.loads_master_rdb_from_socket())
+            .await?;
+        if is_diskless {
+            let rdb = link.receive_rdb_bytes().await?;
+            database
+                .call(move |database| database.load_master_rdb_bytes(&rdb, replid, offset))
+                .await??;
+        } else {
+            let dir = database.call(|database| database.dir()).await?;
+            let path = link.receive_rdb(&dir).await?;
+            database
+                .call(move |database| database.load_master_rdb(&path, replid, offset))
+                .await??;
+        }
+    } else if let Some(replid) = reply.strip_prefix("+CONTINUE") {
+        let replid = Some(replid.trim().to_string()).filter(|replid| !replid.is_empty());
+        database
+            .call(move |database| database.continue_from_master(replid))
+            .await?;
+        println!("-> MASTER <-> REPLICA sync: Master accepted a Partial Resynchronization.");
+    } else {
+        anyhow::bail!("Unexpected reply to PSYNC from master: {reply}")
+    }
+
+    link.apply_stream(database).await
+}
+
+struct MasterLink {
+    stream: TcpStream,
+    /// Bytes read and not handled yet
+    buffer: Vec<u8>,
+    timeout: Duration,
+    last_ack: Instant,
+    /// The AOF offset sent with the last ACK, WAITAOF waits for it to move
+    last_ack_aof_offset: Option<u64>,
+}
+
+impl MasterLink {
+    /// Sends a command of the handshake and returns its reply line, failing on errors
+    async fn command(&mut self, arguments: &[&str]) -> anyhow::Result<String> {
+        let arguments: Vec<Vec<u8>> = arguments
+            .iter()
+            .map(|argument| argument.as_bytes().to_vec())
+            .collect();
+        let mut bytes = Vec::new();
+        encode_command(&mut bytes, &arguments);
+        self.stream.write_all(&bytes).await?;
+
+        let reply = self.read_line().await?;
+        if reply.starts_with('-') {
+            anyhow::bail!(
+                "Error reply to {} from master: {reply}",
+                String::from_utf8_lossy(&arguments[0])
+            )
+        }
+        Ok(reply)
+    }
+
+    /// Reads the next line, skipping the empty ones a master may send to keep the link
+    /// alive while it prepares the RDB file
+    async fn read_line(&mut self) -> anyhow::Result<String> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                if line.is_empty() {
+                    continue;
+                }
+                return Ok(line);
+            }
+            self.read().await?;
+        }
+    }
+
+    async fn read(&mut self) -> anyhow::Result<()> {
+        let Ok(read) = time::timeout(self.timeout, self.stream.read_buf(&mut self.buffer)).await
+        else {
+            anyhow::bail!("Timeout, no data from the MASTER")
+        };
+        if read? == 0 {
+            anyhow::bail!("Connection with MASTER lost")
+        }
+        Ok(())
+    }
+
+    /// Reads the length of the RDB of a full resync, sent as a bulk string
+    async fn read_rdb_length(&mut self) -> anyhow::Result<usize> {
+        let line = self.read_line().await?;
+        let Some(length) = line
+            .strip_prefix('$')
+            .and_then(|length| length.parse::<usize>().ok())
+        else {
+            anyhow::bail!("Bad protocol from MASTER, the first byte is not '$': {line}")
+        };
+        Ok(length)
+    }
+
+    /// Receives the RDB of a full resync in memory, to load it without writing it to disk
+    async fn receive_rdb_bytes(&mut self) -> anyhow::Result<Vec<u8>> {
+        let length = self.read_rdb_length().await?;
+        println!("-> MASTER <-> REPLICA sync: receiving {length} bytes from master to memory");
+        while self.buffer.len() < length {
+            self.read().await?;
+        }
+        Ok(self.buffer.drain(..length).collect())
+    }
+
+    /// Saves the RDB file of a full resync, sent as a bulk string, to a temporary file
+    async fn receive_rdb(&mut self, dir: &str) -> anyhow::Result<PathBuf> {
+        let length = self.read_rdb_length().await?;
+        println!("-> MASTER <-> REPLICA sync: receiving {length} bytes from master to disk");
+
+        let path = Path::new(dir).join(format!(
+            "temp-{}.{}.rdb",
+            get_current_time_ms()?,
+            std::process::id()
+        ));
+        let mut file = File::create(&path).await?;
+        let mut remaining = length;
+        let result = async {
+            while remaining > 0 {
+                if self.buffer.is_empty() {
+                    self.read().await?;
+                }
+                let count = remaining.min(self.buffer.len());
+                file.write_all(&self.buffer[..count]).await?;
+                self.buffer.drain(..count);
+                remaining -= count;
+            }
+            file.sync_all().await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(error) = result {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(error);
+        }
+        Ok(path)
+    }
+
+    /// Applies the commands of the master as they come, until the link breaks.
+    /// Acknowledges the offsets reached every second, when the master asks, and when
+    /// more writes are fsynced to the AOF.
+    async fn apply_stream(&mut self, database: &DatabaseHandle) -> anyhow::Result<()> {
+        let mut ack_interval = time::interval(ACK_CHECK_PERIOD);
+        let mut last_read = Instant::now();
+        loop {
+            let mut commands = Vec::new();
+            while let Some((arguments, read_count)) = parse_command(&self.buffer)? {
+                let bytes: Vec<u8> = self.buffer.drain(..read_count).collect();
+                if arguments.is_empty() {
+                    continue;
+                }
+                commands.push(MasterCommand {
+                    bytes,
+                    message: InboundMessage::try_from(&arguments[..]),
+                    arguments,
+                });
+            }
+            if !commands.is_empty() {
+                let is_ack_asked = database
+                    .call(move |database| run_master_commands(database, commands))
+                    .await?;
+                if is_ack_asked {
+                    self.send_ack(database, true).await?;
+                }
+            }
+
+            let is_read = tokio::select! {
+                read = self.stream.read_buf(&mut self.buffer) => {
+                    if read? == 0 {
+                        anyhow::bail!("Connection with MASTER lost")
+                    }
+                    true
+                }
+                _ = ack_interval.tick() => false,
+            };
+            if is_read {
+                last_read = Instant::now();
+            } else if last_read.elapsed() > self.timeout {
+                anyhow::bail!("Timeout, no data from the MASTER")
+            } else {
+                self.send_ack(database, false).await?;
+            }
+        }
+    }
+
+    /// Sends REPLCONF ACK with the offset reached, and the one fsynced to the AOF. Unless
+    /// forced, only when the last one is a period old or the AOF offset moved.
+    async fn send_ack(&mut self, database: &DatabaseHandle, is_forced: bool) -> anyhow::Result<()> {
+        let (offset, aof_offset) = database.call(|database| database.replication_ack()).await?;
+        let is_due = is_forced
+            || self.last_ack.elapsed() >= ACK_PERIOD
+            || aof_offset != self.last_ack_aof_offset;
+        if !is_due {
+            return Ok(());
+        }
+        self.last_ack = Instant::now();
+        self.last_ack_aof_offset = aof_offset;
+        let mut arguments = vec![
+            b"REPLCONF".to_vec(),
+            b"ACK".to_vec(),
+            offset.to_string().into_bytes(),
+        ];
+        if let Some(aof_offset) = aof_offset {
+            arguments.push(b"FACK".to_vec());
+            arguments.push(aof_offset.to_string().into_bytes());
+        }
+        let mut bytes = Vec::new();
+        encode_command(&mut bytes, &arguments);
+        self.stream.write_all(&bytes).await?;
+        Ok(())
+    }
+}
+
+/// Runs the commands of the master. Returns whether it asked for an acknowledgement of
+/// the offset reached.
+fn run_master_commands(database: &mut Database, commands: Vec<MasterCommand>) -> bool {
+    let mut is_ack_asked = false;
+    for command in commands {
+        is_ack_asked |= matches!(command.message, Ok(InboundMessage::ReplConfGetAck));
+        let db = database.master_selected_db();
+        let mut selected_db = db;
+        match &command.message {
+            Ok(message) => {
+                // The master ran it already, so an error means the datasets differ
+                if let Err(error) = handle_message(database, &mut selected_db, message) {
+                    eprintln!("-> Command from MASTER failed: {error}");
+                }
+            }
+            Err(error) => eprintln!("-> Bad command from MASTER: {error}"),
+        }
+        database.propagate_expired_keys();
+
+        let is_write = command
+            .message
+            .as_ref()
+            .is_ok_and(|message| message.is_write());
+        let write_arguments = is_write.then_some(&command.arguments[..]);
+        database.propagate_from_master(db, selected_db, write_arguments, &command.bytes);
+    }
+    is_ack_asked
+}
@@ -1,10 +1,21 @@
 pub const END_OF_LINE: &str = "\r\n";
 pub const NULL_BULK_STRING: &str = "$-1";
+/// Max size of a bulk string sent by clients (proto-max-bulk-len)
+pub const MAX_BULK_LENGTH: i64 = 512 * 1024 * 1024;
 
 pub fn create_simple_string_reply(string: &str) -> String {
     format!("+{string}{END_OF_LINE}")
 }
 
+pub fn create_error_reply(string: &str) -> String {
+    let string = string.replace(['\r', '\n'], " ");
+    format!("-{string}{END_OF_LINE}")
+}
+
+pub fn create_integer_reply(integer: i64) -> String {
+    format!(":{integer}{END_OF_LINE}")
+}
+
 pub fn create_null_bulk_strings_reply() -> String {
     format!("{NULL_BULK_STRING}{END_OF_LINE}")
 }
@@ -22,9 +33,81 @@ pub fn create_bulk_strings_reply(lines: Vec<String>) -> String {
 }
 
 pub fn create_array_reply(lines: Vec<String>) -> String {
+    if lines.is_empty() {
+        return format!("*0{END_OF_LINE}");
+    }
     format!(
         "*{}{END_OF_LINE}{}",
         lines.len(),
         create_bulk_strings_reply(lines)
     )
 }
+
+pub fn create_bulk_bytes_reply(bytes: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}{END_OF_LINE}", bytes.len()).into_bytes();
+    reply.extend_from_slice(bytes);
+    reply.extend_from_slice(END_OF_LINE.as_bytes());
+    reply
+}
+
+/// Parses a command sent either as a RESP array of bulk strings or as an inline command.
+/// Returns the arguments and the number of bytes read, or None if the command is incomplete.
+pub fn parse_command(bytes: &[u8]) -> anyhow::Result<Option<(Vec<Vec<u8>>, usize)>> {
+    if bytes.first() != Some(&b'*') {
+        return Ok(parse_inline_command(bytes));
+    }
+
+    let Some((count, mut read_count)) = read_line_number(bytes, 1)? else {
+        return Ok(None);
+    };
+    let mut arguments = Vec::with_capacity(count.clamp(0, 1024) as usize);
+    for _ in 0..count {
+        match bytes.get(read_count) {
+            None => return Ok(None),
+            Some(b'$') => {}
+            Some(_) => anyhow::bail!("ERR Protocol error: expected '$'"),
+        }
+        let Some((length, bulk_start)) = read_line_number(bytes, read_count + 1)? else {
+            return Ok(None);
+        };
+        if !(0..=MAX_BULK_LENGTH).contains(&length) {
+            anyhow::bail!("ERR Protocol error: invalid bulk length")
+        }
+
+        let bulk_end = bulk_start + length as usize;
+        if bytes.len() < bulk_end + END_OF_LINE.len() {
+            return Ok(None);
+        }
+        arguments.push(bytes[bulk_start..bulk_end].to_vec());
+        read_count = bulk_end + END_OF_LINE.len();
+    }
+    Ok(Some((arguments, read_count)))
+}
+
+fn parse_inline_command(bytes: &[u8]) -> Option<(Vec<Vec<u8>>, usize)> {
+    let end = bytes.iter().position(|byte| *byte == b'\n')?;
+    let arguments = bytes[..end]
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|argument| !argument.is_empty())
+        .map(|argument| argument.to_vec())
+        .collect();
+    Some((arguments, end + 1))
+}
+
+/// Reads the number ending the line that starts at `start`.
+/// Returns the number and the position following the line, or None if the line is incomplete.
+fn read_line_number(bytes: &[u8], start: usize) -> anyhow::Result<Option<(i64, usize)>> {
+    let Some(bytes) = bytes.get(start..) else {
+        return Ok(None);
+    };
+    let Some(end) = bytes
+        .windows(2)
+        .position(|window| window == END_OF_LINE.as_bytes())
+    else {
+        return Ok(None);
+    };
+    let Ok(number) = String::from_utf8_lossy(&bytes[..end]).parse::<i64>() else {
+        anyhow::bail!("ERR Protocol error: invalid length")
+    };
+    Ok(Some((number, start + end + END_OF_LINE.len())))
+}